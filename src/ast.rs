@@ -1,10 +1,58 @@
+use crate::error::Span;
+
+// Spans record the source range of a node, they do not
+// take part in comparisons of the AST (as in syn).
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     I32,
+    // the other integer types, `u8`, `i64`, ..
+    Int(IntTy),
+    F64,
+    Char,
     Bool,
+    // an owned `String`, the text lives in the heap
     String,
+    // `str`, only behind a reference, `&str`
+    Str,
     Unit,
     Ref(Box<Type>),
+    // a struct or enum, by name
+    Named(String),
+    // `(T1, T2, ..)`, with at least one element (`()` is `Unit`)
+    Tuple(Vec<Type>),
+    // `[T; N]`
+    Array(Box<Type>, usize),
+    // `Vec<T>`, the elements live in the heap
+    Vec(Box<Type>),
+    // `fn(T1, T2) -> R`, a function pointer (also the type of a closure
+    // capturing nothing)
+    Fn(Vec<Type>, Box<Type>),
+    // `impl Fn(T1, T2) -> R`, any closure taking and returning the given
+    // types, only as the type of a function parameter
+    ImplFn(Vec<Type>, Box<Type>),
+    // the type of diverging expressions (`break`, `continue`, ..)
+    Never,
+    // `{integer}`, the type of an integer literal without a suffix while it
+    // is inferred, by its variable (only in the type checker)
+    Integer(usize),
+    // a type parameter `T` of a generic function, opaque while its body is
+    // checked where it is declared (only in the type checker)
+    Param(String),
+}
+
+// The integer types, `i32` included (as the type of a cast or a value)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IntTy {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -13,60 +61,251 @@ pub struct Mutable(pub bool);
 #[derive(Debug, Clone, PartialEq)]
 pub struct Parameters(pub Vec<Parameter>);
 
-#[derive(Debug, Clone, PartialEq)]
+// `p: T`, where the pattern `p` is irrefutable (e.g., `mut a` or `(a, b)`)
+#[derive(Debug, Clone)]
 pub struct Parameter {
-    pub mutable: Mutable,
-    pub id: String,
+    pub pat: Pattern,
     pub ty: Type,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl PartialEq for Parameter {
+    fn eq(&self, other: &Self) -> bool {
+        self.pat == other.pat && self.ty == other.ty
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct FnDeclaration {
     pub id: String,
+    // the type parameters of a generic function, `fn f<T, U>(..)`
+    pub generics: Vec<String>,
     pub parameters: Parameters,
     pub ty: Option<Type>,
     pub body: Block,
+    pub span: Span,
+}
+
+impl PartialEq for FnDeclaration {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+            && self.generics == other.generics
+            && self.parameters == other.parameters
+            && self.ty == other.ty
+            && self.body == other.body
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub id: String,
+    pub ty: Type,
+    pub span: Span,
+}
+
+impl PartialEq for Field {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.ty == other.ty
+    }
+}
+
+// `struct S { a: i32, b: bool }`
+#[derive(Debug, Clone)]
+pub struct StructDeclaration {
+    pub id: String,
+    pub fields: Vec<Field>,
+    pub span: Span,
+}
+
+impl PartialEq for StructDeclaration {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.fields == other.fields
+    }
+}
+
+// `enum E { A, B(i32, bool), C { x: i32 } }`
+#[derive(Debug, Clone)]
+pub struct EnumDeclaration {
+    pub id: String,
+    pub variants: Vec<Variant>,
+    pub span: Span,
+}
+
+impl PartialEq for EnumDeclaration {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.variants == other.variants
+    }
+}
+
+// The fields of a tuple variant are named by position ("0", "1", ..)
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub id: String,
+    pub kind: VariantKind,
+    pub fields: Vec<Field>,
+    pub span: Span,
+}
+
+impl PartialEq for Variant {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.kind == other.kind && self.fields == other.fields
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VariantKind {
+    // `A`
+    Unit,
+    // `B(i32, bool)`
+    Tuple,
+    // `C { x: i32 }`
+    Struct,
 }
 
+// The functions, structs and enums of a program
 #[derive(Debug, Clone, PartialEq)]
-pub struct Prog(pub Vec<FnDeclaration>);
+pub struct Prog(
+    pub Vec<FnDeclaration>,
+    pub Vec<StructDeclaration>,
+    pub Vec<EnumDeclaration>,
+);
+
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Statement {
-    Let(Mutable, String, Option<Type>, Option<Expr>),
+pub enum StatementKind {
+    // `let p: T = e`, where the pattern `p` is irrefutable
+    Let(Pattern, Option<Type>, Option<Expr>),
     Assign(Expr, Expr),
-    While(Expr, Block),
+    // an optional label, e.g. `'outer: while c {}`
+    While(Option<String>, Expr, Block),
+    // `'label: for i in range {}` or `for c in s.chars() {}`
+    For(Option<String>, String, Iter, Block),
     Expr(Expr),
     Fn(FnDeclaration),
+    Struct(StructDeclaration),
+    Enum(EnumDeclaration),
+}
+
+// An integer range `start..end` or `start..=end`, `rev` iterates
+// it backwards, as `(start..end).rev()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Range {
+    pub start: Box<Expr>,
+    pub end: Box<Expr>,
+    pub inclusive: bool,
+    pub rev: bool,
 }
 
+// What a `for` loop iterates over
 #[derive(Debug, Clone, PartialEq)]
+pub enum Iter {
+    Range(Range),
+    // `s.chars()`, the characters of a `String` or `&str`
+    Chars(Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
 pub struct Block {
     pub statements: Vec<Statement>,
     pub semi: bool,
+    pub span: Span,
+}
+
+impl PartialEq for Block {
+    fn eq(&self, other: &Self) -> bool {
+        self.statements == other.statements && self.semi == other.semi
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Arguments(pub Vec<Expr>);
 
+// `|x: i32| x + y`, capturing the variables it uses by reference, or by
+// value for `move |x: i32| x + y`, the return type is optional
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub moves: bool,
+    pub parameters: Parameters,
+    pub ty: Option<Type>,
+    pub body: Box<Expr>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum UnOp {
     Ref,
     DeRef,
     Mut,
     Bang,
+    Neg,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expr {
+pub enum ExprKind {
     Ident(String),
     Lit(Literal),
     BinOp(Op, Box<Expr>, Box<Expr>),
     Par(Box<Expr>),
-    Call(String, Arguments),
+    // `f(a, b)`, calling a function, or the closure held by a variable `f`,
+    // and `f::<T>(a, b)` with explicit type arguments for a generic function
+    Call(String, Vec<Type>, Arguments),
     IfThenElse(Box<Expr>, Block, Option<Block>),
     Block(Block),
     UnOp(UnOp, Box<Expr>),
+    // labels include the leading `'`
+    Loop(Option<String>, Block),
+    Break(Option<String>, Option<Box<Expr>>),
+    Continue(Option<String>),
+    Return(Option<Box<Expr>>),
+    // `S { a: 1, b: true }`, the fields in the order given
+    Struct(String, Vec<(String, Expr)>),
+    // `e.a`, and `e.0` for the elements of tuples
+    Field(Box<Expr>, String),
+    // `(a, b)`, with at least one element (`(a,)`)
+    Tuple(Vec<Expr>),
+    // `E::A`, `E::B(1, true)` or `E::C { x: 1 }`, the fields in the order given
+    Variant(String, String, VariantKind, Vec<(String, Expr)>),
+    // `match e { p => e, .. }`
+    Match(Box<Expr>, Vec<Arm>),
+    // `[a, b, c]`
+    Array(Vec<Expr>),
+    // `[e; N]`, N copies of the value of `e`
+    Repeat(Box<Expr>, usize),
+    // `a[i]`
+    Index(Box<Expr>, Box<Expr>),
+    // `e.len()`, the methods are built in
+    Method(Box<Expr>, String, Arguments),
+    // `e as T`, between integer types (and from `bool`)
+    Cast(Box<Expr>, Type),
+    // `Vec::new()`, the associated functions are built in
+    Assoc(String, String, Arguments),
+    // `vec![a, b, c]`
+    Vec(Vec<Expr>),
+    // `vec![e; n]`, the length is computed at run time
+    VecRepeat(Box<Expr>, Box<Expr>),
+    Closure(Closure),
     //Not(Box<Expr>),
 }
 
@@ -74,6 +313,13 @@ pub enum Expr {
 pub enum Literal {
     Bool(bool),
     Int(i32),
+    // an integer of another type than `i32`, e.g., `10u8`
+    Sized(i128, IntTy),
+    // an integer without a suffix, of the type inferred by the type checker
+    // (see `FnDeclaration::type_literals`), or `i32` (see `Literal::or_i32`)
+    Unsuffixed(i128),
+    Float(f64),
+    Char(char),
     String(String),
     Unit,
 }
@@ -84,10 +330,54 @@ pub enum Op {
     Sub,
     Mul,
     Div,
+    Rem,
     And,
     Or,
     Eq,
+    Ne,
     Lt,
+    Le,
     Gt,
+    Ge,
     //Not,
 }
+
+// `p => e`
+#[derive(Debug, Clone)]
+pub struct Arm {
+    pub pat: Pattern,
+    pub body: Expr,
+    pub span: Span,
+}
+
+impl PartialEq for Arm {
+    fn eq(&self, other: &Self) -> bool {
+        self.pat == other.pat && self.body == other.body
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternKind {
+    // `_`
+    Wild,
+    Lit(Literal),
+    // a binding, `x` or `mut x`
+    Ident(Mutable, String),
+    // `E::A`, `E::B(p, q)` or `E::C { x: p }`, the fields in the order given,
+    // the fields not given are matched by a trailing `..` (if true)
+    Variant(String, String, VariantKind, Vec<(String, Pattern)>, bool),
+    // `(p, q)`, with at least one element (`(p,)`)
+    Tuple(Vec<Pattern>),
+}
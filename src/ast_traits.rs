@@ -1,6 +1,8 @@
 // Extra traits implemented for AST
 
 use crate::ast::*;
+use crate::error::{Error, Span, LITERAL_OVERFLOW};
+use std::convert::TryFrom;
 use std::fmt;
 
 #[derive(Debug)]
@@ -22,13 +24,186 @@ impl Literal {
             _ => Err(VmErr::Err(format!("cannot get Bool from {:?}", self))),
         }
     }
+
+    // the integer `i` of type `t`, an `i32` is an `Int`
+    pub fn int(i: i128, t: IntTy) -> Literal {
+        match t {
+            IntTy::I32 => Literal::Int(i as i32),
+            t => Literal::Sized(i, t),
+        }
+    }
+
+    // the value and type of an integer
+    pub fn int_value(&self) -> Option<(i128, IntTy)> {
+        match self {
+            Literal::Int(i) => Some((*i as i128, IntTy::I32)),
+            Literal::Sized(i, t) => Some((*i, *t)),
+            Literal::Unsuffixed(i) => Some((*i, IntTy::I32)),
+            _ => None,
+        }
+    }
+
+    // the literal as evaluated, an integer without a suffix is an `i32`
+    // unless the type checker gave it another type, e.g., when the program
+    // is not type checked, and must then be in the range of `i32`
+    pub fn or_i32(&self) -> Result<Literal, Error> {
+        match self {
+            Literal::Unsuffixed(i) => match i32::try_from(*i) {
+                Ok(i) => Ok(Literal::Int(i)),
+                Err(_) => Err(Error::runtime(LITERAL_OVERFLOW, "literal out of range for `i32`")
+                    .with_note(format!("the literal `{}` does not fit into the type `i32` whose range is `{}..={}`", i, i32::MIN, i32::MAX))
+                    .with_help("without the types inferred by the type checker, an integer literal without a suffix is an `i32`")),
+            },
+            l => Ok(l.clone()),
+        }
+    }
+}
+
+impl IntTy 
+{
+    pub fn from_name(s: &str) -> Option<IntTy> 
+    {
+        Some(match s 
+        {
+            "i8" => IntTy::I8,
+            "i16" => IntTy::I16,
+            "i32" => IntTy::I32,
+            "i64" => IntTy::I64,
+            "u8" => IntTy::U8,
+            "u16" => IntTy::U16,
+            "u32" => IntTy::U32,
+            "u64" => IntTy::U64,
+            "usize" => IntTy::Usize,
+            _ => return None,
+        })
+    }
+
+    pub fn bits(self) -> u32 
+    {
+        match self 
+        {
+            IntTy::I8 | IntTy::U8 => 8,
+            IntTy::I16 | IntTy::U16 => 16,
+            IntTy::I32 | IntTy::U32 => 32,
+            // `usize` is 64 bits wide, as on the usual targets
+            IntTy::I64 | IntTy::U64 | IntTy::Usize => 64,
+        }
+    }
+
+    pub fn signed(self) -> bool 
+    {
+        matches!(self, IntTy::I8 | IntTy::I16 | IntTy::I32 | IntTy::I64)
+    }
+
+    pub fn min(self) -> i128 
+    {
+        match self.signed() 
+        {
+            true => -(1 << (self.bits() - 1)),
+            false => 0,
+        }
+    }
+
+    pub fn max(self) -> i128 
+    {
+        match self.signed() 
+        {
+            true => (1 << (self.bits() - 1)) - 1,
+            false => (1 << self.bits()) - 1,
+        }
+    }
+
+    pub fn fits(self, i: i128) -> bool 
+    {
+        self.min() <= i && i <= self.max()
+    }
+
+    // `i` truncated to the width of the type (two's complement), as by `as`
+    pub fn wrap(self, i: i128) -> i128 
+    {
+        let i = i & ((1 << self.bits()) - 1);
+        match self.signed() && i > self.max() 
+        {
+            true => i - (1 << self.bits()),
+            false => i,
+        }
+    }
+}
+
+impl From<IntTy> for Type 
+{
+    fn from(t: IntTy) -> Self 
+    {
+        match t 
+        {
+            IntTy::I32 => Type::I32,
+            t => Type::Int(t),
+        }
+    }
+}
+
+impl Type 
+{
+    // the integer type, if it is one
+    pub fn int_ty(&self) -> Option<IntTy> 
+    {
+        match self 
+        {
+            Type::I32 => Some(IntTy::I32),
+            Type::Int(t) => Some(*t),
+            _ => None,
+        }
+    }
+
+    // `&str`, the type of string literals
+    pub fn str_ref() -> Type 
+    {
+        Type::Ref(Box::new(Type::Str))
+    }
+
+    // the type with the type parameters (named like structs) replaced by
+    // their arguments
+    pub fn subst(&self, args: &[(String, Type)]) -> Type 
+    {
+        let all = |ts: &[Type]| ts.iter().map(|t| t.subst(args)).collect();
+        match self 
+        {
+            Type::Named(id) => match args.iter().find(|(g, _)| g == id) 
+            {
+                Some((_, t)) => t.clone(),
+                None => self.clone(),
+            },
+            Type::Ref(t) => Type::Ref(Box::new(t.subst(args))),
+            Type::Tuple(ts) => Type::Tuple(all(ts)),
+            Type::Array(t, n) => Type::Array(Box::new(t.subst(args)), *n),
+            Type::Vec(t) => Type::Vec(Box::new(t.subst(args))),
+            Type::Fn(ts, r) => Type::Fn(all(ts), Box::new(r.subst(args))),
+            Type::ImplFn(ts, r) => Type::ImplFn(all(ts), Box::new(r.subst(args))),
+            _ => self.clone(),
+        }
+    }
 }
 
 impl Expr 
 {
+    pub fn new(kind: ExprKind, span: Span) -> Self 
+    {
+        Expr { kind, span }
+    }
+
     pub fn bin_op(o: Op, left: Expr, right: Expr) -> Self 
     {
-        Expr::BinOp(o, Box::new(left), Box::new(right))
+        let span = left.span.to(right.span);
+        Expr::new(ExprKind::BinOp(o, Box::new(left), Box::new(right)), span)
+    }
+}
+
+// Nodes built outside of the parser get a default span
+impl From<ExprKind> for Expr 
+{
+    fn from(kind: ExprKind) -> Self 
+    {
+        Expr::new(kind, Span::default())
     }
 }
 
@@ -36,15 +211,535 @@ impl From<Literal> for Expr
 {
     fn from(lit: Literal) -> Self 
     {
-        Expr::Lit(lit)
+        ExprKind::Lit(lit).into()
     }
 }
 
+// `1`, as parsed, without a suffix
 impl From<i32> for Expr 
 {
     fn from(i: i32) -> Self 
     {
-        Expr::Lit(Literal::Int(i))
+        ExprKind::Lit(Literal::Unsuffixed(i as i128)).into()
+    }
+}
+
+impl Statement 
+{
+    pub fn new(kind: StatementKind, span: Span) -> Self 
+    {
+        Statement { kind, span }
+    }
+}
+
+impl From<StatementKind> for Statement 
+{
+    fn from(kind: StatementKind) -> Self 
+    {
+        Statement::new(kind, Span::default())
+    }
+}
+
+impl From<PatternKind> for Pattern 
+{
+    fn from(kind: PatternKind) -> Self 
+    {
+        Pattern { kind, span: Span::default() }
+    }
+}
+
+impl Pattern 
+{
+    // The variables bound by the pattern, from left to right
+    pub fn bindings(&self) -> Vec<&String> 
+    {
+        match &self.kind 
+        {
+            PatternKind::Ident(_, id) => vec![id],
+            PatternKind::Variant(_, _, _, fields, _) => fields.iter().flat_map(|(_, p)| p.bindings()).collect(),
+            PatternKind::Tuple(pats) => pats.iter().flat_map(|p| p.bindings()).collect(),
+            PatternKind::Wild | PatternKind::Lit(_) => vec![],
+        }
+    }
+}
+
+impl FnDeclaration 
+{
+    // `fn(T1, T2) -> R`, the type of the function used as a value
+    pub fn fn_type(&self) -> Type 
+    {
+        let params = self.parameters.0.iter().map(|p| p.ty.clone()).collect();
+        Type::Fn(params, Box::new(self.ty.clone().unwrap_or(Type::Unit)))
+    }
+
+    // The instance `id` of a generic function, with the type parameters
+    // replaced by the type arguments in the signature and the body
+    pub fn instantiate(&self, id: &str, args: &[(String, Type)]) -> FnDeclaration 
+    {
+        let mut f = self.clone();
+        f.id = id.to_string();
+        f.generics = vec![];
+        for p in &mut f.parameters.0 
+        {
+            p.ty = p.ty.subst(args);
+        }
+        f.ty = f.ty.map(|t| t.subst(args));
+        Rewrite 
+        {
+            expr: &mut |_| (),
+            pat: &mut |_| (),
+            ty: &mut |t| *t = t.subst(args),
+        }
+        .block(&mut f.body);
+        f
+    }
+
+    // The function with its calls of generic functions replaced by calls
+    // of the instances, given by the span of each call
+    pub fn rename_calls(&self, calls: &[(Span, String)]) -> FnDeclaration 
+    {
+        let mut f = self.clone();
+        Rewrite 
+        {
+            expr: &mut |e| rename_call(e, calls),
+            pat: &mut |_| (),
+            ty: &mut |_| (),
+        }
+        .block(&mut f.body);
+        f
+    }
+
+    // The function with its integer literals without a suffix given the
+    // types inferred for them, by the span of each literal (in expressions
+    // and patterns)
+    pub fn type_literals(&self, lits: &[(Span, IntTy)]) -> FnDeclaration 
+    {
+        let mut f = self.clone();
+        Rewrite 
+        {
+            expr: &mut |e| if let ExprKind::Lit(l) = &mut e.kind { type_literal(l, e.span, lits) },
+            pat: &mut |p| if let PatternKind::Lit(l) = &mut p.kind { type_literal(l, p.span, lits) },
+            ty: &mut |_| (),
+        }
+        .block(&mut f.body);
+        f
+    }
+}
+
+impl Statement 
+{
+    // as for functions, the calls of generic functions renamed
+    pub fn rename_calls(&self, calls: &[(Span, String)]) -> Statement 
+    {
+        let mut stmt = self.clone();
+        Rewrite 
+        {
+            expr: &mut |e| rename_call(e, calls),
+            pat: &mut |_| (),
+            ty: &mut |_| (),
+        }
+        .statement(&mut stmt);
+        stmt
+    }
+
+    // as for functions, the integer literals typed
+    pub fn type_literals(&self, lits: &[(Span, IntTy)]) -> Statement 
+    {
+        let mut stmt = self.clone();
+        Rewrite 
+        {
+            expr: &mut |e| if let ExprKind::Lit(l) = &mut e.kind { type_literal(l, e.span, lits) },
+            pat: &mut |p| if let PatternKind::Lit(l) = &mut p.kind { type_literal(l, p.span, lits) },
+            ty: &mut |_| (),
+        }
+        .statement(&mut stmt);
+        stmt
+    }
+}
+
+fn type_literal(l: &mut Literal, span: Span, lits: &[(Span, IntTy)]) 
+{
+    if let Literal::Unsuffixed(i) = *l 
+    {
+        if let Some((_, t)) = lits.iter().find(|(s, _)| *s == span) 
+        {
+            *l = Literal::int(i, *t);
+        }
+    }
+}
+
+fn rename_call(e: &mut Expr, calls: &[(Span, String)]) 
+{
+    let span = e.span;
+    if let ExprKind::Call(id, targs, _) = &mut e.kind 
+    {
+        if let Some((_, instance)) = calls.iter().find(|(s, _)| *s == span) 
+        {
+            *id = instance.clone();
+            targs.clear();
+        }
+    }
+}
+
+// A walk over the expressions and the patterns of `match` arms (outer
+// before inner) and the types of a function body, changing them in place
+struct Rewrite<'a> 
+{
+    expr: &'a mut dyn FnMut(&mut Expr),
+    pat: &'a mut dyn FnMut(&mut Pattern),
+    ty: &'a mut dyn FnMut(&mut Type),
+}
+
+impl Rewrite<'_> 
+{
+    fn exprs<'a>(&mut self, es: impl IntoIterator<Item = &'a mut Expr>) 
+    {
+        for e in es 
+        {
+            self.expr(e);
+        }
+    }
+
+    fn parameters(&mut self, ps: &mut Parameters) 
+    {
+        for p in &mut ps.0 
+        {
+            (self.ty)(&mut p.ty);
+        }
+    }
+
+    fn expr(&mut self, e: &mut Expr) 
+    {
+        (self.expr)(e);
+        match &mut e.kind 
+        {
+            ExprKind::Ident(_) | ExprKind::Lit(_) | ExprKind::Continue(_) => (),
+            ExprKind::Call(_, targs, args) => 
+            {
+                for t in targs 
+                {
+                    (self.ty)(t);
+                }
+                self.exprs(&mut args.0);
+            },
+            ExprKind::BinOp(_, l, r) | ExprKind::Index(l, r) | ExprKind::VecRepeat(l, r) => self.exprs(vec![&mut **l, &mut **r]),
+            ExprKind::Par(e) | ExprKind::UnOp(_, e) | ExprKind::Field(e, _) | ExprKind::Repeat(e, _) => self.expr(e),
+            ExprKind::Cast(e, t) => 
+            {
+                self.expr(e);
+                (self.ty)(t);
+            },
+            ExprKind::IfThenElse(c, t, f) => 
+            {
+                self.expr(c);
+                self.block(t);
+                if let Some(f) = f 
+                {
+                    self.block(f);
+                }
+            },
+            ExprKind::Block(b) | ExprKind::Loop(_, b) => self.block(b),
+            ExprKind::Break(_, e) | ExprKind::Return(e) => self.exprs(e.iter_mut().map(|e| &mut **e)),
+            ExprKind::Struct(_, fields) | ExprKind::Variant(_, _, _, fields) => self.exprs(fields.iter_mut().map(|(_, e)| e)),
+            ExprKind::Tuple(es) | ExprKind::Array(es) | ExprKind::Vec(es) | ExprKind::Assoc(_, _, Arguments(es)) => self.exprs(es),
+            ExprKind::Match(e, arms) => 
+            {
+                self.expr(e);
+                for arm in arms 
+                {
+                    self.pattern(&mut arm.pat);
+                    self.expr(&mut arm.body);
+                }
+            },
+            ExprKind::Method(e, _, args) => 
+            {
+                self.expr(e);
+                self.exprs(&mut args.0);
+            },
+            ExprKind::Closure(c) => 
+            {
+                self.parameters(&mut c.parameters);
+                if let Some(t) = &mut c.ty 
+                {
+                    (self.ty)(t);
+                }
+                self.expr(&mut c.body);
+            },
+        }
+    }
+
+    fn pattern(&mut self, p: &mut Pattern) 
+    {
+        (self.pat)(p);
+        match &mut p.kind 
+        {
+            PatternKind::Variant(_, _, _, fields, _) => 
+            {
+                for (_, p) in fields 
+                {
+                    self.pattern(p);
+                }
+            },
+            PatternKind::Tuple(pats) => 
+            {
+                for p in pats 
+                {
+                    self.pattern(p);
+                }
+            },
+            PatternKind::Wild | PatternKind::Lit(_) | PatternKind::Ident(..) => (),
+        }
+    }
+
+    fn block(&mut self, b: &mut Block) 
+    {
+        for stmt in &mut b.statements 
+        {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &mut Statement) 
+    {
+        match &mut stmt.kind 
+        {
+            StatementKind::Let(_, t, e) => 
+            {
+                if let Some(t) = t 
+                {
+                    (self.ty)(t);
+                }
+                self.exprs(e);
+            },
+            StatementKind::Assign(l, r) => self.exprs(vec![l, r]),
+            StatementKind::While(_, c, b) => 
+            {
+                self.expr(c);
+                self.block(b);
+            },
+            StatementKind::For(_, _, iter, b) => 
+            {
+                match iter 
+                {
+                    Iter::Range(range) => self.exprs(vec![&mut *range.start, &mut *range.end]),
+                    Iter::Chars(s) => self.expr(s),
+                }
+                self.block(b);
+            },
+            StatementKind::Expr(e) => self.expr(e),
+            StatementKind::Fn(decl) => 
+            {
+                self.parameters(&mut decl.parameters);
+                if let Some(t) = &mut decl.ty 
+                {
+                    (self.ty)(t);
+                }
+                self.block(&mut decl.body);
+            },
+            StatementKind::Struct(decl) => 
+            {
+                for field in &mut decl.fields 
+                {
+                    (self.ty)(&mut field.ty);
+                }
+            },
+            StatementKind::Enum(decl) => 
+            {
+                for field in decl.variants.iter_mut().flat_map(|v| &mut v.fields) 
+                {
+                    (self.ty)(&mut field.ty);
+                }
+            },
+        }
+    }
+}
+
+impl Closure 
+{
+    // Capture analysis, the variables used by the body but bound outside of
+    // it (some may name functions rather than variables), in order of first
+    // use, each with the span of its first assignment or mutable borrow
+    pub fn free_vars(&self) -> Vec<(String, Option<Span>)> 
+    {
+        let mut uses = Uses 
+        {
+            bound: self.parameters.0.iter().flat_map(|p| p.pat.bindings()).cloned().collect(),
+            free: vec![],
+        };
+        uses.expr(&self.body);
+        uses.free
+    }
+}
+
+// The walk of capture analysis, with the variables in scope (innermost last)
+// and the free variables found so far
+struct Uses 
+{
+    bound: Vec<String>,
+    free: Vec<(String, Option<Span>)>,
+}
+
+impl Uses 
+{
+    fn var(&mut self, id: &str, mutated: Option<Span>) 
+    {
+        if self.bound.iter().any(|b| b == id) 
+        {
+            return;
+        }
+        match self.free.iter_mut().find(|(f, _)| f == id) 
+        {
+            Some((_, m)) => *m = m.or(mutated),
+            None => self.free.push((id.to_string(), mutated)),
+        }
+    }
+
+    // a place assigned or mutably borrowed at `span`, e.g., `a.b[i]` or `*r`
+    fn place(&mut self, e: &Expr, span: Span) 
+    {
+        match &e.kind 
+        {
+            ExprKind::Ident(id) => self.var(id, Some(span)),
+            ExprKind::Par(e) | ExprKind::Field(e, _) | ExprKind::UnOp(UnOp::Mut, e) | ExprKind::UnOp(UnOp::DeRef, e) => self.place(e, span),
+            ExprKind::Index(e, i) => 
+            {
+                self.expr(i);
+                self.place(e, span);
+            },
+            _ => self.expr(e),
+        }
+    }
+
+    fn exprs<'a>(&mut self, es: impl IntoIterator<Item = &'a Expr>) 
+    {
+        for e in es 
+        {
+            self.expr(e);
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) 
+    {
+        match &e.kind 
+        {
+            ExprKind::Ident(id) => self.var(id, None),
+            ExprKind::Call(id, _, args) => 
+            {
+                self.var(id, None);
+                self.exprs(&args.0);
+            },
+            ExprKind::Lit(_) | ExprKind::Continue(_) => (),
+            ExprKind::BinOp(_, l, r) | ExprKind::Index(l, r) | ExprKind::VecRepeat(l, r) => self.exprs(vec![&**l, &**r]),
+            ExprKind::UnOp(UnOp::Ref, inner) => match &inner.kind 
+            {
+                ExprKind::UnOp(UnOp::Mut, place) => self.place(place, e.span),
+                _ => self.expr(inner),
+            },
+            ExprKind::Par(e) | ExprKind::UnOp(_, e) | ExprKind::Field(e, _) | ExprKind::Repeat(e, _) | ExprKind::Cast(e, _) => self.expr(e),
+            ExprKind::IfThenElse(c, t, f) => 
+            {
+                self.expr(c);
+                self.block(t);
+                if let Some(f) = f 
+                {
+                    self.block(f);
+                }
+            },
+            ExprKind::Block(b) | ExprKind::Loop(_, b) => self.block(b),
+            ExprKind::Break(_, e) | ExprKind::Return(e) => self.exprs(e.iter().map(|e| &**e)),
+            ExprKind::Struct(_, fields) | ExprKind::Variant(_, _, _, fields) => self.exprs(fields.iter().map(|(_, e)| e)),
+            ExprKind::Tuple(es) | ExprKind::Array(es) | ExprKind::Vec(es) | ExprKind::Assoc(_, _, Arguments(es)) => self.exprs(es),
+            ExprKind::Match(e, arms) => 
+            {
+                self.expr(e);
+                for arm in arms 
+                {
+                    let n = self.bound.len();
+                    self.bound.extend(arm.pat.bindings().into_iter().cloned());
+                    self.expr(&arm.body);
+                    self.bound.truncate(n);
+                }
+            },
+            // the methods mutating the receiver
+            ExprKind::Method(e, m, args) => 
+            {
+                match m.as_str() 
+                {
+                    "push" | "pop" | "push_str" => self.place(e, e.span),
+                    _ => self.expr(e),
+                }
+                self.exprs(&args.0);
+            },
+            ExprKind::Closure(c) => 
+            {
+                let n = self.bound.len();
+                self.bound.extend(c.parameters.0.iter().flat_map(|p| p.pat.bindings()).cloned());
+                self.expr(&c.body);
+                self.bound.truncate(n);
+            },
+        }
+    }
+
+    // functions declared in the block do not capture, their names are bound
+    fn block(&mut self, b: &Block) 
+    {
+        let n = self.bound.len();
+        for stmt in &b.statements 
+        {
+            if let StatementKind::Fn(decl) = &stmt.kind 
+            {
+                self.bound.push(decl.id.clone());
+            }
+        }
+        for stmt in &b.statements 
+        {
+            match &stmt.kind 
+            {
+                StatementKind::Let(p, _, e) => 
+                {
+                    self.exprs(e);
+                    self.bound.extend(p.bindings().into_iter().cloned());
+                },
+                StatementKind::Assign(l, r) => 
+                {
+                    self.expr(r);
+                    self.place(l, stmt.span);
+                },
+                StatementKind::While(_, c, b) => 
+                {
+                    self.expr(c);
+                    self.block(b);
+                },
+                StatementKind::For(_, id, iter, b) => 
+                {
+                    match iter 
+                    {
+                        Iter::Range(range) => self.exprs(vec![&*range.start, &*range.end]),
+                        Iter::Chars(s) => self.expr(s),
+                    }
+                    self.bound.push(id.clone());
+                    self.block(b);
+                    self.bound.pop();
+                },
+                StatementKind::Expr(e) => self.expr(e),
+                StatementKind::Fn(_) | StatementKind::Struct(_) | StatementKind::Enum(_) => (),
+            }
+        }
+        self.bound.truncate(n);
+    }
+}
+
+// `(a, b)`, and `(a,)` for a single element
+fn tuple<T: fmt::Display>(elements: &[T]) -> String
+{
+    match elements
+    {
+        [e] => format!("({},)", e),
+        _ => 
+        {
+            let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+            format!("({})", elements.join(", "))
+        },
     }
 }
 
@@ -60,9 +755,9 @@ impl From<Expr> for Literal
 {
     fn from(e: Expr) -> Self 
     {
-        match e 
+        match e.kind 
         {
-            Expr::Lit(l) => l,
+            ExprKind::Lit(l) => l,
             _ => unreachable!(),
         }
     }
@@ -79,11 +774,15 @@ impl fmt::Display for Op
             Op::Sub => "-",
             Op::Mul => "*",
             Op::Div => "/",
+            Op::Rem => "%",
             Op::And => "&&",
             Op::Or => "||",
             Op::Eq => "==",
+            Op::Ne => "!=",
             Op::Lt => "<",
+            Op::Le => "<=",
             Op::Gt => ">",
+            Op::Ge => ">=",
             //Op::Not => "!",
         };
         write!(f, "{}", s)
@@ -102,6 +801,10 @@ impl fmt::Display for Literal
         {
             Literal::Bool(b) => b.to_string(),
             Literal::Int(i) => i.to_string(),
+            Literal::Sized(i, _) | Literal::Unsuffixed(i) => i.to_string(),
+            // as by `{}`, `1.0` is shown as `1`
+            Literal::Float(x) => x.to_string(),
+            Literal::Char(c) => c.to_string(),
             Literal::Unit => "()".to_string(),
             Literal::String(content) => content.to_string(),
         };
@@ -127,22 +830,83 @@ impl fmt::Display for Type
         let s = match self
         {
             Type::I32 => "i32".to_string(),
+            Type::F64 => "f64".to_string(),
+            Type::Char => "char".to_string(),
+            Type::Int(t) => t.to_string(),
             Type::Bool => "bool".to_string(),
             Type::Unit => "()".to_string(),
             Type::String => "String".to_string(),
+            Type::Str => "str".to_string(),
             Type::Ref(e) => format!("&{}", *e.clone()),
+            Type::Never => "!".to_string(),
+            Type::Integer(_) => "{integer}".to_string(),
+            Type::Named(id) | Type::Param(id) => id.clone(),
+            Type::Tuple(types) => tuple(types),
+            Type::Array(t, n) => format!("[{}; {}]", t, n),
+            Type::Vec(t) => format!("Vec<{}>", t),
+            Type::Fn(params, ret) => format!("fn{}", signature(params, ret)),
+            Type::ImplFn(params, ret) => format!("impl Fn{}", signature(params, ret)),
         };
         write!(f, "{}", s)
     }
 }
 
+// `(T1, T2) -> R`, the return type is left out if `()`
+fn signature(params: &[Type], ret: &Type) -> String
+{
+    let params: Vec<String> = params.iter().map(|t| t.to_string()).collect();
+    match ret
+    {
+        Type::Unit => format!("({})", params.join(", ")),
+        _ => format!("({}) -> {}", params.join(", "), ret),
+    }
+}
+
+// `f::<i32, bool>`, a generic function with its type arguments, also the
+// name of the instance of `f` for these arguments
+pub fn turbofish(id: &str, args: &[Type]) -> String
+{
+    let args: Vec<String> = args.iter().map(|t| t.to_string()).collect();
+    format!("{}::<{}>", id, args.join(", "))
+}
+
 #[test]
 fn display_type() 
 {
     assert_eq!(format!("{}", Type::I32), "i32");
+    assert_eq!(format!("{}", Type::F64), "f64");
+    assert_eq!(format!("{}", Type::Char), "char");
     assert_eq!(format!("{}", Type::Bool), "bool");
     assert_eq!(format!("{}", Type::Unit), "()");
     assert_eq!(format!("{}", Type::String), "String");
+    assert_eq!(format!("{}", Type::Ref(Box::new(Type::Str))), "&str");
+    assert_eq!(format!("{}", Type::Tuple(vec![Type::I32, Type::Bool])), "(i32, bool)");
+    assert_eq!(format!("{}", Type::Tuple(vec![Type::I32])), "(i32,)");
+    assert_eq!(format!("{}", Type::Array(Box::new(Type::Bool), 3)), "[bool; 3]");
+    assert_eq!(format!("{}", Type::Vec(Box::new(Type::I32))), "Vec<i32>");
+    assert_eq!(format!("{}", Type::Int(IntTy::Usize)), "usize");
+    assert_eq!(format!("{}", Type::Fn(vec![Type::I32, Type::Bool], Box::new(Type::I32))), "fn(i32, bool) -> i32");
+    assert_eq!(format!("{}", Type::ImplFn(vec![Type::I32], Box::new(Type::Unit))), "impl Fn(i32)");
+}
+
+impl fmt::Display for IntTy 
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
+    {
+        let s = match self
+        {
+            IntTy::I8 => "i8",
+            IntTy::I16 => "i16",
+            IntTy::I32 => "i32",
+            IntTy::I64 => "i64",
+            IntTy::U8 => "u8",
+            IntTy::U16 => "u16",
+            IntTy::U32 => "u32",
+            IntTy::U64 => "u64",
+            IntTy::Usize => "usize",
+        };
+        write!(f, "{}", s)
+    }
 }
 
 impl fmt::Display for UnOp 
@@ -151,10 +915,11 @@ impl fmt::Display for UnOp
     {
         let s = match self
         {
-            UnOp::Bang => format!("!"),
-            UnOp::DeRef => format!("*"),
-            UnOp::Mut => format!("mut "),
-            UnOp::Ref => format!("&"),
+            UnOp::Bang => "!",
+            UnOp::DeRef => "*",
+            UnOp::Mut => "mut ",
+            UnOp::Ref => "&",
+            UnOp::Neg => "-",
         };
         write!(f, "{}", s)
     }
@@ -164,9 +929,9 @@ impl fmt::Display for Expr
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
     {
-        let s = match self
+        let s = match &self.kind
         {
-            Expr::Call(s,args) => 
+            ExprKind::Call(s, targs, args) => 
             {
                 let mut params = String::new();
                 let mut iter_param = args.0.iter().peekable();
@@ -178,24 +943,189 @@ impl fmt::Display for Expr
                         params.push_str(", ");
                     }
                 }
-                format!("{}({})", s, params)
+                match targs.as_slice()
+                {
+                    [] => format!("{}({})", s, params),
+                    _ => format!("{}({})", turbofish(s, targs), params),
+                }
             },
-            Expr::Ident(a) => a.to_owned(),
-            Expr::Lit(l) => format!("{}", l),
-            Expr::BinOp(op, l, r) => format!("{} {} {}", l, op, r),
-            Expr::Par(e) => format!("({})", e),
-            Expr::IfThenElse(c, f, e) => 
+            ExprKind::Ident(a) => a.to_owned(),
+            // as in the source, `1.0` and `'a'`
+            ExprKind::Lit(Literal::Float(x)) => format!("{:?}", x),
+            ExprKind::Lit(Literal::Char(c)) => format!("{:?}", c),
+            ExprKind::Lit(Literal::String(s)) => format!("{:?}", s),
+            ExprKind::Lit(l) => format!("{}", l),
+            ExprKind::BinOp(op, l, r) => format!("{} {} {}", l, op, r),
+            ExprKind::Par(e) => format!("({})", e),
+            ExprKind::IfThenElse(c, f, e) => 
             {
                 format!("if {} {{\n{}}}\n else\n {{ {:?} }}", c, &f, e)
             },
-            Expr::Block(bl) => format!("{}", bl),
-            Expr::UnOp(uop, e) => format!("{}{}", uop, e),
+            ExprKind::Block(bl) => format!("{}", bl),
+            ExprKind::UnOp(uop, e) => format!("{}{}", uop, e),
+            ExprKind::Loop(label, bl) => format!("{}loop {{\n{}}}", label_prefix(label), bl),
+            ExprKind::Break(label, e) => 
+            {
+                let mut s = String::from("break");
+                if let Some(label) = label
+                {
+                    s.push_str(&format!(" {}", label));
+                }
+                if let Some(e) = e
+                {
+                    s.push_str(&format!(" {}", e));
+                }
+                s
+            },
+            ExprKind::Continue(label) => match label
+            {
+                Some(label) => format!("continue {}", label),
+                None => "continue".to_string(),
+            },
+            ExprKind::Struct(id, fields) => 
+            {
+                let fields: Vec<String> = fields.iter().map(|(f, e)| format!("{}: {}", f, e)).collect();
+                format!("{} {{ {} }}", id, fields.join(", "))
+            },
+            ExprKind::Field(e, f) => format!("{}.{}", e, f),
+            ExprKind::Tuple(elements) => tuple(elements),
+            ExprKind::Variant(e, v, kind, fields) => 
+            {
+                let fields: Vec<(String, String)> = fields.iter().map(|(f, e)| (f.clone(), e.to_string())).collect();
+                format!("{}::{}{}", e, v, variant_fields(*kind, &fields, false))
+            },
+            ExprKind::Match(e, arms) => 
+            {
+                let arms: Vec<String> = arms.iter().map(|arm| format!("{} => {},\n", arm.pat, arm.body)).collect();
+                format!("match {} {{\n{}}}", e, arms.concat())
+            },
+            ExprKind::Array(elements) => 
+            {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                format!("[{}]", elements.join(", "))
+            },
+            ExprKind::Repeat(e, n) => format!("[{}; {}]", e, n),
+            ExprKind::Index(e, i) => format!("{}[{}]", e, i),
+            ExprKind::Method(e, m, args) => 
+            {
+                let args: Vec<String> = args.0.iter().map(|a| a.to_string()).collect();
+                format!("{}.{}({})", e, m, args.join(", "))
+            },
+            ExprKind::Cast(e, t) => format!("{} as {}", e, t),
+            ExprKind::Assoc(t, id, args) => 
+            {
+                let args: Vec<String> = args.0.iter().map(|a| a.to_string()).collect();
+                format!("{}::{}({})", t, id, args.join(", "))
+            },
+            ExprKind::Vec(elements) => 
+            {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                format!("vec![{}]", elements.join(", "))
+            },
+            ExprKind::VecRepeat(e, n) => format!("vec![{}; {}]", e, n),
+            ExprKind::Closure(c) => c.to_string(),
+            ExprKind::Return(e) => match e
+            {
+                Some(e) => format!("return {}", e),
+                None => "return".to_string(),
+            },
             //Expr::Not(c) => format!("!{}", c),
         };
         write!(f, "{}", s)
     }
 }
 
+impl fmt::Display for Closure 
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result 
+    {
+        let params: Vec<String> = self.parameters.0.iter().map(|p| format!("{}: {}", p.pat, p.ty)).collect();
+        let ret = match &self.ty
+        {
+            Some(t) => format!(" -> {}", t),
+            None => String::new(),
+        };
+        write!(f, "{}|{}|{} {}", if self.moves { "move " } else { "" }, params.join(", "), ret, self.body)
+    }
+}
+
+impl fmt::Display for Range 
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result 
+    {
+        let dots = if self.inclusive { "..=" } else { ".." };
+        match self.rev 
+        {
+            true => write!(f, "({}{}{}).rev()", self.start, dots, self.end),
+            false => write!(f, "{}{}{}", self.start, dots, self.end),
+        }
+    }
+}
+
+impl fmt::Display for Iter 
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result 
+    {
+        match self 
+        {
+            Iter::Range(range) => write!(f, "{}", range),
+            Iter::Chars(e) => write!(f, "{}.chars()", e),
+        }
+    }
+}
+
+// The fields of a variant, e.g., `(1, true)` or ` { x: 1, .. }`
+fn variant_fields(kind: VariantKind, fields: &[(String, String)], rest: bool) -> String
+{
+    let mut fields: Vec<String> = match kind
+    {
+        VariantKind::Struct => fields.iter().map(|(f, s)| format!("{}: {}", f, s)).collect(),
+        _ => fields.iter().map(|(_, s)| s.clone()).collect(),
+    };
+    if rest
+    {
+        fields.push("..".to_string());
+    }
+    match kind
+    {
+        VariantKind::Unit => String::new(),
+        VariantKind::Tuple => format!("({})", fields.join(", ")),
+        VariantKind::Struct => format!(" {{ {} }}", fields.join(", ")),
+    }
+}
+
+impl fmt::Display for Pattern 
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
+    {
+        match &self.kind
+        {
+            PatternKind::Wild => write!(f, "_"),
+            PatternKind::Lit(Literal::String(s)) => write!(f, "{:?}", s),
+            PatternKind::Lit(Literal::Float(x)) => write!(f, "{:?}", x),
+            PatternKind::Lit(Literal::Char(c)) => write!(f, "{:?}", c),
+            PatternKind::Lit(l) => write!(f, "{}", l),
+            PatternKind::Ident(m, id) => write!(f, "{}{}", m, id),
+            PatternKind::Variant(e, v, kind, fields, rest) => 
+            {
+                let fields: Vec<(String, String)> = fields.iter().map(|(f, p)| (f.clone(), p.to_string())).collect();
+                write!(f, "{}::{}{}", e, v, variant_fields(*kind, &fields, *rest))
+            },
+            PatternKind::Tuple(pats) => write!(f, "{}", tuple(pats)),
+        }
+    }
+}
+
+// `'label: ` in front of loops
+fn label_prefix(label: &Option<String>) -> String
+{
+    match label
+    {
+        Some(label) => format!("{}: ", label),
+        None => String::new(),
+    }
+}
+
 impl fmt::Display for Block 
 {
     #[allow(unused_variables)]
@@ -227,7 +1157,7 @@ impl fmt::Display for Parameter
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
     {
-        let _s = format!("{}:: {}", self.id, self.ty);
+        let _s = format!("{}:: {}", self.pat, self.ty);
         write!(f, "{}", _s)
     }
 }
@@ -264,14 +1194,19 @@ impl fmt::Display for FnDeclaration
     {
         //fn + {name} (args) "{" "}"
         let mut s: String = String::from("fn ");
-        s.push_str(&format!("{}(", self.id));
+        s.push_str(&self.id);
+        if !self.generics.is_empty()
+        {
+            s.push_str(&format!("<{}>", self.generics.join(", ")));
+        }
+        s.push('(');
         for(i, parameter) in self.parameters.0.iter().enumerate()
         {
             if i != 0
             {
                 s.push_str(", ");
             }
-            s.push_str(&format!("{}: {}{}", parameter.id, parameter.mutable, parameter.ty));
+            s.push_str(&format!("{}: {}", parameter.pat, parameter.ty));
         }
         s.push_str(") ");
         // 3 tests fail when I uncomment this and I don't know why
@@ -281,11 +1216,41 @@ impl fmt::Display for FnDeclaration
     }
 }
 
+impl fmt::Display for StructDeclaration 
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
+    {
+        let fields: Vec<String> = self.fields.iter().map(|field| format!("{}: {}", field.id, field.ty)).collect();
+        write!(f, "struct {} {{ {} }}", self.id, fields.join(", "))
+    }
+}
+
+impl fmt::Display for EnumDeclaration 
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
+    {
+        let variants: Vec<String> = self.variants.iter().map(|v| 
+        {
+            let fields: Vec<(String, String)> = v.fields.iter().map(|field| (field.id.clone(), field.ty.to_string())).collect();
+            format!("{}{}", v.id, variant_fields(v.kind, &fields, false))
+        }).collect();
+        write!(f, "enum {} {{ {} }}", self.id, variants.join(", "))
+    }
+}
+
 impl fmt::Display for Prog 
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
     {
         let mut s : String = String::new();
+        for st in &self.1
+        {
+            s.push_str(&format!("{}\n", st));
+        }
+        for en in &self.2
+        {
+            s.push_str(&format!("{}\n", en));
+        }
         for p in self.0.clone()
         {
             s.push_str(&format!("{}\n", p));
@@ -298,9 +1263,9 @@ impl fmt::Display for Statement
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
     {
-        let s = match self
+        let s = match &self.kind
         {
-            Statement::Let(_mut, ex1, _type, ex2) => 
+            StatementKind::Let(pat, _type, ex2) => 
             {
                 let t = match _type 
                 {
@@ -313,21 +1278,33 @@ impl fmt::Display for Statement
                     None => "".to_string(),
                 };
 
-                format!("let {}{}{};", ex1, t, re)
+                format!("let {}{}{};", pat, t, re)
             },
-            Statement::Expr(e) => 
+            StatementKind::Expr(e) => 
             {
                 e.to_string()
             },
-            Statement::Assign(expr1, expr2) => 
+            StatementKind::Assign(expr1, expr2) => 
             {
                 format!("{} = {};", expr1, expr2)
             },
-            Statement::While(expr, block) => 
+            StatementKind::While(label, expr, block) => 
+            {
+                format!("{}while {} {{{}}};", label_prefix(label), expr, block)
+            },
+            StatementKind::For(label, id, range, block) => 
             {
-                format!("while {} {{{}}};", expr, block)
+                format!("{}for {} in {} {{{}}};", label_prefix(label), id, range, block)
+            },
+            StatementKind::Fn(decl) => 
+            {
+                format!("{}", decl)
+            },
+            StatementKind::Struct(decl) => 
+            {
+                format!("{}", decl)
             },
-            Statement::Fn(decl) => 
+            StatementKind::Enum(decl) => 
             {
                 format!("{}", decl)
             },
@@ -376,13 +1353,13 @@ fn display_while() {
 
 #[test]
 fn display_expr() {
-    println!("{}", Expr::Ident("a".to_string()));
-    println!("{}", Expr::Lit(Literal::Int(7)));
-    println!("{}", Expr::Lit(Literal::Bool(false)));
-    let e = Expr::BinOp(
+    println!("{}", Expr::from(ExprKind::Ident("a".to_string())));
+    println!("{}", Expr::from(Literal::Int(7)));
+    println!("{}", Expr::from(Literal::Bool(false)));
+    let e = Expr::bin_op(
         Op::Add,
-        Box::new(Expr::Ident("a".to_string())),
-        Box::new(Expr::Lit(Literal::Int(7))),
+        ExprKind::Ident("a".to_string()).into(),
+        Literal::Int(7).into(),
     );
     println!("{}", e);
     assert_eq!(format!("{}", e), "a + 7");
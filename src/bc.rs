@@ -1,68 +1,909 @@
-use crate::ast::{Block, Expr, FnDeclaration, Prog};
+use crate::ast::{
+    Arguments, Arm, Block, Expr, ExprKind, FnDeclaration, Iter, Parameters, PatternKind, Prog,
+    Statement, StatementKind, Type, UnOp,
+};
 use crate::common::Eval;
-use crate::env::{Env, Ref};
-use crate::error::Error;
+use crate::env::{Env, Fields, Ref, VarEnv};
+use crate::error::*;
+use crate::type_check::move_spans;
 
+// A loan is identified by the slot holding the reference,
+// (or the temporary, in case the reference is not yet bound).
 #[derive(Debug, Clone, PartialEq)]
 pub enum Loan {
     Unique(Ref),
     Shared(Ref),
 }
 
-type Stack = Vec<Loan>;
+impl Loan {
+    fn tag(&self) -> Ref {
+        match self {
+            Loan::Unique(r) | Loan::Shared(r) => r.clone(),
+        }
+    }
+}
+
+// The borrow stack of a place, the owner being implicitly at the bottom.
+// Each loan keeps the span where it was created, and popped loans
+// remember the access that invalidated them, for diagnostics.
+type Stack = Vec<(Loan, Span)>;
 #[derive(Debug, Clone, PartialEq)]
-pub struct Loans(Stack);
+pub struct Loans {
+    stack: Stack,
+    popped: Vec<(Loan, Span, Span)>,
+}
 
 impl Loans {
-    //
+    pub fn new() -> Self {
+        Loans {
+            stack: Stack::new(),
+            popped: vec![],
+        }
+    }
+
+    // a new borrow is pushed on top of the stack
+    pub fn push(&mut self, loan: Loan, span: Span) {
+        self.stack.push((loan, span));
+    }
+
+    fn position(&self, tag: Ref) -> Option<usize> {
+        self.stack.iter().rposition(|(l, _)| l.tag() == tag)
+    }
+
+    fn pop_from(&mut self, i: usize, at: Span) {
+        let popped = self.stack.split_off(i);
+        self.popped
+            .extend(popped.into_iter().map(|(l, created)| (l, created, at)));
+    }
+
+    pub fn contains(&self, tag: Ref) -> bool {
+        self.position(tag).is_some()
+    }
+
+    pub fn is_shared(&self, tag: Ref) -> bool {
+        matches!(
+            self.position(tag).map(|i| &self.stack[i].0),
+            Some(Loan::Shared(_))
+        )
+    }
+
+    // The span of the loan `tag` (if still on the stack).
+    pub fn created(&self, tag: Ref) -> Option<Span> {
+        self.position(tag).map(|i| self.stack[i].1)
+    }
+
+    // Where the loan `tag` was created and where it was popped.
+    pub fn invalidated(&self, tag: Ref) -> Option<(Span, Span)> {
+        self.popped
+            .iter()
+            .rev()
+            .find(|(l, _, _)| l.tag() == tag)
+            .map(|(_, created, at)| (*created, *at))
+    }
+
+    // Read access at `at`, by the owner (None) or through the loan `tag`.
+    // Pops any unique loan above the accessing item (and everything above it).
+    // Returns false if `tag` is no longer on the stack.
+    pub fn read(&mut self, tag: Option<Ref>, at: Span) -> bool {
+        let base = match tag {
+            None => 0,
+            Some(tag) => match self.position(tag) {
+                Some(i) => i + 1,
+                None => return false,
+            },
+        };
+        if let Some(i) = self.stack[base..]
+            .iter()
+            .position(|(l, _)| matches!(l, Loan::Unique(_)))
+        {
+            self.pop_from(base + i, at);
+        }
+        true
+    }
+
+    // Write access at `at`, by the owner (None) or through the loan `tag`.
+    // Pops everything above the accessing item.
+    // Returns false if `tag` is no longer on the stack.
+    pub fn write(&mut self, tag: Option<Ref>, at: Span) -> bool {
+        match tag {
+            None => {
+                self.pop_from(0, at);
+                true
+            }
+            Some(tag) => match self.position(tag) {
+                Some(i) => {
+                    self.pop_from(i + 1, at);
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    // Hands the loan over to a new holder (e.g., when a unique reference is moved).
+    // Returns false if `old` is not on the stack.
+    pub fn retag(&mut self, old: Ref, new: Ref) -> bool {
+        match self.position(old) {
+            Some(i) => {
+                self.stack[i].0 = match self.stack[i].0 {
+                    Loan::Unique(_) => Loan::Unique(new),
+                    Loan::Shared(_) => Loan::Shared(new),
+                };
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Drops the loans held by references in scope `scope_index` or deeper.
+    pub fn release(&mut self, scope_index: usize) {
+        self.stack
+            .retain(|(l, _)| l.tag().scope_index() < scope_index);
+        self.popped
+            .retain(|(l, _, _)| l.tag().scope_index() < scope_index);
+    }
+
+    // The loans that are valid on both sides of a control flow merge.
+    pub fn meet(&self, other: &Loans) -> Loans {
+        let mut popped = self.popped.clone();
+        popped.extend(
+            other
+                .popped
+                .iter()
+                .filter(|p| !self.popped.contains(p))
+                .cloned(),
+        );
+        Loans {
+            stack: self
+                .stack
+                .iter()
+                .filter(|l| other.stack.contains(l))
+                .cloned()
+                .collect(),
+            popped,
+        }
+    }
 }
 
+impl Default for Loans {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Fields are not tracked separately, a struct is borrowed as a whole
+impl Fields for Bc {}
+
 // Borrow check
 #[derive(Debug, Clone, PartialEq)]
 pub enum Bc {
+    // a value, with the loans taken against it
     Lit(Loans),
-    Ref(bool, Ref),
+    // a reference (unique if true) to a place, with the loans taken against it
+    Ref(bool, Ref, Loans),
+    // a closure, sharing the places it captures by reference (and those
+    // referenced by the references it captures by value)
+    Closure(Vec<Ref>, Loans),
+}
+
+impl Bc {
+    fn loans(&self) -> &Loans {
+        match self {
+            Bc::Lit(loans) | Bc::Ref(_, _, loans) | Bc::Closure(_, loans) => loans,
+        }
+    }
+
+    fn loans_mut(&mut self) -> &mut Loans {
+        match self {
+            Bc::Lit(loans) | Bc::Ref(_, _, loans) | Bc::Closure(_, loans) => loans,
+        }
+    }
+
+    // a copy of the value, not borrowed by anyone
+    fn fresh(&self) -> Bc {
+        match self {
+            Bc::Lit(_) => Bc::Lit(Loans::new()),
+            Bc::Ref(unique, r, _) => Bc::Ref(*unique, r.clone(), Loans::new()),
+            Bc::Closure(targets, _) => Bc::Closure(targets.clone(), Loans::new()),
+        }
+    }
+}
+
+// A place expression resolved to its slot,
+// and the loan (if any) through which it is accessed.
+struct Place {
+    target: Ref,
+    tag: Option<Ref>,
+}
+
+fn place(e: &Expr, env: &mut Env<Bc>) -> Result<Place, Error> {
+    match &e.kind {
+        ExprKind::Ident(id) => match env.v.get_ref(id) {
+            Some(target) => Ok(Place { target, tag: None }),
+            None => Err(
+                Error::type_err(UNRESOLVED_NAME, format!("variable `{}` not found", id))
+                    .with_span(e.span),
+            ),
+        },
+        ExprKind::Par(e) | ExprKind::UnOp(UnOp::Mut, e) => place(e, env),
+        // a field (or element) is a part of the struct (or array), borrowed as a whole
+        ExprKind::Field(inner, _) | ExprKind::Index(inner, _) => {
+            if let ExprKind::Index(_, i) = &e.kind {
+                i.eval(env)?;
+            }
+            let p = place(inner, env)?;
+            match env.v.de_ref(p.target.clone()) {
+                // auto-deref
+                Bc::Ref(_, target, _) => {
+                    read(env, &p, inner)?;
+                    Ok(Place {
+                        target,
+                        tag: Some(p.target),
+                    })
+                }
+                _ => Ok(p),
+            }
+        }
+        ExprKind::UnOp(UnOp::DeRef, inner) => match inner.eval(env)? {
+            (Bc::Ref(_, target, _), tag) => Ok(Place { target, tag }),
+            // references stored in fields (or bound from them by
+            // patterns) are not tracked
+            (Bc::Lit(_), _) | (Bc::Closure(..), _) => {
+                let target = env.v.stack_val(Bc::Lit(Loans::new()));
+                Ok(Place { target, tag: None })
+            }
+        },
+        // not a place, the value is put in a temporary
+        _ => {
+            let (v, _) = e.eval(env)?;
+            let target = env.v.stack_val(v);
+            Ok(Place { target, tag: None })
+        }
+    }
+}
+
+// The error for a use of `e` through the invalidated loan `tag`,
+// pointing at where the loan was created and popped.
+fn invalidated(v: &Bc, tag: Option<Ref>, e: &Expr, message: String) -> Error {
+    let err = Error::borrow(INVALIDATED_BORROW, message).with_span(e.span);
+    match tag.and_then(|tag| v.loans().invalidated(tag)) {
+        Some((created, at)) => err
+            .with_label(created, "borrow created here")
+            .with_label(at, "invalidated by this access"),
+        None => err,
+    }
+}
+
+fn read(env: &mut Env<Bc>, p: &Place, e: &Expr) -> Result<Bc, Error> {
+    let mut v = env.v.de_ref(p.target.clone());
+    if !v.loans_mut().read(p.tag.clone(), e.span) {
+        return Err(invalidated(
+            &v,
+            p.tag.clone(),
+            e,
+            format!(
+                "cannot use `{}`, its borrow has been invalidated by a conflicting access",
+                e
+            ),
+        ));
+    }
+    let fresh = v.fresh();
+    env.v.set_ref(p.target.clone(), v);
+    Ok(fresh)
+}
+
+fn write(env: &mut Env<Bc>, p: &Place, e: &Expr) -> Result<(), Error> {
+    let mut v = env.v.de_ref(p.target.clone());
+    if let Some(tag) = &p.tag {
+        if v.loans().is_shared(tag.clone()) {
+            let err = Error::borrow(
+                SHARED_MUTATION,
+                format!(
+                    "cannot assign to `{}`, which is behind a shared reference",
+                    e
+                ),
+            )
+            .with_span(e.span);
+            return Err(match v.loans().created(tag.clone()) {
+                Some(span) => err.with_label(span, "shared borrow created here"),
+                None => err,
+            });
+        }
+    }
+    if !v.loans_mut().write(p.tag.clone(), e.span) {
+        return Err(invalidated(
+            &v,
+            p.tag.clone(),
+            e,
+            format!(
+                "cannot assign to `{}`, its borrow has been invalidated by a conflicting access",
+                e
+            ),
+        ));
+    }
+    env.v.set_ref(p.target.clone(), v);
+    Ok(())
+}
+
+// Registers the loan held by the reference `v` now stored in `dest`.
+// Unique references are moved from `source`, shared references are copied
+// (the copy is attributed to the original borrow, if known, or else to `span`).
+// A closure holds a shared loan for each place it captures.
+fn bind(env: &mut Env<Bc>, v: &Bc, source: Option<Ref>, dest: Ref, span: Span) {
+    match v {
+        Bc::Ref(unique, target, _) => bind_loan(env, *unique, target, source, dest, span),
+        Bc::Closure(targets, _) => {
+            for target in targets {
+                bind_loan(env, false, target, source.clone(), dest.clone(), span);
+            }
+        }
+        Bc::Lit(_) => {}
+    }
+}
+
+fn bind_loan(
+    env: &mut Env<Bc>,
+    unique: bool,
+    target: &Ref,
+    source: Option<Ref>,
+    dest: Ref,
+    span: Span,
+) {
+    let mut t = env.v.de_ref(target.clone());
+    let moved = match &source {
+        Some(source) if unique => t.loans_mut().retag(source.clone(), dest.clone()),
+        _ => false,
+    };
+    if !moved {
+        let span = source
+            .and_then(|source| t.loans().created(source))
+            .unwrap_or(span);
+        t.loans_mut().push(
+            match unique {
+                true => Loan::Unique(dest),
+                false => Loan::Shared(dest),
+            },
+            span,
+        );
+    }
+    env.v.set_ref(target.clone(), t);
+}
+
+// Only loans valid on both paths survive a control flow merge.
+fn join(env: &mut VarEnv<Bc>, other: &VarEnv<Bc>) {
+    for (v, o) in env.values_mut().zip(other.values()) {
+        let loans = v.loans().meet(o.loans());
+        *v.loans_mut() = loans;
+    }
 }
 
 impl Eval<Bc> for Expr {
     fn eval(&self, env: &mut Env<Bc>) -> Result<(Bc, Option<Ref>), Error> {
-        todo!("not implemented {:?}", self)
+        match &self.kind {
+            // a function item holds no loans
+            ExprKind::Ident(id) if env.v.get_ref(id).is_none() && env.f.0.contains_key(id) => {
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            // a move out of a variable (or a field of it) invalidates
+            // the loans on it, as a write does
+            ExprKind::Ident(_) | ExprKind::Field(..) if env.moves.spans.contains(&self.span) => {
+                let p = place(self, env)?;
+                let v = env.v.de_ref(p.target.clone()).fresh();
+                write(env, &p, self)?;
+                Ok((v, Some(p.target)))
+            }
+            ExprKind::Ident(_) | ExprKind::UnOp(UnOp::DeRef, _) => {
+                let p = place(self, env)?;
+                let v = read(env, &p, self)?;
+                Ok((v, Some(p.target)))
+            }
+            ExprKind::Field(..) | ExprKind::Index(..) => {
+                let p = place(self, env)?;
+                read(env, &p, self)?;
+                Ok((Bc::Lit(Loans::new()), Some(p.target)))
+            }
+            ExprKind::Struct(_, fields) | ExprKind::Variant(_, _, _, fields) => {
+                for (_, e) in fields {
+                    e.eval(env)?;
+                }
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            ExprKind::Tuple(es) | ExprKind::Array(es) => {
+                for e in es {
+                    e.eval(env)?;
+                }
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            ExprKind::Repeat(e, _) | ExprKind::Cast(e, _) => {
+                e.eval(env)?;
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            // the receiver is read (through a reference), and written by
+            // `push`, `pop` and `push_str` once the arguments are evaluated
+            ExprKind::Method(e, m, args) => {
+                let p = place(e, env)?;
+                let p = match read(env, &p, e)? {
+                    Bc::Ref(_, target, _) => {
+                        let tag = Some(p.target);
+                        let p = Place { target, tag };
+                        read(env, &p, e)?;
+                        p
+                    }
+                    _ => p,
+                };
+                for arg in &args.0 {
+                    arg.eval(env)?;
+                }
+                if m == "push" || m == "pop" || m == "push_str" {
+                    write(env, &p, e)?;
+                }
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            ExprKind::Assoc(_, _, Arguments(es)) | ExprKind::Vec(es) => {
+                for e in es {
+                    e.eval(env)?;
+                }
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            ExprKind::VecRepeat(e, n) => {
+                e.eval(env)?;
+                n.eval(env)?;
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            ExprKind::Lit(_) => Ok((Bc::Lit(Loans::new()), None)),
+            ExprKind::BinOp(_, l, r) => {
+                l.eval(env)?;
+                r.eval(env)?;
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            ExprKind::Par(e) | ExprKind::UnOp(UnOp::Mut, e) => e.eval(env),
+            ExprKind::UnOp(UnOp::Bang, e) | ExprKind::UnOp(UnOp::Neg, e) => {
+                e.eval(env)?;
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            ExprKind::UnOp(UnOp::Ref, e) => {
+                let (unique, e) = match &e.kind {
+                    ExprKind::UnOp(UnOp::Mut, e) => (true, e),
+                    _ => (false, e),
+                };
+                let p = place(e, env)?;
+                let mut t = env.v.de_ref(p.target.clone());
+                if let Some(tag) = &p.tag {
+                    if unique && t.loans().is_shared(tag.clone()) {
+                        return Err(Error::borrow(
+                            SHARED_MUTATION,
+                            format!(
+                                "cannot borrow `{}` as mutable, as it is behind a shared reference",
+                                e
+                            ),
+                        )
+                        .with_span(e.span));
+                    }
+                }
+                // borrowing is an access, `&mut` a write and `&` a read, through
+                // the reference we go through (if any), which must be valid
+                let valid = match unique {
+                    true => t.loans_mut().write(p.tag.clone(), self.span),
+                    false => t.loans_mut().read(p.tag.clone(), self.span),
+                };
+                if !valid {
+                    return Err(invalidated(
+                        &t,
+                        p.tag.clone(),
+                        e,
+                        format!("cannot borrow `{}`, its borrow has been invalidated by a conflicting access", e),
+                    ));
+                }
+                // the reference is held by a temporary until it is bound
+                let r = Bc::Ref(unique, p.target.clone(), Loans::new());
+                let tmp = env.v.stack_val(r.clone());
+                t.loans_mut().push(
+                    match unique {
+                        true => Loan::Unique(tmp.clone()),
+                        false => Loan::Shared(tmp.clone()),
+                    },
+                    self.span,
+                );
+                env.v.set_ref(p.target, t);
+                Ok((r, Some(tmp)))
+            }
+            ExprKind::Call(id, _, args) => {
+                // calling a closure uses the places it captures
+                if let Some(Bc::Closure(targets, _)) = env.v.get(id) {
+                    let callee = Expr::new(ExprKind::Ident(id.clone()), self.span);
+                    let p = place(&callee, env)?;
+                    read(env, &p, &callee)?;
+                    for target in targets {
+                        let tag = Some(p.target.clone());
+                        read(env, &Place { target, tag }, &callee)?;
+                    }
+                }
+                let mut refs = vec![];
+                for arg in &args.0 {
+                    match arg.eval(env)? {
+                        (Bc::Ref(unique, target, _), tag) => {
+                            refs.push((unique, Place { target, tag }, arg))
+                        }
+                        (Bc::Closure(targets, _), tag) => {
+                            for target in targets {
+                                let tag = tag.clone();
+                                refs.push((false, Place { target, tag }, arg));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                // the references are used by the callee, in argument order
+                for (unique, p, arg) in refs {
+                    match unique {
+                        true => write(env, &p, arg)?,
+                        false => {
+                            read(env, &p, arg)?;
+                        }
+                    }
+                }
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            // the closure holds a shared loan on each captured place (in a
+            // temporary until it is bound), taken when it is created
+            ExprKind::Closure(c) => {
+                let mut targets = vec![];
+                for (id, _) in c.free_vars() {
+                    if env.v.get_ref(&id).is_none() {
+                        continue;
+                    }
+                    let e = Expr::new(ExprKind::Ident(id), self.span);
+                    let p = place(&e, env)?;
+                    match (c.moves, read(env, &p, &e)?) {
+                        (false, _) => targets.push(p.target),
+                        (true, Bc::Ref(_, target, _)) => targets.push(target),
+                        (true, Bc::Closure(captured, _)) => targets.extend(captured),
+                        (true, Bc::Lit(_)) => {}
+                    }
+                }
+                let mut fn_env = fn_env(env, &c.parameters);
+                for (id, _) in c.free_vars() {
+                    if env.v.get_ref(&id).is_some() {
+                        fn_env.v.alloc(&id, Bc::Lit(Loans::new()));
+                    }
+                }
+                c.body.eval(&mut fn_env)?;
+
+                let r = Bc::Closure(targets.clone(), Loans::new());
+                let tmp = env.v.stack_val(r.clone());
+                for target in targets {
+                    let mut t = env.v.de_ref(target.clone());
+                    t.loans_mut().push(Loan::Shared(tmp.clone()), self.span);
+                    env.v.set_ref(target, t);
+                }
+                Ok((r, Some(tmp)))
+            }
+            ExprKind::IfThenElse(c, t, e) => {
+                c.eval(env)?;
+                let mut other = env.clone();
+                let (v, _) = t.eval(env)?;
+                if let Some(e) = e {
+                    e.eval(&mut other)?;
+                }
+                join(&mut env.v, &other.v);
+                Ok((v, None))
+            }
+            ExprKind::Block(b) => b.eval(env),
+            ExprKind::Loop(_, b) => {
+                let before = env.clone();
+                b.eval(env)?;
+                // a second iteration catches loans invalidated by the previous one
+                b.eval(env)?;
+                join(&mut env.v, &before.v);
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            ExprKind::Break(_, e) | ExprKind::Return(e) => {
+                if let Some(e) = e {
+                    e.eval(env)?;
+                }
+                Ok((Bc::Lit(Loans::new()), None))
+            }
+            ExprKind::Continue(_) => Ok((Bc::Lit(Loans::new()), None)),
+            ExprKind::Match(e, arms) => {
+                let (v, source) = e.eval(env)?;
+                // as for `if`, each arm starts from the same state
+                let before = env.clone();
+                let mut result = Bc::Lit(Loans::new());
+                for (i, arm) in arms.iter().enumerate() {
+                    match i {
+                        0 => result = match_arm(env, arm, &v, source.clone())?,
+                        _ => {
+                            let mut other = before.clone();
+                            match_arm(&mut other, arm, &v, source.clone())?;
+                            join(&mut env.v, &other.v);
+                        }
+                    }
+                }
+                Ok((result, None))
+            }
+        }
+    }
+}
+
+// The body of an arm, with the pattern variables in a scope of their own.
+// A variable binding the whole value holds on to its loan (as in `let`).
+fn match_arm(env: &mut Env<Bc>, arm: &Arm, v: &Bc, source: Option<Ref>) -> Result<Bc, Error> {
+    env.v.push_scope();
+    match &arm.pat.kind {
+        PatternKind::Ident(_, id) => {
+            let dest = env.v.alloc(id, v.fresh());
+            bind(env, v, source, dest, arm.pat.span);
+        }
+        _ => {
+            for id in arm.pat.bindings() {
+                env.v.alloc(id, Bc::Lit(Loans::new()));
+            }
+        }
+    }
+    let (mut result, _) = arm.body.eval(env)?;
+    pop_scope(env, &mut result)?;
+    Ok(result.fresh())
+}
+
+impl Eval<Bc> for Statement {
+    fn eval(&self, env: &mut Env<Bc>) -> Result<(Bc, Option<Ref>), Error> {
+        match &self.kind {
+            StatementKind::Let(p, _, e) => {
+                let (v, source) = match e {
+                    Some(e) => e.eval(env)?,
+                    None => (Bc::Lit(Loans::new()), None),
+                };
+                match &p.kind {
+                    PatternKind::Ident(_, id) => {
+                        let dest = env.v.alloc(id, v.fresh());
+                        bind(env, &v, source, dest, self.span);
+                    }
+                    // the parts of a value are not tracked (as for fields)
+                    _ => {
+                        for id in p.bindings() {
+                            env.v.alloc(id, Bc::Lit(Loans::new()));
+                        }
+                    }
+                }
+            }
+            StatementKind::Assign(l, r) if matches!(l.kind, ExprKind::Field(..) | ExprKind::Index(..)) => {
+                r.eval(env)?;
+                let p = place(l, env)?;
+                write(env, &p, l)?;
+            }
+            StatementKind::Assign(l, r) => {
+                let (v, source) = r.eval(env)?;
+                let p = place(l, env)?;
+                write(env, &p, l)?;
+                // the place keeps the loans taken against it
+                let loans = env.v.de_ref(p.target.clone()).loans().clone();
+                let mut new = v.fresh();
+                *new.loans_mut() = loans;
+                env.v.set_ref(p.target.clone(), new);
+                bind(env, &v, source, p.target, r.span);
+            }
+            StatementKind::While(_, c, b) => {
+                c.eval(env)?;
+                let before = env.clone();
+                b.eval(env)?;
+                c.eval(env)?;
+                // a second iteration catches loans invalidated by the previous one
+                b.eval(env)?;
+                c.eval(env)?;
+                join(&mut env.v, &before.v);
+            }
+            StatementKind::For(_, id, iter, b) => {
+                match iter {
+                    Iter::Range(range) => {
+                        range.start.eval(env)?;
+                        range.end.eval(env)?;
+                    }
+                    Iter::Chars(s) => {
+                        s.eval(env)?;
+                    }
+                }
+                // the loop variable is scoped to the body
+                env.v.push_scope();
+                env.v.alloc(id, Bc::Lit(Loans::new()));
+                let before = env.clone();
+                b.eval(env)?;
+                // a second iteration catches loans invalidated by the previous one
+                b.eval(env)?;
+                join(&mut env.v, &before.v);
+                pop_scope(env, &mut Bc::Lit(Loans::new()))?;
+            }
+            StatementKind::Expr(e) => return e.eval(env),
+            StatementKind::Fn(decl) => {
+                decl.eval(env)?;
+            }
+            StatementKind::Struct(_) | StatementKind::Enum(_) => {}
+        }
+        Ok((Bc::Lit(Loans::new()), None))
     }
 }
 
 impl Eval<Bc> for Block {
     fn eval(&self, env: &mut Env<Bc>) -> Result<(Bc, Option<Ref>), Error> {
-        todo!("not implemented {:?}", self)
+        // a block checked on its own, outside of any function
+        if env.v.depth() == 0 {
+            env.moves.spans = move_spans(self);
+        }
+        env.v.push_scope();
+        // the functions declared in the block may be used as values in it
+        let fns = env.f.clone();
+        for stmt in &self.statements {
+            if let StatementKind::Fn(decl) = &stmt.kind {
+                env.f.0.insert(decl.id.clone(), (decl.clone(), None));
+            }
+        }
+        let mut last = Bc::Lit(Loans::new());
+        for stmt in &self.statements {
+            last = stmt.eval(env)?.0;
+        }
+        env.f = fns;
+        pop_scope(env, &mut last)?;
+        match self.semi {
+            true => Ok((Bc::Lit(Loans::new()), None)),
+            false => Ok((last.fresh(), None)),
+        }
     }
 }
 
+// Pops the innermost scope, references local to it no longer hold any loans.
+// A reference (or closure) to a variable of the scope outliving it, as the
+// value `last` of the block or held by a variable of an outer scope, is an
+// error. References to temporaries of the scope are no longer tracked.
+fn pop_scope(env: &mut Env<Bc>, last: &mut Bc) -> Result<(), Error> {
+    let scope_index = env.v.depth() - 1;
+    // the variables of the scope, and where they were last borrowed
+    let locals: Vec<(String, Ref, Option<Span>)> = env
+        .v
+        .bindings()
+        .into_iter()
+        .filter(|(_, r)| r.scope_index() >= scope_index)
+        .map(|(id, r)| {
+            let borrowed = env
+                .v
+                .de_ref(r.clone())
+                .loans()
+                .stack
+                .last()
+                .map(|(_, span)| *span);
+            (id, r, borrowed)
+        })
+        .collect();
+    env.v.pop_scope();
+    for v in env.v.values_mut().chain(std::iter::once(&mut *last)) {
+        v.loans_mut().release(scope_index);
+        let targets = match v {
+            Bc::Ref(_, target, _) => vec![target.clone()],
+            Bc::Closure(targets, _) => targets.clone(),
+            Bc::Lit(_) => vec![],
+        };
+        for target in targets.iter().filter(|t| t.scope_index() >= scope_index) {
+            if let Some((id, _, borrowed)) = locals.iter().find(|(_, r, _)| r == target) {
+                let err = Error::borrow(
+                    DOES_NOT_LIVE_LONG_ENOUGH,
+                    format!("`{}` does not live long enough", id),
+                )
+                .with_note(format!(
+                    "`{}` is dropped at the end of its block while still borrowed",
+                    id
+                ));
+                return Err(match borrowed {
+                    Some(span) => err.with_span(*span),
+                    None => err,
+                });
+            }
+        }
+        match v {
+            Bc::Ref(_, target, loans) if target.scope_index() >= scope_index => {
+                *v = Bc::Lit(loans.clone());
+            }
+            Bc::Closure(targets, _) => targets.retain(|t| t.scope_index() < scope_index),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 impl Eval<Bc> for FnDeclaration {
     fn eval(&self, env: &mut Env<Bc>) -> Result<(Bc, Option<Ref>), Error> {
-        todo!("not implemented {:?}", self)
+        // functions do not capture, so the body is checked in a fresh environment
+        let mut fn_env = fn_env(env, &self.parameters);
+        self.body.eval(&mut fn_env)?;
+        Ok((Bc::Lit(Loans::new()), None))
     }
 }
 
+// A fresh environment for a function (or closure) body, with the parameters bound.
+fn fn_env(env: &Env<Bc>, parameters: &Parameters) -> Env<Bc> {
+    let mut fn_env: Env<Bc> = Env::new();
+    fn_env.f = env.f.clone();
+    fn_env.moves.spans = env.moves.spans.clone();
+    fn_env.v.push_scope();
+    for p in &parameters.0 {
+        match (&p.pat.kind, &p.ty) {
+            (PatternKind::Ident(_, id), Type::Ref(_)) => {
+                // the referenced value lives in the caller, borrowed by the parameter
+                let owner = fn_env.v.stack_val(Bc::Lit(Loans::new()));
+                let r = Bc::Ref(true, owner, Loans::new());
+                let dest = fn_env.v.alloc(id, r.clone());
+                bind(&mut fn_env, &r, None, dest, p.span);
+            }
+            _ => {
+                for id in p.pat.bindings() {
+                    fn_env.v.alloc(id, Bc::Lit(Loans::new()));
+                }
+            }
+        }
+    }
+    fn_env
+}
+
 impl Eval<Bc> for Prog {
     fn eval(&self, env: &mut Env<Bc>) -> Result<(Bc, Option<Ref>), Error> {
-        todo!("not implemented {:?}", self)
+        env.f.add_functions_unique(self.0.clone())?;
+        env.moves.spans = move_spans(self);
+        for f in &self.0 {
+            f.eval(env)?;
+        }
+        Ok((Bc::Lit(Loans::new()), None))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Bc;
+    use super::{Bc, Loan, Loans};
     use crate::ast::{Block, Prog};
     use crate::common::parse_test;
+    use crate::env::Ref;
+    use crate::error::{Span, DOES_NOT_LIVE_LONG_ENOUGH, INVALIDATED_BORROW};
 
     // Tests for the Bc specific handling
     // of Loans etc.
     #[test]
-    fn loan() {}
+    fn loan() {
+        let (b, c, d) = (Ref::new(0, 1), Ref::new(0, 2), Ref::new(0, 3));
+        let mut loans = Loans::new();
+        let span = Span::default();
+        loans.push(Loan::Unique(b.clone()), span);
+        loans.push(Loan::Shared(c.clone()), span);
+        loans.push(Loan::Shared(d.clone()), span);
+
+        // reading through c keeps the shared loans
+        assert!(loans.read(Some(c.clone()), span));
+        assert!(loans.contains(d.clone()));
+
+        // writing through b pops c and d
+        assert!(loans.write(Some(b.clone()), span));
+        assert!(!loans.contains(c.clone()));
+        assert!(!loans.read(Some(d.clone()), span));
+        assert!(loans.invalidated(d).is_some());
+
+        // reading by the owner pops the unique loan
+        assert!(loans.read(None, span));
+        assert!(!loans.contains(b));
+    }
+
+    #[test]
+    fn loan_meet() {
+        let (b, c) = (Ref::new(0, 1), Ref::new(0, 2));
+        let mut l1 = Loans::new();
+        l1.push(Loan::Shared(b.clone()), Span::default());
+        l1.push(Loan::Shared(c.clone()), Span::default());
+        let mut l2 = l1.clone();
+        l2.write(Some(b.clone()), Span::default());
+
+        let l = l1.meet(&l2);
+        assert!(l.contains(b.clone()));
+        assert!(!l.contains(c));
+    }
 
     // Tests for borrow checking of Block
     #[test]
     fn test_block_let() {
-        let _v = parse_test::<Block, Bc>(
+        let v = parse_test::<Block, Bc>(
             "
         {
             let a: i32 = 1;
@@ -71,15 +912,210 @@ mod tests {
             a + b
         }",
         );
-        // Suitable assertion
+        assert_eq!(v.unwrap(), Bc::Lit(Loans::new()));
+    }
+
+    #[test]
+    fn test_block_shared() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let a = 1;
+            let b = &a;
+            let c = &a;
+            let d = *b;
+            *c
+        }",
+        );
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_block_unique_write() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let b = &mut a;
+            *b = 2;
+            *b = 3;
+            a
+        }",
+        );
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_block_owner_write_err() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let b = &a;
+            a = 2;
+            *b
+        }",
+        );
+        let err = v.unwrap_err();
+        let labels: Vec<_> = err.labels.iter().map(|(_, l)| l.as_str()).collect();
+        assert_eq!(
+            labels,
+            ["borrow created here", "invalidated by this access"]
+        );
     }
 
-    // Come up with your own set of tests for Block
+    #[test]
+    fn test_block_two_unique_err() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let b = &mut a;
+            let c = &mut a;
+            *b = 2;
+            *c = 3;
+        }",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_block_borrow_pops_err() {
+        // taking `&mut a` is a write, and taking `&a` a read, of `a`
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let c = &a;
+            let b = &mut a;
+            *b = 4;
+            let d = *c;
+        }",
+        );
+        assert_eq!(v.unwrap_err().code, INVALIDATED_BORROW);
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let b = &mut a;
+            let c = &mut a;
+            *c = 1;
+            *b = 4;
+        }",
+        );
+        assert_eq!(v.unwrap_err().code, INVALIDATED_BORROW);
+    }
+
+    #[test]
+    fn test_block_move_borrowed_err() {
+        // moving a `String` out of `a` invalidates the loans on it
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let a = String::from(\"x\");
+            let r = &a;
+            let b = a;
+            *r
+        }",
+        );
+        assert_eq!(v.unwrap_err().code, INVALIDATED_BORROW);
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let a = String::from(\"x\");
+            let r = &a;
+            let n = r.len();
+            let b = a;
+        }",
+        );
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_block_shared_write_err() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let b = &a;
+            *b = 2;
+        }",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_block_moved_unique_err() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let b = &mut a;
+            let c = b;
+            *c = 2;
+            *b = 3;
+        }",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_block_if_then_else() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let c = &a;
+            if true {
+                let b = &mut a;
+                *b = 2;
+            } else {
+                let d = *c;
+            };
+        }",
+        );
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_block_if_then_else_err() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let c = &a;
+            if true {
+                let b = &mut a;
+                *b = 2;
+            } else {
+            };
+            *c
+        }",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_block_while_err() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let c = &a;
+            while true {
+                let d = *c;
+                let b = &mut a;
+                *b = 2;
+            }
+        }",
+        );
+        assert!(v.is_err());
+    }
 
     // Tests for borrow checking of Prog
     #[test]
     fn test_prog_fn_sig() {
-        let _v = parse_test::<Prog, Bc>(
+        let v = parse_test::<Prog, Bc>(
             "
         fn main() {
             let mut a = 0;
@@ -90,8 +1126,215 @@ mod tests {
         }
             ",
         );
-        // suitable assertion
+        assert_eq!(v.unwrap_err().code, INVALIDATED_BORROW);
+    }
+
+    #[test]
+    fn test_prog_call_aliasing_err() {
+        let v = parse_test::<Prog, Bc>(
+            "
+        fn f(a: &i32, b: &i32) {}
+
+        fn main() {
+            let mut a = 0;
+            f(&a, &mut a);
+        }
+            ",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_prog_ref_param() {
+        let v = parse_test::<Prog, Bc>(
+            "
+        fn inc(a: &i32) {
+            *a = (*a) + 1;
+        }
+
+        fn main() {
+            let mut a = 0;
+            let b = &mut a;
+            inc(b);
+            inc(&mut a);
+        }
+            ",
+        );
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_block_field_ref() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            struct P { x: i32, y: i32 }
+            let mut p = P { x: 1, y: 2 };
+            let r = &mut p.x;
+            *r = 3;
+            p.y = 4;
+            p.x
+        }",
+        );
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_block_field_ref_err() {
+        // a struct is borrowed as a whole
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            struct P { x: i32, y: i32 }
+            let mut p = P { x: 1, y: 2 };
+            let r = &p.x;
+            p.y = 4;
+            *r
+        }",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_block_match_ref() {
+        // a pattern variable binding the whole value holds its loan
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut a = 1;
+            let b = match &mut a {
+                r => {
+                    *r = 2;
+                    r
+                }
+            };
+            a = 3;
+            *b
+        }",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_block_vec_push_err() {
+        // a `push` may reallocate, invalidating references into the vector
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut v = vec![1];
+            let r = &v[0];
+            v.push(2);
+            *r
+        }",
+        );
+        assert!(v.is_err());
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut v = vec![1];
+            let r = &v[0];
+            v.push(*r);
+            v.len()
+        }",
+        );
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_block_closure_err() {
+        // a closure holds a shared loan on the variables it captures by reference
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut y = 1;
+            let f = |x: i32| x + y;
+            y = 2;
+            f(1)
+        }",
+        );
+        assert!(v.is_err());
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let mut y = 1;
+            let f = move |x: i32| x + y;
+            y = 2;
+            f(1)
+        }",
+        );
+        assert!(v.is_ok());
+        let v = parse_test::<Prog, Bc>(
+            "
+        fn apply(f: impl Fn(i32) -> i32) -> i32 { f(1) }
+        fn main() {
+            let mut y = 1;
+            let f = |x: i32| x + y;
+            y = 2;
+            apply(f);
+        }",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_block_fn_pointer() {
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            fn double(x: i32) -> i32 { x * 2 }
+            let mut y = 1;
+            let f = double;
+            y = f(y);
+        }",
+        );
+        assert!(v.is_ok());
     }
 
-    // Come up with your own set of test for Prog
+    #[test]
+    fn test_block_dangling_err() {
+        // a reference outliving the block of the variable it borrows
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let r = { let y = 5; &y };
+            let z = 100;
+            *r
+        }",
+        );
+        let err = v.unwrap_err();
+        assert_eq!(err.code, DOES_NOT_LIVE_LONG_ENOUGH);
+        assert_eq!(err.message, "`y` does not live long enough");
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let a = 1;
+            let mut r = &a;
+            {
+                let y = 5;
+                r = &y;
+            }
+            *r
+        }",
+        );
+        assert_eq!(v.unwrap_err().code, DOES_NOT_LIVE_LONG_ENOUGH);
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let f = { let y = 5; |x: i32| x + y };
+            f(1)
+        }",
+        );
+        assert_eq!(v.unwrap_err().code, DOES_NOT_LIVE_LONG_ENOUGH);
+        // references to outer variables, and values moved out, may escape
+        let v = parse_test::<Block, Bc>(
+            "
+        {
+            let a = 1;
+            let r = { let b = &a; b };
+            let f = { let y = 5; move |x: i32| x + y };
+            f(*r)
+        }",
+        );
+        assert!(v.is_ok());
+    }
 }
@@ -0,0 +1,1227 @@
+// Lowering of a Prog to bytecode for the stack machine (stack_vm.rs)
+//
+// Each function is compiled to a sequence of instructions operating on an
+// operand stack, with its locals (parameters first) addressed by slot.
+// Every statement and expression leaves exactly one value on the stack.
+// Struct (and enum variant) fields are accessed by name, as the field index
+// depends on the (unknown) type of the struct.
+
+use crate::ast::*;
+use crate::env::arity_message;
+use crate::error::*;
+use crate::intrinsics::{vm_println, Intrinsic};
+use crate::type_check::monomorphize;
+use crate::vm::Val;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    // push a constant
+    Push(Val),
+    // drop the top of stack
+    Pop,
+    // load/store of a local slot in the current frame
+    Load(usize),
+    Store(usize),
+    // push a reference to a local slot in the current frame
+    Ref(usize),
+    // a local slot going out of scope, references to it dangle
+    Drop(usize),
+    // replace a reference by the referenced value
+    DeRef,
+    // pop a value and a reference, and store the value through the reference
+    StoreRef,
+    // pop the values of the fields (in declaration order) and push the struct
+    Struct(String, Vec<String>),
+    // pop the values of the fields (in declaration order) and push the enum value
+    Variant(String, String, Vec<String>),
+    // pop the given number of values and push the tuple (or array)
+    Tuple(usize),
+    Array(usize),
+    // replace a value by an array of the given number of copies
+    Repeat(usize),
+    // pop an index and an array (or a reference to it), and push the element
+    Index,
+    // pop an index and a reference to an array (or to a reference to it),
+    // and push a reference to the element
+    RefIndex,
+    // pop the given number of arguments and the receiver (or a reference to
+    // it), and push the result of the built in method
+    Method(String, usize),
+    // pop the given number of values and push a vector of them, in the heap
+    Vec(usize),
+    // pop a length and a value, and push a vector of as many copies
+    VecRepeat,
+    // replace an enum value by whether it is the given variant
+    IsVariant(String, String),
+    // replace a value by whether it is a tuple of the given length
+    IsTuple(usize),
+    // replace a struct, enum or tuple value (or a reference to it) by the value of a field
+    Field(String),
+    // replace a reference to a struct (or to a reference to it) by a reference to a field
+    RefField(String),
+    BinOp(Op),
+    Not,
+    Neg,
+    // replace an integer by the integer of type `T` it is cast to
+    Cast(Type),
+    // replace an integer by its successor (`Add`) or predecessor (`Sub`),
+    // in its type
+    Step(Op),
+    // absolute jumps within the function
+    Jump(usize),
+    JumpIfFalse(usize),
+    // call function (index into Program::fns) with the given number of arguments
+    Call(usize, usize),
+    // call intrinsic (index into Program::intrinsics) with the given number of arguments
+    Intrinsic(usize, usize),
+    // pop the given number of captured values and push a closure of the
+    // function (index into Program::fns) taking them before its parameters
+    Closure(usize, usize),
+    // pop the given number of arguments and a closure (held by the variable
+    // named, for diagnostics), and call it
+    CallValue(String, usize),
+    // return the top of stack to the caller
+    Ret,
+    // no arm of a `match` matched, a runtime error
+    NoMatch,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub id: String,
+    pub arity: usize,
+    // the parameters (after the captured values of a closure), the
+    // arguments are checked against their types as in the AST interpreter
+    pub params: Vec<Parameter>,
+    // the type of a function item, `None` for a closure
+    pub ty: Option<Type>,
+    // number of local slots, including the parameters
+    pub locals: usize,
+    pub code: Vec<Instr>,
+    // the source span of each instruction, for runtime errors
+    pub spans: Vec<Span>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub fns: Vec<Function>,
+    pub intrinsics: Vec<Intrinsic>,
+    pub main: usize,
+}
+
+impl Instr {
+    // the change in operand stack height
+    fn stack_effect(&self) -> isize {
+        match self {
+            // `NoMatch` never continues, as if the value of the `match`
+            Instr::Push(_) | Instr::Load(_) | Instr::Ref(_) | Instr::NoMatch => 1,
+            Instr::Pop
+            | Instr::Store(_)
+            | Instr::BinOp(_)
+            | Instr::JumpIfFalse(_)
+            | Instr::Ret
+            | Instr::Index
+            | Instr::RefIndex
+            | Instr::VecRepeat => -1,
+            Instr::StoreRef => -2,
+            Instr::DeRef
+            | Instr::Field(_)
+            | Instr::RefField(_)
+            | Instr::IsVariant(..)
+            | Instr::IsTuple(_)
+            | Instr::Repeat(_)
+            | Instr::Not
+            | Instr::Neg
+            | Instr::Cast(_)
+            | Instr::Step(_)
+            | Instr::Drop(_)
+            | Instr::Jump(_) => 0,
+            Instr::Struct(_, fields) | Instr::Variant(_, _, fields) => 1 - fields.len() as isize,
+            Instr::Tuple(n)
+            | Instr::Array(n)
+            | Instr::Vec(n)
+            | Instr::Call(_, n)
+            | Instr::Intrinsic(_, n) => 1 - *n as isize,
+            Instr::Closure(_, n) => 1 - *n as isize,
+            Instr::Method(_, n) | Instr::CallValue(_, n) => -(*n as isize),
+        }
+    }
+}
+
+// An enclosing loop, the target of `break` and `continue`
+struct Loop {
+    label: Option<String>,
+    // the start of an iteration
+    top: usize,
+    // the operand stack height at `top`
+    depth: usize,
+    // `loop` exits with the break value, `while` without
+    value: bool,
+    // the break jumps, patched to the loop exit
+    breaks: Vec<usize>,
+}
+
+// The function being compiled
+struct Frame {
+    code: Vec<Instr>,
+    spans: Vec<Span>,
+    // local variables in scope, innermost last
+    scopes: Vec<HashMap<String, usize>>,
+    locals: usize,
+    // the operand stack height after the last instruction
+    depth: usize,
+    // enclosing loops, innermost last
+    loops: Vec<Loop>,
+}
+
+impl Frame {
+    fn new() -> Self {
+        Frame {
+            code: vec![],
+            spans: vec![],
+            scopes: vec![HashMap::new()],
+            locals: 0,
+            depth: 0,
+            loops: vec![],
+        }
+    }
+
+    // the arguments are in the next slots, patterns take them apart
+    fn parameters(&mut self, parameters: &Parameters) {
+        let mut slots = vec![];
+        for p in &parameters.0 {
+            let slot = match &p.pat.kind {
+                PatternKind::Ident(_, id) => self.alloc(id),
+                _ => self.alloc_tmp(),
+            };
+            slots.push(slot);
+        }
+        for (p, slot) in parameters.0.iter().zip(slots) {
+            if !matches!(p.pat.kind, PatternKind::Ident(..)) {
+                destructure(self, &p.pat, slot, p.span);
+            }
+        }
+    }
+
+    fn emit(&mut self, instr: Instr, span: Span) -> usize {
+        self.depth = (self.depth as isize + instr.stack_effect()) as usize;
+        self.code.push(instr);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    // the innermost loop, or the one with the given label
+    fn find_loop(&self, label: &Option<String>, keyword: &str, span: Span) -> Result<usize, Error> {
+        let found = match label {
+            None => self.loops.len().checked_sub(1),
+            Some(_) => self.loops.iter().rposition(|l| &l.label == label),
+        };
+        found.ok_or_else(|| {
+            let msg = match label {
+                Some(l) => format!("use of undeclared label `{}`", l),
+                None => format!("`{}` outside of a loop", keyword),
+            };
+            Error::runtime(LOOP_NOT_FOUND, msg).with_span(span)
+        })
+    }
+
+    // drops the operands down to the given stack height
+    fn unwind(&mut self, depth: usize, span: Span) {
+        for _ in depth..self.depth {
+            self.emit(Instr::Pop, span);
+        }
+    }
+
+    // the address of the next instruction
+    fn here(&self) -> usize {
+        self.code.len()
+    }
+
+    // back-patching of a forward jump
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.code[at] {
+            Instr::Jump(addr) | Instr::JumpIfFalse(addr) => *addr = target,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+
+    fn alloc(&mut self, id: &str) -> usize {
+        let slot = self.alloc_tmp();
+        self.scopes.last_mut().unwrap().insert(id.to_string(), slot);
+        slot
+    }
+
+    // a slot not bound to any name, e.g., for a borrowed temporary
+    fn alloc_tmp(&mut self) -> usize {
+        self.locals += 1;
+        self.locals - 1
+    }
+
+    fn get(&self, id: &str) -> Option<usize> {
+        self.scopes.iter().rev().find_map(|s| s.get(id).cloned())
+    }
+}
+
+struct Compiler {
+    fns: Vec<Option<Function>>,
+    // functions in scope (index, arity), innermost last
+    items: Vec<HashMap<String, (usize, usize)>>,
+    // structs and enums in scope, innermost last
+    structs: Vec<HashMap<String, StructDeclaration>>,
+    enums: Vec<HashMap<String, EnumDeclaration>>,
+    intrinsics: Vec<(String, Intrinsic)>,
+}
+
+impl Compiler {
+    // functions are visible in their whole block, so all are declared upfront
+    fn declare<'a>(
+        &mut self,
+        decls: impl Iterator<Item = &'a FnDeclaration>,
+    ) -> Vec<(&'a FnDeclaration, usize)> {
+        let mut declared = vec![];
+        for decl in decls {
+            self.fns.push(None);
+            let index = self.fns.len() - 1;
+            self.items
+                .last_mut()
+                .unwrap()
+                .insert(decl.id.clone(), (index, decl.parameters.0.len()));
+            declared.push((decl, index));
+        }
+        declared
+    }
+
+    fn function(&mut self, decl: &FnDeclaration, index: usize) -> Result<(), Error> {
+        let mut f = Frame::new();
+        f.parameters(&decl.parameters);
+        self.block(&mut f, &decl.body)?;
+        f.emit(Instr::Ret, decl.span);
+        self.fns[index] = Some(Function {
+            id: decl.id.clone(),
+            arity: decl.parameters.0.len(),
+            params: decl.parameters.0.clone(),
+            ty: Some(decl.fn_type()),
+            locals: f.locals,
+            code: f.code,
+            spans: f.spans,
+        });
+        Ok(())
+    }
+
+    // A closure is compiled to a function taking the captured values before
+    // the parameters, references to the captured variables unless `move`
+    // (replaced by the referenced values on entry, as the body cannot
+    // assign them)
+    fn closure(&mut self, f: &mut Frame, c: &Closure, span: Span) -> Result<(), Error> {
+        let captures: Vec<(String, usize)> = c
+            .free_vars()
+            .into_iter()
+            .filter_map(|(id, _)| f.get(&id).map(|slot| (id, slot)))
+            .collect();
+        for (_, slot) in &captures {
+            match c.moves {
+                true => f.emit(Instr::Load(*slot), span),
+                false => f.emit(Instr::Ref(*slot), span),
+            };
+        }
+        self.fns.push(None);
+        let index = self.fns.len() - 1;
+        f.emit(Instr::Closure(index, captures.len()), span);
+
+        let mut g = Frame::new();
+        for (id, _) in &captures {
+            let slot = g.alloc(id);
+            if !c.moves {
+                g.emit(Instr::Load(slot), span);
+                g.emit(Instr::DeRef, span);
+                g.emit(Instr::Store(slot), span);
+            }
+        }
+        g.parameters(&c.parameters);
+        self.expr(&mut g, &c.body)?;
+        g.emit(Instr::Ret, span);
+        self.fns[index] = Some(Function {
+            id: "closure".to_string(),
+            arity: captures.len() + c.parameters.0.len(),
+            params: c.parameters.0.clone(),
+            ty: None,
+            locals: g.locals,
+            code: g.code,
+            spans: g.spans,
+        });
+        Ok(())
+    }
+
+    fn function_index(&self, id: &str) -> Option<(usize, usize)> {
+        self.items.iter().rev().find_map(|s| s.get(id).cloned())
+    }
+
+    fn struct_decl(&self, id: &str) -> Option<&StructDeclaration> {
+        self.structs.iter().rev().find_map(|s| s.get(id))
+    }
+
+    fn variant_decl(&self, e: &str, v: &str) -> Option<&Variant> {
+        let decl = self.enums.iter().rev().find_map(|s| s.get(e))?;
+        decl.variants.iter().find(|variant| variant.id == v)
+    }
+
+    fn intrinsic_index(&self, id: &str) -> Option<usize> {
+        self.intrinsics.iter().position(|(i, _)| i == id)
+    }
+
+    fn block(&mut self, f: &mut Frame, b: &Block) -> Result<(), Error> {
+        let first = f.locals;
+        f.scopes.push(HashMap::new());
+        self.items.push(HashMap::new());
+        self.structs.push(HashMap::new());
+        self.enums.push(HashMap::new());
+        for stmt in &b.statements {
+            match &stmt.kind {
+                StatementKind::Struct(decl) => {
+                    self.structs
+                        .last_mut()
+                        .unwrap()
+                        .insert(decl.id.clone(), decl.clone());
+                }
+                StatementKind::Enum(decl) => {
+                    self.enums
+                        .last_mut()
+                        .unwrap()
+                        .insert(decl.id.clone(), decl.clone());
+                }
+                _ => (),
+            }
+        }
+        let decls = self.declare(b.statements.iter().filter_map(|s| match &s.kind {
+            StatementKind::Fn(decl) => Some(decl),
+            _ => None,
+        }));
+        for (decl, index) in decls {
+            self.function(decl, index)?;
+        }
+
+        let unit = Instr::Push(Val::Lit(Literal::Unit));
+        for (i, stmt) in b.statements.iter().enumerate() {
+            if i > 0 {
+                f.emit(Instr::Pop, stmt.span);
+            }
+            self.statement(f, stmt)?;
+        }
+        if b.statements.is_empty() {
+            f.emit(unit, b.span);
+        } else if b.semi {
+            f.emit(Instr::Pop, b.span);
+            f.emit(unit, b.span);
+        }
+        // the locals of a nested block (and its temporaries) are dropped, as
+        // the scope is popped by the AST interpreter (those of the body of
+        // the function, below the scope of the parameters, go with the frame)
+        if f.scopes.len() > 2 {
+            for slot in first..f.locals {
+                f.emit(Instr::Drop(slot), b.span);
+            }
+        }
+        self.enums.pop();
+        self.structs.pop();
+        self.items.pop();
+        f.scopes.pop();
+        Ok(())
+    }
+
+    fn statement(&mut self, f: &mut Frame, stmt: &Statement) -> Result<(), Error> {
+        let span = stmt.span;
+        match &stmt.kind {
+            StatementKind::Let(p, _, e) => {
+                // the right hand side, before the new bindings are in scope
+                match e {
+                    Some(e) => self.expr(f, e)?,
+                    None => {
+                        f.emit(Instr::Push(Val::UnInit), span);
+                    }
+                }
+                match &p.kind {
+                    PatternKind::Ident(_, id) => {
+                        let slot = f.alloc(id);
+                        f.emit(Instr::Store(slot), span);
+                    }
+                    _ if e.is_none() => {
+                        f.emit(Instr::Pop, span);
+                        for id in p.bindings() {
+                            f.emit(Instr::Push(Val::UnInit), span);
+                            let slot = f.alloc(id);
+                            f.emit(Instr::Store(slot), span);
+                        }
+                    }
+                    _ => {
+                        let tmp = f.alloc_tmp();
+                        f.emit(Instr::Store(tmp), span);
+                        destructure(f, p, tmp, span);
+                    }
+                }
+            }
+            StatementKind::Assign(l, r) => self.assign(f, l, r, span)?,
+            StatementKind::While(label, c, b) => {
+                let top = f.here();
+                f.loops.push(Loop {
+                    label: label.clone(),
+                    top,
+                    depth: f.depth,
+                    value: false,
+                    breaks: vec![],
+                });
+                self.expr(f, c)?;
+                let exit = f.emit(Instr::JumpIfFalse(0), span);
+                self.block(f, b)?;
+                f.emit(Instr::Pop, span);
+                f.emit(Instr::Jump(top), span);
+                f.patch(exit);
+                for at in f.loops.pop().unwrap().breaks {
+                    f.patch(at);
+                }
+            }
+            StatementKind::For(label, id, Iter::Range(range), b) => {
+                self.for_loop(f, label, id, range, b, span)?
+            }
+            StatementKind::For(label, id, Iter::Chars(s), b) => {
+                self.chars_loop(f, label, id, s, b, span)?
+            }
+            StatementKind::Expr(e) => return self.expr(f, e),
+            // compiled (or registered) along with the enclosing block
+            StatementKind::Fn(_) | StatementKind::Struct(_) | StatementKind::Enum(_) => {}
+        }
+        f.emit(Instr::Push(Val::Lit(Literal::Unit)), span);
+        Ok(())
+    }
+
+    // The range is normalized to its first and last value, stepping by one
+    // from first to last (-1 if reversed), to never compute past the bounds:
+    //
+    //     start, end := the bounds
+    //     if the range is empty, goto exit
+    //     cur, last := first, last
+    //     goto body
+    // step:
+    //     if cur == last, goto exit
+    //     cur := cur + step
+    // body:
+    //     id := cur
+    //     block, continue jumps to step
+    //     goto step
+    // exit:
+    fn for_loop(
+        &mut self,
+        f: &mut Frame,
+        label: &Option<String>,
+        id: &str,
+        range: &Range,
+        b: &Block,
+        span: Span,
+    ) -> Result<(), Error> {
+        let (start, end, cur, last) = (f.alloc_tmp(), f.alloc_tmp(), f.alloc_tmp(), f.alloc_tmp());
+        self.expr(f, &range.start)?;
+        f.emit(Instr::Store(start), span);
+        self.expr(f, &range.end)?;
+        f.emit(Instr::Store(end), span);
+
+        f.emit(Instr::Load(start), span);
+        f.emit(Instr::Load(end), span);
+        let nonempty = if range.inclusive { Op::Le } else { Op::Lt };
+        f.emit(Instr::BinOp(nonempty), span);
+        let empty = f.emit(Instr::JumpIfFalse(0), span);
+
+        let (first, last_value, step) = match range.rev {
+            false => (start, end, Op::Add),
+            true => (end, start, Op::Sub),
+        };
+        f.emit(Instr::Load(first), span);
+        if range.rev && !range.inclusive {
+            // (start..end).rev() begins at end - 1
+            f.emit(Instr::Step(Op::Sub), span);
+        }
+        f.emit(Instr::Store(cur), span);
+        f.emit(Instr::Load(last_value), span);
+        if !range.rev && !range.inclusive {
+            // start..end ends at end - 1
+            f.emit(Instr::Step(Op::Sub), span);
+        }
+        f.emit(Instr::Store(last), span);
+        let to_body = f.emit(Instr::Jump(0), span);
+
+        let top = f.here();
+        f.loops.push(Loop {
+            label: label.clone(),
+            top,
+            depth: f.depth,
+            value: false,
+            breaks: vec![],
+        });
+        f.emit(Instr::Load(cur), span);
+        f.emit(Instr::Load(last), span);
+        f.emit(Instr::BinOp(Op::Ne), span);
+        let done = f.emit(Instr::JumpIfFalse(0), span);
+        f.emit(Instr::Load(cur), span);
+        f.emit(Instr::Step(step), span);
+        f.emit(Instr::Store(cur), span);
+
+        f.patch(to_body);
+        // the loop variable is scoped to the body
+        f.scopes.push(HashMap::new());
+        let slot = f.alloc(id);
+        f.emit(Instr::Load(cur), span);
+        f.emit(Instr::Store(slot), span);
+        self.block(f, b)?;
+        f.scopes.pop();
+        f.emit(Instr::Pop, span);
+        f.emit(Instr::Jump(top), span);
+
+        f.patch(empty);
+        f.patch(done);
+        for at in f.loops.pop().unwrap().breaks {
+            f.patch(at);
+        }
+        Ok(())
+    }
+
+    // The characters are copied into a vector by the `chars` method, and
+    // indexed from 0 up to its length:
+    //
+    //     cs := s.chars()
+    //     i := 0
+    // step:
+    //     if i >= cs.len(), goto exit
+    //     c := cs[i]
+    //     i := i + 1
+    //     id := c
+    //     block, continue jumps to step
+    //     goto step
+    // exit:
+    fn chars_loop(
+        &mut self,
+        f: &mut Frame,
+        label: &Option<String>,
+        id: &str,
+        s: &Expr,
+        b: &Block,
+        span: Span,
+    ) -> Result<(), Error> {
+        let (cs, i) = (f.alloc_tmp(), f.alloc_tmp());
+        self.expr(f, s)?;
+        f.emit(Instr::Method("chars".to_string(), 0), span);
+        f.emit(Instr::Store(cs), span);
+        f.emit(Instr::Push(Val::Lit(Literal::int(0, IntTy::Usize))), span);
+        f.emit(Instr::Store(i), span);
+
+        let top = f.here();
+        f.loops.push(Loop {
+            label: label.clone(),
+            top,
+            depth: f.depth,
+            value: false,
+            breaks: vec![],
+        });
+        f.emit(Instr::Load(i), span);
+        f.emit(Instr::Load(cs), span);
+        f.emit(Instr::Method("len".to_string(), 0), span);
+        f.emit(Instr::BinOp(Op::Lt), span);
+        let done = f.emit(Instr::JumpIfFalse(0), span);
+        f.emit(Instr::Load(cs), span);
+        f.emit(Instr::Load(i), span);
+        f.emit(Instr::Index, span);
+        f.emit(Instr::Load(i), span);
+        f.emit(Instr::Step(Op::Add), span);
+        f.emit(Instr::Store(i), span);
+
+        // the loop variable is scoped to the body
+        f.scopes.push(HashMap::new());
+        let slot = f.alloc(id);
+        f.emit(Instr::Store(slot), span);
+        self.block(f, b)?;
+        f.scopes.pop();
+        f.emit(Instr::Pop, span);
+        f.emit(Instr::Jump(top), span);
+
+        f.patch(done);
+        for at in f.loops.pop().unwrap().breaks {
+            f.patch(at);
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, f: &mut Frame, l: &Expr, r: &Expr, span: Span) -> Result<(), Error> {
+        match &l.kind {
+            ExprKind::Ident(id) => {
+                let slot = f.get(id).ok_or_else(|| unbound_variable(id, l.span))?;
+                self.expr(f, r)?;
+                f.emit(Instr::Store(slot), span);
+            }
+            ExprKind::Par(l) | ExprKind::UnOp(UnOp::Mut, l) => self.assign(f, l, r, span)?,
+            ExprKind::UnOp(UnOp::DeRef, e) => {
+                self.expr(f, e)?;
+                self.expr(f, r)?;
+                f.emit(Instr::StoreRef, span);
+            }
+            ExprKind::Field(..) | ExprKind::Index(..) => {
+                self.reference(f, l, span)?;
+                self.expr(f, r)?;
+                f.emit(Instr::StoreRef, span);
+            }
+            _ => {
+                return Err(
+                    Error::runtime(NOT_A_PLACE, "Expected ref in assignment").with_span(span)
+                )
+            }
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, f: &mut Frame, e: &Expr) -> Result<(), Error> {
+        let span = e.span;
+        match &e.kind {
+            ExprKind::Ident(id) => match (f.get(id), self.function_index(id)) {
+                (Some(slot), _) => {
+                    f.emit(Instr::Load(slot), span);
+                }
+                // a function item used as a value, a closure capturing nothing
+                (None, Some((index, _))) => {
+                    f.emit(Instr::Closure(index, 0), span);
+                }
+                (None, None) => return Err(unbound_variable(id, span)),
+            },
+            ExprKind::Lit(l) => {
+                let l = l.or_i32().map_err(|err| err.with_span(span))?;
+                f.emit(Instr::Push(Val::Lit(l)), span);
+            }
+            ExprKind::BinOp(op, l, r) => {
+                self.expr(f, l)?;
+                self.expr(f, r)?;
+                f.emit(Instr::BinOp(*op), span);
+            }
+            ExprKind::Par(e) | ExprKind::UnOp(UnOp::Mut, e) => self.expr(f, e)?,
+            // a variable shadows the functions of the same name
+            ExprKind::Call(id, _, args) if f.get(id).is_some() => {
+                let slot = f.get(id).unwrap();
+                f.emit(Instr::Load(slot), span);
+                for arg in &args.0 {
+                    self.expr(f, arg)?;
+                }
+                f.emit(Instr::CallValue(id.clone(), args.0.len()), span);
+            }
+            ExprKind::Call(id, _, args) => {
+                for arg in &args.0 {
+                    self.expr(f, arg)?;
+                }
+                let argc = args.0.len();
+                match (self.function_index(id), self.intrinsic_index(id)) {
+                    (Some((_, arity)), _) if arity != argc => {
+                        return Err(
+                            Error::runtime(ARITY_MISMATCH, arity_message(id, arity, argc))
+                                .with_span(span),
+                        )
+                    }
+                    (Some((index, _)), _) => f.emit(Instr::Call(index, argc), span),
+                    (None, Some(index)) => f.emit(Instr::Intrinsic(index, argc), span),
+                    (None, None) => {
+                        return Err(Error::runtime(
+                            UNBOUND_FUNCTION,
+                            format!("cannot find function `{}` in this scope", id),
+                        )
+                        .with_span(span))
+                    }
+                };
+            }
+            ExprKind::IfThenElse(c, t, e) => {
+                self.expr(f, c)?;
+                let to_else = f.emit(Instr::JumpIfFalse(0), span);
+                self.block(f, t)?;
+                let to_end = f.emit(Instr::Jump(0), span);
+                f.patch(to_else);
+                // the else branch starts without the value of the then branch
+                f.depth -= 1;
+                match e {
+                    Some(e) => self.block(f, e)?,
+                    None => {
+                        f.emit(Instr::Push(Val::Lit(Literal::Unit)), span);
+                    }
+                }
+                f.patch(to_end);
+            }
+            ExprKind::Block(b) => self.block(f, b)?,
+            ExprKind::UnOp(UnOp::Bang, e) => {
+                self.expr(f, e)?;
+                f.emit(Instr::Not, span);
+            }
+            ExprKind::UnOp(UnOp::Neg, e) => {
+                self.expr(f, e)?;
+                f.emit(Instr::Neg, span);
+            }
+            ExprKind::UnOp(UnOp::DeRef, e) => {
+                self.expr(f, e)?;
+                f.emit(Instr::DeRef, span);
+            }
+            ExprKind::UnOp(UnOp::Ref, e) => self.reference(f, e, span)?,
+            ExprKind::Loop(label, b) => {
+                let top = f.here();
+                f.loops.push(Loop {
+                    label: label.clone(),
+                    top,
+                    depth: f.depth,
+                    value: true,
+                    breaks: vec![],
+                });
+                self.block(f, b)?;
+                f.emit(Instr::Pop, span);
+                f.emit(Instr::Jump(top), span);
+                let l = f.loops.pop().unwrap();
+                for at in l.breaks {
+                    f.patch(at);
+                }
+                // the break value
+                f.depth = l.depth + 1;
+            }
+            ExprKind::Break(label, e) => {
+                let depth = f.depth;
+                let index = f.find_loop(label, "break", span)?;
+                if let Some(e) = e {
+                    self.expr(f, e)?;
+                }
+                let (base, value) = (f.loops[index].depth, f.loops[index].value);
+                if value {
+                    if e.is_none() {
+                        f.emit(Instr::Push(Val::Lit(Literal::Unit)), span);
+                    }
+                    // keep the break value, dropping the operands of the loop body
+                    if f.depth > base + 1 {
+                        let slot = f.alloc_tmp();
+                        f.emit(Instr::Store(slot), span);
+                        f.unwind(base, span);
+                        f.emit(Instr::Load(slot), span);
+                    }
+                } else {
+                    f.unwind(base, span);
+                }
+                let at = f.emit(Instr::Jump(0), span);
+                f.loops[index].breaks.push(at);
+                // as if the break was an ordinary value, the code following is unreachable
+                f.depth = depth + 1;
+            }
+            ExprKind::Return(e) => {
+                let depth = f.depth;
+                match e {
+                    Some(e) => self.expr(f, e)?,
+                    None => {
+                        f.emit(Instr::Push(Val::Lit(Literal::Unit)), span);
+                    }
+                }
+                // keep the return value, dropping all other operands of the function
+                if f.depth > 1 {
+                    let slot = f.alloc_tmp();
+                    f.emit(Instr::Store(slot), span);
+                    f.unwind(0, span);
+                    f.emit(Instr::Load(slot), span);
+                }
+                f.emit(Instr::Ret, span);
+                f.depth = depth + 1;
+            }
+            ExprKind::Continue(label) => {
+                let depth = f.depth;
+                let index = f.find_loop(label, "continue", span)?;
+                let (base, top) = (f.loops[index].depth, f.loops[index].top);
+                f.unwind(base, span);
+                f.emit(Instr::Jump(top), span);
+                f.depth = depth + 1;
+            }
+            ExprKind::Struct(id, fields) => {
+                let decl: Vec<String> = match self.struct_decl(id) {
+                    Some(decl) => decl.fields.iter().map(|field| field.id.clone()).collect(),
+                    None => {
+                        return Err(Error::runtime(
+                            VALUE_MISMATCH,
+                            format!("cannot find struct `{}` in this scope", id),
+                        )
+                        .with_span(span))
+                    }
+                };
+                let what = format!("struct `{}`", id);
+                self.fields_lit(f, &what, id, &decl, fields, span)?;
+                f.emit(Instr::Struct(id.to_string(), decl), span);
+            }
+            ExprKind::Variant(e, v, _, fields) => {
+                let decl: Vec<String> = match self.variant_decl(e, v) {
+                    Some(decl) => decl.fields.iter().map(|field| field.id.clone()).collect(),
+                    None => {
+                        return Err(Error::runtime(
+                            VALUE_MISMATCH,
+                            format!("no variant named `{}` found for enum `{}`", v, e),
+                        )
+                        .with_span(span))
+                    }
+                };
+                let id = format!("{}::{}", e, v);
+                let what = format!("variant `{}`", id);
+                self.fields_lit(f, &what, &id, &decl, fields, span)?;
+                f.emit(Instr::Variant(e.clone(), v.clone(), decl), span);
+            }
+            ExprKind::Match(e, arms) => self.match_arms(f, e, arms, span)?,
+            ExprKind::Tuple(es) => {
+                for e in es {
+                    self.expr(f, e)?;
+                }
+                f.emit(Instr::Tuple(es.len()), span);
+            }
+            ExprKind::Array(es) => {
+                for e in es {
+                    self.expr(f, e)?;
+                }
+                f.emit(Instr::Array(es.len()), span);
+            }
+            ExprKind::Repeat(e, n) => {
+                self.expr(f, e)?;
+                f.emit(Instr::Repeat(*n), span);
+            }
+            ExprKind::Index(e, i) => {
+                self.expr(f, e)?;
+                self.expr(f, i)?;
+                f.emit(Instr::Index, span);
+            }
+            // the methods are built in, and checked when called
+            ExprKind::Cast(e, t) => {
+                self.expr(f, e)?;
+                f.emit(Instr::Cast(t.clone()), span);
+            }
+            ExprKind::Method(e, m, args) => {
+                self.expr(f, e)?;
+                for arg in &args.0 {
+                    self.expr(f, arg)?;
+                }
+                f.emit(Instr::Method(m.clone(), args.0.len()), span);
+            }
+            ExprKind::Assoc(t, id, args) => match (t.as_str(), id.as_str(), args.0.as_slice()) {
+                ("Vec", "new", []) => {
+                    f.emit(Instr::Vec(0), span);
+                }
+                ("String", "new", []) => {
+                    f.emit(Instr::Push(Val::Lit(Literal::String(String::new()))), span);
+                    f.emit(Instr::Method("to_string".to_string(), 0), span);
+                }
+                ("String", "from", [s]) => {
+                    self.expr(f, s)?;
+                    f.emit(Instr::Method("to_string".to_string(), 0), span);
+                }
+                _ => {
+                    return Err(Error::runtime(
+                        UNBOUND_FUNCTION,
+                        format!("cannot find function `{}::{}`", t, id),
+                    )
+                    .with_span(span))
+                }
+            },
+            ExprKind::Vec(es) => {
+                for e in es {
+                    self.expr(f, e)?;
+                }
+                f.emit(Instr::Vec(es.len()), span);
+            }
+            ExprKind::VecRepeat(e, n) => {
+                self.expr(f, e)?;
+                self.expr(f, n)?;
+                f.emit(Instr::VecRepeat, span);
+            }
+            ExprKind::Closure(c) => self.closure(f, c, span)?,
+            ExprKind::Field(e, id) => {
+                self.expr(f, e)?;
+                f.emit(Instr::Field(id.clone()), span);
+            }
+        }
+        Ok(())
+    }
+
+    // a reference to the place `e`, or to a temporary holding its value
+    fn reference(&mut self, f: &mut Frame, e: &Expr, span: Span) -> Result<(), Error> {
+        match &e.kind {
+            ExprKind::Ident(id) => {
+                let slot = f.get(id).ok_or_else(|| unbound_variable(id, e.span))?;
+                f.emit(Instr::Ref(slot), span);
+            }
+            ExprKind::Par(e) | ExprKind::UnOp(UnOp::Mut, e) => self.reference(f, e, span)?,
+            // the reference we deref
+            ExprKind::UnOp(UnOp::DeRef, e) => self.expr(f, e)?,
+            ExprKind::Field(e, id) => {
+                self.reference(f, e, span)?;
+                f.emit(Instr::RefField(id.clone()), span);
+            }
+            ExprKind::Index(e, i) => {
+                self.reference(f, e, span)?;
+                self.expr(f, i)?;
+                f.emit(Instr::RefIndex, span);
+            }
+            _ => {
+                self.expr(f, e)?;
+                let slot = f.alloc_tmp();
+                f.emit(Instr::Store(slot), span);
+                f.emit(Instr::Ref(slot), span);
+            }
+        }
+        Ok(())
+    }
+
+    // The fields of a struct (or variant) literal are evaluated in source
+    // order, and put in declaration order (through temporaries) if the two differ.
+    fn fields_lit(
+        &mut self,
+        f: &mut Frame,
+        what: &str,
+        id: &str,
+        decl: &[String],
+        fields: &[(String, Expr)],
+        span: Span,
+    ) -> Result<(), Error> {
+        if let Some(missing) = decl.iter().find(|d| fields.iter().all(|(f, _)| f != *d)) {
+            return Err(Error::runtime(
+                VALUE_MISMATCH,
+                format!("missing field `{}` in initializer of `{}`", missing, id),
+            )
+            .with_span(span));
+        }
+        if let Some((unknown, _)) = fields.iter().find(|(f, _)| !decl.contains(f)) {
+            return Err(Error::runtime(
+                VALUE_MISMATCH,
+                format!("{} has no field named `{}`", what, unknown),
+            )
+            .with_span(span));
+        }
+        if fields.iter().map(|(f, _)| f).eq(decl.iter()) {
+            for (_, e) in fields {
+                self.expr(f, e)?;
+            }
+        } else {
+            let mut slots = HashMap::new();
+            for (field, e) in fields {
+                self.expr(f, e)?;
+                let slot = f.alloc_tmp();
+                f.emit(Instr::Store(slot), e.span);
+                slots.insert(field, slot);
+            }
+            for field in decl {
+                f.emit(Instr::Load(slots[field]), span);
+            }
+        }
+        Ok(())
+    }
+
+    // The scrutinee is kept in a temporary, each arm tests its pattern
+    // against it and jumps to the next arm on failure:
+    //
+    //     tmp := scrutinee
+    //     tests of arm 1, each failure jumps to next
+    //     bindings of arm 1
+    //     body of arm 1
+    //     goto end
+    // next:
+    //     ...
+    //     no match
+    // end:
+    fn match_arms(&mut self, f: &mut Frame, e: &Expr, arms: &[Arm], span: Span) -> Result<(), Error> {
+        self.expr(f, e)?;
+        let tmp = f.alloc_tmp();
+        f.emit(Instr::Store(tmp), span);
+        let depth = f.depth;
+        let mut ends = vec![];
+        for arm in arms {
+            let (mut fails, mut bindings) = (vec![], vec![]);
+            pattern(f, &arm.pat, tmp, &mut vec![], &mut fails, &mut bindings);
+            // the pattern variables are scoped to the arm
+            f.scopes.push(HashMap::new());
+            for (id, path) in bindings {
+                load_path(f, tmp, &path, arm.pat.span);
+                let slot = f.alloc(id);
+                f.emit(Instr::Store(slot), arm.pat.span);
+            }
+            self.expr(f, &arm.body)?;
+            f.scopes.pop();
+            ends.push(f.emit(Instr::Jump(0), span));
+            for at in fails {
+                f.patch(at);
+            }
+            // the next arm starts without the value of this one
+            f.depth = depth;
+        }
+        f.emit(Instr::NoMatch, span);
+        for at in ends {
+            f.patch(at);
+        }
+        Ok(())
+    }
+}
+
+// Binds the variables of the (irrefutable) pattern of a `let` or a parameter
+// to the parts of the value in `slot`, a refutable pattern that does not
+// match is a runtime error.
+fn destructure(f: &mut Frame, p: &Pattern, slot: usize, span: Span) {
+    let (mut fails, mut bindings) = (vec![], vec![]);
+    pattern(f, p, slot, &mut vec![], &mut fails, &mut bindings);
+    if !fails.is_empty() {
+        let matched = f.emit(Instr::Jump(0), span);
+        for at in fails {
+            f.patch(at);
+        }
+        f.emit(Instr::NoMatch, span);
+        f.emit(Instr::Pop, span);
+        f.patch(matched);
+    }
+    for (id, path) in bindings {
+        load_path(f, slot, &path, p.span);
+        let binding = f.alloc(id);
+        f.emit(Instr::Store(binding), p.span);
+    }
+}
+
+// Pushes the part of the value in `slot` reached by the field names of `path`
+fn load_path(f: &mut Frame, slot: usize, path: &[String], span: Span) {
+    f.emit(Instr::Load(slot), span);
+    for id in path {
+        f.emit(Instr::Field(id.clone()), span);
+    }
+}
+
+// Emits the tests of pattern `p` against the part of the value in `slot`
+// at `path`, collecting the failure jumps and the paths of the bindings.
+// The variant (or the length of the tuple) is tested before its fields are
+// accessed.
+fn pattern<'a>(
+    f: &mut Frame,
+    p: &'a Pattern,
+    slot: usize,
+    path: &mut Vec<String>,
+    fails: &mut Vec<usize>,
+    bindings: &mut Vec<(&'a String, Vec<String>)>,
+) {
+    let span = p.span;
+    match &p.kind {
+        PatternKind::Wild => (),
+        PatternKind::Ident(_, id) => bindings.push((id, path.clone())),
+        PatternKind::Lit(l) => match l.or_i32() {
+            Ok(l) => {
+                load_path(f, slot, path, span);
+                f.emit(Instr::Push(Val::Lit(l)), span);
+                f.emit(Instr::BinOp(Op::Eq), span);
+                fails.push(f.emit(Instr::JumpIfFalse(0), span));
+            }
+            // an `i32` never matches a literal out of its range
+            Err(_) => {
+                f.emit(Instr::Push(Val::Lit(Literal::Bool(false))), span);
+                fails.push(f.emit(Instr::JumpIfFalse(0), span));
+            }
+        },
+        PatternKind::Variant(e, v, _, fields, _) => {
+            load_path(f, slot, path, span);
+            f.emit(Instr::IsVariant(e.clone(), v.clone()), span);
+            fails.push(f.emit(Instr::JumpIfFalse(0), span));
+            for (id, p) in fields {
+                path.push(id.clone());
+                pattern(f, p, slot, path, fails, bindings);
+                path.pop();
+            }
+        }
+        PatternKind::Tuple(pats) => {
+            load_path(f, slot, path, span);
+            f.emit(Instr::IsTuple(pats.len()), span);
+            fails.push(f.emit(Instr::JumpIfFalse(0), span));
+            for (i, p) in pats.iter().enumerate() {
+                path.push(i.to_string());
+                pattern(f, p, slot, path, fails, bindings);
+                path.pop();
+            }
+        }
+    }
+}
+
+fn unbound_variable(id: &str, span: Span) -> Error {
+    Error::runtime(UNBOUND_VARIABLE, format!("Variable `{}` not found", id)).with_span(span)
+}
+
+// Compiles all functions of the program, `main` being the entry point
+pub fn compile(prog: &Prog) -> Result<Program, Error> {
+    // a function of its own for each instance of a generic function
+    let prog = &monomorphize(prog)?;
+    let (print, intrinsic) = vm_println();
+    let mut c = Compiler {
+        fns: vec![],
+        items: vec![HashMap::new()],
+        structs: vec![prog.1.iter().map(|s| (s.id.clone(), s.clone())).collect()],
+        enums: vec![prog.2.iter().map(|e| (e.id.clone(), e.clone())).collect()],
+        intrinsics: vec![(print.id, intrinsic)],
+    };
+
+    match prog.0.iter().find(|f| f.id == "main") {
+        Some(main) if !main.parameters.0.is_empty() => {
+            return Err(Error::runtime(
+                NO_ENTRY_POINT,
+                "Main doesn't support arguments.",
+            ))
+        }
+        Some(_) => (),
+        None => return Err(Error::runtime(NO_ENTRY_POINT, "Main not found")),
+    }
+
+    let decls = c.declare(prog.0.iter());
+    for (decl, index) in decls {
+        c.function(decl, index)?;
+    }
+    Ok(Program {
+        main: c.function_index("main").unwrap().0,
+        fns: c.fns.into_iter().map(|f| f.unwrap()).collect(),
+        intrinsics: c.intrinsics.into_iter().map(|(_, i)| i).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, Instr};
+    use crate::ast::{Literal, Op, Prog};
+    use crate::error::NO_ENTRY_POINT;
+    use crate::vm::Val;
+
+    fn prog(s: &str) -> Prog {
+        let ts: proc_macro2::TokenStream = s.parse().unwrap();
+        syn::parse2(ts).unwrap()
+    }
+
+    #[test]
+    fn compile_if() {
+        let p = compile(&prog("fn main() { let a = 1; if a < 2 { a } else { 0 } }")).unwrap();
+        let main = &p.fns[p.main];
+        assert_eq!(main.locals, 1);
+        assert_eq!(
+            main.code,
+            vec![
+                Instr::Push(Val::Lit(Literal::Int(1))),
+                Instr::Store(0),
+                Instr::Push(Val::Lit(Literal::Unit)),
+                Instr::Pop,
+                Instr::Load(0),
+                Instr::Push(Val::Lit(Literal::Int(2))),
+                Instr::BinOp(Op::Lt),
+                Instr::JumpIfFalse(10),
+                Instr::Load(0),
+                Instr::Jump(11),
+                Instr::Push(Val::Lit(Literal::Int(0))),
+                Instr::Ret,
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_nested_fn() {
+        let p = compile(&prog("fn main() { fn f() {} f() }")).unwrap();
+        let f = p.fns.iter().position(|f| f.id == "f").unwrap();
+        assert!(p.fns[p.main].code.contains(&Instr::Call(f, 0)));
+    }
+
+    #[test]
+    fn compile_no_main() {
+        let err = compile(&prog("fn f() {}")).unwrap_err();
+        assert_eq!(err.code, NO_ENTRY_POINT);
+    }
+}
@@ -1,9 +1,8 @@
-use crate::ast::{
-    Expr::{self, *},
-    Literal, Op,
-};
+use crate::ast::{Expr, Op};
+use crate::parse::parse_operand;
+use syn::parse::ParseStream;
 
-// As we seen, the simple parse implementation for Expr
+// A plain recursive descent parser for `operand op expr`
 // renders us a right associative representation of Expr
 // and thus its evaluation does not adhere to the
 // mathematical intuition of expressions.
@@ -27,164 +26,168 @@ use crate::ast::{
 //         lhs := the result of applying op with operands lhs and rhs
 //     return lhs
 //
-// In order to implement the algorithm for our Expr
-// we first want to turn the right associated graph to
-// to a flat vector of elements ExprItems.
-#[derive(Debug)]
-enum ExprItems {
-    Op(Op),
-    Lit(Literal),
-}
+// We run the algorithm directly on the token stream, the primaries
+// being any operand (literals, identifiers, calls, parenthesized
+// expressions, blocks, if-then-else and unary operations).
 
 impl Op {
-    // The operator priority
-    // Mul/Div has higher priority (binds harder) than Add/Sub
+    // The operator priority, following Rust's table
+    // Mul/Div/Rem binds harder than Add/Sub, which binds harder
+    // than comparisons, then && and lowest ||
     pub fn priority(&self) -> u8 {
         match self {
-            Op::Add => 0,
-            Op::Sub => 0,
-            Op::Mul => 1,
-            Op::Div => 1,
-            Op::And => 0,
+            Op::Mul | Op::Div | Op::Rem => 4,
+            Op::Add | Op::Sub => 3,
+            Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => 2,
+            Op::And => 1,
             Op::Or => 0,
-            Op::Eq => 2,
-            Op::Lt => 2,
-            Op::Gt => 2,
-            //Op::Not => 3,
-        }
-    }
-}
-
-impl ExprItems {
-    fn get_op(&self) -> Op {
-        match self {
-            ExprItems::Op(op) => *op,
-            _ => panic!(),
-        }
-    }
-    fn get_lit(&self) -> Literal {
-        match self {
-            ExprItems::Lit(lit) => lit.clone(),
-            _ => panic!(),
         }
     }
-}
 
-// Flattens an Expr into a vector of ExprItems
-fn to_vec(e: Expr) -> Vec<ExprItems> {
-    match e {
-        Lit(l) => vec![ExprItems::Lit(l)],
-        BinOp(op, l, r) => {
-            let mut r = to_vec(*r);
-            match *l {
-                Lit(l) => {
-                    r.push(ExprItems::Op(op));
-                    r.push(ExprItems::Lit(l));
-                    r
-                }
-                // should never occur due to the Expr structure
-                _ => unreachable!(),
-            }
-        }
-        // not yet implemented
-        _ => unimplemented!(),
+    // Comparisons are non-associative, `a < b < c` is an error
+    pub fn is_comparison(&self) -> bool {
+        self.priority() == 2
     }
 }
 
-use std::iter::{Peekable, Rev};
-
-// A Peekable iterator allows allows to peek next item
-// Rev reverses the scanning order
-// std::slice::Iter is the iterator implementation for Vec
-type Scanner<'a> = Peekable<Rev<std::slice::Iter<'a, ExprItems>>>;
-
-fn peek_precedence<F>(scanner: &mut Scanner, f: F) -> bool
-where
-    F: Fn(u8) -> bool,
-{
-    if let Some(ExprItems::Op(op)) = scanner.peek().clone() {
-        f(op.priority())
-    } else {
-        false
-    }
+// The next binary operator, without consuming it
+fn peek_op(input: ParseStream) -> Option<Op> {
+    input.fork().parse::<Op>().ok()
 }
 
-// A one-to-one implementation of the "wikipedia" algorithm.
-fn climb_rec(mut lhs: Expr, min_precedence: u8, scanner: &mut Scanner) -> Expr {
-    while peek_precedence(scanner, |op_precedence| op_precedence >= min_precedence) {
+// A one-to-one implementation of the "wikipedia" algorithm,
+// all operators being left associative.
+fn climb_rec(input: ParseStream, mut lhs: Expr, min_precedence: u8) -> syn::Result<Expr> {
+    while let Some(op) = peek_op(input).filter(|op| op.priority() >= min_precedence) {
         // op := lookahead
-        let op: Op = scanner.next().unwrap().get_op();
         // advance to next token
+        let _: Op = input.parse()?;
         // rhs := parse_primary ()
-        let mut rhs: Expr = scanner.next().unwrap().get_lit().into();
-        // while lookahead is a binary operator whose precedence is greater
-        //                  than op's, or a right-associative operator
-        //                  whose precedence is equal to op's
-        while peek_precedence(scanner, |op_precedence| op_precedence > op.priority()) {
-            // rhs := parse_expression_1 (rhs, min_precedence + 1)
-            rhs = climb_rec(rhs, min_precedence + 1, scanner);
-            // lookahead := peek next token
-            // scanner will be updated since we passed it recursively
+        let mut rhs = parse_operand(input)?;
+        // while lookahead is a binary operator whose precedence is greater than op's
+        while peek_op(input).is_some_and(|next| next.priority() > op.priority()) {
+            // rhs := parse_expression_1 (rhs, precedence of op + 1)
+            rhs = climb_rec(input, rhs, op.priority() + 1)?;
+        }
+        if op.is_comparison() && peek_op(input).is_some_and(|next| next.is_comparison()) {
+            return Err(input.error("comparison operators cannot be chained, use parentheses"));
         }
         // lhs := the result of applying op with operands lhs and rhs
-        lhs = BinOp(op, Box::new(lhs), Box::new(rhs));
+        lhs = Expr::bin_op(op, lhs, rhs);
     }
-    lhs
+    Ok(lhs)
 }
 
 // A trampoline to start of the precedence climbing
-pub fn climb(e: Expr) -> Expr {
-    // flatten the Expr into a vector
-    let v: Vec<ExprItems> = to_vec(e);
-    // turn the vector into a Scanner
-    let mut scanner = v.iter().rev().peekable();
-    // take the first literal
-    let lhs: Expr = scanner.next().unwrap().get_lit().into();
+pub fn climb(input: ParseStream) -> syn::Result<Expr> {
+    // take the first operand
+    let lhs = parse_operand(input)?;
     // now call the climber
-    climb_rec(lhs, 0, &mut scanner)
+    climb_rec(input, lhs, 0)
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::ast::{Expr, ExprKind, Op, UnOp};
 
-#[test]
-fn climb_test1() {
-    let ts: proc_macro2::TokenStream = "2 - 3 - 4 - 5".parse().unwrap();
-    let e: Expr = syn::parse2(ts).unwrap();
-    println!("e {:?}", e);
-    let e = climb(e);
-    println!("e {:?}", e);
-    // println!("evaluation {:?}", e.eval());
-    // assert_eq!(e.eval(), Literal::Int(2 - 3 - 4 - 5));
-}
+    fn parse(s: &str) -> Expr {
+        let ts: proc_macro2::TokenStream = s.parse().unwrap();
+        syn::parse2(ts).unwrap()
+    }
 
-#[test]
-fn climb_test2() {
-    let ts: proc_macro2::TokenStream = "2 - 3 * 4 - 5".parse().unwrap();
-    let e: Expr = syn::parse2(ts).unwrap();
-    println!("e {:?}", e);
-    let e = climb(e);
-    println!("e {:?}", e);
-    // println!("evaluation {:?}", e.eval());
-    // assert_eq!(e.eval(), Literal::Int(2 - 3 * 4 - 5));
-}
+    fn bin(op: Op, l: Expr, r: Expr) -> Expr {
+        Expr::bin_op(op, l, r)
+    }
 
-#[test]
-fn climb_test3() {
-    let ts: proc_macro2::TokenStream = "4 - 5 - 2 * 8 * 3 - 1 - 2 * 5".parse().unwrap();
-    let e: Expr = syn::parse2(ts).unwrap();
-    println!("e {:?}", e);
-    let e = climb(e);
-    println!("e {:?}", e);
-    // println!("evaluation {:?}", e.eval());
-    // assert_eq!(e.eval(), Literal::Int(4 - 5 - 2 * 8 * 3 - 1 - 2 * 5));
-}
+    fn id(s: &str) -> Expr {
+        ExprKind::Ident(s.to_string()).into()
+    }
+
+    #[test]
+    fn climb_test1() {
+        // left associative
+        let e = parse("2 - 3 - 4 - 5");
+        let expected = bin(
+            Op::Sub,
+            bin(Op::Sub, bin(Op::Sub, 2.into(), 3.into()), 4.into()),
+            5.into(),
+        );
+        assert_eq!(e, expected);
+    }
+
+    #[test]
+    fn climb_test2() {
+        let e = parse("2 - 3 * 4 - 5");
+        let expected = bin(
+            Op::Sub,
+            bin(Op::Sub, 2.into(), bin(Op::Mul, 3.into(), 4.into())),
+            5.into(),
+        );
+        assert_eq!(e, expected);
+    }
+
+    #[test]
+    fn climb_test3() {
+        let e = parse("4 - 5 - 2 * 8 * 3 - 1 - 2 * 5");
+        let mul = bin(Op::Mul, bin(Op::Mul, 2.into(), 8.into()), 3.into());
+        let expected = bin(
+            Op::Sub,
+            bin(
+                Op::Sub,
+                bin(Op::Sub, bin(Op::Sub, 4.into(), 5.into()), mul),
+                1.into(),
+            ),
+            bin(Op::Mul, 2.into(), 5.into()),
+        );
+        assert_eq!(e, expected);
+    }
+
+    #[test]
+    fn climb_test4() {
+        // operands other than literals
+        let e = parse("a(1) - *b % (c) == -d");
+        let call = ExprKind::Call(
+            "a".to_string(),
+            vec![],
+            crate::ast::Arguments(vec![1.into()]),
+        );
+        let deref = ExprKind::UnOp(UnOp::DeRef, Box::new(id("b")));
+        let par = ExprKind::Par(Box::new(id("c")));
+        let neg = ExprKind::UnOp(UnOp::Neg, Box::new(id("d")));
+        let expected = bin(
+            Op::Eq,
+            bin(Op::Sub, call.into(), bin(Op::Rem, deref.into(), par.into())),
+            neg.into(),
+        );
+        assert_eq!(e, expected);
+    }
+
+    #[test]
+    fn climb_logic() {
+        // && binds tighter than ||, comparisons tighter than &&
+        let e = parse("a || b && c < 1");
+        let expected = bin(
+            Op::Or,
+            id("a"),
+            bin(Op::And, id("b"), bin(Op::Lt, id("c"), 1.into())),
+        );
+        assert_eq!(e, expected);
+    }
+
+    #[test]
+    fn climb_comparison_chain() {
+        let ts: proc_macro2::TokenStream = "a < b < c".parse().unwrap();
+        assert!(syn::parse2::<Expr>(ts).is_err());
+        let ts: proc_macro2::TokenStream = "a == b != c".parse().unwrap();
+        assert!(syn::parse2::<Expr>(ts).is_err());
+        // but parenthesized comparisons are fine
+        let e = parse("(a < b) == c");
+        assert_eq!(e.to_string(), "(a < b) == c");
+    }
 
-#[test]
-fn climb_test4() {
-    let ts: proc_macro2::TokenStream = "8 - 7 - 6 * 5 - 4 * 3".parse().unwrap();
-    let e: Expr = syn::parse2(ts).unwrap();
-    println!("e {:?}", e);
-    let e = climb(e);
-    println!("e {:?}", e);
-    // println!("evaluation {:?}", e.eval());
-    // assert_eq!(e.eval(), Literal::Int(8 - 7 - 6 * 5 - 4 * 3));
+    #[test]
+    fn climb_span() {
+        let e = parse("1 + 2 * 3");
+        assert_eq!((e.span.lo.column, e.span.hi.column), (0, 9));
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{env::Env, env::Ref};
+use crate::{env::Env, env::Fields, env::Ref};
 
 use crate::error::Error;
 
@@ -14,7 +14,7 @@ where
 pub fn parse<T1, T2>(s: &str) -> (T1, Env<T2>)
 where
     T1: syn::parse::Parse + std::fmt::Display,
-    T2: Clone,
+    T2: Clone + Fields,
     {
         let ts: proc_macro2::TokenStream = s.parse().unwrap();
         let r: T1 = syn::parse2(ts).unwrap();
@@ -25,7 +25,7 @@ where
 pub fn parse_test<T1, T2>(s: &str) -> Result<T2, Error>
 where
     T1: syn::parse::Parse + std::fmt::Display + Eval<T2>,
-    T2: std::fmt::Debug + Clone,
+    T2: std::fmt::Debug + Clone + Fields,
     {
         let (bl, mut env) = parse::<T1, T2>(s);
         let (v, _) = bl.eval(&mut env)?;
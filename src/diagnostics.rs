@@ -0,0 +1,395 @@
+// rustc style rendering of diagnostics
+//
+// error[T0001]: expected type `i32`, got type `bool`
+//  --> examples/let.rs:3:13
+//   |
+// 2 |     let b = true;
+//   |             ---- defined here
+// 3 |     let a = b + 1;
+//   |             ^
+//   |
+//   = note: ...
+
+use crate::error::{Error, LineColumn, Span};
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("invalid color choice `{}`", s)),
+        }
+    }
+}
+
+impl ColorChoice {
+    // auto colors only when stderr is a terminal
+    pub fn use_color(&self) -> bool {
+        match self {
+            ColorChoice::Auto => std::io::stderr().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("invalid error format `{}`", s)),
+        }
+    }
+}
+
+// ANSI escape sequences
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+// A span to underline, the primary span has no label
+struct Annotation<'e> {
+    span: Span,
+    label: Option<&'e str>,
+}
+
+impl Annotation<'_> {
+    fn is_primary(&self) -> bool {
+        self.label.is_none()
+    }
+}
+
+// a JSON string literal
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub struct Emitter<'a> {
+    path: &'a str,
+    source: &'a str,
+    color: bool,
+    format: ErrorFormat,
+}
+
+impl<'a> Emitter<'a> {
+    pub fn new(path: &'a str, source: &'a str, color: ColorChoice, format: ErrorFormat) -> Self {
+        Emitter {
+            path,
+            source,
+            color: color.use_color() && format == ErrorFormat::Human,
+            format,
+        }
+    }
+
+    fn paint(&self, style: &str, s: &str) -> String {
+        match self.color {
+            true => format!("{}{}{}", style, s, RESET),
+            false => s.to_string(),
+        }
+    }
+
+    // spans outside of the source (e.g., of intrinsics) are not shown
+    fn line(&self, span: Span) -> Option<&'a str> {
+        match span.lo.line {
+            0 => None,
+            line => self.source.lines().nth(line as usize - 1),
+        }
+    }
+
+    pub fn render(&self, err: &Error) -> String {
+        let mut out = format!(
+            "{}{}\n",
+            self.paint(RED, &format!("error[{}]", err.code)),
+            self.paint(BOLD, &format!(": {}", err.message))
+        );
+
+        let mut annotations: Vec<Annotation> = err
+            .span
+            .iter()
+            .map(|span| Annotation {
+                span: *span,
+                label: None,
+            })
+            .chain(err.labels.iter().map(|(span, label)| Annotation {
+                span: *span,
+                label: Some(label),
+            }))
+            .filter(|a| self.line(a.span).is_some())
+            .collect();
+
+        let width = annotations
+            .iter()
+            .map(|a| a.span.lo.line.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let pad = " ".repeat(width);
+        let gutter = self.paint(BLUE, "|");
+
+        if let Some(first) = annotations.first() {
+            out.push_str(&format!(
+                "{}{} {}:{}:{}\n",
+                pad,
+                self.paint(BLUE, "-->"),
+                self.path,
+                first.span.lo.line,
+                first.span.lo.column + 1
+            ));
+            out.push_str(&format!("{} {}\n", pad, gutter));
+
+            annotations.sort_by_key(|a| (a.span.lo.line, a.span.lo.column));
+            let mut last_line = None;
+            for a in &annotations {
+                let line = a.span.lo.line;
+                let text = self.line(a.span).unwrap_or_default();
+                if last_line != Some(line) {
+                    if let Some(last) = last_line {
+                        if line > last + 1 {
+                            out.push_str(&format!("{}\n", self.paint(BLUE, "...")));
+                        }
+                    }
+                    let number = format!("{:>width$}", line, width = width);
+                    out.push_str(&format!(
+                        "{} {} {}\n",
+                        self.paint(BLUE, &number),
+                        gutter,
+                        text
+                    ));
+                    last_line = Some(line);
+                }
+
+                // spans covering several lines are underlined to the end of the first line
+                let lo = a.span.lo.column as usize;
+                let hi = match a.span.hi.line == line {
+                    true => a.span.hi.column as usize,
+                    false => text.chars().count(),
+                };
+                let (marker, style) = match a.is_primary() {
+                    true => ("^", RED),
+                    false => ("-", BLUE),
+                };
+                let mut underline = marker.repeat(hi.saturating_sub(lo).max(1));
+                if let Some(label) = a.label {
+                    underline = format!("{} {}", underline, label);
+                }
+                out.push_str(&format!(
+                    "{} {} {}{}\n",
+                    pad,
+                    gutter,
+                    " ".repeat(lo),
+                    self.paint(style, &underline)
+                ));
+            }
+            if !err.notes.is_empty() || err.help.is_some() {
+                out.push_str(&format!("{} {}\n", pad, gutter));
+            }
+        }
+
+        for note in &err.notes {
+            out.push_str(&format!(
+                "{} = {}: {}\n",
+                pad,
+                self.paint(BOLD, "note"),
+                note
+            ));
+        }
+        if let Some(help) = &err.help {
+            out.push_str(&format!(
+                "{} = {}: {}\n",
+                pad,
+                self.paint(BOLD, "help"),
+                help
+            ));
+        }
+        out
+    }
+
+    // byte offset of a (1-indexed) line and (0-indexed, in chars) column
+    fn offset(&self, lc: LineColumn) -> usize {
+        let mut offset = 0;
+        for (i, line) in self.source.split_inclusive('\n').enumerate() {
+            if i + 1 == lc.line as usize {
+                return offset
+                    + line
+                        .char_indices()
+                        .nth(lc.column as usize)
+                        .map_or(line.len(), |(b, _)| b);
+            }
+            offset += line.len();
+        }
+        offset
+    }
+
+    fn json_span(&self, span: Span, label: Option<&str>) -> String {
+        format!(
+            "{{\"file\":{},\"byte_start\":{},\"byte_end\":{},\"line_start\":{},\"column_start\":{},\"line_end\":{},\"column_end\":{},\"is_primary\":{},\"label\":{}}}",
+            json_str(self.path),
+            self.offset(span.lo),
+            self.offset(span.hi),
+            span.lo.line,
+            span.lo.column + 1,
+            span.hi.line,
+            span.hi.column + 1,
+            label.is_none(),
+            label.map_or("null".to_string(), json_str)
+        )
+    }
+
+    // One JSON object (on a single line) per diagnostic, columns are 1-indexed
+    pub fn render_json(&self, err: &Error) -> String {
+        let spans: Vec<String> = err
+            .span
+            .iter()
+            .map(|span| (*span, None))
+            .chain(err.labels.iter().map(|(span, l)| (*span, Some(l.as_str()))))
+            .filter(|(span, _)| self.line(*span).is_some())
+            .map(|(span, label)| self.json_span(span, label))
+            .collect();
+        let children: Vec<String> = err
+            .notes
+            .iter()
+            .map(|note| ("note", note))
+            .chain(err.help.iter().map(|help| ("help", help)))
+            .map(|(severity, message)| {
+                format!(
+                    "{{\"severity\":\"{}\",\"message\":{},\"spans\":[]}}",
+                    severity,
+                    json_str(message)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"code\":\"{}\",\"severity\":\"error\",\"kind\":\"{}\",\"message\":{},\"spans\":[{}],\"children\":[{}]}}",
+            err.code,
+            err.kind,
+            json_str(&err.message),
+            spans.join(","),
+            children.join(",")
+        )
+    }
+
+    pub fn emit(&self, err: &Error) {
+        match self.format {
+            ErrorFormat::Human => eprint!("{}", self.render(err)),
+            ErrorFormat::Json => eprintln!("{}", self.render_json(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColorChoice, Emitter, ErrorFormat};
+    use crate::error::{Error, LineColumn, Span, MISMATCHED_TYPES};
+
+    fn span(line: u32, lo: u32, hi: u32) -> Span {
+        Span {
+            lo: LineColumn { line, column: lo },
+            hi: LineColumn { line, column: hi },
+        }
+    }
+
+    const SOURCE: &str = "fn main() {\n    let b = true;\n\n    let a = b + 1;\n}\n";
+
+    #[test]
+    fn render_primary() {
+        let emitter = Emitter::new("main.rs", SOURCE, ColorChoice::Never, ErrorFormat::Human);
+        let err = Error::type_err(MISMATCHED_TYPES, "mismatched types")
+            .with_span(span(4, 12, 17))
+            .with_note("in binary operation");
+        assert_eq!(
+            emitter.render(&err),
+            "error[T0001]: mismatched types
+ --> main.rs:4:13
+  |
+4 |     let a = b + 1;
+  |             ^^^^^
+  |
+  = note: in binary operation
+"
+        );
+    }
+
+    #[test]
+    fn render_labels() {
+        let emitter = Emitter::new("main.rs", SOURCE, ColorChoice::Never, ErrorFormat::Human);
+        let err = Error::type_err(MISMATCHED_TYPES, "mismatched types")
+            .with_span(span(4, 12, 13))
+            .with_label(span(2, 12, 16), "defined here");
+        assert_eq!(
+            emitter.render(&err),
+            "error[T0001]: mismatched types
+ --> main.rs:4:13
+  |
+2 |     let b = true;
+  |             ---- defined here
+...
+4 |     let a = b + 1;
+  |             ^
+"
+        );
+    }
+
+    #[test]
+    fn render_no_span() {
+        let emitter = Emitter::new("main.rs", SOURCE, ColorChoice::Never, ErrorFormat::Human);
+        let err = Error::type_err(MISMATCHED_TYPES, "mismatched types").with_help("fix it");
+        assert_eq!(
+            emitter.render(&err),
+            "error[T0001]: mismatched types\n = help: fix it\n"
+        );
+    }
+
+    #[test]
+    fn render_color() {
+        let emitter = Emitter::new("main.rs", SOURCE, ColorChoice::Always, ErrorFormat::Human);
+        let err = Error::type_err(MISMATCHED_TYPES, "mismatched types");
+        assert!(emitter.render(&err).starts_with("\x1b[1;31merror[T0001]"));
+    }
+
+    #[test]
+    fn render_json() {
+        let emitter = Emitter::new("main.rs", SOURCE, ColorChoice::Always, ErrorFormat::Json);
+        let err = Error::type_err(MISMATCHED_TYPES, "expected `i32`, got \"bool\"")
+            .with_span(span(4, 12, 13))
+            .with_label(span(2, 12, 16), "defined here")
+            .with_note("in binary operation");
+        assert_eq!(
+            emitter.render_json(&err),
+            r#"{"code":"T0001","severity":"error","kind":"type","message":"expected `i32`, got \"bool\"","spans":[{"file":"main.rs","byte_start":43,"byte_end":44,"line_start":4,"column_start":13,"line_end":4,"column_end":14,"is_primary":true,"label":null},{"file":"main.rs","byte_start":24,"byte_end":28,"line_start":2,"column_start":13,"line_end":2,"column_end":17,"is_primary":false,"label":"defined here"}],"children":[{"severity":"note","message":"in binary operation","spans":[]}]}"#
+        );
+    }
+}
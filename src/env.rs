@@ -1,23 +1,69 @@
 // Type generic environment
 
-use crate::error::Error;
-use crate::{ast::FnDeclaration, intrinsics::Intrinsic};
-use std::collections::{HashMap, HashSet};
+use crate::error::{Error, Span, DANGLING_REFERENCE, DUPLICATE_FUNCTION, DUPLICATE_TYPE};
+use crate::{
+    ast::{EnumDeclaration, Field, FnDeclaration, StructDeclaration, Type, Variant},
+    intrinsics::Intrinsic,
+};
+use std::collections::HashMap;
 use std::fmt::Debug;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+// Where a referenced value is allocated
+#[derive(Debug, Clone, PartialEq)]
+enum Base {
+    // the offset in the stack of a scope, as pushed when the reference was
+    // taken (the scope index is reused by later scopes)
+    Stack(usize, usize, usize),
+    // a heap block, as allocated when the reference was taken
+    Heap(usize, usize),
+}
+
+// A reference to a value allocated in a scope, or to an element of a heap
+// block, or to a part of it, following the path of field indices (e.g.,
+// `&s.a.b`). For the heap, the first index is that of the element.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Ref {
-    scope_index: usize,
-    scope_offset: usize,
+    base: Base,
+    path: Vec<usize>,
 }
 
 impl Ref {
     pub fn new(scope_index: usize, scope_offset: usize) -> Self {
+        Ref::stack(scope_index, scope_offset, 0)
+    }
+
+    fn stack(scope_index: usize, scope_offset: usize, generation: usize) -> Self {
         Ref {
-            scope_index,
-            scope_offset,
+            base: Base::Stack(scope_index, scope_offset, generation),
+            path: vec![],
         }
     }
+
+    // heap values belong to no scope, as if allocated in the outermost one
+    pub fn scope_index(&self) -> usize {
+        match self.base {
+            Base::Stack(scope_index, _, _) => scope_index,
+            Base::Heap(..) => 0,
+        }
+    }
+
+    // a reference to the field with index `i` of the referenced value
+    pub fn field(&self, i: usize) -> Ref {
+        let mut r = self.clone();
+        r.path.push(i);
+        r
+    }
+}
+
+// Values with fields, the parts a `Ref` may point into
+pub trait Fields: Sized {
+    fn field(&self, _i: usize) -> Option<&Self> {
+        None
+    }
+
+    fn field_mut(&mut self, _i: usize) -> Option<&mut Self> {
+        None
+    }
 }
 
 type Stack<T> = Vec<T>;
@@ -26,81 +72,145 @@ type Var = HashMap<String, Ref>;
 pub struct Scope<T> {
     stack: Stack<T>,
     var: Var,
+    // distinguishes the scopes pushed at the same index
+    generation: usize,
 }
 
 impl<T> Scope<T> {
-    fn new() -> Self {
+    fn new(generation: usize) -> Self {
         Scope {
             stack: Stack::new(),
             var: Var::new(),
+            generation,
         }
     }
 }
 
 type Scopes<T> = Vec<Scope<T>>;
+
+// A growable block of the heap, reallocated (as a new generation) when
+// pushing beyond its capacity
+#[derive(Debug, Clone)]
+struct Block<T> {
+    elems: Vec<T>,
+    cap: usize,
+    generation: usize,
+}
+
+// The scoped stacks, and the heap for values outliving their scope
 #[derive(Debug, Clone)]
-pub struct VarEnv<T>(Scopes<T>);
+pub struct VarEnv<T> {
+    scopes: Scopes<T>,
+    heap: Vec<Block<T>>,
+    // the first scope of each function call, the variables of the caller
+    // are out of scope in the callee (but reachable by reference)
+    frames: Vec<usize>,
+    // the number of scopes pushed so far
+    generations: usize,
+}
 
 impl<T> VarEnv<T>
 where
-    T: Clone,
+    T: Clone + Fields,
 {
     pub fn new() -> Self {
-        VarEnv(Scopes::new())
+        VarEnv {
+            scopes: Scopes::new(),
+            heap: vec![],
+            frames: vec![],
+            generations: 0,
+        }
     }
 
     pub fn push_scope(&mut self) {
-        self.0.push(Scope::new());
+        self.scopes.push(Scope::new(self.generations));
+        self.generations += 1;
     }
 
     pub fn pop_scope(&mut self) {
-        self.0.pop();
+        self.scopes.pop();
+    }
+
+    // a scope for the parameters of a call, hiding the scopes of the caller
+    pub fn push_frame(&mut self) {
+        self.frames.push(self.scopes.len());
+        self.push_scope();
+    }
+
+    pub fn pop_frame(&mut self) {
+        self.pop_scope();
+        self.frames.pop();
     }
 
     // allocation in current scope
     // re-use current allocation if identifier shadows old binding
     pub fn alloc(&mut self, id: &str, v: T) -> Ref {
-        let (scope_index, scope) = self.0.iter_mut().enumerate().last().unwrap();
+        let scope_index = self.scopes.len() - 1;
+        let scope = &mut self.scopes[scope_index];
         match scope.var.get(id).cloned() {
             Some(r) => {
-                self.set_ref(r, v);
+                self.set_ref(r.clone(), v);
                 r
             }
             None => {
                 scope.stack.push(v);
-                let (scope_offset, _) = scope.stack.iter().enumerate().last().unwrap();
-                let r = Ref {
-                    scope_index,
-                    scope_offset,
-                };
-                scope.var.insert(id.to_owned(), r);
+                let r = Ref::stack(scope_index, scope.stack.len() - 1, scope.generation);
+                scope.var.insert(id.to_owned(), r.clone());
                 r
             }
         }
     }
 
     pub fn stack_val(&mut self, v: T) -> Ref {
-        let (scope_index, scope) = self.0.iter_mut().enumerate().last().unwrap();
-
+        let scope_index = self.scopes.len() - 1;
+        let scope = &mut self.scopes[scope_index];
         scope.stack.push(v);
-        let (offset, _) = scope.stack.iter().enumerate().last().unwrap();
-        Ref {
-            scope_index,
-            scope_offset: offset,
-        }
+        Ref::stack(scope_index, scope.stack.len() - 1, scope.generation)
+    }
+
+    // a reference to the slot `offset` of the innermost scope
+    pub fn local(&self, offset: usize) -> Ref {
+        let scope_index = self.scopes.len() - 1;
+        Ref::stack(scope_index, offset, self.scopes[scope_index].generation)
     }
 
     pub fn set_ref(&mut self, r: Ref, v: T) {
-        self.0[r.scope_index].stack[r.scope_offset] = v;
+        self.try_set_ref(r, v).expect("ICE - dangling reference");
+    }
+
+    // assignment through a reference may fail, as the element (or the
+    // variable) may be gone
+    pub fn try_set_ref(&mut self, r: Ref, v: T) -> Result<(), Error> {
+        let (mut t, path) = match r.base {
+            Base::Stack(scope_index, scope_offset, generation) => {
+                self.in_scope(scope_index, scope_offset, generation)?;
+                (
+                    &mut self.scopes[scope_index].stack[scope_offset],
+                    &r.path[..],
+                )
+            }
+            Base::Heap(addr, generation) => {
+                let i = self.live(addr, generation, &r.path)?;
+                (&mut self.heap[addr].elems[i], &r.path[1..])
+            }
+        };
+        for i in path {
+            t = t
+                .field_mut(*i)
+                .expect("ICE - reference to a field of a value without fields");
+        }
+        *t = v;
+        Ok(())
     }
 
     pub fn get(&self, id: &str) -> Option<T> {
         let r = self.get_ref(id)?;
-        Some(self.0[r.scope_index].stack[r.scope_offset].clone())
+        Some(self.de_ref(r))
     }
 
     pub fn get_ref(&self, id: &str) -> Option<Ref> {
-        for scope in self.0.iter().rev() {
+        let frame = self.frames.last().copied().unwrap_or(0);
+        for scope in self.scopes[frame..].iter().rev() {
             if let Some(r) = scope.var.get(id) {
                 return Some(r.clone());
             }
@@ -109,8 +219,222 @@ where
     }
 
     pub fn de_ref(&self, r: Ref) -> T {
-        self.0[r.scope_index].stack[r.scope_offset].clone()
+        self.try_de_ref(r).expect("ICE - dangling reference")
+    }
+
+    pub fn try_de_ref(&self, r: Ref) -> Result<T, Error> {
+        let (mut t, path) = match r.base {
+            Base::Stack(scope_index, scope_offset, generation) => {
+                self.in_scope(scope_index, scope_offset, generation)?;
+                (&self.scopes[scope_index].stack[scope_offset], &r.path[..])
+            }
+            Base::Heap(addr, generation) => {
+                let i = self.live(addr, generation, &r.path)?;
+                (&self.heap[addr].elems[i], &r.path[1..])
+            }
+        };
+        for i in path {
+            t = t
+                .field(*i)
+                .expect("ICE - reference to a field of a value without fields");
+        }
+        Ok(t.clone())
+    }
+
+    // whether the referenced slot is still allocated, by the same scope
+    fn in_scope(&self, scope_index: usize, offset: usize, generation: usize) -> Result<(), Error> {
+        match self.scopes.get(scope_index) {
+            Some(scope) if scope.generation == generation && offset < scope.stack.len() => Ok(()),
+            _ => Err(Error::runtime(
+                DANGLING_REFERENCE,
+                "use of a reference to a variable that is out of scope",
+            )),
+        }
+    }
+
+    // the index of the referenced element, if neither reallocated nor popped
+    fn live(&self, addr: usize, generation: usize, path: &[usize]) -> Result<usize, Error> {
+        let block = &self.heap[addr];
+        if block.generation != generation {
+            return Err(Error::runtime(
+                DANGLING_REFERENCE,
+                "use of a reference into a vector reallocated by a `push`",
+            ));
+        }
+        let i = path[0];
+        if i >= block.elems.len() {
+            return Err(Error::runtime(
+                DANGLING_REFERENCE,
+                "use of a reference to an element removed by a `pop`",
+            ));
+        }
+        Ok(i)
+    }
+
+    // a new heap block, with room for just the given elements
+    pub fn heap_alloc(&mut self, elems: Vec<T>) -> usize {
+        self.heap.push(Block {
+            cap: elems.len(),
+            elems,
+            generation: 0,
+        });
+        self.heap.len() - 1
+    }
+
+    pub fn heap_elems(&self, addr: usize) -> &[T] {
+        &self.heap[addr].elems
+    }
+
+    // a reference to the element `i` of the current generation of the block
+    pub fn heap_ref(&self, addr: usize, i: usize) -> Ref {
+        Ref {
+            base: Base::Heap(addr, self.heap[addr].generation),
+            path: vec![i],
+        }
+    }
+
+    // a full block is reallocated, doubling its capacity (at least 4)
+    pub fn heap_push(&mut self, addr: usize, v: T) {
+        let block = &mut self.heap[addr];
+        if block.elems.len() == block.cap {
+            block.cap = std::cmp::max(4, 2 * block.cap);
+            block.generation += 1;
+        }
+        block.elems.push(v);
+    }
+
+    pub fn heap_pop(&mut self, addr: usize) -> Option<T> {
+        self.heap[addr].elems.pop()
+    }
+
+    // the visible bindings (shadowed ones excluded), sorted by name
+    pub fn bindings(&self) -> Vec<(String, Ref)> {
+        let mut bindings: HashMap<&String, Ref> = HashMap::new();
+        for scope in &self.scopes {
+            bindings.extend(scope.var.iter().map(|(id, r)| (id, r.clone())));
+        }
+        let mut bindings: Vec<_> = bindings
+            .into_iter()
+            .map(|(id, r)| (id.clone(), r))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+
+    // number of currently active scopes
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    // all allocated values, outermost scope first
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.scopes.iter().flat_map(|scope| scope.stack.iter())
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.scopes
+            .iter_mut()
+            .flat_map(|scope| scope.stack.iter_mut())
+    }
+}
+
+// The struct declarations in scope
+#[derive(Debug, Clone)]
+pub struct StructEnv(pub HashMap<String, StructDeclaration>);
+
+impl StructEnv {
+    fn new() -> Self {
+        StructEnv(HashMap::new())
     }
+
+    // the index and declared type of the field `id` of struct `s`
+    pub fn field(&self, s: &str, id: &str) -> Option<(usize, &Field)> {
+        self.0
+            .get(s)?
+            .fields
+            .iter()
+            .enumerate()
+            .find(|(_, f)| f.id == id)
+    }
+}
+
+// The enum declarations in scope
+#[derive(Debug, Clone)]
+pub struct EnumEnv(pub HashMap<String, EnumDeclaration>);
+
+impl EnumEnv {
+    fn new() -> Self {
+        EnumEnv(HashMap::new())
+    }
+
+    // the index and declaration of the variant `id` of enum `e`
+    pub fn variant(&self, e: &str, id: &str) -> Option<(usize, &Variant)> {
+        self.0
+            .get(e)?
+            .variants
+            .iter()
+            .enumerate()
+            .find(|(_, v)| v.id == id)
+    }
+}
+
+// The instances of generic functions made by the type checker, their
+// declarations and, by the span of each call site, the instance called.
+// `pending` are the calls in the function being checked, whose instances
+// are checked at its end, and `depth` is the number of instances being
+// checked, one inside the other.
+#[derive(Debug, Clone, Default)]
+pub struct Instances {
+    pub fns: Vec<FnDeclaration>,
+    pub calls: Vec<(Span, String)>,
+    pub pending: Vec<Pending>,
+    pub depth: usize,
+}
+
+// A call of the generic function `f`, with type arguments that may hold the
+// types of integer literals not yet inferred, and the functions and types
+// in scope at the call
+#[derive(Debug, Clone)]
+pub struct Pending {
+    pub span: Span,
+    pub f: FnDeclaration,
+    pub targs: Vec<Type>,
+    pub fns: FnEnv,
+    pub structs: StructEnv,
+    pub enums: EnumEnv,
+}
+
+// The integer literals without a suffix seen by the type checker, by the
+// span of each, with its value and type variable. A variable is bound to
+// the integer type it is unified with, or to another variable (a
+// `Type::Integer`), and is `i32` if it is never bound.
+#[derive(Debug, Clone, Default)]
+pub struct Literals {
+    pub lits: Vec<(Span, i128, usize)>,
+    pub vars: Vec<Option<Type>>,
+}
+
+// A move out of a variable, or out of a field of it (`fields` is the path,
+// e.g. `["a", "0"]` for `s.a.0`), of a value of type `ty`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Move {
+    pub var: Ref,
+    pub fields: Vec<String>,
+    pub ty: Type,
+    pub span: Span,
+}
+
+// The moves seen by the type checker. For each enclosing loop, the moves
+// leaving it by `break` and those reaching its next iteration by `continue`.
+// The variables captured by a closure cannot be moved out of in its body.
+// The spans of all moves are kept for the borrow checker, where a move
+// invalidates the loans on the place moved out of.
+#[derive(Debug, Clone, Default)]
+pub struct Moves {
+    pub moved: Vec<Move>,
+    pub loops: Vec<(Vec<Move>, Vec<Move>)>,
+    pub captured: Vec<Ref>,
+    pub spans: Vec<Span>,
 }
 
 #[derive(Clone)]
@@ -132,12 +456,17 @@ impl FnEnv {
     }
 
     pub fn add_functions_unique(&mut self, new_fns: Vec<FnDeclaration>) -> Result<(), Error> {
-        let mut hm = HashSet::new();
+        let mut hm = HashMap::new();
         for f in new_fns.clone() {
             match hm.get(&f.id) {
-                Some(_) => Err(&format!("Function {} already defined", f.id))?,
+                Some(span) => Err(Error::type_err(
+                    DUPLICATE_FUNCTION,
+                    format!("Function {} already defined", f.id),
+                )
+                .with_span(f.span)
+                .with_label(*span, "previous definition here"))?,
                 None => {
-                    hm.insert(f.id.clone());
+                    hm.insert(f.id.clone(), f.span);
                 }
             };
         }
@@ -149,6 +478,32 @@ impl FnEnv {
     }
 }
 
+// e.g. "function `f` takes 2 arguments but 1 argument was supplied"
+pub fn arity_message(id: &str, params: usize, args: usize) -> String {
+    let arguments = |n: usize| match n {
+        1 => "argument",
+        _ => "arguments",
+    };
+    format!(
+        "function `{}` takes {} {} but {} {} {} supplied",
+        id,
+        params,
+        arguments(params),
+        args,
+        arguments(args),
+        if args == 1 { "was" } else { "were" }
+    )
+}
+
+// Non-local control flow, pending until the targeted loop (or for
+// `return` the enclosing function call) is reached
+#[derive(Debug, Clone, PartialEq)]
+pub enum Flow<T> {
+    Break(Option<String>, T),
+    Continue(Option<String>),
+    Return(T),
+}
+
 #[derive(Debug, Clone)]
 pub struct Env<T>
 where
@@ -156,25 +511,84 @@ where
 {
     pub v: VarEnv<T>,
     pub f: FnEnv,
+    pub s: StructEnv,
+    pub e: EnumEnv,
+    // number of active function calls
+    pub calls: usize,
+    // the enclosing loops, innermost last, with their label, keyword and
+    // the type of their break values (`None` for `while` and `for` loops)
+    pub loops: Vec<(Option<String>, &'static str, Option<T>)>,
+    // the enclosing function and its return type
+    pub ret: Option<(String, T)>,
+    // a `break` or `continue` leaving the current evaluation
+    pub flow: Option<Flow<T>>,
+    // the generic functions instantiated (by the type checker)
+    pub inst: Instances,
+    // the types of integer literals (by the type checker)
+    pub lits: Literals,
+    // the variables moved out of (by the type checker)
+    pub moves: Moves,
 }
 
 impl<T> Env<T>
 where
-    T: Clone,
+    T: Clone + Fields,
 {
     pub fn new() -> Self {
         Env {
             v: VarEnv::new(),
             f: FnEnv::new(),
+            s: StructEnv::new(),
+            e: EnumEnv::new(),
+            calls: 0,
+            loops: vec![],
+            ret: None,
+            flow: None,
+            inst: Instances::default(),
+            lits: Literals::default(),
+            moves: Moves::default(),
         }
     }
+
+    // structs and enums share one namespace
+    pub fn add_types_unique(
+        &mut self,
+        structs: Vec<StructDeclaration>,
+        enums: Vec<EnumDeclaration>,
+    ) -> Result<(), Error> {
+        let mut hm = HashMap::new();
+        let ids = structs
+            .iter()
+            .map(|s| (&s.id, s.span))
+            .chain(enums.iter().map(|e| (&e.id, e.span)));
+        for (id, span) in ids {
+            if let Some(previous) = hm.insert(id.clone(), span) {
+                Err(Error::type_err(
+                    DUPLICATE_TYPE,
+                    format!("the name `{}` is defined multiple times", id),
+                )
+                .with_span(span)
+                .with_label(previous, "previous definition here"))?
+            }
+        }
+
+        for s in structs {
+            self.s.0.insert(s.id.clone(), s);
+        }
+        for e in enums {
+            self.e.0.insert(e.id.clone(), e);
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::VarEnv;
+    use super::{Fields, VarEnv};
     use crate::ast::*;
 
+    impl Fields for Literal {}
+
     #[test]
     fn t() {
         let mut env = VarEnv::new();
@@ -208,4 +622,38 @@ mod tests {
         let v = env.get("a");
         println!("v {:?}", v);
     }
+
+    #[test]
+    fn bindings() {
+        let mut env = VarEnv::new();
+        env.push_scope();
+        env.alloc("b", Literal::Int(1));
+        env.alloc("a", Literal::Int(2));
+        env.push_scope();
+        let inner = env.alloc("b", Literal::Int(3));
+
+        let bindings = env.bindings();
+        let names: Vec<_> = bindings.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(bindings[1].1, inner);
+    }
+
+    #[test]
+    fn heap() {
+        let mut env = VarEnv::new();
+        let addr = env.heap_alloc(vec![Literal::Int(1)]);
+        let r = env.heap_ref(addr, 0);
+        env.try_set_ref(r.clone(), Literal::Int(2)).unwrap();
+        assert_eq!(env.try_de_ref(r.clone()).unwrap(), Literal::Int(2));
+        // full, the block is reallocated
+        env.heap_push(addr, Literal::Int(3));
+        assert!(env.try_de_ref(r).is_err());
+        let r = env.heap_ref(addr, 1);
+        env.heap_push(addr, Literal::Int(4));
+        assert_eq!(env.try_de_ref(r.clone()).unwrap(), Literal::Int(3));
+        assert_eq!(env.heap_pop(addr), Some(Literal::Int(4)));
+        env.heap_pop(addr);
+        assert!(env.try_de_ref(r).is_err());
+        assert_eq!(env.heap_elems(addr), [Literal::Int(2)]);
+    }
 }
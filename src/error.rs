@@ -1,3 +1,248 @@
 // error
 
-pub type Error = String;
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorKind {
+    Parse,
+    Type,
+    Borrow,
+    Runtime,
+    Ice,
+}
+
+// Stable error codes, the leading letter gives the kind.
+// parse errors
+pub const SYNTAX_ERROR: &str = "P0001";
+// type errors
+pub const MISMATCHED_TYPES: &str = "T0001";
+pub const UNRESOLVED_NAME: &str = "T0002";
+pub const DUPLICATE_FUNCTION: &str = "T0003";
+pub const IMMUTABLE_ASSIGN: &str = "T0004";
+pub const INVALID_DEREF: &str = "T0005";
+pub const INVALID_REF: &str = "T0006";
+pub const MAIN_NOT_FOUND: &str = "T0007";
+pub const WRONG_ARG_COUNT: &str = "T0008";
+pub const UNDECLARED_LABEL: &str = "T0009";
+pub const OUTSIDE_OF_LOOP: &str = "T0010";
+pub const BREAK_WITH_VALUE: &str = "T0011";
+pub const RETURN_OUTSIDE_FN: &str = "T0012";
+pub const DUPLICATE_TYPE: &str = "T0013";
+pub const MISSING_FIELD: &str = "T0014";
+pub const UNKNOWN_FIELD: &str = "T0015";
+pub const DUPLICATE_FIELD: &str = "T0016";
+pub const UNKNOWN_VARIANT: &str = "T0017";
+pub const NON_EXHAUSTIVE: &str = "T0018";
+pub const UNREACHABLE_PATTERN: &str = "T0019";
+pub const DUPLICATE_BINDING: &str = "T0020";
+pub const TYPE_ANNOTATIONS_NEEDED: &str = "T0021";
+pub const LITERAL_OUT_OF_RANGE: &str = "T0022";
+pub const USE_OF_MOVED_VALUE: &str = "T0023";
+pub const MOVE_OUT_OF_BORROW: &str = "T0024";
+pub const RECURSION_LIMIT: &str = "T0025";
+// borrow errors
+pub const INVALIDATED_BORROW: &str = "B0001";
+pub const SHARED_MUTATION: &str = "B0002";
+pub const DOES_NOT_LIVE_LONG_ENOUGH: &str = "B0003";
+// runtime errors
+pub const VALUE_MISMATCH: &str = "R0001";
+pub const UNBOUND_VARIABLE: &str = "R0002";
+pub const UNBOUND_FUNCTION: &str = "R0003";
+pub const NOT_A_PLACE: &str = "R0004";
+pub const NOT_A_REFERENCE: &str = "R0005";
+pub const NO_ENTRY_POINT: &str = "R0006";
+pub const STACK_OVERFLOW: &str = "R0007";
+pub const ARITY_MISMATCH: &str = "R0008";
+pub const DIVIDE_BY_ZERO: &str = "R0009";
+pub const LOOP_NOT_FOUND: &str = "R0010";
+pub const NO_MATCHING_ARM: &str = "R0011";
+pub const INDEX_OUT_OF_BOUNDS: &str = "R0012";
+pub const DANGLING_REFERENCE: &str = "R0013";
+pub const EMPTY_POP: &str = "R0014";
+pub const ARITHMETIC_OVERFLOW: &str = "R0015";
+pub const LITERAL_OVERFLOW: &str = "R0016";
+// internal compiler errors
+pub const ICE: &str = "I0001";
+
+// Source position, line is 1-indexed and column 0-indexed (as in proc_macro2)
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct LineColumn {
+    pub line: u32,
+    pub column: u32,
+}
+
+impl From<proc_macro2::LineColumn> for LineColumn {
+    fn from(lc: proc_macro2::LineColumn) -> Self {
+        LineColumn {
+            line: lc.line as u32,
+            column: lc.column as u32,
+        }
+    }
+}
+
+// Source range, from `lo` up to (not including) `hi`
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Span {
+    pub lo: LineColumn,
+    pub hi: LineColumn,
+}
+
+impl Span {
+    // the range covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            lo: self.lo,
+            hi: other.hi,
+        }
+    }
+}
+
+impl From<proc_macro2::Span> for Span {
+    fn from(span: proc_macro2::Span) -> Self {
+        Span {
+            lo: span.start().into(),
+            hi: span.end().into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub code: &'static str,
+    pub message: String,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+    pub span: Option<Span>,
+    // secondary spans, e.g., "first borrowed here"
+    pub labels: Vec<(Span, String)>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, code: &'static str, message: impl Into<String>) -> Self {
+        Error {
+            kind,
+            code,
+            message: message.into(),
+            notes: vec![],
+            help: None,
+            span: None,
+            labels: vec![],
+        }
+    }
+
+    pub fn parse(message: impl Into<String>) -> Self {
+        Error::new(ErrorKind::Parse, SYNTAX_ERROR, message)
+    }
+
+    pub fn type_err(code: &'static str, message: impl Into<String>) -> Self {
+        Error::new(ErrorKind::Type, code, message)
+    }
+
+    pub fn borrow(code: &'static str, message: impl Into<String>) -> Self {
+        Error::new(ErrorKind::Borrow, code, message)
+    }
+
+    pub fn runtime(code: &'static str, message: impl Into<String>) -> Self {
+        Error::new(ErrorKind::Runtime, code, message)
+    }
+
+    pub fn ice(message: impl Into<String>) -> Self {
+        Error::new(ErrorKind::Ice, ICE, message)
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, label: impl Into<String>) -> Self {
+        self.labels.push((span, label.into()));
+        self
+    }
+
+    // keeps the innermost span, if already set
+    pub fn or_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+}
+
+impl From<syn::Error> for Error {
+    fn from(err: syn::Error) -> Self {
+        Error::parse(err.to_string()).with_span(err.span().into())
+    }
+}
+
+impl From<proc_macro2::LexError> for Error {
+    fn from(err: proc_macro2::LexError) -> Self {
+        Error::parse(err.to_string()).with_span(err.span().into())
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ErrorKind::Parse => "parse",
+            ErrorKind::Type => "type",
+            ErrorKind::Borrow => "borrow",
+            ErrorKind::Runtime => "runtime",
+            ErrorKind::Ice => "internal compiler",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// error[T0001]: message
+//   = note: ...
+//   = help: ...
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error[{}]: {}", self.code, self.message)?;
+        for note in &self.notes {
+            write!(f, "\n  = note: {}", note)?;
+        }
+        if let Some(help) = &self.help {
+            write!(f, "\n  = help: {}", help)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_error() {
+        let err = Error::type_err(MISMATCHED_TYPES, "expected `i32`, found `bool`")
+            .with_note("in let statement")
+            .with_help("change the type");
+        assert_eq!(err.kind, ErrorKind::Type);
+        assert_eq!(
+            format!("{}", err),
+            "error[T0001]: expected `i32`, found `bool`\n  = note: in let statement\n  = help: change the type"
+        );
+    }
+
+    #[test]
+    fn from_syn_error() {
+        let ts: proc_macro2::TokenStream = "1".parse().unwrap();
+        let err: Error = syn::parse2::<syn::Ident>(ts).unwrap_err().into();
+        assert_eq!(err.kind, ErrorKind::Parse);
+        assert_eq!(err.code, SYNTAX_ERROR);
+        let span = err.span.unwrap();
+        assert_eq!((span.lo.line, span.lo.column), (1, 0));
+        assert_eq!((span.hi.line, span.hi.column), (1, 1));
+    }
+}
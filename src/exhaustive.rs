@@ -0,0 +1,302 @@
+// Exhaustiveness and usefulness of match arms, following Maranget,
+// "Warnings for pattern matching" (JFP 2007).
+//
+// The arms are the rows of a matrix of patterns, the columns are the
+// (sub-)values under test. A pattern is useful w.r.t. the rows above it
+// if it matches some value none of them matches. An arm is unreachable
+// if its pattern is not useful, the match is exhaustive if `_` is not.
+
+use crate::ast::{Literal, Pattern, PatternKind, Type};
+use crate::env::EnumEnv;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    // the index of a variant of the enum
+    Variant(usize),
+    // the only constructor of a tuple type
+    Tuple,
+    Lit(Literal),
+}
+
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+type Row = Vec<Pat>;
+
+// The constructors of a type, `None` if there are too many to list.
+// `!` and enums without variants have no constructors at all.
+fn ctors(ty: &Type, enums: &EnumEnv) -> Option<Vec<Ctor>> {
+    match ty {
+        Type::Bool => Some(vec![
+            Ctor::Lit(Literal::Bool(false)),
+            Ctor::Lit(Literal::Bool(true)),
+        ]),
+        Type::Unit => Some(vec![Ctor::Lit(Literal::Unit)]),
+        Type::Never => Some(vec![]),
+        Type::Tuple(_) => Some(vec![Ctor::Tuple]),
+        Type::Named(e) => enums
+            .0
+            .get(e)
+            .map(|decl| (0..decl.variants.len()).map(Ctor::Variant).collect()),
+        _ => None,
+    }
+}
+
+// The types of the fields of `c`, in declaration order
+fn field_types(c: &Ctor, ty: &Type, enums: &EnumEnv) -> Vec<Type> {
+    match (c, ty) {
+        (Ctor::Variant(i), Type::Named(e)) => enums.0[e].variants[*i]
+            .fields
+            .iter()
+            .map(|f| f.ty.clone())
+            .collect(),
+        (Ctor::Tuple, Type::Tuple(tys)) => tys.clone(),
+        _ => vec![],
+    }
+}
+
+fn lower(p: &Pattern, ty: &Type, enums: &EnumEnv) -> Pat {
+    match &p.kind {
+        PatternKind::Wild | PatternKind::Ident(..) => Pat::Wild,
+        PatternKind::Lit(l) => Pat::Ctor(Ctor::Lit(l.clone()), vec![]),
+        PatternKind::Variant(e, v, _, fields, _) => {
+            let (i, variant) = enums
+                .variant(e, v)
+                .expect("ICE - variant pattern not type checked");
+            // fields left out (by `..`) match anything
+            let args = variant
+                .fields
+                .iter()
+                .map(|f| match fields.iter().find(|(id, _)| *id == f.id) {
+                    Some((_, p)) => lower(p, &f.ty, enums),
+                    None => Pat::Wild,
+                })
+                .collect();
+            Pat::Ctor(Ctor::Variant(i), args)
+        }
+        PatternKind::Tuple(pats) => {
+            let tys = match ty {
+                Type::Tuple(tys) => tys,
+                _ => unreachable!("ICE - tuple pattern not type checked"),
+            };
+            let args = pats.iter().zip(tys).map(|(p, ty)| lower(p, ty, enums));
+            Pat::Ctor(Ctor::Tuple, args.collect())
+        }
+    }
+}
+
+// Back to the surface syntax, for the listing of missing patterns
+fn raise(p: &Pat, ty: &Type, enums: &EnumEnv) -> Pattern {
+    let kind = match (p, ty) {
+        (Pat::Wild, _) => PatternKind::Wild,
+        (Pat::Ctor(Ctor::Lit(l), _), _) => PatternKind::Lit(l.clone()),
+        (Pat::Ctor(Ctor::Variant(i), args), Type::Named(e)) => {
+            let variant = &enums.0[e].variants[*i];
+            let fields = variant
+                .fields
+                .iter()
+                .zip(args)
+                .map(|(f, p)| (f.id.clone(), raise(p, &f.ty, enums)))
+                .collect();
+            PatternKind::Variant(e.clone(), variant.id.clone(), variant.kind, fields, false)
+        }
+        (Pat::Ctor(Ctor::Tuple, args), Type::Tuple(tys)) => PatternKind::Tuple(
+            args.iter()
+                .zip(tys)
+                .map(|(p, ty)| raise(p, ty, enums))
+                .collect(),
+        ),
+        (Pat::Ctor(..), _) => unreachable!("ICE - constructor of another type"),
+    };
+    Pattern {
+        kind,
+        span: Default::default(),
+    }
+}
+
+// The rows starting with `c` (or `_`), with the arguments of `c` in its place
+fn specialize(rows: &[Row], c: &Ctor, arity: usize) -> Vec<Row> {
+    rows.iter()
+        .filter_map(|row| {
+            let mut args = match &row[0] {
+                Pat::Wild => vec![Pat::Wild; arity],
+                Pat::Ctor(d, args) if d == c => args.clone(),
+                Pat::Ctor(..) => return None,
+            };
+            args.extend_from_slice(&row[1..]);
+            Some(args)
+        })
+        .collect()
+}
+
+// The rows starting with `_`, without it
+fn default(rows: &[Row]) -> Vec<Row> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pat::Wild))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+// The constructors in the first column
+fn heads(rows: &[Row]) -> Vec<&Ctor> {
+    let mut heads = vec![];
+    for row in rows {
+        if let Pat::Ctor(c, _) = &row[0] {
+            if !heads.contains(&c) {
+                heads.push(c);
+            }
+        }
+    }
+    heads
+}
+
+// The constructors of the type, if all of them appear in the first column
+fn complete(rows: &[Row], ty: &Type, enums: &EnumEnv) -> Option<Vec<Ctor>> {
+    let all = ctors(ty, enums)?;
+    let heads = heads(rows);
+    match all.iter().all(|c| heads.contains(&c)) {
+        true => Some(all),
+        false => None,
+    }
+}
+
+fn useful(rows: &[Row], q: &[Pat], tys: &[Type], enums: &EnumEnv) -> bool {
+    if q.is_empty() {
+        return rows.is_empty();
+    }
+    // `c(args)` first, the arguments take its place in `q`
+    let useful_ctor = |c: &Ctor, args: Vec<Pat>| {
+        let mut c_tys = field_types(c, &tys[0], enums);
+        c_tys.extend_from_slice(&tys[1..]);
+        let arity = args.len();
+        let mut c_q = args;
+        c_q.extend_from_slice(&q[1..]);
+        useful(&specialize(rows, c, arity), &c_q, &c_tys, enums)
+    };
+    match &q[0] {
+        Pat::Ctor(c, args) => useful_ctor(c, args.clone()),
+        Pat::Wild => match complete(rows, &tys[0], enums) {
+            Some(all) => all.iter().any(|c| {
+                let arity = field_types(c, &tys[0], enums).len();
+                useful_ctor(c, vec![Pat::Wild; arity])
+            }),
+            None => useful(&default(rows), &q[1..], &tys[1..], enums),
+        },
+    }
+}
+
+// Value vectors (as patterns) matched by none of the rows. Unlike for
+// usefulness, every constructor is tried so that all of them are listed.
+fn missing(rows: &[Row], tys: &[Type], enums: &EnumEnv) -> Vec<Row> {
+    if rows.is_empty() {
+        return vec![vec![Pat::Wild; tys.len()]];
+    }
+    if tys.is_empty() {
+        return vec![];
+    }
+    let mut witnesses = vec![];
+    match ctors(&tys[0], enums) {
+        Some(all) => {
+            for c in all {
+                let mut c_tys = field_types(&c, &tys[0], enums);
+                let arity = c_tys.len();
+                c_tys.extend_from_slice(&tys[1..]);
+                for mut w in missing(&specialize(rows, &c, arity), &c_tys, enums) {
+                    let rest = w.split_off(arity);
+                    let mut row = vec![Pat::Ctor(c.clone(), w)];
+                    row.extend(rest);
+                    witnesses.push(row);
+                }
+            }
+        }
+        None => {
+            for w in missing(&default(rows), &tys[1..], enums) {
+                let mut row = vec![Pat::Wild];
+                row.extend(w);
+                witnesses.push(row);
+            }
+        }
+    }
+    witnesses
+}
+
+// The index of the first pattern matching only values matched by earlier ones
+pub fn unreachable(pats: &[&Pattern], ty: &Type, enums: &EnumEnv) -> Option<usize> {
+    let tys = std::slice::from_ref(ty);
+    let mut rows = vec![];
+    for (i, p) in pats.iter().enumerate() {
+        let row = vec![lower(p, ty, enums)];
+        if !useful(&rows, &row, tys, enums) {
+            return Some(i);
+        }
+        rows.push(row);
+    }
+    None
+}
+
+// Patterns for the values of type `ty` matched by none of `pats`
+pub fn missing_patterns(pats: &[&Pattern], ty: &Type, enums: &EnumEnv) -> Vec<Pattern> {
+    let rows: Vec<Row> = pats.iter().map(|p| vec![lower(p, ty, enums)]).collect();
+    missing(&rows, std::slice::from_ref(ty), enums)
+        .iter()
+        .map(|w| raise(&w[0], ty, enums))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{missing_patterns, unreachable};
+    use crate::ast::{EnumDeclaration, Pattern, Type};
+    use crate::env::EnumEnv;
+    use std::collections::HashMap;
+
+    fn check(pats: &[&str]) -> (Option<usize>, Vec<String>) {
+        let decls = [
+            "enum E { A, B(bool, F), C { x: i32 } }",
+            "enum F { X, Y }",
+        ];
+        let mut enums = EnumEnv(HashMap::new());
+        for decl in decls.iter() {
+            let decl: EnumDeclaration = syn::parse_str(decl).unwrap();
+            enums.0.insert(decl.id.clone(), decl);
+        }
+        let pats: Vec<Pattern> = pats.iter().map(|p| syn::parse_str(p).unwrap()).collect();
+        let pats: Vec<&Pattern> = pats.iter().collect();
+        let ty = Type::Named("E".to_string());
+        let missing = missing_patterns(&pats, &ty, &enums)
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        (unreachable(&pats, &ty, &enums), missing)
+    }
+
+    #[test]
+    fn test_exhaustive() {
+        let (unreachable, missing) = check(&["E::A", "E::B(true, _)", "E::B(false, F::X)", "x"]);
+        assert_eq!(unreachable, None);
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn test_missing() {
+        let (_, missing) = check(&["E::B(true, F::X)", "E::C { x: 1 }"]);
+        assert_eq!(
+            missing,
+            [
+                "E::A",
+                "E::B(false, _)",
+                "E::B(true, F::Y)",
+                "E::C { x: _ }"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let (unreachable, _) = check(&["E::B(_, F::X)", "E::B(b, ..)", "E::B(true, F::Y)"]);
+        assert_eq!(unreachable, Some(2));
+    }
+}
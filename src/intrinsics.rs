@@ -1,4 +1,5 @@
-use crate::ast::{Block, FnDeclaration, Mutable, Parameter, Parameters, Type};
+use crate::ast::{Block, FnDeclaration, Mutable, Parameter, Parameters, PatternKind, Type};
+use crate::error::Span;
 use regex::Regex;
 // Implementation of intrinsics for the vm
 use crate::ast::Literal;
@@ -7,23 +8,26 @@ pub fn vm_println() -> (FnDeclaration, Intrinsic) {
     (
         FnDeclaration {
             id: "println!".to_string(),
+            generics: vec![],
             parameters: Parameters(vec![
                 Parameter {
-                    mutable: Mutable(false),
-                    id: "str".to_string(),
-                    ty: Type::String,
+                    pat: PatternKind::Ident(Mutable(false), "str".to_string()).into(),
+                    ty: Type::str_ref(),
+                    span: Span::default(),
                 },
                 Parameter {
-                    mutable: Mutable(false),
-                    id: "i".to_string(),
+                    pat: PatternKind::Ident(Mutable(false), "i".to_string()).into(),
                     ty: Type::I32,
+                    span: Span::default(),
                 },
             ]),
             ty: None,
             body: Block {
                 statements: vec![],
                 semi: false,
+                span: Span::default(),
             },
+            span: Span::default(),
         },
         |lit_vec| {
             match &lit_vec[0] {
@@ -4,9 +4,14 @@
 // you should remove the above attributes.
 // Strive to keep your code free of warnings.
 
+// Diagnostics are large by design, and only built on the error path.
+#![allow(clippy::result_large_err)]
+
 // common definitions
 pub mod common;
 pub mod error;
+// rendering of errors
+pub mod diagnostics;
 
 // AST related
 pub mod ast;
@@ -21,9 +26,18 @@ pub mod intrinsics;
 
 // semantic analysis
 pub mod type_check;
+// exhaustiveness of match arms
+pub mod exhaustive;
 // natural interpretation
 pub mod vm;
 // borrow checking
 pub mod bc;
 
 // optional backend goes here..
+// lowering to bytecode
+pub mod bytecode;
+// stack machine for the bytecode
+pub mod stack_vm;
+
+// interactive use
+pub mod repl;
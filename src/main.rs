@@ -1,45 +1,136 @@
-use rnr::{ast::Prog, common::*, env::Env, type_check::Ty, vm::Val};
+use rnr::{
+    ast::Prog,
+    bc::Bc,
+    bytecode::compile,
+    common::*,
+    diagnostics::{ColorChoice, Emitter, ErrorFormat},
+    env::Env,
+    error::Error,
+    repl::Repl,
+    stack_vm::Machine,
+    type_check::Ty,
+    vm::Val,
+};
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use structopt::StructOpt;
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Backend {
+    Ast,
+    Bytecode,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ast" => Ok(Backend::Ast),
+            "bytecode" => Ok(Backend::Bytecode),
+            _ => Err(format!("invalid backend `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Interactive read-eval-print loop (`:help` lists the commands)
+    Repl,
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "rnr", about = "RNR Rust In Rust - Let's Rock'n Roll")]
 struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
     /// Input file
     #[structopt(parse(from_os_str))]
-    path: PathBuf,
+    path: Option<PathBuf>,
 
     /// Vm
     #[structopt(short, long)]
     vm: bool,
 
+    /// Interpreter used by the vm, walking the AST or running compiled bytecode
+    #[structopt(long, default_value = "ast", possible_values = &["ast", "bytecode"])]
+    backend: Backend,
+
     /// Type checking
     #[structopt(short, long)]
     type_check: bool,
+
+    /// Borrow checking
+    #[structopt(short, long)]
+    borrow_check: bool,
+
+    /// Coloring of error messages
+    #[structopt(long, global = true, default_value = "auto", possible_values = &["auto", "always", "never"])]
+    color: ColorChoice,
+
+    /// Format of error messages, `json` emits one object per line
+    #[structopt(long, global = true, default_value = "human", possible_values = &["human", "json"])]
+    error_format: ErrorFormat,
 }
 
+// The AST interpreter and the checkers recurse natively, on the nesting of
+// the program and on calls (up to `MAX_CALL_DEPTH`), so they run on a
+// thread with a larger stack than the main thread.
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+
 fn main() {
+    let child = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(cli)
+        .unwrap();
+    if child.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn cli() {
     let opt = Opt::from_args();
+    match (&opt.command, &opt.path) {
+        (Some(Command::Repl), _) => repl(&opt),
+        (None, Some(path)) => run(&opt, path),
+        (None, None) => {
+            Opt::clap().print_help().unwrap();
+            println!();
+            std::process::exit(2);
+        }
+    }
+}
 
+fn run(opt: &Opt, path: &Path) {
     // Open the path in read-only mode, returns `io::Result<File>`
-    let mut file = match File::open(&opt.path) {
-        Err(why) => panic!("couldn't open {}: {}", opt.path.display(), why),
+    let mut file = match File::open(path) {
+        Err(why) => panic!("couldn't open {}: {}", path.display(), why),
         Ok(file) => file,
     };
 
     // reads the file to a string and parses it
     let mut s = String::new();
     match file.read_to_string(&mut s) {
-        Err(why) => panic!("couldn't read {}: {}", opt.path.display(), why),
+        Err(why) => panic!("couldn't read {}: {}", path.display(), why),
         Ok(_) => {
             print!("rnr input:\n{}", s);
-            let ts: proc_macro2::TokenStream = s.parse().unwrap();
+            let path = path.display().to_string();
+            let emitter = Emitter::new(&path, &s, opt.color, opt.error_format);
+            let mut failed = false;
             print!("rnr parsing: ");
-            let parse: Result<Prog, _> = syn::parse2(ts);
+            let parse: Result<Prog, Error> = match s.parse() {
+                Ok(ts) => syn::parse2(ts).map_err(Error::from),
+                Err(err) => Err(Error::from(err)),
+            };
             match parse {
-                Err(err) => println!("error: {}", err),
+                Err(err) => {
+                    println!("failed");
+                    emitter.emit(&err);
+                    failed = true;
+                }
                 Ok(prog) => {
                     println!("\nrnr prog:\n{}", prog);
 
@@ -48,20 +139,76 @@ fn main() {
                         let mut env: Env<Ty> = Env::new();
                         match prog.eval(&mut env) {
                             Ok(_) => println!("passed"),
-                            Err(err) => println!("error: {}", err),
+                            Err(err) => {
+                                println!("failed");
+                                emitter.emit(&err);
+                                failed = true;
+                            }
+                        }
+                    }
+
+                    if opt.borrow_check {
+                        print!("rnr borrow checking: ");
+                        let mut env: Env<Bc> = Env::new();
+                        match prog.eval(&mut env) {
+                            Ok(_) => println!("passed"),
+                            Err(err) => {
+                                println!("failed");
+                                emitter.emit(&err);
+                                failed = true;
+                            }
                         }
                     }
 
                     if opt.vm {
                         println!("rnr evaluating");
-                        let mut env: Env<Val> = Env::new();
-                        match prog.eval(&mut env) {
+                        let result = match opt.backend {
+                            Backend::Ast => {
+                                let mut env: Env<Val> = Env::new();
+                                prog.eval(&mut env).map(|(v, _)| v)
+                            }
+                            Backend::Bytecode => match compile(&prog) {
+                                Ok(program) => Machine::new(&program).run(),
+                                Err(err) => Err(err),
+                            },
+                        };
+                        match result {
                             Ok(_) => println!("rnr evaluating done"),
-                            Err(err) => println!("error: {}", err),
+                            Err(err) => {
+                                println!("failed");
+                                emitter.emit(&err);
+                                failed = true;
+                            }
                         }
                     }
                 }
             }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn repl(opt: &Opt) {
+    let mut repl = Repl::new();
+    let stdin = std::io::stdin();
+    loop {
+        print!("{}", if repl.is_continued() { ".. " } else { ">> " });
+        std::io::stdout().flush().unwrap();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(why) => panic!("couldn't read stdin: {}", why),
+        }
+        if let Some(entry) = repl.feed(line.trim_end_matches('\n')) {
+            match repl.eval(&entry) {
+                Ok(Some(out)) => println!("{}", out),
+                Ok(None) => (),
+                Err(err) => Emitter::new("<repl>", &entry, opt.color, opt.error_format).emit(&err),
+            }
         }
     }
+    println!();
 }
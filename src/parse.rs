@@ -1,10 +1,34 @@
 use crate::ast::*;
+use crate::climb::climb;
+use crate::error::Span;
 use syn::
 {
-    parse::{Parse, ParseStream},
+    buffer::Cursor,
+    parse::{discouraged::Speculative, Parse, ParseStream},
     Result, Token,
 };
 
+// The span from `begin` up to the last token consumed from `input`
+fn span_from(begin: Cursor, input: ParseStream) -> Span
+{
+    let end = input.cursor();
+    let mut hi = begin.span();
+    let mut cursor = begin;
+    while cursor != end
+    {
+        match cursor.token_tree()
+        {
+            Some((tt, next)) =>
+            {
+                hi = tt.span();
+                cursor = next;
+            }
+            None => break,
+        }
+    }
+    Span::from(begin.span()).to(hi.into())
+}
+
 // Back-port your parser
 // You may want to put the tests in a module.
 // See e.g., the vm.rs
@@ -18,7 +42,15 @@ impl Parse for Literal
 
         Ok(match l 
         {
-            syn::Lit::Int(l) => Literal::Int(l.base10_parse().unwrap()),
+            syn::Lit::Int(l) => parse_int(&l, false)?,
+            // `1.5`, `1e3` or `2f64`
+            syn::Lit::Float(l) => match l.suffix() 
+            {
+                "" | "f64" => Literal::Float(l.base10_parse()?),
+                suffix => return Err(syn::Error::new(l.span(), format!("invalid suffix `{}` for float literal", suffix))),
+            },
+            // with escapes resolved, e.g., `'\n'` or `'\u{e9}'`
+            syn::Lit::Char(c) => Literal::Char(c.value()),
             syn::Lit::Bool(b) => Literal::Bool(b.value),
             syn::Lit::Str(s) => Literal::String(s.value()),
             _ => unimplemented!(),
@@ -26,11 +58,42 @@ impl Parse for Literal
     }
 }
 
+// An integer literal, negated for `neg`. As in rustc, the range is checked
+// after the negation, so that `-2147483648` and `-128i8` are allowed.
+fn parse_int(l: &syn::LitInt, neg: bool) -> Result<Literal> 
+{
+    let sign = if neg { -1 } else { 1 };
+    let t = match l.suffix() 
+    {
+        // the type is inferred by the type checker, which checks the range
+        // for it, here it is only bounded by the widest types
+        "" => 
+        {
+            let i = sign * l.base10_parse::<i128>()?;
+            if !IntTy::I64.fits(i) && !IntTy::U64.fits(i) 
+            {
+                return Err(syn::Error::new(l.span(), "integer literal is too large"));
+            }
+            return Ok(Literal::Unsuffixed(i));
+        },
+        // `2f64` is a float
+        "f64" => return Ok(Literal::Float(sign as f64 * l.base10_parse::<f64>()?)),
+        suffix => IntTy::from_name(suffix)
+            .ok_or_else(|| syn::Error::new(l.span(), format!("invalid suffix `{}` for number literal", suffix)))?,
+    };
+    let i = sign * l.base10_parse::<i128>()?;
+    if !t.fits(i) 
+    {
+        return Err(syn::Error::new(l.span(), format!("literal out of range for `{}`", t)));
+    }
+    Ok(Literal::int(i, t))
+}
+
 #[test]
 fn parse_lit_int() {
     let ts: proc_macro2::TokenStream = "1".parse().unwrap();
     let l: Literal = syn::parse2(ts).unwrap();
-    assert_eq!(l, Literal::Int(1));
+    assert_eq!(l, Literal::Unsuffixed(1));
 }
 
 #[test]
@@ -47,6 +110,28 @@ fn parse_lit_string() {
     assert_eq!(l, Literal::String("abba".to_string()));
 }
 
+#[test]
+fn parse_lit_float() {
+    let lit = |s: &str| syn::parse2::<Literal>(s.parse().unwrap());
+    assert_eq!(lit("1.5").unwrap(), Literal::Float(1.5));
+    assert_eq!(lit("1e3").unwrap(), Literal::Float(1000.0));
+    assert_eq!(lit("2f64").unwrap(), Literal::Float(2.0));
+    assert_eq!(lit("2.5f32").unwrap_err().to_string(), "invalid suffix `f32` for float literal");
+}
+
+#[test]
+fn parse_lit_char() {
+    let lit = |s: &str| syn::parse2::<Literal>(s.parse().unwrap()).unwrap();
+    assert_eq!(lit("'a'"), Literal::Char('a'));
+    assert_eq!(lit(r"'\n'"), Literal::Char('\n'));
+    assert_eq!(lit(r"'\''"), Literal::Char('\''));
+    assert_eq!(lit(r"'\u{e9}'"), Literal::Char('é'));
+    assert_eq!(lit(r#""a\tb""#), Literal::String("a\tb".to_string()));
+    let ts: proc_macro2::TokenStream = r"let c: char = '\n'; let x: f64 = -1.0;".parse().unwrap();
+    let b: Block = syn::parse2(quote! {{ #ts }}).unwrap();
+    assert_eq!(b.to_string(), "let c: char = '\\n';\nlet x: f64 = -1.0;\n");
+}
+
 #[test]
 fn parse_lit_fail() {
     let ts: proc_macro2::TokenStream = "a".parse().unwrap();
@@ -80,6 +165,11 @@ impl Parse for Op
             let _: Token![/] = input.parse()?;
             Ok(Op::Div)
         } 
+        else if input.peek(Token![%]) 
+        {
+            let _: Token![%] = input.parse()?;
+            Ok(Op::Rem)
+        } 
         else if input.peek(Token![&&]) 
         {
             let _: Token![&&] = input.parse()?;
@@ -95,6 +185,22 @@ impl Parse for Op
             let _: Token![==] = input.parse()?;
             Ok(Op::Eq)
         } 
+        else if input.peek(Token![!=]) 
+        {
+            let _: Token![!=] = input.parse()?;
+            Ok(Op::Ne)
+        } 
+        // `>=` and `<=` before `>` and `<`, which match their first character
+        else if input.peek(Token![>=]) 
+        {
+            let _: Token![>=] = input.parse()?;
+            Ok(Op::Ge)
+        } 
+        else if input.peek(Token![<=]) 
+        {
+            let _: Token![<=] = input.parse()?;
+            Ok(Op::Le)
+        } 
         else if input.peek(Token![>]) 
         {
             let _: Token![>] = input.parse()?;
@@ -142,6 +248,11 @@ impl Parse for UnOp {
             let _: Token![!] = input.parse()?;
             Ok(UnOp::Bang)
         } 
+        else if input.peek(Token![-]) 
+        {
+            let _: Token![-] = input.parse()?;
+            Ok(UnOp::Neg)
+        } 
         else 
         {
             // to explicitly create an error at the current position
@@ -166,6 +277,15 @@ fn parse_op_mul() {
     assert_eq!(op, Op::Mul);
 }
 
+#[test]
+fn parse_op_compare() {
+    for (s, op) in [("<=", Op::Le), (">=", Op::Ge), ("!=", Op::Ne), ("<", Op::Lt), ("%", Op::Rem)] {
+        let ts: proc_macro2::TokenStream = s.parse().unwrap();
+        let parsed: Op = syn::parse2(ts).unwrap();
+        assert_eq!(parsed, op);
+    }
+}
+
 #[test]
 fn parse_op_fail() {
     let ts: proc_macro2::TokenStream = "1".parse().unwrap();
@@ -174,74 +294,535 @@ fn parse_op_fail() {
     assert!(err.is_err());
 }
 
-// Render a "right associative" AST
-impl Parse for Expr 
+// An operand of a binary operation, unary operators bind tighter than
+// casts, and casts tighter than binary operators, e.g., `-a as u8 + 1` is
+// `((-a) as u8) + 1`
+pub(crate) fn parse_operand(input: ParseStream) -> Result<Expr> 
 {
-    // Use a custom parser for expressions
-    fn parse(input: ParseStream) -> Result<Self> 
+    let begin = input.cursor();
+    let mut e = parse_unary(input)?;
+    while input.peek(Token![as]) 
+    {
+        let _as: Token![as] = input.parse()?;
+        let t: Type = input.parse()?;
+        e = Expr::new(ExprKind::Cast(Box::new(e), t), span_from(begin, input));
+    }
+    Ok(e)
+}
+
+// Whether the next token is an integer literal folded with a leading `-`,
+// one without a suffix (its range is only known once its type is inferred)
+// or one out of range unless negated
+fn negated_int(input: ParseStream) -> bool 
+{
+    let fork = input.fork();
+    match fork.parse::<syn::LitInt>() 
+    {
+        Ok(l) => !fork.peek(Token![.]) && parse_int(&l, true).is_ok() && (l.suffix().is_empty() || parse_int(&l, false).is_err()),
+        Err(_) => false,
+    }
+}
+
+// An operand without casts, e.g., `*a` or `a.b[i]`
+fn parse_unary(input: ParseStream) -> Result<Expr> 
+{
+    let begin = input.cursor();
+    let left = if input.peek(syn::token::Paren) 
     {
-        let left = if input.peek(syn::token::Paren) 
+        // we have a left (Expr), e.g., "(1 + 2)", a tuple "(1, 2)", "(1,)" or "()"
+        let content;
+        let _ = syn::parenthesized!(content in input);
+        let elements: Punctuated<Expr, Token![,]> = content.parse_terminated(Expr::parse)?;
+        if elements.is_empty() 
         {
-            // we have a left (Expr), e.g., "(1 + 2)"
-            let content;
-            let _ = syn::parenthesized!(content in input);
-            let e: Expr = content.parse()?;
-            Expr::Par(Box::new(e))
+            ExprKind::Lit(Literal::Unit)
         } 
-        else if input.peek(syn::Ident) 
+        else if elements.len() == 1 && !elements.trailing_punct() 
         {
-            let ident: syn::Ident = input.parse()?;
-            if input.peek(syn::token::Paren) 
+            ExprKind::Par(Box::new(elements.into_iter().next().unwrap()))
+        } 
+        else 
+        {
+            ExprKind::Tuple(elements.into_iter().collect())
+        }
+    } 
+    else if input.peek(syn::token::Bracket) 
+    {
+        // an array, "[1, 2, 3]" or "[0; 10]"
+        let content;
+        let _ = syn::bracketed!(content in input);
+        if content.is_empty() 
+        {
+            ExprKind::Array(vec![])
+        } 
+        else 
+        {
+            let first: Expr = content.parse()?;
+            if content.peek(Token![;]) 
             {
-                let arguments = input.parse()?;
-                Expr::Call(ident.to_string(), arguments)
+                let _semi: Token![;] = content.parse()?;
+                let n: syn::LitInt = content.parse()?;
+                ExprKind::Repeat(Box::new(first), n.base10_parse()?)
             } 
-            else if input.peek(Token![!]) 
+            else 
+            {
+                let mut elements = vec![first];
+                while !content.is_empty() 
+                {
+                    let _comma: Token![,] = content.parse()?;
+                    if content.is_empty() 
+                    {
+                        break;
+                    }
+                    elements.push(content.parse()?);
+                }
+                ExprKind::Array(elements)
+            }
+        }
+    } 
+    else if input.peek(syn::Ident) 
+    {
+        let ident: syn::Ident = input.parse()?;
+        let fork = input.fork();
+        if fork.parse::<Token![::]>().is_ok() && fork.peek(Token![<]) 
+        {
+            // f::<i32>(a, b), a call with explicit type arguments
+            let _colons: Token![::] = input.parse()?;
+            let _lt: Token![<] = input.parse()?;
+            let mut types = vec![];
+            while !input.peek(Token![>]) 
+            {
+                types.push(input.parse()?);
+                if !input.peek(Token![>]) 
+                {
+                    let _comma: Token![,] = input.parse()?;
+                }
+            }
+            let _gt: Token![>] = input.parse()?;
+            let arguments = input.parse()?;
+            ExprKind::Call(ident.to_string(), types, arguments)
+        } 
+        else if input.peek(Token![::]) 
+        {
+            // E::A, E::B(1, 2) or E::C { x: 1 }
+            let _colons: Token![::] = input.parse()?;
+            let variant: syn::Ident = input.parse()?;
+            if ident == "Vec" || ident == "String" 
             {
-                let _macro: Token![!] = input.parse()?; //Checks if macro (println!, write!, etc)
+                // an associated function of a built in type, `Vec::new()` or `String::from(s)`
                 let arguments = input.parse()?;
-                let mut id: String = ident.to_string();
-                id.push('!');
-                Expr::Call(id, arguments)
+                ExprKind::Assoc(ident.to_string(), variant.to_string(), arguments)
             } 
             else 
             {
-                Expr::Ident(ident.to_string())
+                let (kind, fields) = if input.peek(syn::token::Paren) 
+                {
+                    let content;
+                    let _ = syn::parenthesized!(content in input);
+                    let fields: Punctuated<Expr, Token![,]> = content.parse_terminated(Expr::parse)?;
+                    (VariantKind::Tuple, fields.into_iter().enumerate().map(|(i, e)| (i.to_string(), e)).collect())
+                } 
+                else if input.peek(syn::token::Brace) 
+                {
+                    let content;
+                    let _ = syn::braced!(content in input);
+                    let fields: Punctuated<FieldInit, Token![,]> = content.parse_terminated(FieldInit::parse)?;
+                    (VariantKind::Struct, fields.into_iter().map(|f| (f.0, f.1)).collect())
+                } 
+                else 
+                {
+                    (VariantKind::Unit, vec![])
+                };
+                ExprKind::Variant(ident.to_string(), variant.to_string(), kind, fields)
             }
         } 
-        else if input.peek(syn::token::If) 
+        else if input.peek(syn::token::Paren) 
         {
-            let IfThenOptElse(c, t, e) = input.parse()?;
-            Expr::IfThenElse(Box::new(c), t, e)
+            let arguments = input.parse()?;
+            ExprKind::Call(ident.to_string(), vec![], arguments)
         } 
-        else if  input.peek(syn::token::And) || input.peek(syn::token::Bang) || input.peek(syn::token::Mut) || input.peek(Token![*])
+        else if input.peek(syn::token::Brace) 
         {
-            let un_op = input.parse()?;
-            let expr = input.parse()?;
-            Expr::UnOp(un_op, expr)
+            // S { a: 1, b }
+            let content;
+            let _ = syn::braced!(content in input);
+            let fields: Punctuated<FieldInit, Token![,]> = content.parse_terminated(FieldInit::parse)?;
+            ExprKind::Struct(ident.to_string(), fields.into_iter().map(|f| (f.0, f.1)).collect())
         } 
-        else if input.peek(syn::token::Brace) 
+        else if ident == "vec" && input.peek(Token![!]) && input.peek2(syn::token::Bracket) 
+        {
+            // `vec![1, 2, 3]` or `vec![0; n]`
+            let _macro: Token![!] = input.parse()?;
+            let content;
+            let _ = syn::bracketed!(content in input);
+            if content.is_empty() 
+            {
+                ExprKind::Vec(vec![])
+            } 
+            else 
+            {
+                let first: Expr = content.parse()?;
+                if content.peek(Token![;]) 
+                {
+                    let _semi: Token![;] = content.parse()?;
+                    let n: Expr = content.parse()?;
+                    ExprKind::VecRepeat(Box::new(first), Box::new(n))
+                } 
+                else 
+                {
+                    let mut elements = vec![first];
+                    while !content.is_empty() 
+                    {
+                        let _comma: Token![,] = content.parse()?;
+                        if content.is_empty() 
+                        {
+                            break;
+                        }
+                        elements.push(content.parse()?);
+                    }
+                    ExprKind::Vec(elements)
+                }
+            }
+        } 
+        else if input.peek(Token![!]) && !input.peek(Token![!=]) 
+        {
+            let _macro: Token![!] = input.parse()?; //Checks if macro (println!, write!, etc)
+            let arguments = input.parse()?;
+            let mut id: String = ident.to_string();
+            id.push('!');
+            ExprKind::Call(id, vec![], arguments)
+        } 
+        else 
+        {
+            ExprKind::Ident(ident.to_string())
+        }
+    } 
+    else if input.peek(syn::token::If) 
+    {
+        let IfThenOptElse(c, t, e) = input.parse()?;
+        ExprKind::IfThenElse(Box::new(c), t, e)
+    } 
+    else if  input.peek(syn::token::And) || input.peek(syn::token::Bang) || input.peek(syn::token::Mut) || input.peek(Token![*]) || input.peek(Token![-])
+    {
+        let un_op = input.parse()?;
+        match un_op 
+        {
+            // a literal without a suffix or only in range when negated, e.g.
+            // `-2147483648` or `-128i8`, is folded into a negative literal
+            // (unless a method call follows)
+            UnOp::Neg if negated_int(input) => 
+            {
+                let l: syn::LitInt = input.parse()?;
+                ExprKind::Lit(parse_int(&l, true)?)
+            },
+            _ => ExprKind::UnOp(un_op, Box::new(parse_unary(input)?)),
+        }
+    } 
+    else if input.peek(syn::token::Brace) 
+    {
+        let e: Block = input.parse()?;
+        ExprKind::Block(e)
+    } 
+    else if input.peek(syn::Lifetime) || input.peek(syn::token::Loop) 
+    {
+        // ['label:] loop { .. }
+        let label = parse_label(input)?;
+        let _loop: syn::token::Loop = input.parse()?;
+        let body: Block = input.parse()?;
+        ExprKind::Loop(label, body)
+    } 
+    else if input.peek(syn::token::Break) 
+    {
+        // break ['label] [expr]
+        let _break: syn::token::Break = input.parse()?;
+        let label = parse_lifetime(input)?;
+        ExprKind::Break(label, parse_value(input)?)
+    } 
+    else if input.peek(syn::token::Continue) 
+    {
+        // continue ['label]
+        let _continue: syn::token::Continue = input.parse()?;
+        ExprKind::Continue(parse_lifetime(input)?)
+    } 
+    else if input.peek(syn::token::Return) 
+    {
+        // return [expr]
+        let _return: syn::token::Return = input.parse()?;
+        ExprKind::Return(parse_value(input)?)
+    } 
+    else if input.peek(syn::token::Match) 
+    {
+        // match e { p => e, .. }
+        let _match: syn::token::Match = input.parse()?;
+        let e: Expr = parse_cond(input)?;
+        let content;
+        let _ = syn::braced!(content in input);
+        let mut arms = vec![];
+        while !content.is_empty() 
+        {
+            let arm: Arm = content.parse()?;
+            // the `,` is optional after a block like arm, and after the last one
+            let block_like = matches!(arm.body.kind, ExprKind::Block(_) | ExprKind::IfThenElse(..) | ExprKind::Loop(..) | ExprKind::Match(..));
+            if content.peek(Token![,]) || !(block_like || content.is_empty()) 
+            {
+                let _comma: Token![,] = content.parse()?;
+            }
+            arms.push(arm);
+        }
+        ExprKind::Match(Box::new(e), arms)
+    } 
+    else if input.peek(Token![|]) || input.peek(Token![||]) || input.peek(Token![move]) 
+    {
+        ExprKind::Closure(input.parse()?)
+    } 
+    else 
+    {
+        // else we require a left literal
+        let left: Literal = input.parse()?;
+        ExprKind::Lit(left)
+    };
+    let mut e = Expr::new(left, span_from(begin, input));
+    // field accesses `e.a.b`, tuple elements `e.0`, method calls `e.len()`
+    // and indexing `e[i]`, but not ranges `e..`
+    while (input.peek(Token![.]) && !input.peek(Token![..])) || input.peek(syn::token::Bracket) 
+    {
+        if input.peek(syn::token::Bracket) 
+        {
+            let content;
+            let _ = syn::bracketed!(content in input);
+            let i: Expr = content.parse()?;
+            e = Expr::new(ExprKind::Index(Box::new(e), Box::new(i)), span_from(begin, input));
+            continue;
+        }
+        let _dot: Token![.] = input.parse()?;
+        if input.peek(syn::Ident) && input.peek2(syn::token::Paren) 
         {
-            let e: Block = input.parse()?;
-            Expr::Block(e)
+            let method: syn::Ident = input.parse()?;
+            let args: Arguments = input.parse()?;
+            e = Expr::new(ExprKind::Method(Box::new(e), method.to_string(), args), span_from(begin, input));
+            continue;
+        }
+        let fields = if input.peek(syn::LitInt) 
+        {
+            let index: syn::LitInt = input.parse()?;
+            vec![index.base10_digits().to_string()]
+        } 
+        else if input.peek(syn::LitFloat) 
+        {
+            // `e.0.1` is lexed as `e` `.` `0.1`
+            let index: syn::LitFloat = input.parse()?;
+            let digits = index.base10_digits().to_string();
+            if !digits.split('.').all(|i| !i.is_empty() && i.chars().all(|c| c.is_ascii_digit())) 
+            {
+                return Err(syn::Error::new(index.span(), "expected a tuple index"));
+            }
+            digits.split('.').map(|i| i.to_string()).collect()
         } 
         else 
         {
-            // else we require a left literal
-            let left: Literal = input.parse()?;
-            left.into()
+            let field: syn::Ident = input.parse()?;
+            vec![field.to_string()]
         };
-        // now check if right is an Op Expr
-        match input.parse::<Op>() 
+        for field in fields 
         {
-            Ok(op) => 
+            e = Expr::new(ExprKind::Field(Box::new(e), field), span_from(begin, input));
+        }
+    }
+    Ok(e)
+}
+
+// `|x: i32, y: i32| x + y`, `move || x` or `|x: i32| -> i32 { x }`, the
+// parameters need a type, the body extends as far as possible
+impl Parse for Closure 
+{
+    fn parse(input: ParseStream) -> Result<Closure> 
+    {
+        let moves = input.parse::<Option<Token![move]>>()?.is_some();
+        let mut parameters = vec![];
+        if input.parse::<Option<Token![||]>>()?.is_none() 
+        {
+            let _bar: Token![|] = input.parse()?;
+            while !input.peek(Token![|]) 
             {
-                let right: Expr = input.parse()?;
-                Ok(Expr::bin_op(op, left, right))
+                let begin = input.cursor();
+                let pat: Pattern = input.parse()?;
+                if !input.peek(Token![:]) 
+                {
+                    return Err(input.error("type annotations needed, closure parameters must have a type"));
+                }
+                let _colon: Token![:] = input.parse()?;
+                let ty: Type = input.parse()?;
+                parameters.push(Parameter { pat, ty, span: span_from(begin, input) });
+                if !input.peek(Token![|]) 
+                {
+                    let _comma: Token![,] = input.parse()?;
+                }
             }
-            // no op, just return the left, no error
-            Err(_) => Ok(left),
+            let _bar: Token![|] = input.parse()?;
+        }
+        let (ty, body) = if input.peek(Token![->]) 
+        {
+            // with a return type, the body is a block
+            let _arrow: Token![->] = input.parse()?;
+            let ty: Type = input.parse()?;
+            let begin = input.cursor();
+            let b: Block = input.parse()?;
+            (Some(ty), Expr::new(ExprKind::Block(b), span_from(begin, input)))
+        } 
+        else 
+        {
+            (None, input.parse()?)
+        };
+        Ok(Closure { moves, parameters: Parameters(parameters), ty, body: Box::new(body) })
+    }
+}
+
+#[test]
+fn test_closure() {
+    let e: Expr = syn::parse_str("|x: i32, (a, b): (i32, i32)| x + a * b").unwrap();
+    match &e.kind {
+        ExprKind::Closure(c) => {
+            assert!(!c.moves);
+            assert_eq!(c.parameters.0.len(), 2);
+            assert_eq!(c.body.to_string(), "x + a * b");
+        }
+        _ => panic!("expected a closure"),
+    }
+    let e: Expr = syn::parse_str("move || -> i32 { y }").unwrap();
+    match &e.kind {
+        ExprKind::Closure(c) => {
+            assert!(c.moves);
+            assert_eq!(c.ty, Some(Type::I32));
         }
+        _ => panic!("expected a closure"),
+    }
+    let e: Expr = syn::parse_str("f(|x: bool| !x, 1)").unwrap();
+    assert_eq!(e.to_string(), "f(|x: bool| !x, 1, )");
+    assert!(syn::parse_str::<Expr>("|x| x").is_err());
+
+    let t: Type = syn::parse_str("impl Fn(i32, bool) -> i32").unwrap();
+    assert_eq!(t, Type::ImplFn(vec![Type::I32, Type::Bool], Box::new(Type::I32)));
+    let t: Type = syn::parse_str("fn(i32)").unwrap();
+    assert_eq!(t, Type::Fn(vec![Type::I32], Box::new(Type::Unit)));
+    assert!(syn::parse_str::<Type>("impl FnMut(i32)").is_err());
+}
+
+#[test]
+fn test_generics() {
+    let f: FnDeclaration = syn::parse_str("fn swap<A, B>(p: (A, B)) -> (B, A) { (p.1, p.0) }").unwrap();
+    assert_eq!(f.generics, vec!["A".to_string(), "B".to_string()]);
+    assert_eq!(f.parameters.0[0].ty, Type::Tuple(vec![Type::Named("A".to_string()), Type::Named("B".to_string())]));
+    let e: Expr = syn::parse_str("max::<i32, Vec<u8>>(a, b)").unwrap();
+    match &e.kind {
+        ExprKind::Call(id, targs, args) => {
+            assert_eq!(id, "max");
+            assert_eq!(targs, &vec![Type::I32, Type::Vec(Box::new(Type::Int(IntTy::U8)))]);
+            assert_eq!(args.0.len(), 2);
+        }
+        _ => panic!("expected a call"),
+    }
+    assert!(syn::parse_str::<FnDeclaration>("fn max<T: Ord>(a: T, b: T) -> T { a }").is_err());
+    assert!(syn::parse_str::<FnDeclaration>("fn f<T, T>(a: T) {}").is_err());
+}
+
+// A field of a struct literal, `a: e` or the shorthand `a` for `a: a`
+struct FieldInit(String, Expr);
+
+impl Parse for FieldInit 
+{
+    fn parse(input: ParseStream) -> Result<FieldInit> 
+    {
+        let begin = input.cursor();
+        let id: syn::Ident = input.parse()?;
+        if input.peek(Token![:]) 
+        {
+            let _colon: Token![:] = input.parse()?;
+            Ok(FieldInit(id.to_string(), input.parse()?))
+        } 
+        else 
+        {
+            let e = Expr::new(ExprKind::Ident(id.to_string()), span_from(begin, input));
+            Ok(FieldInit(id.to_string(), e))
+        }
+    }
+}
+
+// Parses the tokens up to the next `{ .. }`, e.g., the condition of an `if`.
+// As in Rust, a struct literal is not allowed there (unless in parentheses),
+// so that `if a { .. }` is not mistaken for one.
+pub(crate) fn parse_cond<T: Parse>(input: ParseStream) -> Result<T> 
+{
+    let tokens = input.step(|cursor| 
+    {
+        let mut rest = *cursor;
+        let mut tokens = proc_macro2::TokenStream::new();
+        while let Some((tt, next)) = rest.token_tree() 
+        {
+            match &tt 
+            {
+                proc_macro2::TokenTree::Group(g) if g.delimiter() == proc_macro2::Delimiter::Brace => break,
+                _ => 
+                {
+                    tokens.extend(std::iter::once(tt));
+                    rest = next;
+                }
+            }
+        }
+        Ok((tokens, rest))
+    })?;
+    if tokens.is_empty() 
+    {
+        return Err(input.error("expected an expression"));
+    }
+    syn::parse2(tokens)
+}
+
+// An optional `'label` (as used by `break` and `continue`)
+fn parse_lifetime(input: ParseStream) -> Result<Option<String>> 
+{
+    match input.peek(syn::Lifetime) 
+    {
+        true => 
+        {
+            let lifetime: syn::Lifetime = input.parse()?;
+            Ok(Some(lifetime.to_string()))
+        }
+        false => Ok(None),
+    }
+}
+
+// The optional value of `break` and `return`, absent at the end of the
+// statement or argument
+fn parse_value(input: ParseStream) -> Result<Option<Box<Expr>>> 
+{
+    if input.is_empty() || input.peek(Token![;]) || input.peek(Token![,]) 
+    {
+        Ok(None)
+    } 
+    else 
+    {
+        Ok(Some(Box::new(input.parse()?)))
+    }
+}
+
+// An optional `'label:` in front of a loop
+fn parse_label(input: ParseStream) -> Result<Option<String>> 
+{
+    let label = parse_lifetime(input)?;
+    if label.is_some() 
+    {
+        let _colon: Token![:] = input.parse()?;
+    }
+    Ok(label)
+}
+
+// Binary operations are parsed by precedence climbing (climb.rs)
+impl Parse for Expr 
+{
+    // Use a custom parser for expressions
+    fn parse(input: ParseStream) -> Result<Self> 
+    {
+        climb(input)
     }
 }
 
@@ -260,7 +841,7 @@ impl Parse for IfThenOptElse
     fn parse(input: ParseStream) -> Result<IfThenOptElse> 
     {
         let _if: syn::token::If = input.parse()?;
-        let cond_expr: Expr = input.parse()?;
+        let cond_expr: Expr = parse_cond(input)?;
 
         let then_block: Block = input.parse()?;
 
@@ -271,9 +852,11 @@ impl Parse for IfThenOptElse
 
             if input.peek(syn::token::If)
             {
+                let stmt: Statement = input.parse()?;
                 else_block = Block
                 {
-                    statements : vec![input.parse()?],
+                    span: stmt.span,
+                    statements : vec![stmt],
                     semi: false,
                 };
             }
@@ -455,11 +1038,128 @@ fn test_expr_call_comma() {
     println!("e {}", e);
 }
 
-#[test]
-fn test_expr_call_block() {
-    let ts: proc_macro2::TokenStream = "ident({1}, {let a = 6; a },)".parse().unwrap();
-    let e: Expr = syn::parse2(ts).unwrap();
-    println!("e {}", e);
+#[test]
+fn test_expr_call_block() {
+    let ts: proc_macro2::TokenStream = "ident({1}, {let a = 6; a },)".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    println!("e {}", e);
+}
+
+#[test]
+fn test_expr_span() {
+    let ts: proc_macro2::TokenStream = "a +\n  (7 * b)".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    assert_eq!((e.span.lo.line, e.span.lo.column), (1, 0));
+    assert_eq!((e.span.hi.line, e.span.hi.column), (2, 9));
+    match e.kind {
+        ExprKind::BinOp(_, l, r) => {
+            assert_eq!((l.span.lo.column, l.span.hi.column), (0, 1));
+            assert_eq!((r.span.lo.line, r.span.lo.column), (2, 2));
+        }
+        _ => panic!("expected binary operation"),
+    }
+}
+
+#[test]
+fn test_expr_unop_operand() {
+    let ts: proc_macro2::TokenStream = "*a + 1".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    let a = Expr::from(ExprKind::Ident("a".to_string()));
+    assert_eq!(
+        e,
+        Expr::bin_op(
+            Op::Add,
+            ExprKind::UnOp(UnOp::DeRef, Box::new(a)).into(),
+            1.into()
+        )
+    );
+}
+
+#[test]
+fn test_expr_neg() {
+    let ts: proc_macro2::TokenStream = "1 - -a".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    let a = Expr::from(ExprKind::Ident("a".to_string()));
+    assert_eq!(
+        e,
+        Expr::bin_op(Op::Sub, 1.into(), ExprKind::UnOp(UnOp::Neg, Box::new(a)).into())
+    );
+    assert_eq!(e.to_string(), "1 - -a");
+}
+
+#[test]
+fn test_expr_loop() {
+    let ts: proc_macro2::TokenStream = "'a: loop { break 'a 1 + 2; continue; break }".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    match &e.kind {
+        ExprKind::Loop(Some(l), b) => {
+            assert_eq!(l, "'a");
+            assert_eq!(
+                b.statements[0],
+                StatementKind::Expr(
+                    ExprKind::Break(
+                        Some("'a".to_string()),
+                        Some(Box::new(Expr::bin_op(Op::Add, 1.into(), 2.into())))
+                    )
+                    .into()
+                )
+                .into()
+            );
+            assert_eq!(b.statements[1], StatementKind::Expr(ExprKind::Continue(None).into()).into());
+            assert_eq!(b.statements[2], StatementKind::Expr(ExprKind::Break(None, None).into()).into());
+        }
+        _ => panic!("expected a labeled loop, got {:?}", e),
+    }
+}
+
+#[test]
+fn test_statement_labeled_while() {
+    let ts: proc_macro2::TokenStream = "'w: while a { continue 'w; }".parse().unwrap();
+    let stmt: Statement = syn::parse2(ts).unwrap();
+    match stmt.kind {
+        StatementKind::While(Some(l), _, _) => assert_eq!(l, "'w"),
+        _ => panic!("expected a labeled while, got {:?}", stmt),
+    }
+}
+
+#[test]
+fn test_statement_for() {
+    let ts: proc_macro2::TokenStream = "for i in 0..n + 1 { }".parse().unwrap();
+    let stmt: Statement = syn::parse2(ts).unwrap();
+    let range = Range {
+        start: Box::new(0.into()),
+        end: Box::new(Expr::bin_op(Op::Add, ExprKind::Ident("n".to_string()).into(), 1.into())),
+        inclusive: false,
+        rev: false,
+    };
+    assert_eq!(
+        stmt,
+        StatementKind::For(
+            None,
+            "i".to_string(),
+            Iter::Range(range),
+            Block {
+                statements: vec![],
+                semi: false,
+                span: Span::default(),
+            }
+        )
+        .into()
+    );
+
+    let ts: proc_macro2::TokenStream = "'a: for i in (1..=3).rev() { }".parse().unwrap();
+    let stmt: Statement = syn::parse2(ts).unwrap();
+    match &stmt.kind {
+        StatementKind::For(Some(l), _, Iter::Range(range), _) => {
+            assert_eq!(l, "'a");
+            assert!(range.inclusive && range.rev);
+            assert_eq!(range.to_string(), "(1..=3).rev()");
+        }
+        _ => panic!("expected a labeled for, got {:?}", stmt),
+    }
+
+    let ts: proc_macro2::TokenStream = "for i in (1..3).len() { }".parse().unwrap();
+    assert!(syn::parse2::<Statement>(ts).is_err());
 }
 
 #[test]
@@ -489,6 +1189,81 @@ impl Parse for Type
         // To make things simple, we just turn the syn::Type
         // to a token stream (`quote`) and turn that into a String
         // and turn that into an &str (`as_str`)
+        if input.peek(syn::token::Bracket) 
+        {
+            // `[T; N]`
+            let content;
+            let _ = syn::bracketed!(content in input);
+            let t: Type = content.parse()?;
+            let _semi: Token![;] = content.parse()?;
+            let n: syn::LitInt = content.parse()?;
+            return Ok(Type::Array(Box::new(t), n.base10_parse()?));
+        }
+        if input.peek(Token![fn]) || input.peek(Token![impl]) 
+        {
+            // `fn(T1, T2) -> R` or `impl Fn(T1, T2) -> R`
+            let closure = input.parse::<Option<Token![impl]>>()?.is_some();
+            if closure 
+            {
+                let tr: syn::Ident = input.parse()?;
+                if tr != "Fn" 
+                {
+                    return Err(syn::Error::new(tr.span(), "only `Fn` closures are supported"));
+                }
+            } 
+            else 
+            {
+                let _fn: Token![fn] = input.parse()?;
+            }
+            let content;
+            let _ = syn::parenthesized!(content in input);
+            let params: Punctuated<Type, Token![,]> = content.parse_terminated(Type::parse)?;
+            let ret = match input.parse::<Option<Token![->]>>()? 
+            {
+                Some(_) => input.parse()?,
+                None => Type::Unit,
+            };
+            let params = params.into_iter().collect();
+            return Ok(match closure 
+            {
+                true => Type::ImplFn(params, Box::new(ret)),
+                false => Type::Fn(params, Box::new(ret)),
+            });
+        }
+        if let Some(t) = input.fork().parse::<syn::Ident>().ok().and_then(|id| IntTy::from_name(&id.to_string())) 
+        {
+            // parsed here, as `syn::Type` would take `+` in `a as u8 + 1` for a bound
+            let _id: syn::Ident = input.parse()?;
+            return Ok(t.into());
+        }
+        if input.peek(syn::Ident) && input.peek2(Token![<]) && input.fork().parse::<syn::Ident>()? == "Vec" 
+        {
+            // `Vec<T>`
+            let _vec: syn::Ident = input.parse()?;
+            let _lt: Token![<] = input.parse()?;
+            let t: Type = input.parse()?;
+            let _gt: Token![>] = input.parse()?;
+            return Ok(Type::Vec(Box::new(t)));
+        }
+        if input.peek(syn::token::Paren) 
+        {
+            // `()`, `(T)`, or a tuple `(T,)`, `(T1, T2)`
+            let content;
+            let _ = syn::parenthesized!(content in input);
+            let types: Punctuated<Type, Token![,]> = content.parse_terminated(Type::parse)?;
+            return Ok(if types.is_empty() 
+            {
+                Type::Unit
+            } 
+            else if types.len() == 1 && !types.trailing_punct() 
+            {
+                types.into_iter().next().unwrap()
+            } 
+            else 
+            {
+                Type::Tuple(types.into_iter().collect())
+            });
+        }
         Ok(match input.parse::<Token![&]>() 
         {
             Ok(_) => 
@@ -502,10 +1277,20 @@ impl Parse for Type
                 let ts = quote! {#t}.to_string();
                 match ts.as_str() 
                 {
-                    "i32" => Type::I32,
                     "bool" => Type::Bool,
+                    "f64" => Type::F64,
+                    "char" => Type::Char,
                     "()" => Type::Unit,
                     "String" => Type::String,
+                    "str" => Type::Str,
+                    "!" => Type::Never,
+                    // primitive types we do not support
+                    "i128" | "isize" | "u128" | "f32" => 
+                    {
+                        input.step(|cursor| Err(cursor.error("unsupported type")))?
+                    }
+                    // any other name is a struct
+                    id if syn::parse_str::<syn::Ident>(id).is_ok() => Type::Named(id.to_string()),
                     _ =>
                     // to explicitly create an error at the current position
                     {
@@ -540,26 +1325,22 @@ fn test_type_unit() {
 
 #[test]
 fn test_type_fail() {
-    let ts: proc_macro2::TokenStream = "u32".parse().unwrap();
+    let ts: proc_macro2::TokenStream = "u128".parse().unwrap();
     let e: Result<Type> = syn::parse2(ts);
     assert_eq!(e.is_err(), true);
 }
 
 impl Parse for Parameter {
     fn parse(input: ParseStream) -> Result<Parameter> {
-        let mutable = if input.peek(syn::token::Mut) {
-            let _m: syn::token::Mut = input.parse()?;
-            Mutable(true)
-        } else {
-            Mutable(false)
-        };
-        let id: syn::Ident = input.parse()?;
-        let id = id.to_string();
+        let begin = input.cursor();
+        // a, mut a or (a, b)
+        let pat: Pattern = input.parse()?;
 
         let _colon: syn::Token![:] = input.parse()?;
 
         let ty: Type = input.parse()?;
-        Ok(Parameter { mutable, id, ty })
+        let span = span_from(begin, input);
+        Ok(Parameter { pat, ty, span })
     }
 }
 
@@ -612,11 +1393,35 @@ fn test_call() {
 
 impl Parse for FnDeclaration {
     fn parse(input: ParseStream) -> Result<FnDeclaration> {
+        let begin = input.cursor();
         // fn ident
         let _fn: syn::token::Fn = input.parse()?;
         let id: syn::Ident = input.parse()?;
         let id = id.to_string();
 
+        // fn ident<T, U>
+        let mut generics = vec![];
+        if input.peek(syn::Token![<]) {
+            let _: syn::Token![<] = input.parse()?;
+            while !input.peek(syn::Token![>]) {
+                let g: syn::Ident = input.parse()?;
+                if input.peek(syn::Token![:]) {
+                    return Err(input.error("trait bounds are not supported"));
+                }
+                if generics.contains(&g.to_string()) {
+                    return Err(syn::Error::new(
+                        g.span(),
+                        format!("the name `{}` is already used for a generic parameter", g),
+                    ));
+                }
+                generics.push(g.to_string());
+                if !input.peek(syn::Token![>]) {
+                    let _: syn::Token![,] = input.parse()?;
+                }
+            }
+            let _: syn::Token![>] = input.parse()?;
+        }
+
         // fn ident(...)
         let args: Parameters = input.parse()?;
         // fn ident(...) -> i32
@@ -634,9 +1439,11 @@ impl Parse for FnDeclaration {
 
         Ok(FnDeclaration {
             id,
+            generics,
             parameters: args,
             ty,
             body,
+            span: span_from(begin, input),
         })
     }
 }
@@ -655,27 +1462,497 @@ fn test_fn_type() {
     println!("{}", fn_);
 }
 
+impl Parse for Field {
+    fn parse(input: ParseStream) -> Result<Field> {
+        let begin = input.cursor();
+        let id: syn::Ident = input.parse()?;
+        let _colon: syn::Token![:] = input.parse()?;
+        let ty: Type = input.parse()?;
+        Ok(Field {
+            id: id.to_string(),
+            ty,
+            span: span_from(begin, input),
+        })
+    }
+}
+
+impl Parse for StructDeclaration {
+    fn parse(input: ParseStream) -> Result<StructDeclaration> {
+        let begin = input.cursor();
+        // struct ident { a: i32, .. }
+        let _struct: syn::token::Struct = input.parse()?;
+        let id: syn::Ident = input.parse()?;
+        let content;
+        let _ = syn::braced!(content in input);
+        let fields: Punctuated<Field, Token![,]> = content.parse_terminated(Field::parse)?;
+        Ok(StructDeclaration {
+            id: id.to_string(),
+            fields: fields.into_iter().collect(),
+            span: span_from(begin, input),
+        })
+    }
+}
+
+#[test]
+fn test_struct_declaration() {
+    let ts: proc_macro2::TokenStream = "struct P { x: i32, next: &P, }".parse().unwrap();
+    let s: StructDeclaration = syn::parse2(ts).unwrap();
+    assert_eq!(s.id, "P");
+    assert_eq!(s.fields[1].ty, Type::Ref(Box::new(Type::Named("P".to_string()))));
+    assert_eq!(s.to_string(), "struct P { x: i32, next: &P }");
+}
+
+impl Parse for Variant {
+    fn parse(input: ParseStream) -> Result<Variant> {
+        let begin = input.cursor();
+        let id: syn::Ident = input.parse()?;
+        let (kind, fields) = if input.peek(syn::token::Paren) {
+            // B(i32, bool), the fields are named by position
+            let content;
+            let _ = syn::parenthesized!(content in input);
+            let types: Punctuated<Type, Token![,]> = content.parse_terminated(Type::parse)?;
+            let fields = types
+                .into_iter()
+                .enumerate()
+                .map(|(i, ty)| Field {
+                    id: i.to_string(),
+                    ty,
+                    span: span_from(begin, input),
+                })
+                .collect();
+            (VariantKind::Tuple, fields)
+        } else if input.peek(syn::token::Brace) {
+            // C { x: i32 }
+            let content;
+            let _ = syn::braced!(content in input);
+            let fields: Punctuated<Field, Token![,]> = content.parse_terminated(Field::parse)?;
+            (VariantKind::Struct, fields.into_iter().collect())
+        } else {
+            (VariantKind::Unit, vec![])
+        };
+        Ok(Variant {
+            id: id.to_string(),
+            kind,
+            fields,
+            span: span_from(begin, input),
+        })
+    }
+}
+
+impl Parse for EnumDeclaration {
+    fn parse(input: ParseStream) -> Result<EnumDeclaration> {
+        let begin = input.cursor();
+        // enum ident { A, B(i32), C { x: i32 } }
+        let _enum: syn::token::Enum = input.parse()?;
+        let id: syn::Ident = input.parse()?;
+        let content;
+        let _ = syn::braced!(content in input);
+        let variants: Punctuated<Variant, Token![,]> = content.parse_terminated(Variant::parse)?;
+        Ok(EnumDeclaration {
+            id: id.to_string(),
+            variants: variants.into_iter().collect(),
+            span: span_from(begin, input),
+        })
+    }
+}
+
+#[test]
+fn test_enum_declaration() {
+    let ts: proc_macro2::TokenStream = "enum E { A, B(i32, &E), C { x: bool } }".parse().unwrap();
+    let e: EnumDeclaration = syn::parse2(ts).unwrap();
+    assert_eq!(e.variants[1].kind, VariantKind::Tuple);
+    assert_eq!(e.variants[1].fields[1].id, "1");
+    assert_eq!(e.to_string(), "enum E { A, B(i32, &E), C { x: bool } }");
+}
+
+// A field of a struct variant pattern, `x: p` or the shorthand `x` for `x: x`
+struct FieldPattern(String, Pattern);
+
+impl Parse for FieldPattern {
+    fn parse(input: ParseStream) -> Result<FieldPattern> {
+        let begin = input.cursor();
+        let id: syn::Ident = input.parse()?;
+        if input.peek(Token![:]) {
+            let _colon: Token![:] = input.parse()?;
+            Ok(FieldPattern(id.to_string(), input.parse()?))
+        } else {
+            let kind = PatternKind::Ident(Mutable(false), id.to_string());
+            Ok(FieldPattern(
+                id.to_string(),
+                Pattern {
+                    kind,
+                    span: span_from(begin, input),
+                },
+            ))
+        }
+    }
+}
+
+// The fields of a variant pattern, in `(..)` or `{..}`, and whether they end with `..`
+fn parse_field_patterns<T: Parse>(content: ParseStream) -> Result<(Vec<T>, bool)> {
+    let mut fields = vec![];
+    while !content.is_empty() {
+        if content.peek(Token![..]) {
+            let _rest: Token![..] = content.parse()?;
+            if !content.is_empty() {
+                return Err(content.error("`..` must be at the end of the pattern"));
+            }
+            return Ok((fields, true));
+        }
+        fields.push(content.parse()?);
+        if !content.is_empty() {
+            let _comma: Token![,] = content.parse()?;
+        }
+    }
+    Ok((fields, false))
+}
+
+impl Parse for Pattern {
+    fn parse(input: ParseStream) -> Result<Pattern> {
+        let begin = input.cursor();
+        let kind = if input.peek(Token![_]) {
+            let _: Token![_] = input.parse()?;
+            PatternKind::Wild
+        } else if input.peek(syn::token::Mut) {
+            let _mut: syn::token::Mut = input.parse()?;
+            let id: syn::Ident = input.parse()?;
+            PatternKind::Ident(Mutable(true), id.to_string())
+        } else if input.peek(syn::Ident) && input.peek2(Token![::]) {
+            // E::A, E::B(p, ..) or E::C { x, y: p, .. }
+            let e: syn::Ident = input.parse()?;
+            let _colons: Token![::] = input.parse()?;
+            let v: syn::Ident = input.parse()?;
+            let (kind, fields, rest) = if input.peek(syn::token::Paren) {
+                let content;
+                let _ = syn::parenthesized!(content in input);
+                let (fields, rest) = parse_field_patterns::<Pattern>(&content)?;
+                let fields = fields
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, p)| (i.to_string(), p))
+                    .collect();
+                (VariantKind::Tuple, fields, rest)
+            } else if input.peek(syn::token::Brace) {
+                let content;
+                let _ = syn::braced!(content in input);
+                let (fields, rest) = parse_field_patterns::<FieldPattern>(&content)?;
+                let fields = fields.into_iter().map(|f| (f.0, f.1)).collect();
+                (VariantKind::Struct, fields, rest)
+            } else {
+                (VariantKind::Unit, vec![], false)
+            };
+            PatternKind::Variant(e.to_string(), v.to_string(), kind, fields, rest)
+        } else if input.peek(syn::Ident) {
+            let id: syn::Ident = input.parse()?;
+            PatternKind::Ident(Mutable(false), id.to_string())
+        } else if input.peek(syn::token::Paren) {
+            // (), (p), or a tuple (p,), (p, q)
+            let content;
+            let _ = syn::parenthesized!(content in input);
+            let pats: Punctuated<Pattern, Token![,]> = content.parse_terminated(Pattern::parse)?;
+            if pats.is_empty() {
+                PatternKind::Lit(Literal::Unit)
+            } else if pats.len() == 1 && !pats.trailing_punct() {
+                return Ok(pats.into_iter().next().unwrap());
+            } else {
+                PatternKind::Tuple(pats.into_iter().collect())
+            }
+        } else if input.peek(Token![-]) {
+            let _neg: Token![-] = input.parse()?;
+            let l = match input.peek(syn::LitInt) {
+                true => parse_int(&input.parse()?, true)?,
+                false => match input.parse()? {
+                    Literal::Float(x) => Literal::Float(-x),
+                    l => l,
+                },
+            };
+            match l {
+                Literal::Float(_) => PatternKind::Lit(l),
+                l => match l.int_value() {
+                    Some((_, t)) if t.signed() => PatternKind::Lit(l),
+                    _ => return Err(input.error("expected a signed integer or a float")),
+                },
+            }
+        } else {
+            PatternKind::Lit(input.parse()?)
+        };
+        Ok(Pattern {
+            kind,
+            span: span_from(begin, input),
+        })
+    }
+}
+
+impl Parse for Arm {
+    fn parse(input: ParseStream) -> Result<Arm> {
+        let begin = input.cursor();
+        let pat: Pattern = input.parse()?;
+        let _arrow: Token![=>] = input.parse()?;
+        let body: Expr = input.parse()?;
+        Ok(Arm {
+            pat,
+            body,
+            span: span_from(begin, input),
+        })
+    }
+}
+
+#[test]
+fn test_pattern() {
+    let ts: proc_macro2::TokenStream = "E::C { x, y: E::B(-1, _, ..), .. }".parse().unwrap();
+    let p: Pattern = syn::parse2(ts).unwrap();
+    assert_eq!(p.to_string(), "E::C { x: x, y: E::B(-1, _, ..), .. }");
+    let ts: proc_macro2::TokenStream = "E::B(.., x)".parse().unwrap();
+    assert!(syn::parse2::<Pattern>(ts).is_err());
+}
+
+#[test]
+fn test_tuple() {
+    let ts: proc_macro2::TokenStream = "let (a, (mut b,), _): (i32, (bool,), ()) = ((1), (t.0.1,), ())".parse().unwrap();
+    let stmt: Statement = syn::parse2(ts).unwrap();
+    assert_eq!(stmt.to_string(), "let (a, (mut b,), _): (i32, (bool,), ()) = ((1), (t.0.1,), ());\n");
+    match &stmt.kind {
+        StatementKind::Let(p, _, _) => assert_eq!(p.bindings(), ["a", "b"]),
+        _ => panic!("expected a let, got {:?}", stmt),
+    }
+    let ts: proc_macro2::TokenStream = "fn f((a, b): (i32, i32), mut c: i32) {}".parse().unwrap();
+    let f: FnDeclaration = syn::parse2(ts).unwrap();
+    assert_eq!(f.parameters.0[0].pat.to_string(), "(a, b)");
+    let ts: proc_macro2::TokenStream = "t.1e3".parse().unwrap();
+    assert!(syn::parse2::<Expr>(ts).is_err());
+}
+
+#[test]
+fn test_array() {
+    let ts: proc_macro2::TokenStream = "let a: [[i32; 2]; 3] = [[0; 2], [1, 2], [3, 4,]]".parse().unwrap();
+    let stmt: Statement = syn::parse2(ts).unwrap();
+    assert_eq!(stmt.to_string(), "let a: [[i32; 2]; 3] = [[0; 2], [1, 2], [3, 4]];\n");
+    let ts: proc_macro2::TokenStream = "(*r)[i + 1][0].len()".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    match &e.kind {
+        ExprKind::Method(e, m, args) => {
+            assert_eq!(e.to_string(), "(*r)[i + 1][0]");
+            assert_eq!(m, "len");
+            assert!(args.0.is_empty());
+        }
+        _ => panic!("expected a method call, got {:?}", e),
+    }
+    let ts: proc_macro2::TokenStream = "[0; n]".parse().unwrap();
+    assert!(syn::parse2::<Expr>(ts).is_err());
+}
+
+#[test]
+fn test_vec() {
+    let ts: proc_macro2::TokenStream = "let v: Vec<Vec<i32>> = vec![vec![0; n + 1], Vec::new(), vec![1, 2,]]".parse().unwrap();
+    let stmt: Statement = syn::parse2(ts).unwrap();
+    assert_eq!(stmt.to_string(), "let v: Vec<Vec<i32>> = vec![vec![0; n + 1], Vec::new(), vec![1, 2]];\n");
+    let ts: proc_macro2::TokenStream = "Vec::new().len()".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    assert!(matches!(e.kind, ExprKind::Method(..)));
+}
+
+#[test]
+fn test_int_types() {
+    let ts: proc_macro2::TokenStream = "-a as u8 + 255u8 as i64".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    assert_eq!(e.to_string(), "-a as u8 + 255 as i64");
+    assert!(matches!(e.kind, ExprKind::BinOp(Op::Add, ..)));
+    let ts: proc_macro2::TokenStream = "let x: usize = 10usize".parse().unwrap();
+    let stmt: Statement = syn::parse2(ts).unwrap();
+    assert_eq!(stmt.to_string(), "let x: usize = 10;\n");
+    let ts: proc_macro2::TokenStream = "256u8".parse().unwrap();
+    let e: Result<Expr> = syn::parse2(ts);
+    assert_eq!(e.unwrap_err().to_string(), "literal out of range for `u8`");
+    let ts: proc_macro2::TokenStream = "1u7".parse().unwrap();
+    let e: Result<Expr> = syn::parse2(ts);
+    assert_eq!(e.unwrap_err().to_string(), "invalid suffix `u7` for number literal");
+}
+
+#[test]
+fn test_int_min() {
+    let ts: proc_macro2::TokenStream = "-2147483648".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    assert_eq!(e.kind, ExprKind::Lit(Literal::Unsuffixed(i32::MIN as i128)));
+    let ts: proc_macro2::TokenStream = "-128i8 as i32".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    match &e.kind {
+        ExprKind::Cast(l, _) => assert_eq!(l.kind, ExprKind::Lit(Literal::Sized(-128, IntTy::I8))),
+        _ => panic!("expected a cast, got {:?}", e),
+    }
+    // the range of a literal without a suffix is checked by the type checker
+    let ts: proc_macro2::TokenStream = "-2147483649".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    assert_eq!(e.kind, ExprKind::Lit(Literal::Unsuffixed(-2147483649)));
+    let ts: proc_macro2::TokenStream = "-1u8".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    assert!(matches!(e.kind, ExprKind::UnOp(UnOp::Neg, _)));
+    let ts: proc_macro2::TokenStream = "18446744073709551616".parse().unwrap();
+    let e: Result<Expr> = syn::parse2(ts);
+    assert_eq!(e.unwrap_err().to_string(), "integer literal is too large");
+    let ts: proc_macro2::TokenStream = "-128i8".parse().unwrap();
+    let p: Pattern = syn::parse2(ts).unwrap();
+    assert_eq!(p.kind, PatternKind::Lit(Literal::Sized(-128, IntTy::I8)));
+}
+
+#[test]
+fn test_string() {
+    let ts: proc_macro2::TokenStream = "for c in s.to_string().chars() { }".parse().unwrap();
+    let stmt: Statement = syn::parse2(ts).unwrap();
+    match &stmt.kind {
+        StatementKind::For(_, _, Iter::Chars(s), _) => assert_eq!(s.to_string(), "s.to_string()"),
+        _ => panic!("expected a for over chars, got {:?}", stmt),
+    }
+    let ts: proc_macro2::TokenStream = "let s: &str = \"a\\n\"; let t: String = String::from(s) + \"b\";".parse().unwrap();
+    let b: Block = syn::parse2(quote! {{ #ts }}).unwrap();
+    assert_eq!(b.to_string(), "let s: &str = \"a\\n\";\nlet t: String = String::from(s) + \"b\";\n");
+}
+
+#[test]
+fn test_expr_match() {
+    let ts: proc_macro2::TokenStream = "match e { E::A => 1, E::B(x) => { x } mut y => y + 1 }".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    match &e.kind {
+        ExprKind::Match(e, arms) => {
+            assert_eq!(e.to_string(), "e");
+            assert_eq!(arms.len(), 3);
+            assert_eq!(arms[2].pat.kind, PatternKind::Ident(Mutable(true), "y".to_string()));
+        }
+        _ => panic!("expected a match, got {:?}", e),
+    }
+    let ts: proc_macro2::TokenStream = "match e { 1 => 2 3 => 4 }".parse().unwrap();
+    assert!(syn::parse2::<Expr>(ts).is_err());
+}
+
+#[test]
+fn test_expr_struct() {
+    let ts: proc_macro2::TokenStream = "P { x: 1, y }.x".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    assert_eq!(e.to_string(), "P { x: 1, y: y }.x");
+    // the block of an `if` is not a struct literal
+    let ts: proc_macro2::TokenStream = "if a.b { c.d.e }".parse().unwrap();
+    let e: Expr = syn::parse2(ts).unwrap();
+    match &e.kind {
+        ExprKind::IfThenElse(c, _, None) => assert_eq!(c.to_string(), "a.b"),
+        _ => panic!("expected an if, got {:?}", e),
+    }
+}
+
 impl Parse for Statement {
     fn parse(input: ParseStream) -> Result<Statement> {
+        let begin = input.cursor();
+        let kind = StatementKind::parse(input)?;
+        Ok(Statement::new(kind, span_from(begin, input)))
+    }
+}
+
+// `'label: while` and `'label: for` are statements, labeled `loop`s are expressions
+fn is_labeled_while(input: ParseStream) -> bool {
+    let fork = input.fork();
+    matches!(parse_label(&fork), Ok(Some(_))) && fork.peek(syn::token::While)
+}
+
+fn is_labeled_for(input: ParseStream) -> bool {
+    let fork = input.fork();
+    matches!(parse_label(&fork), Ok(Some(_))) && fork.peek(syn::token::For)
+}
+
+// A block like expression at the start of a statement, unless a method
+// call, field access or index follows, e.g., `match e { .. }.len() + 1`
+fn block_like(input: ParseStream) -> Result<Option<Expr>> {
+    if !(input.peek(syn::token::If)
+        || input.peek(syn::token::Loop)
+        || input.peek(syn::token::Match)
+        || input.peek(syn::token::Brace)
+        || input.peek(syn::Lifetime))
+    {
+        return Ok(None);
+    }
+    let fork = input.fork();
+    let e = parse_unary(&fork)?;
+    match e.kind {
+        ExprKind::Block(_)
+        | ExprKind::IfThenElse(..)
+        | ExprKind::Loop(..)
+        | ExprKind::Match(..) => {
+            input.advance_to(&fork);
+            Ok(Some(e))
+        }
+        _ => Ok(None),
+    }
+}
+
+impl Parse for Range {
+    fn parse(input: ParseStream) -> Result<Range> {
+        if input.peek(syn::token::Paren) {
+            // (a..b).rev()
+            let content;
+            let _ = syn::parenthesized!(content in input);
+            let range: Range = content.parse()?;
+            let _dot: Token![.] = input.parse()?;
+            let rev: syn::Ident = input.parse()?;
+            if rev != "rev" {
+                return Err(syn::Error::new(rev.span(), "expected `rev()`"));
+            }
+            let args;
+            let _ = syn::parenthesized!(args in input);
+            if !args.is_empty() {
+                return Err(args.error("`rev` takes no arguments"));
+            }
+            return Ok(Range { rev: !range.rev, ..range });
+        }
+        let start: Expr = input.parse()?;
+        let inclusive = if input.peek(Token![..=]) {
+            let _: Token![..=] = input.parse()?;
+            true
+        } else {
+            let _: Token![..] = input.parse()?;
+            false
+        };
+        let end: Expr = input.parse()?;
+        Ok(Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            inclusive,
+            rev: false,
+        })
+    }
+}
+
+impl Parse for Iter {
+    fn parse(input: ParseStream) -> Result<Iter> {
+        // s.chars(), all of the tokens up to the body
+        let fork = input.fork();
+        let chars = matches!(fork.parse(), Ok(Expr { kind: ExprKind::Method(_, m, args), .. })
+            if m == "chars" && args.0.is_empty());
+        if chars && fork.is_empty() {
+            if let ExprKind::Method(s, ..) = input.parse::<Expr>()?.kind {
+                return Ok(Iter::Chars(s));
+            }
+        }
+        Ok(Iter::Range(input.parse()?))
+    }
+}
+
+impl StatementKind {
+    fn parse(input: ParseStream) -> Result<StatementKind> {
         if input.peek(syn::token::Fn) {
             // fn
             let fn_: FnDeclaration = input.parse()?;
-            Ok(Statement::Fn(fn_))
+            Ok(StatementKind::Fn(fn_))
+        } else if input.peek(syn::token::Struct) {
+            // struct
+            let struct_: StructDeclaration = input.parse()?;
+            Ok(StatementKind::Struct(struct_))
+        } else if input.peek(syn::token::Enum) {
+            // enum
+            let enum_: EnumDeclaration = input.parse()?;
+            Ok(StatementKind::Enum(enum_))
         } else if input.peek(syn::token::Let) {
             // let ...
             let _let: syn::token::Let = input.parse()?;
 
-            // let mut ...
-            let m = if input.peek(syn::token::Mut) {
-                let _mut: syn::token::Mut = input.parse()?;
-                Mutable(true)
-            } else {
-                Mutable(false)
-            };
-
-            // let mut a ...
-            let id: syn::Ident = input.parse()?;
-            let id = id.to_string();
+            // let a, let mut a or let (a, mut b) ...
+            let pat: Pattern = input.parse()?;
 
             // let a: i32 ...
             let ty = if input.peek(Token![:]) {
@@ -694,15 +1971,29 @@ impl Parse for Statement {
                 None
             };
 
-            Ok(Statement::Let(m, id, ty, right))
-        } else if input.peek(syn::token::While) {
-            // while a {}
+            Ok(StatementKind::Let(pat, ty, right))
+        } else if input.peek(syn::token::While) || is_labeled_while(input) {
+            // ['label:] while a {}
+            let label = parse_label(input)?;
             let _while: syn::token::While = input.parse()?;
 
-            let e: Expr = input.parse()?;
+            let e: Expr = parse_cond(input)?;
 
             let bl: Block = input.parse()?;
-            Ok(Statement::While(e, bl))
+            Ok(StatementKind::While(label, e, bl))
+        } else if input.peek(syn::token::For) || is_labeled_for(input) {
+            // ['label:] for i in a..b {} or for c in s.chars() {}
+            let label = parse_label(input)?;
+            let _for: syn::token::For = input.parse()?;
+            let id: syn::Ident = input.parse()?;
+            let _in: Token![in] = input.parse()?;
+            let iter: Iter = parse_cond(input)?;
+            let bl: Block = input.parse()?;
+            Ok(StatementKind::For(label, id.to_string(), iter, bl))
+        } else if let Some(e) = block_like(input)? {
+            // if c { .. }, loop { .. }, match e { .. } or { .. } ends the
+            // statement, e.g., `if c { a = 2; } *r = 1` are two statements
+            Ok(StatementKind::Expr(e))
         } else {
             // a = 1 + 2, as a assignment
             // 1 + 2, as an expression
@@ -713,10 +2004,10 @@ impl Parse for Statement {
                 let _eq: syn::token::Eq = input.parse()?;
                 let right: Expr = input.parse()?;
 
-                Ok(Statement::Assign(left, right))
+                Ok(StatementKind::Assign(left, right))
             } else {
                 // 1 + 2
-                Ok(Statement::Expr(left))
+                Ok(StatementKind::Expr(left))
             }
         }
     }
@@ -730,12 +2021,12 @@ fn test_statement_let_ty_expr() {
 
     assert_eq!(
         stmt,
-        Statement::Let(
-            Mutable(false),
-            "a".to_string(),
+        StatementKind::Let(
+            PatternKind::Ident(Mutable(false), "a".to_string()).into(),
             Some(Type::I32),
-            Some(Expr::Lit(Literal::Int(2)))
+            Some(Literal::Unsuffixed(2).into())
         )
+        .into()
     );
 }
 
@@ -747,12 +2038,12 @@ fn test_statement_let_mut_ty_expr() {
 
     assert_eq!(
         stmt,
-        Statement::Let(
-            Mutable(true),
-            "a".to_string(),
+        StatementKind::Let(
+            PatternKind::Ident(Mutable(true), "a".to_string()).into(),
             Some(Type::I32),
-            Some(Expr::Lit(Literal::Int(2)))
+            Some(Literal::Unsuffixed(2).into())
         )
+        .into()
     );
 }
 
@@ -764,7 +2055,12 @@ fn test_statement_let() {
 
     assert_eq!(
         stmt,
-        Statement::Let(Mutable(false), "a".to_string(), None, None,)
+        StatementKind::Let(
+            PatternKind::Ident(Mutable(false), "a".to_string()).into(),
+            None,
+            None,
+        )
+        .into()
     );
 }
 
@@ -776,10 +2072,11 @@ fn test_statement_assign() {
 
     assert_eq!(
         stmt,
-        Statement::Assign(
-            Expr::Ident("a".to_string()),
-            Expr::Lit(Literal::Bool(false))
+        StatementKind::Assign(
+            ExprKind::Ident("a".to_string()).into(),
+            Literal::Bool(false).into()
         )
+        .into()
     );
 }
 
@@ -791,13 +2088,16 @@ fn test_statement_while() {
 
     assert_eq!(
         stmt,
-        Statement::While(
-            Expr::Ident("a".to_string()),
+        StatementKind::While(
+            None,
+            ExprKind::Ident("a".to_string()).into(),
             Block {
                 statements: vec![],
-                semi: false
+                semi: false,
+                span: Span::default(),
             }
         )
+        .into()
     );
 }
 
@@ -806,7 +2106,20 @@ fn test_statement_expr() {
     let ts: proc_macro2::TokenStream = "a".parse().unwrap();
     let stmt: Statement = syn::parse2(ts).unwrap();
     println!("stmt {:?}", stmt);
-    assert_eq!(stmt, Statement::Expr(Expr::Ident("a".to_string())));
+    assert_eq!(
+        stmt,
+        StatementKind::Expr(ExprKind::Ident("a".to_string()).into()).into()
+    );
+}
+
+#[test]
+fn test_statement_span() {
+    let ts: proc_macro2::TokenStream = "{\n    let a = 1;\n    a = a + 2\n}".parse().unwrap();
+    let bl: Block = syn::parse2(ts).unwrap();
+    assert_eq!((bl.span.lo.line, bl.span.hi.line), (1, 4));
+    let assign = &bl.statements[1];
+    assert_eq!((assign.span.lo.line, assign.span.lo.column), (3, 4));
+    assert_eq!((assign.span.hi.line, assign.span.hi.column), (3, 13));
 }
 
 use syn::punctuated::Punctuated;
@@ -834,6 +2147,7 @@ use syn::punctuated::Punctuated;
 // Here we take advantage of the parser function `parse_terminated`
 impl Parse for Block {
     fn parse(input: ParseStream) -> Result<Block> {
+        let begin = input.cursor();
         let content;
         let _ = syn::braced!(content in input);
 
@@ -847,14 +2161,24 @@ impl Parse for Block {
                 let _: Token![;] = content.parse()?;
                 has_semi = true;
             }
-            match stmt {
-                Statement::Let(_, _, _, _) => {
+            match stmt.kind {
+                StatementKind::Let(_, _, _) => {
                     if !has_semi {
                         // generate an error (we know that it is not a ";")
                         let _: Token![;] = content.parse()?;
                     }
                 }
-                Statement::Assign(_, _) | Statement::Expr(_) => {
+                StatementKind::Expr(Expr {
+                    kind:
+                        ExprKind::Block(_)
+                        | ExprKind::IfThenElse(..)
+                        | ExprKind::Loop(..)
+                        | ExprKind::Match(..),
+                    ..
+                }) => {
+                    // block like expressions may or may not be trailed by ";"
+                }
+                StatementKind::Assign(_, _) | StatementKind::Expr(_) => {
                     if !content.is_empty() {
                         if !has_semi {
                             // generate an error (we know that it is not a ";")
@@ -863,7 +2187,11 @@ impl Parse for Block {
                     }
                 }
 
-                Statement::While(_, _) | Statement::Fn(_) => {
+                StatementKind::While(..)
+                | StatementKind::For(..)
+                | StatementKind::Fn(_)
+                | StatementKind::Struct(_)
+                | StatementKind::Enum(_) => {
                     // these may or may not be trailed by ";"
                     // so we do nothing
                 }
@@ -876,6 +2204,7 @@ impl Parse for Block {
             // turn the Punctuated into a vector
             statements,
             semi,
+            span: span_from(begin, input),
         })
     }
 }
@@ -888,6 +2217,55 @@ fn test_block_expr_fail() {
     assert_eq!(stmt.is_err(), true);
 }
 
+#[test]
+fn test_block_block_stmt() {
+    let ts: proc_macro2::TokenStream = "
+    {
+        { let b = 2; }
+        if true { 1; } else { 2; }
+        a
+    }"
+    .parse()
+    .unwrap();
+    let bl: Block = syn::parse2(ts).unwrap();
+    assert_eq!(bl.statements.len(), 3);
+    assert!(!bl.semi);
+}
+
+#[test]
+fn test_block_block_like_stmt() {
+    let ts: proc_macro2::TokenStream = "
+    {
+        if c { a = 2; } *r = 1;
+        loop { break; } -x;
+        match a { _ => {} } (1, 2);
+        { b } (c);
+        match v { _ => v }.len() + 1
+    }"
+    .parse()
+    .unwrap();
+    let bl: Block = syn::parse2(ts).unwrap();
+    println!("bl {}", bl);
+    assert_eq!(bl.statements.len(), 9);
+    assert!(matches!(
+        &bl.statements[1].kind,
+        StatementKind::Assign(
+            Expr {
+                kind: ExprKind::UnOp(UnOp::DeRef, _),
+                ..
+            },
+            _
+        )
+    ));
+    assert!(matches!(
+        &bl.statements[8].kind,
+        StatementKind::Expr(Expr {
+            kind: ExprKind::BinOp(..),
+            ..
+        })
+    ));
+}
+
 #[test]
 fn test_block_semi() {
     let ts: proc_macro2::TokenStream = "
@@ -975,12 +2353,26 @@ fn test_block_fail() {
 impl Parse for Prog {
     fn parse(input: ParseStream) -> Result<Prog> {
         let mut fns = vec![];
-        while input.peek(syn::token::Fn) {
-            let fn_: FnDeclaration = input.parse()?;
-            fns.push(fn_);
+        let mut structs = vec![];
+        let mut enums = vec![];
+        loop {
+            // outer attributes, e.g., `#[allow(dead_code)]`, are ignored
+            input.call(syn::Attribute::parse_outer)?;
+            if input.peek(syn::token::Fn) {
+                let fn_: FnDeclaration = input.parse()?;
+                fns.push(fn_);
+            } else if input.peek(syn::token::Struct) {
+                let struct_: StructDeclaration = input.parse()?;
+                structs.push(struct_);
+            } else if input.peek(syn::token::Enum) {
+                let enum_: EnumDeclaration = input.parse()?;
+                enums.push(enum_);
+            } else {
+                break;
+            }
         }
 
-        Ok(Prog(fns))
+        Ok(Prog(fns, structs, enums))
     }
 }
 
@@ -1000,6 +2392,22 @@ fn test_prog() {
     println!("prog\n{}", pr.unwrap());
 }
 
+#[test]
+fn test_prog_attributes() {
+    let ts: proc_macro2::TokenStream = "
+    #[allow(dead_code)]
+    fn a() {}
+
+    #[allow(unused_variables)]
+    #[allow(unconditional_recursion)]
+    fn main() {}
+    "
+    .parse()
+    .unwrap();
+    let pr: Prog = syn::parse2(ts).unwrap();
+    assert_eq!(pr.0.len(), 2);
+}
+
 #[test]
 fn test_ref_de_ref() {
     let ts: proc_macro2::TokenStream = "
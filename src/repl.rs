@@ -0,0 +1,454 @@
+// Read-eval-print loop, run by `rnr repl`
+//
+// Each entry (a statement, fn or struct declaration, or expression) is type checked
+// and evaluated in persistent environments, entries with unbalanced
+// delimiters continue on the next line.
+
+use crate::ast::{Expr, ExprKind, Literal, Statement, StatementKind};
+use crate::common::Eval;
+use crate::env::Env;
+use crate::error::*;
+use crate::intrinsics::vm_println;
+use crate::type_check::{literal_types, resolve_ty, Ty};
+use crate::vm::{text, Val};
+
+const HELP: &str = "\
+:type <expr>  show the type of an expression
+:env          list the variables and functions in scope
+:reset        clear the environment
+:help         show this message";
+
+pub struct Repl {
+    ty: Env<Ty>,
+    val: Env<Val>,
+    // the lines of an unfinished entry
+    buffer: String,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        let mut ty = Env::new();
+        let mut val = Env::new();
+        ty.v.push_scope();
+        val.v.push_scope();
+        let (print, intrinsic) = vm_println();
+        ty.f.0
+            .insert(print.id.clone(), (print.clone(), Some(intrinsic)));
+        val.f.0.insert(print.id.clone(), (print, Some(intrinsic)));
+        Repl {
+            ty,
+            val,
+            buffer: String::new(),
+        }
+    }
+
+    // true while an entry spans several lines
+    pub fn is_continued(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    // Adds a line of input, returning the entry once its delimiters are balanced.
+    pub fn feed(&mut self, line: &str) -> Option<String> {
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        match depth(&self.buffer) > 0 {
+            true => None,
+            false => Some(std::mem::take(&mut self.buffer)),
+        }
+    }
+
+    // Evaluates an entry, returning the text to show (if any).
+    pub fn eval(&mut self, entry: &str) -> Result<Option<String>, Error> {
+        let entry = entry.trim();
+        if let Some(command) = entry.strip_prefix(':') {
+            return self.command(command);
+        }
+        // a trailing `;` is optional
+        let entry = entry.trim_end_matches(';');
+        if entry.is_empty() {
+            return Ok(None);
+        }
+        let stmt: Statement = parse(entry)?;
+        self.run(&stmt).map_err(|mut err| {
+            // labels may point into earlier entries, which are not shown
+            err.labels.clear();
+            err
+        })
+    }
+
+    fn run(&mut self, stmt: &Statement) -> Result<Option<String>, Error> {
+        // the environments are only updated if the entry succeeds
+        let mut ty = self.ty.clone();
+        let mut val = self.val.clone();
+        match &stmt.kind {
+            StatementKind::Fn(decl) => {
+                ty.f.0.insert(decl.id.clone(), (decl.clone(), None));
+            }
+            StatementKind::Struct(decl) => {
+                ty.s.0.insert(decl.id.clone(), decl.clone());
+                val.s.0.insert(decl.id.clone(), decl.clone());
+            }
+            StatementKind::Enum(decl) => {
+                ty.e.0.insert(decl.id.clone(), decl.clone());
+                val.e.0.insert(decl.id.clone(), decl.clone());
+            }
+            _ => (),
+        }
+        // the spans of calls and literals only identify them within the entry
+        ty.inst.calls.clear();
+        ty.lits.lits.clear();
+        let instances = ty.inst.fns.len();
+        let moved = ty.moves.moved.clone();
+        stmt.eval(&mut ty)?;
+        // showing a variable (or a field of it) reads it, and does not move it
+        if let StatementKind::Expr(e) = &stmt.kind {
+            if matches!(e.kind, ExprKind::Ident(_) | ExprKind::Field(..)) {
+                ty.moves.moved = moved;
+            }
+        }
+        let lits = literal_types(&mut ty)?;
+        // calls of generic functions go to the instances checked for them
+        let stmt = &stmt.rename_calls(&ty.inst.calls).type_literals(&lits);
+        for f in &ty.inst.fns[instances..] {
+            val.f.0.insert(f.id.clone(), (f.type_literals(&lits), None));
+        }
+        let (v, _) = stmt.eval(&mut val)?;
+        let out = match (&stmt.kind, &v) {
+            (StatementKind::Expr(_), Val::Lit(Literal::Unit)) => None,
+            (StatementKind::Expr(_), v) => Some(show(v, &val)),
+            _ => None,
+        };
+        self.ty = ty;
+        self.val = val;
+        Ok(out)
+    }
+
+    fn command(&mut self, command: &str) -> Result<Option<String>, Error> {
+        let (name, arg) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        match name {
+            "type" => {
+                let e: Expr = parse(arg)?;
+                let mut ty = self.ty.clone();
+                let (t, _) = e.eval(&mut ty)?;
+                // an integer literal is `i32` unless inferred otherwise
+                literal_types(&mut ty)?;
+                Ok(Some(resolve_ty(t, &ty).to_string()))
+            }
+            "env" => {
+                let env = self.env();
+                Ok((!env.is_empty()).then_some(env))
+            }
+            "reset" => {
+                *self = Repl::new();
+                Ok(None)
+            }
+            "help" => Ok(Some(HELP.to_string())),
+            _ => Err(Error::parse(format!("unknown command `:{}`", name))
+                .with_help("the commands are `:type <expr>`, `:env`, `:reset` and `:help`")),
+        }
+    }
+
+    // the variables (with type and value) and the user defined functions
+    fn env(&self) -> String {
+        let mut lines = vec![];
+        for (id, r) in self.val.v.bindings() {
+            // the types of integer literals are resolved, as for `:type`
+            let ty = match self.ty.v.get(&id) {
+                Some(t) => format!(": {}", resolve_ty(t, &self.ty)),
+                None => String::new(),
+            };
+            lines.push(format!(
+                "let {}{} = {}",
+                id,
+                ty,
+                show(&self.val.v.de_ref(r), &self.val)
+            ));
+        }
+        let mut fns: Vec<_> = self
+            .val
+            .f
+            .0
+            .values()
+            .filter(|(decl, intrinsic)| intrinsic.is_none() && !decl.id.contains("::<"))
+            .map(|(decl, _)| {
+                let parameters: Vec<String> = decl
+                    .parameters
+                    .0
+                    .iter()
+                    .map(|p| format!("{}: {}", p.pat, p.ty))
+                    .collect();
+                let ty = match &decl.ty {
+                    Some(t) => format!(" -> {}", t),
+                    None => String::new(),
+                };
+                let generics = match decl.generics.is_empty() {
+                    true => String::new(),
+                    false => format!("<{}>", decl.generics.join(", ")),
+                };
+                format!(
+                    "fn {}{}({}){}",
+                    decl.id,
+                    generics,
+                    parameters.join(", "),
+                    ty
+                )
+            })
+            .collect();
+        fns.sort();
+        lines.extend(fns);
+        lines.join("\n")
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse<T: syn::parse::Parse>(s: &str) -> Result<T, Error> {
+    let ts: proc_macro2::TokenStream = s.parse()?;
+    Ok(syn::parse2(ts)?)
+}
+
+// a value, following references
+fn show(v: &Val, env: &Env<Val>) -> String {
+    match v {
+        Val::Lit(Literal::String(s)) => format!("{:?}", s),
+        Val::String(_) => format!("{:?}", text(v, &env.v).unwrap_or_default()),
+        Val::Lit(Literal::Float(x)) => format!("{:?}", x),
+        Val::Lit(Literal::Char(c)) => format!("{:?}", c),
+        Val::Lit(l) => l.to_string(),
+        Val::Ref(r) => match env.v.try_de_ref(r.clone()) {
+            Ok(v) => format!("&{}", show(&v, env)),
+            Err(_) => "&<dangling>".to_string(),
+        },
+        Val::Struct(s, fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(id, v)| format!("{}: {}", id, show(v, env)))
+                .collect();
+            format!("{} {{ {} }}", s, fields.join(", "))
+        }
+        // the fields of tuple variants are named by position
+        Val::Enum(e, v, fields) => match fields.first() {
+            None => format!("{}::{}", e, v),
+            Some((id, _)) if id == "0" => {
+                let fields: Vec<String> = fields.iter().map(|(_, v)| show(v, env)).collect();
+                format!("{}::{}({})", e, v, fields.join(", "))
+            }
+            Some(_) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(id, v)| format!("{}: {}", id, show(v, env)))
+                    .collect();
+                format!("{}::{} {{ {} }}", e, v, fields.join(", "))
+            }
+        },
+        Val::Tuple(vs) => match vs.as_slice() {
+            [v] => format!("({},)", show(v, env)),
+            _ => {
+                let vs: Vec<String> = vs.iter().map(|v| show(v, env)).collect();
+                format!("({})", vs.join(", "))
+            }
+        },
+        Val::Array(vs) => {
+            let vs: Vec<String> = vs.iter().map(|v| show(v, env)).collect();
+            format!("[{}]", vs.join(", "))
+        }
+        Val::Vec(addr) => {
+            let vs: Vec<String> = env
+                .v
+                .heap_elems(*addr)
+                .iter()
+                .map(|v| show(v, env))
+                .collect();
+            format!("vec![{}]", vs.join(", "))
+        }
+        Val::Fn(id) => format!("fn {}", id),
+        Val::Closure(c, _) => c.to_string(),
+        Val::Code(..) => "<closure>".to_string(),
+        Val::UnInit => "<uninitialized>".to_string(),
+    }
+}
+
+// the nesting of delimiters at the end of `s`, skipping strings and comments
+fn depth(s: &str) -> i32 {
+    let mut depth = 0;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => (),
+                    }
+                }
+            }
+            // a char literal, `'x'` or `'\x'`, rather than a label `'outer`
+            '\'' => {
+                let mut ahead = chars.clone();
+                match (ahead.next(), ahead.next()) {
+                    (Some('\\'), _) => {
+                        chars.next();
+                        chars.next();
+                        for c in chars.by_ref() {
+                            if c == '\'' {
+                                break;
+                            }
+                        }
+                    }
+                    (Some(_), Some('\'')) => {
+                        chars.next();
+                        chars.next();
+                    }
+                    _ => (),
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repl;
+
+    fn eval(repl: &mut Repl, entry: &str) -> Option<String> {
+        repl.eval(entry).unwrap()
+    }
+
+    #[test]
+    fn persistent_env() {
+        let mut repl = Repl::new();
+        assert_eq!(eval(&mut repl, "let a = 1;"), None);
+        assert_eq!(eval(&mut repl, "let b = &a"), None);
+        assert_eq!(eval(&mut repl, "a + 2"), Some("3".to_string()));
+        assert_eq!(eval(&mut repl, "b"), Some("&1".to_string()));
+        assert_eq!(eval(&mut repl, ":type a + 2"), Some("i32".to_string()));
+        assert_eq!(
+            eval(&mut repl, ":env"),
+            Some("let a: i32 = 1\nlet b: &i32 = &1".to_string())
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let mut repl = Repl::new();
+        eval(&mut repl, "fn add(a: i32, b: i32) -> i32 { a + b }");
+        assert_eq!(eval(&mut repl, "add(1, 2)"), Some("3".to_string()));
+        eval(&mut repl, "let c = true");
+        assert_eq!(
+            eval(&mut repl, ":env"),
+            Some("let c: bool = true\nfn add(a: i32, b: i32) -> i32".to_string())
+        );
+    }
+
+    #[test]
+    fn generic_functions() {
+        let mut repl = Repl::new();
+        eval(
+            &mut repl,
+            "fn max<T>(a: T, b: T) -> T { if a > b { a } else { b } }",
+        );
+        assert_eq!(eval(&mut repl, "max(1, 2)"), Some("2".to_string()));
+        eval(&mut repl, "fn last() -> char { max('a', 'z') }");
+        assert_eq!(eval(&mut repl, "last()"), Some("'z'".to_string()));
+        assert_eq!(
+            eval(&mut repl, "max::<bool>(true, false)"),
+            Some("true".to_string())
+        );
+        eval(&mut repl, "let b: u8 = max(1, 255);");
+        assert_eq!(eval(&mut repl, "b"), Some("255".to_string()));
+        // the instances are not listed
+        assert_eq!(
+            eval(&mut repl, ":env"),
+            Some("let b: u8 = 255\nfn last() -> char\nfn max<T>(a: T, b: T) -> T".to_string())
+        );
+    }
+
+    #[test]
+    fn integer_literals() {
+        let mut repl = Repl::new();
+        eval(&mut repl, "let x: u8 = 250;");
+        assert_eq!(eval(&mut repl, "x + 5"), Some("255".to_string()));
+        assert_eq!(eval(&mut repl, ":type x + 5"), Some("u8".to_string()));
+        assert_eq!(eval(&mut repl, ":type 1"), Some("i32".to_string()));
+        assert_eq!(
+            eval(&mut repl, ":type vec![1].len()"),
+            Some("usize".to_string())
+        );
+        assert!(repl.eval("x + 6").is_err());
+        assert!(repl.eval("let y: u8 = 256;").is_err());
+    }
+
+    #[test]
+    fn moves() {
+        let mut repl = Repl::new();
+        eval(&mut repl, "let s = String::from(\"ab\");");
+        assert_eq!(eval(&mut repl, "s"), Some("\"ab\"".to_string()));
+        assert_eq!(eval(&mut repl, "s.len()"), Some("2".to_string()));
+        eval(&mut repl, "let t = s;");
+        assert!(repl.eval("s.len()").is_err());
+        assert_eq!(eval(&mut repl, "t.len()"), Some("2".to_string()));
+    }
+
+    #[test]
+    fn multi_line() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.feed("fn f() -> i32 {"), None);
+        assert!(repl.is_continued());
+        assert_eq!(repl.feed("    \"}\" // }"), None);
+        let entry = repl.feed("}").unwrap();
+        assert_eq!(entry, "fn f() -> i32 {\n    \"}\" // }\n}\n");
+        assert!(!repl.is_continued());
+    }
+
+    #[test]
+    fn char_delimiters() {
+        let mut repl = Repl::new();
+        assert!(repl.feed("let c = '{';").is_some());
+        assert!(repl.feed("let d = '\\'';").is_some());
+        assert_eq!(repl.feed("'outer: loop {"), None);
+        assert_eq!(repl.feed("    if '}' == '\\u{7d}' { break 'outer; }"), None);
+        let entry = repl.feed("}").unwrap();
+        assert_eq!(
+            entry,
+            "'outer: loop {\n    if '}' == '\\u{7d}' { break 'outer; }\n}\n"
+        );
+    }
+
+    #[test]
+    fn errors_keep_env() {
+        let mut repl = Repl::new();
+        eval(&mut repl, "let a = 1");
+        assert!(repl.eval("let a = b").is_err());
+        assert!(repl.eval(":nope").is_err());
+        assert_eq!(eval(&mut repl, "a"), Some("1".to_string()));
+    }
+
+    #[test]
+    fn reset() {
+        let mut repl = Repl::new();
+        eval(&mut repl, "let a = 1");
+        eval(&mut repl, ":reset");
+        assert!(repl.eval("a").is_err());
+    }
+}
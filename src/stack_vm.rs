@@ -0,0 +1,1120 @@
+// Stack machine for the bytecode (bytecode.rs)
+//
+// Each call pushes a scope of the VarEnv holding the locals of the callee,
+// so references are Ref{frame index, slot} and remain valid for the callees.
+
+use crate::ast::{Literal, Type};
+use crate::bytecode::{Instr, Program};
+use crate::env::{arity_message, Fields, Ref, VarEnv};
+use crate::error::*;
+use crate::vm::{
+    call_method, cast, element, has_type, neg, printable, show, vec_repeat, Val, MAX_CALL_DEPTH,
+};
+
+pub struct Machine<'p> {
+    program: &'p Program,
+    vars: VarEnv<Val>,
+    stack: Vec<Val>,
+    // return addresses (function, pc) of the active calls
+    calls: Vec<(usize, usize)>,
+}
+
+impl<'p> Machine<'p> {
+    pub fn new(program: &'p Program) -> Self {
+        Machine {
+            program,
+            vars: VarEnv::new(),
+            stack: vec![],
+            calls: vec![],
+        }
+    }
+
+    fn pop(&mut self) -> Result<Val, Error> {
+        self.stack
+            .pop()
+            .ok_or_else(|| Error::ice("operand stack underflow"))
+    }
+
+    // a local slot of the current frame
+    fn local(&self, slot: usize) -> Ref {
+        self.vars.local(slot)
+    }
+
+    // the referenced value, a dropped local (see `Instr::Drop`) is gone
+    fn de_ref(&self, r: Ref) -> Result<Val, Error> {
+        match self.vars.try_de_ref(r)? {
+            Val::UnInit => Err(Error::runtime(
+                DANGLING_REFERENCE,
+                "use of a reference to a variable that is out of scope",
+            )),
+            v => Ok(v),
+        }
+    }
+
+    // the arguments fit the parameters of function `func`, as checked by
+    // the AST interpreter (the type checker may not have run)
+    fn check_args(&self, func: usize, args: &[Val], id: &str) -> Result<(), Error> {
+        let fits_fn = |v: &Val, t: &Type| self.fits_fn(v, t);
+        for (param, v) in self.program.fns[func].params.iter().zip(args) {
+            if !has_type(v, &param.ty, &self.vars, &fits_fn) {
+                return Err(Error::runtime(
+                    VALUE_MISMATCH,
+                    format!(
+                        "expected type `{}` for parameter `{}` of `{}`, got `{}`",
+                        param.ty,
+                        param.pat,
+                        id,
+                        show(v, &self.vars)
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // a function item or closure fits a `fn` or `impl Fn` type
+    fn fits_fn(&self, v: &Val, ty: &Type) -> bool {
+        match (v, ty) {
+            (Val::Code(index, captures), Type::Fn(params, ret))
+            | (Val::Code(index, captures), Type::ImplFn(params, ret)) => {
+                let f = &self.program.fns[*index];
+                match &f.ty {
+                    Some(t) => *t == Type::Fn(params.clone(), ret.clone()),
+                    // only a closure capturing nothing is a `fn`
+                    None => {
+                        (captures.is_empty() || matches!(ty, Type::ImplFn(..)))
+                            && f.params.iter().map(|p| &p.ty).eq(params.iter())
+                    }
+                }
+            }
+            _ => false,
+        }
+    }
+
+    // a new frame for function `func`, the arguments being on the stack
+    fn enter(&mut self, func: usize, argc: usize) {
+        let f = &self.program.fns[func];
+        // the compiler checked that `argc` matches the arity
+        let mut locals = self.stack.split_off(self.stack.len() - argc);
+        locals.resize(f.locals, Val::UnInit);
+        self.vars.push_scope();
+        for v in locals {
+            self.vars.stack_val(v);
+        }
+    }
+
+    // runs `main` to completion, returning its value
+    pub fn run(&mut self) -> Result<Val, Error> {
+        let (mut func, mut pc) = (self.program.main, 0);
+        self.enter(func, 0);
+        loop {
+            let f = &self.program.fns[func];
+            let span = f.spans[pc];
+            let instr = &f.code[pc];
+            pc += 1;
+            match self.step(instr, &mut func, &mut pc) {
+                Ok(Some(v)) => return Ok(v),
+                Ok(None) => (),
+                Err(err) => return Err(err.or_span(span)),
+            }
+        }
+    }
+
+    // executes one instruction, returning the value of `main` once it returns
+    fn step(
+        &mut self,
+        instr: &Instr,
+        func: &mut usize,
+        pc: &mut usize,
+    ) -> Result<Option<Val>, Error> {
+        match instr {
+            Instr::Push(v) => self.stack.push(v.clone()),
+            Instr::Pop => {
+                self.pop()?;
+            }
+            Instr::Load(slot) => {
+                let v = self.vars.de_ref(self.local(*slot));
+                self.stack.push(v);
+            }
+            Instr::Store(slot) => {
+                let v = self.pop()?;
+                self.vars.set_ref(self.local(*slot), v);
+            }
+            Instr::Ref(slot) => self.stack.push(Val::Ref(self.local(*slot))),
+            Instr::Drop(slot) => self.vars.set_ref(self.local(*slot), Val::UnInit),
+            Instr::DeRef => match self.pop()? {
+                Val::Ref(r) => self.stack.push(self.de_ref(r)?),
+                _ => return Err(Error::runtime(NOT_A_REFERENCE, "Var is not a reference!")),
+            },
+            Instr::StoreRef => {
+                let v = self.pop()?;
+                match self.pop()? {
+                    Val::Ref(r) => {
+                        self.de_ref(r.clone())?;
+                        self.vars.try_set_ref(r, v)?
+                    }
+                    _ => return Err(Error::runtime(NOT_A_PLACE, "Expected ref in assignment")),
+                }
+            }
+            Instr::Struct(id, fields) => {
+                let vals = self.stack.split_off(self.stack.len() - fields.len());
+                let fields = fields.iter().cloned().zip(vals).collect();
+                self.stack.push(Val::Struct(id.clone(), fields));
+            }
+            Instr::Variant(e, v, fields) => {
+                let vals = self.stack.split_off(self.stack.len() - fields.len());
+                let fields = fields.iter().cloned().zip(vals).collect();
+                self.stack.push(Val::Enum(e.clone(), v.clone(), fields));
+            }
+            Instr::Tuple(n) => {
+                let vals = self.stack.split_off(self.stack.len() - n);
+                self.stack.push(Val::Tuple(vals));
+            }
+            Instr::Array(n) => {
+                let vals = self.stack.split_off(self.stack.len() - n);
+                self.stack.push(Val::Array(vals));
+            }
+            Instr::Repeat(n) => {
+                let v = self.pop()?;
+                self.stack.push(Val::Array(vec![v; *n]));
+            }
+            Instr::Index => {
+                let (i, _) = self.pop()?.get_integer()?;
+                let (v, place) = match self.pop()? {
+                    // auto-deref
+                    Val::Ref(r) => (self.de_ref(r.clone())?, Some(r)),
+                    v => (v, None),
+                };
+                let (v, _) = element(&v, place, i, &self.vars)?;
+                self.stack.push(v);
+            }
+            Instr::RefIndex => {
+                let (i, _) = self.pop()?.get_integer()?;
+                let r = match self.pop()? {
+                    Val::Ref(r) => r,
+                    _ => return Err(Error::runtime(NOT_A_REFERENCE, "Var is not a reference!")),
+                };
+                let r = match self.de_ref(r.clone())? {
+                    // auto-deref
+                    Val::Ref(r) => r,
+                    _ => r,
+                };
+                let v = self.de_ref(r.clone())?;
+                match element(&v, Some(r), i, &self.vars)? {
+                    (_, Some(r)) => self.stack.push(Val::Ref(r)),
+                    (_, None) => return Err(Error::ice("element without a place")),
+                }
+            }
+            Instr::Cast(t) => {
+                let v = self.pop()?;
+                self.stack.push(cast(&v, t)?);
+            }
+            Instr::Step(op) => {
+                let v = self.pop()?;
+                let (_, t) = v.get_integer()?;
+                let one = Val::Lit(Literal::int(1, t));
+                self.stack.push(op.eval(v, one, &mut self.vars)?);
+            }
+            Instr::Method(m, argc) => {
+                let args = self.stack.split_off(self.stack.len() - argc);
+                let v = self.pop()?;
+                let v = call_method(v, m, args, &mut self.vars)?;
+                self.stack.push(v);
+            }
+            Instr::Vec(n) => {
+                let vals = self.stack.split_off(self.stack.len() - n);
+                self.stack.push(Val::Vec(self.vars.heap_alloc(vals)));
+            }
+            Instr::VecRepeat => {
+                let (n, _) = self.pop()?.get_integer()?;
+                let v = self.pop()?;
+                let v = vec_repeat(v, n, &mut self.vars)?;
+                self.stack.push(v);
+            }
+            Instr::IsVariant(e, v) => match self.pop()? {
+                Val::Enum(ve, vv, _) => {
+                    let is = Literal::Bool(*e == ve && *v == vv);
+                    self.stack.push(Val::Lit(is));
+                }
+                v => {
+                    return Err(Error::runtime(
+                        VALUE_MISMATCH,
+                        format!("expected an enum value, got `{}`", show(&v, &self.vars)),
+                    ))
+                }
+            },
+            Instr::IsTuple(n) => {
+                let is = matches!(self.pop()?, Val::Tuple(vs) if vs.len() == *n);
+                self.stack.push(Val::Lit(Literal::Bool(is)));
+            }
+            Instr::Field(id) => {
+                let v = match self.pop()? {
+                    // auto-deref
+                    Val::Ref(r) => self.de_ref(r)?,
+                    v => v,
+                };
+                let i = field_index(&v, id, &self.vars)?;
+                self.stack.push(v.field(i).unwrap().clone());
+            }
+            Instr::RefField(id) => {
+                let r = match self.pop()? {
+                    Val::Ref(r) => r,
+                    _ => return Err(Error::runtime(NOT_A_REFERENCE, "Var is not a reference!")),
+                };
+                let r = match self.de_ref(r.clone())? {
+                    // auto-deref
+                    Val::Ref(r) => r,
+                    _ => r,
+                };
+                let i = field_index(&self.de_ref(r.clone())?, id, &self.vars)?;
+                self.stack.push(Val::Ref(r.field(i)));
+            }
+            Instr::BinOp(op) => {
+                let r = self.pop()?;
+                let l = self.pop()?;
+                self.stack.push(op.eval(l, r, &mut self.vars)?);
+            }
+            Instr::Not => {
+                let b = self.pop()?.get_bool()?;
+                self.stack.push(Val::Lit(Literal::Bool(!b)));
+            }
+            Instr::Neg => {
+                let v = self.pop()?;
+                self.stack.push(neg(&v)?);
+            }
+            Instr::Jump(addr) => *pc = *addr,
+            Instr::JumpIfFalse(addr) => {
+                if !self.pop()?.get_bool()? {
+                    *pc = *addr;
+                }
+            }
+            Instr::Call(callee, argc) => {
+                let id = &self.program.fns[*callee].id;
+                self.check_args(*callee, &self.stack[self.stack.len() - argc..], id)?;
+                if self.calls.len() >= MAX_CALL_DEPTH {
+                    return Err(Error::runtime(
+                        STACK_OVERFLOW,
+                        format!(
+                            "stack overflow in call to `{}`, recursion is too deep",
+                            self.program.fns[*callee].id
+                        ),
+                    ));
+                }
+                self.calls.push((*func, *pc));
+                self.enter(*callee, *argc);
+                *func = *callee;
+                *pc = 0;
+            }
+            Instr::Closure(index, n) => {
+                let captures = self.stack.split_off(self.stack.len() - n);
+                self.stack.push(Val::Code(*index, captures));
+            }
+            // the captured values are passed ahead of the arguments
+            Instr::CallValue(id, argc) => {
+                let args = self.stack.split_off(self.stack.len() - argc);
+                let (callee, captures) = match self.pop()? {
+                    Val::Code(callee, captures) => (callee, captures),
+                    v => {
+                        return Err(Error::runtime(
+                            VALUE_MISMATCH,
+                            format!("expected function, found `{}`", show(&v, &self.vars)),
+                        ))
+                    }
+                };
+                let params = self.program.fns[callee].arity - captures.len();
+                if params != *argc {
+                    return Err(Error::runtime(
+                        ARITY_MISMATCH,
+                        arity_message(id, params, *argc).replacen("function", "closure", 1),
+                    ));
+                }
+                self.check_args(callee, &args, id)?;
+                if self.calls.len() >= MAX_CALL_DEPTH {
+                    return Err(Error::runtime(
+                        STACK_OVERFLOW,
+                        format!("stack overflow in call to `{}`, recursion is too deep", id),
+                    ));
+                }
+                let argc = captures.len() + argc;
+                self.stack.extend(captures);
+                self.stack.extend(args);
+                self.calls.push((*func, *pc));
+                self.enter(callee, argc);
+                *func = callee;
+                *pc = 0;
+            }
+            Instr::Intrinsic(index, argc) => {
+                let mut args = vec![];
+                for v in self.stack.split_off(self.stack.len() - argc) {
+                    args.push(printable(v, &self.vars)?);
+                }
+                let v = self.program.intrinsics[*index](args);
+                self.stack.push(Val::Lit(v));
+            }
+            Instr::Ret => {
+                let v = self.pop()?;
+                self.vars.pop_scope();
+                match self.calls.pop() {
+                    Some((caller, ret)) => {
+                        *func = caller;
+                        *pc = ret;
+                        self.stack.push(v);
+                    }
+                    None => return Ok(Some(v)),
+                }
+            }
+            Instr::NoMatch => {
+                return Err(Error::runtime(
+                    NO_MATCHING_ARM,
+                    "non-exhaustive `match`, no arm matches the value",
+                ))
+            }
+        }
+        Ok(None)
+    }
+}
+
+// the index of the field `id` of the struct (or enum) value `v`
+fn field_index(v: &Val, id: &str, vars: &VarEnv<Val>) -> Result<usize, Error> {
+    match v {
+        Val::Struct(_, fields) | Val::Enum(_, _, fields) => {
+            fields.iter().position(|(f, _)| f == id)
+        }
+        Val::Tuple(vals) => id.parse().ok().filter(|i| *i < vals.len()),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        Error::runtime(
+            VALUE_MISMATCH,
+            format!("no field `{}` on `{}`", id, show(v, vars)),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Machine;
+    use crate::ast::{IntTy, Literal, Prog};
+    use crate::bytecode::compile;
+    use crate::common::Eval;
+    use crate::env::Env;
+    use crate::error::{
+        Error, ARITHMETIC_OVERFLOW, ARITY_MISMATCH, DANGLING_REFERENCE, EMPTY_POP,
+        INDEX_OUT_OF_BOUNDS, LITERAL_OVERFLOW, NO_MATCHING_ARM, STACK_OVERFLOW, VALUE_MISMATCH,
+    };
+    use crate::vm::Val;
+
+    fn run(s: &str) -> Result<Val, Error> {
+        let ts: proc_macro2::TokenStream = s.parse().unwrap();
+        let prog: Prog = syn::parse2(ts).unwrap();
+        let program = compile(&prog)?;
+        Machine::new(&program).run()
+    }
+
+    // the AST interpreter and the stack machine agree
+    fn parity(s: &str) -> Val {
+        let ts: proc_macro2::TokenStream = s.parse().unwrap();
+        let prog: Prog = syn::parse2(ts).unwrap();
+        let ast = prog.eval(&mut Env::new()).map(|(v, _)| v);
+        let bytecode = run(s);
+        assert_eq!(ast, bytecode);
+        bytecode.unwrap()
+    }
+
+    #[test]
+    fn test_block_let() {
+        let v = parity(
+            "
+    fn main() -> i32 {
+        let a: i32 = 1;
+        let b: i32 = 2;
+
+        a + b
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let v = parity(
+            "
+    fn main() -> i32 {
+        let a: i32 = 1 + 2;
+        let mut a: i32 = 2 + a;
+        if true {
+            a = a - 1;
+            let mut a: i32 = 0;
+            a = a + 1
+        } else {
+            a = a - 1
+        };
+        a
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_while_ref() {
+        let v = parity(
+            "
+    fn main() -> i32 {
+        let mut a = 2;
+        let mut b = 0;
+        let c = &mut b;
+        let d = &mut a;
+        while *d > 0 {
+            *d = *d - 1;
+            *c = *c + 1;
+        }
+        *c
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_ref_temporary() {
+        let v = parity(
+            "
+    fn main() -> i32 {
+        let a = &1;
+        let mut a = &mut { *a + 5 };
+        *a = *a + 1;
+        *a
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_call() {
+        let v = parity(
+            "
+    fn add(a: i32, b: i32) -> i32 {
+        a + b
+    }
+
+    fn inc(r: &i32) -> i32 {
+        *r + 1
+    }
+
+    fn main() -> i32 {
+        let a = add(1, 2);
+        inc(&a)
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_nested_fn() {
+        let v = parity(
+            "
+    fn main() -> i32 {
+        fn f(i: i32) -> i32 {
+            g(i) + 1
+        }
+        fn g(i: i32) -> i32 {
+            i * 2
+        }
+        f(3)
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_println() {
+        let v = parity(
+            "
+    fn main() {
+        let a = 5;
+        println!(\"a = {}\", a);
+    }",
+        );
+        assert_eq!(v, Val::Lit(Literal::Unit));
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        let err = run("
+    fn f(i: i32) -> i32 {
+        f(i + 1)
+    }
+
+    fn main() {
+        f(0);
+    }")
+        .unwrap_err();
+        assert_eq!(err.code, STACK_OVERFLOW);
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        // the AST interpreter recurses natively, as on the thread of main.rs,
+        // and both backends stop at the same depth
+        std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(|| {
+                let sum = "
+    fn sum(n: i32) -> i32 {
+        if n == 0 { 0 } else { n + sum(n - 1) }
+    }
+    ";
+                let v = parity(&format!("{} fn main() -> i32 {{ sum(999) }}", sum));
+                assert_eq!(v.get_int().unwrap(), 499500);
+                let prog: Prog =
+                    syn::parse_str(&format!("{} fn main() {{ sum(1000); }}", sum)).unwrap();
+                let ast = prog.eval(&mut Env::<Val>::new()).unwrap_err();
+                assert_eq!(ast.code, STACK_OVERFLOW);
+                let err = run(&format!("{} fn main() {{ sum(1000); }}", sum)).unwrap_err();
+                assert_eq!(err.code, STACK_OVERFLOW);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_call_arity_err() {
+        let err = run("
+    fn f(i: i32) -> i32 {
+        i
+    }
+
+    fn main() {
+        f();
+    }")
+        .unwrap_err();
+        assert_eq!(err.code, ARITY_MISMATCH);
+    }
+
+    // both backends fail an unchecked program with the same error
+    fn parity_err(s: &str) -> Error {
+        let prog: Prog = syn::parse_str(s).unwrap();
+        let ast = prog.eval(&mut Env::<Val>::new()).unwrap_err();
+        let err = run(s).unwrap_err();
+        assert_eq!((ast.code, ast.message), (err.code, err.message.clone()));
+        err
+    }
+
+    #[test]
+    fn test_arg_type_err() {
+        let err = parity_err("fn f(a: i32, b: bool) {} fn main() { f(1, 2); }");
+        assert_eq!(err.code, VALUE_MISMATCH);
+        assert_eq!(
+            err.message,
+            "expected type `bool` for parameter `b` of `f`, got `2`"
+        );
+        let err = parity_err("fn main() { let c = |s: (i32, bool)| s.0; c((1, 2)); }");
+        assert_eq!(err.code, VALUE_MISMATCH);
+        let err = parity_err("struct P { x: i32 } fn main() { let t = (1, 2); t.x; }");
+        assert_eq!(err.message, "no field `x` on `(1, 2)`");
+        let err = run("fn main() { let (a, b) = 1; }").unwrap_err();
+        assert_eq!(err.code, NO_MATCHING_ARM);
+    }
+
+    #[test]
+    fn test_literal_overflow() {
+        // fails the type check, the literals are `i32`
+        let err = parity_err("fn main() { let x: u64 = 18446744073709551615; let q: u8 = 256; }");
+        assert_eq!(err.code, LITERAL_OVERFLOW);
+        let v = parity("fn main() -> i32 { match 1 { 5000000000 => 1, _ => 2 } }");
+        assert_eq!(v.get_int().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_operators() {
+        let v = parity(
+            "
+    fn main() -> i32 {
+        let a = -7 % 3;
+        if (3 <= 3) && ((4 >= 5) != true) { a } else { -a }
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), -1);
+    }
+
+    #[test]
+    fn test_loop() {
+        let v = parity(
+            "
+    fn main() -> i32 {
+        let mut i = 0;
+        let mut sum = 0;
+        'a: while i < 10 {
+            i = i + 1;
+            let x = 2 * loop {
+                if i % 3 == 0 { continue 'a; }
+                if i > 7 { break 'a; }
+                break i;
+            };
+            sum = sum + x;
+        }
+        sum + (1 + loop { break 100 })
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 139);
+    }
+
+    #[test]
+    fn test_return() {
+        let v = parity(
+            "
+    fn f(n: i32) -> i32 {
+        let x = 10;
+        while true {
+            let y = x + 1 + {
+                if n > 0 {
+                    return n * (1 + return y0(x));
+                }
+                return 0;
+            };
+        }
+        1
+    }
+    fn y0(x: i32) -> i32 {
+        x + 5
+    }
+    fn main() -> i32 {
+        f(1) + f(0) + 100 * (2 + { return 1 + f(3) + 1; })
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 17);
+    }
+
+    #[test]
+    fn test_for() {
+        let v = parity(
+            "
+    fn main() -> i32 {
+        let mut s = 0;
+        for i in (0..4).rev() { s = s * 10 + i; }
+        for i in 2147483646..=2147483647 { s = s + (i - 2147483640); }
+        for i in 3..3 { s = 0; }
+        'o: for i in 0..=6 {
+            let x = 1 + {
+                for j in 0..10 {
+                    if j > i { continue 'o; }
+                    if i == 5 { break 'o; }
+                    s = s + j;
+                }
+                0
+            };
+        }
+        s
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 3210 + 13 + 20);
+    }
+
+    #[test]
+    fn test_struct() {
+        let v = parity(
+            "
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+    fn shift(p: &Point, d: i32) {
+        p.x = p.x + d;
+        (*p).y = (*p).y + d;
+    }
+    fn main() -> i32 {
+        struct Line {
+            from: Point,
+            to: Point,
+        }
+        let mut l = Line { to: Point { y: 2, x: 1 }, from: Point { x: 0, y: 0 } };
+        let x = &mut l.to.x;
+        *x = 10;
+        shift(&mut l.from, 3);
+        let to = l.to;
+        l.to.y = 5;
+        l.from.x * 1000 + l.from.y * 100 + to.x + to.y + l.to.y
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 3317);
+    }
+
+    #[test]
+    fn test_match() {
+        let v = parity(
+            "
+    enum Shape {
+        Dot,
+        Circle(i32),
+        Rect { w: i32, h: i32 },
+    }
+    fn area(s: &Shape) -> i32 {
+        match *s {
+            Shape::Dot => 0,
+            Shape::Circle(0) => return -1,
+            Shape::Circle(r) => 3 * r * r,
+            Shape::Rect { w: 1, h } => h,
+            Shape::Rect { w, h: mut h } => {
+                h = h * w;
+                h
+            }
+        }
+    }
+    fn main() -> i32 {
+        enum Op {
+            Add(i32),
+            Neg,
+        }
+        let mut total = 0;
+        for i in 0..4 {
+            let op = if i % 2 == 0 { Op::Add(i) } else { Op::Neg };
+            total = match op {
+                Op::Add(n) => total + n,
+                Op::Neg => -total,
+            };
+        }
+        let s = Shape::Rect { h: 3, w: 2 };
+        area(&s) * 1000 + area(&Shape::Circle(2)) * 10 + area(&Shape::Circle(0)) + total
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 6117);
+    }
+
+    #[test]
+    fn test_tuple() {
+        let v = parity(
+            "
+    fn swap((a, b): (i32, bool), mut n: i32) -> (bool, i32) {
+        n = n + 1;
+        (b, a + n)
+    }
+    fn main() -> i32 {
+        let t = (1, (true, 3));
+        let (a, (mut b, c)) = t;
+        b = !b;
+        let s = swap((a, b), c);
+        let mut u = (7,);
+        u.0 = u.0 + t.1.1;
+        let r = &mut u;
+        r.0 = r.0 * 2;
+        let (x, y): (i32, i32);
+        x = 1;
+        y = 2;
+        match s { (false, n) => n * 100 + u.0 + x + y, (true, _) => 0 }
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 523);
+    }
+
+    #[test]
+    fn test_array() {
+        let v = parity(
+            "
+    fn fill(r: &[i32; 4], v: i32) {
+        let mut i = 0;
+        while i < (*r).len() {
+            (*r)[i] = v + i;
+            i = i + 1;
+        }
+    }
+    fn main() -> i32 {
+        let mut a = [0; 4];
+        fill(&a, 10);
+        let e = &a[2];
+        *e = 100;
+        let mut m = [[1, 2], [3, 4]];
+        m[1][1] = m[1][0] + a.len() as i32;
+        a[0] + a[1] + a[2] + a[3] + m[1][1] * 1000
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 7134);
+    }
+
+    #[test]
+    fn test_array_out_of_bounds() {
+        let err = run("fn main() { let mut a = [1, 2, 3]; let r = &a; (*r)[3] = 0; }").unwrap_err();
+        assert_eq!(err.code, INDEX_OUT_OF_BOUNDS);
+        assert_eq!(err.message, "index out of bounds: len is 3 but index is 3");
+    }
+
+    #[test]
+    fn test_vec() {
+        let v = parity(
+            "
+    fn range(n: i32) -> Vec<i32> {
+        let mut v = Vec::new();
+        for i in 0..n {
+            v.push(i);
+        }
+        v
+    }
+    fn main() -> i32 {
+        let mut v = range(5);
+        let last = v.pop();
+        v[0] = 10;
+        let e = &v[1];
+        *e = 20;
+        let mut m = vec![vec![1; 2]; 3];
+        m[2][1] = m[2][0] + v.len() as i32;
+        v[0] + v[1] + last * 100 + m[2][1] * 1000
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 5430);
+    }
+
+    #[test]
+    fn test_vec_dangling() {
+        let err = run("fn main() -> i32 { let mut v = vec![1]; let r = &v[0]; v.push(2); *r }").unwrap_err();
+        assert_eq!(err.code, DANGLING_REFERENCE);
+        assert_eq!(err.message, "use of a reference into a vector reallocated by a `push`");
+        let err = run("fn main() { let mut v = vec![1]; let r = &v[0]; v.pop(); *r = 2; }").unwrap_err();
+        assert_eq!(err.code, DANGLING_REFERENCE);
+        let err = run("fn main() -> i32 { let mut v: Vec<i32> = Vec::new(); v.pop() }").unwrap_err();
+        assert_eq!(err.code, EMPTY_POP);
+    }
+
+    #[test]
+    fn test_scope_dangling() {
+        // a reference outliving the block of the variable (not borrow checked)
+        let err =
+            run("fn main() -> i32 { let r = { let y = 5; &y }; let z = 100; *r }").unwrap_err();
+        assert_eq!(err.code, DANGLING_REFERENCE);
+        assert_eq!(
+            err.message,
+            "use of a reference to a variable that is out of scope"
+        );
+        let err =
+            run("fn main() -> i32 { let f = { let y = 5; |x: i32| x + y }; f(1) }").unwrap_err();
+        assert_eq!(err.code, DANGLING_REFERENCE);
+        let v = parity("fn main() -> i32 { let a = 1; let r = { let b = &a; b }; *r }");
+        assert_eq!(v.get_int().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_int_types() {
+        let v = parity(
+            "
+    fn sum(n: u8) -> u64 {
+        let mut s = 0u64;
+        for i in (0u8..=n).rev() {
+            s = s + i as u64;
+        }
+        s
+    }
+    fn main() -> u64 {
+        let a = [1u64, 2u64, 3u64];
+        let b = -1 as u8;
+        sum(b) + a[1usize] * (300 as u8) as u64
+    }",
+        );
+        assert_eq!(v.get_integer().unwrap(), (32728, IntTy::U64));
+    }
+
+    #[test]
+    fn test_int_overflow() {
+        let err = run("fn main() -> u8 { let a = 128u8; a * 2u8 }").unwrap_err();
+        assert_eq!(err.code, ARITHMETIC_OVERFLOW);
+        assert_eq!(err.message, "attempt to multiply with overflow");
+        let err = run("fn main() -> i8 { -(-128 as i8) }").unwrap_err();
+        assert_eq!(err.code, ARITHMETIC_OVERFLOW);
+    }
+
+    #[test]
+    fn test_int_literals() {
+        // the literals take the types they are used as
+        let v = parity(
+            "
+    fn main() -> u64 {
+        let x: u8 = 250;
+        let y = x + 5;
+        let big: i64 = 3000000000;
+        let v = vec![1, 2, 3];
+        let mut n = 0;
+        for i in 0..v.len() {
+            n = n + i;
+        }
+        y as u64 + (big * 2) as u64 + n as u64 + (18446744073709551615 - 18446744073709551614)
+    }",
+        );
+        assert_eq!(v.get_integer().unwrap(), (6000000259, IntTy::U64));
+        let err = run("fn main() -> u8 { let x: u8 = 250; x + 6 }").unwrap_err();
+        assert_eq!(err.code, ARITHMETIC_OVERFLOW);
+        assert_eq!(err.message, "attempt to add with overflow");
+    }
+
+    #[test]
+    fn test_vec_repeat() {
+        // each element is a clone, not sharing the heap block of the others
+        let v = parity(
+            "
+    fn main() -> usize {
+        let mut v = vec![vec![1; 2]; 3];
+        v[0].push(5);
+        let mut s = vec![String::new(); 2];
+        s[1].push_str(\"ab\");
+        v[0].len() * 100 + v[1].len() * 10 + s[0].len()
+    }",
+        );
+        assert_eq!(v.get_integer().unwrap(), (320, IntTy::Usize));
+    }
+
+    #[test]
+    fn test_float_char() {
+        let v = parity(
+            "
+    fn mean(a: [f64; 3]) -> f64 {
+        (a[0] + a[1] + a[2]) / a.len() as f64
+    }
+    fn main() -> f64 {
+        let c = 'b';
+        let d = match c {
+            'a' => 1.0,
+            'b' => 2.5,
+            _ => 0.0,
+        };
+        let m = mean([1.0, 2.0, -d]);
+        if c > 'a' && m < 1.0 { m + -d * 2.0 } else { 0.0 }
+    }",
+        );
+        assert_eq!(v, Val::Lit(Literal::Float(0.16666666666666666 - 5.0)));
+    }
+
+    #[test]
+    fn test_string() {
+        let v = parity(
+            "
+    fn words(s: &str) -> i32 {
+        let mut n = 0;
+        let mut prev = ' ';
+        for c in s.chars() {
+            if c != ' ' && prev == ' ' {
+                n = n + 1;
+            }
+            prev = c;
+        }
+        n
+    }
+    fn main() -> i32 {
+        let mut s = String::from(\"one two\");
+        s.push_str(\"  three\");
+        let t = s + \" four\";
+        let mut m = 0;
+        if t == \"one two  three four\" && \"a\" < \"ab\" {
+            m = 100;
+        }
+        words(&t) + t.len() as i32 * 10 + m
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 294);
+    }
+
+    #[test]
+    fn test_closure() {
+        let v = parity(
+            "
+    fn apply(f: impl Fn(i32) -> i32, x: i32) -> i32 {
+        f(x)
+    }
+    fn twice(f: fn(i32) -> i32, x: i32) -> i32 {
+        f(f(x))
+    }
+    fn main() -> i32 {
+        let a = 2;
+        let s = String::from(\"abc\");
+        let f = |x: i32| x * a;
+        let g = |x: i32| f(x) + a;
+        let h = move |x: i32| {
+            let k = |z: i32| z + x + s.len() as i32;
+            k(1)
+        };
+        let mut n = 0;
+        for i in 0..3 {
+            let c = |(j, b): (i32, bool)| -> i32 { if b { return i + j; } 0 };
+            n = n + c((10, true));
+        }
+        g(3) + h(4) + n + twice(|x: i32| x - 1, 10) + apply(|x: i32| x * x, 3)
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 8 + 8 + 33 + 8 + 9);
+    }
+
+    #[test]
+    fn test_closure_fn_err() {
+        // not type checked, only a closure capturing nothing is a `fn`
+        let err = parity_err(
+            "
+    fn twice(f: fn(i32) -> i32, x: i32) -> i32 {
+        f(f(x))
+    }
+    fn main() {
+        let y = 1;
+        let c = |x: i32| x + y;
+        twice(c, 3);
+    }",
+        );
+        assert_eq!(err.code, VALUE_MISMATCH);
+        assert_eq!(
+            err.message,
+            "expected type `fn(i32) -> i32` for parameter `f` of `twice`, got `<closure>`"
+        );
+    }
+
+    #[test]
+    fn test_fn_pointer() {
+        let v = parity(
+            "
+    fn add(a: i32, b: i32) -> i32 {
+        a + b
+    }
+    fn compose(f: fn(i32) -> i32, g: impl Fn(i32) -> i32, x: i32) -> i32 {
+        g(f(x))
+    }
+    fn main() -> i32 {
+        fn double(x: i32) -> i32 {
+            x * 2
+        }
+        let f = add;
+        let mut g = double;
+        let n = compose(g, |x: i32| f(x, 1), 5);
+        g = |x: i32| x - 1;
+        n * 100 + g(f(2, 3)) + compose(double, double, 1)
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 1100 + 4 + 4);
+    }
+
+    #[test]
+    fn test_generic() {
+        let v = parity(
+            "
+    fn max<T>(a: T, b: T) -> T {
+        if a > b { a } else { b }
+    }
+    fn largest<T>(v: &Vec<T>) -> T {
+        let mut m = v[0];
+        for i in 1..v.len() {
+            m = max(m, v[i]);
+        }
+        m
+    }
+    fn apply<T>(f: impl Fn(T) -> T, x: T) -> T {
+        f(x)
+    }
+    fn main() -> i32 {
+        let v = vec![3, 9, 4];
+        let c = largest(&vec!['x', 'b']);
+        let f = max::<f64>(0.5, 1.5);
+        let n = apply(|x: i32| x * 10, largest(&v));
+        if c == 'x' && f > 1.0 { n } else { 0 }
+    }",
+        );
+        assert_eq!(v.get_int().unwrap(), 90);
+    }
+
+    #[test]
+    fn test_match_no_arm() {
+        // not type checked
+        let err = run("fn main() -> i32 { let a = 3; a + match a { 1 => 1, 2 => 2 } }").unwrap_err();
+        assert_eq!(err.code, NO_MATCHING_ARM);
+    }
+}
@@ -1,11 +1,14 @@
 #[allow(unused_imports)]
 use syn::token::Else;
 use crate::ast::*;
+use crate::ast_traits::turbofish;
 use crate::common::Eval;
-use crate::env::{Env, Ref};
-use crate::error::Error;
+use crate::env::{arity_message, Env, Fields, Literals, Move, Pending, Ref};
+use crate::error::*;
+use crate::exhaustive;
+use crate::intrinsics::vm_println;
 use std::convert::{From, Into};
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 
 type TypeErr = String;
 
@@ -18,6 +21,22 @@ pub enum Ty
     Mut(Box<Ty>),
 }
 
+// the types of fields are found in the struct declarations
+impl Fields for Ty {}
+
+impl fmt::Display for Ty 
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result 
+    {
+        match self 
+        {
+            Ty::Lit(t) => write!(f, "{}", t),
+            Ty::Ref(_) => write!(f, "&_"),
+            Ty::Mut(t) => write!(f, "{}", t),
+        }
+    }
+}
+
 // Helpers for Ty
 impl From<&Literal> for Ty 
 {
@@ -27,7 +46,12 @@ impl From<&Literal> for Ty
         {
             Literal::Bool(_) => Type::Bool,
             Literal::Int(_) => Type::I32,
-            Literal::String(_) => Type::String,
+            Literal::Sized(_, t) => Type::Int(t),
+            // before its type is inferred (see `integer`)
+            Literal::Unsuffixed(_) => Type::I32,
+            Literal::Float(_) => Type::F64,
+            Literal::Char(_) => Type::Char,
+            Literal::String(_) => Type::str_ref(),
             Literal::Unit => Type::Unit,
         })
     }
@@ -36,31 +60,239 @@ impl From<&Literal> for Ty
 // Helper for Op
 impl Op
 {   
-    pub fn unify(&self, l: Ty, r: Ty) -> Result<(Ty, Option<Ref>), Error> 
+    pub fn unify(&self, l: Ty, r: Ty, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
     {
+        let int = Ty::Lit(Type::I32);
+        let bool = Ty::Lit(Type::Bool);
         match self 
         {
-            Op::Add => unify(l, r, Ty::Lit(Type::I32)),
-            Op::Sub => unify(l, r, Ty::Lit(Type::I32)),
-            Op::Mul => unify(l, r, Ty::Lit(Type::I32)),
-            Op::Div => unify(l, r, Ty::Lit(Type::I32)),
-            Op::And => unify(l, r, Ty::Lit(Type::Bool)),
-            Op::Or => unify(l, r, Ty::Lit(Type::Bool)),
-            Op::Eq => unify(l, r, Ty::Lit(Type::Bool)),
-            Op::Lt => unify(l, r, Ty::Lit(Type::Bool)),
-            Op::Gt => unify(l, r, Ty::Lit(Type::Bool)),
+            // `String + &str`, appending to the left operand
+            Op::Add if l == Ty::Lit(Type::String) => unify(r, Ty::Lit(Type::str_ref()), l, env),
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem if is_text(&l) || is_param(&l) || is_param(&r) => Err(Error::type_err(MISMATCHED_TYPES, self.cannot_apply(&l, &r))),
+            // arithmetic on numbers of the same type, there is no implicit
+            // widening, nor conversion between integers and floats, but an
+            // integer literal takes the type of the other operand
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem => match (is_number(&l), is_number(&r))
+            {
+                (true, true) if !fits_ty(&r, &l, env) => Err(Error::type_err(MISMATCHED_TYPES, self.cannot_apply(&l, &r))),
+                (true, _) => unify(r, l.clone(), l, env),
+                _ => 
+                {
+                    unify(l, int.clone(), int.clone(), env)?;
+                    unify(r, int.clone(), int, env)
+                },
+            },
+            // logic on bool
+            Op::And | Op::Or => 
+            {
+                unify(l, bool.clone(), bool.clone(), env)?;
+                unify(r, bool.clone(), bool, env)
+            },
+            // as in Rust, a `String` equals a `&str` with the same text
+            Op::Eq | Op::Ne if is_text(&l) && is_text(&r) => Ok((bool, None)),
+            // comparison of operands of the same type, structs, tuples, arrays, vectors and functions are not comparable
+            Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => match l
+            {
+                Ty::Lit(Type::Named(_)) | Ty::Lit(Type::Tuple(_)) | Ty::Lit(Type::Array(..)) | Ty::Lit(Type::Vec(_)) | Ty::Lit(Type::Fn(..)) | Ty::Lit(Type::ImplFn(..)) => Err(Error::type_err(MISMATCHED_TYPES, format!("binary operation `{}` cannot be applied to type `{}`", self, l))),
+                _ => unify(r, l, bool, env),
+            },
             //Op::Not => todo!(),
         }
     }
+
+    // As rustc words it, e.g., "cannot add `f64` to `i32`"
+    fn cannot_apply(&self, l: &Ty, r: &Ty) -> String
+    {
+        match self
+        {
+            Op::Add => format!("cannot add `{}` to `{}`", r, l),
+            Op::Sub => format!("cannot subtract `{}` from `{}`", r, l),
+            Op::Mul => format!("cannot multiply `{}` by `{}`", l, r),
+            Op::Div => format!("cannot divide `{}` by `{}`", l, r),
+            _ => format!("cannot calculate the remainder of `{}` divided by `{}`", l, r),
+        }
+    }
+}
+
+
+// Diverging expressions fit any type, also as the element of an array, vector
+// or tuple (e.g., `[]` is `[!; 0]` and `Vec::new()` is `Vec<!>`), a
+// `&String` fits a `&str`, and a `fn` type fits the `impl Fn` alike. An
+// integer literal fits any integer type, and is bound to it.
+fn fits(got: &Type, expected: &Type, lits: &mut Literals) -> bool
+{
+    match (resolve(got, lits), resolve(expected, lits))
+    {
+        (Type::Never, _) => true,
+        (Type::Integer(g), Type::Integer(e)) => 
+        {
+            if g != e
+            {
+                lits.vars[g] = Some(Type::Integer(e));
+            }
+            true
+        },
+        (Type::Integer(g), e) if e.int_ty().is_some() => 
+        {
+            lits.vars[g] = Some(e);
+            true
+        },
+        (g, Type::Integer(e)) if g.int_ty().is_some() => 
+        {
+            lits.vars[e] = Some(g);
+            true
+        },
+        // deref coercion, `&s` for a `s: String` is a `&str`
+        (Type::Ref(g), Type::Ref(e)) if *g == Type::String && *e == Type::Str => true,
+        (Type::Ref(g), Type::Ref(e)) => fits(&g, &e, lits),
+        (Type::Array(g, n), Type::Array(e, m)) => n == m && fits(&g, &e, lits),
+        (Type::Vec(g), Type::Vec(e)) => fits(&g, &e, lits),
+        (Type::Tuple(gs), Type::Tuple(es)) => gs.len() == es.len() && gs.iter().zip(&es).all(|(g, e)| fits(g, e, lits)),
+        // a function pointer (or a closure capturing nothing) is an `impl Fn`
+        (Type::Fn(gs, g), Type::Fn(es, e)) | (Type::Fn(gs, g), Type::ImplFn(es, e)) | (Type::ImplFn(gs, g), Type::ImplFn(es, e)) => 
+            gs.len() == es.len() && gs.iter().zip(&es).all(|(g, e)| fits(g, e, lits)) && fits(&g, &e, lits),
+        (got, expected) => got == expected,
+    }
+}
+
+// A fresh variable for the type of an integer literal without a suffix
+fn integer(span: Span, i: i128, env: &mut Env<Ty>) -> Type
+{
+    let var = env.lits.vars.len();
+    env.lits.vars.push(None);
+    env.lits.lits.push((span, i, var));
+    Type::Integer(var)
+}
+
+// The type with the variables of integer literals replaced by the types
+// they are bound to, the free ones are kept
+fn resolve(t: &Type, lits: &Literals) -> Type
+{
+    let all = |ts: &[Type]| ts.iter().map(|t| resolve(t, lits)).collect();
+    match t
+    {
+        Type::Integer(var) => match &lits.vars[*var]
+        {
+            Some(t) => resolve(t, lits),
+            None => t.clone(),
+        },
+        Type::Ref(t) => Type::Ref(Box::new(resolve(t, lits))),
+        Type::Tuple(ts) => Type::Tuple(all(ts)),
+        Type::Array(t, n) => Type::Array(Box::new(resolve(t, lits)), *n),
+        Type::Vec(t) => Type::Vec(Box::new(resolve(t, lits))),
+        Type::Fn(ts, r) => Type::Fn(all(ts), Box::new(resolve(r, lits))),
+        Type::ImplFn(ts, r) => Type::ImplFn(all(ts), Box::new(resolve(r, lits))),
+        _ => t.clone(),
+    }
+}
+
+// As `resolve`, for the type of an expression
+pub fn resolve_ty(t: Ty, env: &Env<Ty>) -> Ty
+{
+    match t
+    {
+        Ty::Lit(t) => Ty::Lit(resolve(&t, &env.lits)),
+        Ty::Mut(t) => Ty::Mut(Box::new(resolve_ty(*t, env))),
+        t => t,
+    }
+}
+
+// As `resolve`, with the free variables bound to `i32`, the type of an
+// integer literal when nothing else is inferred (as in Rust)
+fn default_ints(t: &Type, lits: &mut Literals) -> Type
+{
+    match resolve(t, lits)
+    {
+        Type::Integer(var) => 
+        {
+            lits.vars[var] = Some(Type::I32);
+            Type::I32
+        },
+        Type::Ref(t) => Type::Ref(Box::new(default_ints(&t, lits))),
+        Type::Tuple(ts) => Type::Tuple(ts.iter().map(|t| default_ints(t, lits)).collect()),
+        Type::Array(t, n) => Type::Array(Box::new(default_ints(&t, lits)), n),
+        Type::Vec(t) => Type::Vec(Box::new(default_ints(&t, lits))),
+        Type::Fn(ts, r) => Type::Fn(ts.iter().map(|t| default_ints(t, lits)).collect(), Box::new(default_ints(&r, lits))),
+        Type::ImplFn(ts, r) => Type::ImplFn(ts.iter().map(|t| default_ints(t, lits)).collect(), Box::new(default_ints(&r, lits))),
+        t => t,
+    }
+}
+
+// The types of the integer literals without a suffix, by their span, once
+// checked, each in the range of its type
+pub fn literal_types(env: &mut Env<Ty>) -> Result<Vec<(Span, IntTy)>, Error>
+{
+    // the calls outside of any function, e.g. in the REPL
+    instances(std::mem::take(&mut env.inst.pending), env)?;
+    let mut types = vec![];
+    for (span, i, var) in env.lits.lits.clone()
+    {
+        let t = default_ints(&Type::Integer(var), &mut env.lits).int_ty().expect("ICE - integer literal of another type");
+        if !t.fits(i)
+        {
+            return Err(Error::type_err(LITERAL_OUT_OF_RANGE, format!("literal out of range for `{}`", t))
+                .with_span(span)
+                .with_note(format!("the literal `{}` does not fit into the type `{}` whose range is `{}..={}`", i, t, t.min(), t.max())));
+        }
+        types.push((span, t));
+    }
+    Ok(types)
+}
+
+// The integer type of `t`, if it is one
+fn int_ty(t: &Ty) -> Option<IntTy>
+{
+    match t
+    {
+        Ty::Lit(t) => t.int_ty(),
+        _ => None,
+    }
+}
+
+// An integer (or integer literal) or a float
+fn is_number(t: &Ty) -> bool
+{
+    int_ty(t).is_some() || matches!(t, Ty::Lit(Type::F64) | Ty::Lit(Type::Integer(_)))
+}
+
+// A `String` or `&str`
+fn is_text(t: &Ty) -> bool
+{
+    *t == Ty::Lit(Type::String) || *t == Ty::Lit(Type::str_ref())
 }
 
+// A type parameter of a generic function checked where it is declared
+fn is_param(t: &Ty) -> bool
+{
+    matches!(t, Ty::Lit(Type::Param(_)))
+}
+
+fn fits_ty(got: &Ty, expected: &Ty, env: &mut Env<Ty>) -> bool
+{
+    match (got, expected)
+    {
+        (Ty::Lit(got), Ty::Lit(expected)) => fits(got, expected, &mut env.lits),
+        _ => got == expected,
+    }
+}
 
 // General unification
-fn unify(got: Ty, expected: Ty, result: Ty) ->Result<(Ty, Option<Ref>), Error> {
-    match got == expected 
+fn unify(got: Ty, expected: Ty, result: Ty, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> {
+    match fits_ty(&got, &expected, env)
     {
         true => Ok((result.into(), None)),
-        false => Err(format!("expected type {:?}, got type {:?}", expected, got)),
+        false => Err(Error::type_err(MISMATCHED_TYPES, format!("expected type `{}`, got type `{}`", resolve_ty(expected, env), resolve_ty(got, env)))),
+    }
+}
+
+// An index or a length, a `usize` or (unlike Rust) an `i32`, where an
+// integer literal is a `usize`
+fn index_type(t: Ty, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error>
+{
+    match int_ty(&t)
+    {
+        Some(IntTy::I32) => Ok((t, None)),
+        _ => unify(t, Ty::Lit(Type::Int(IntTy::Usize)), Ty::Lit(Type::Int(IntTy::Usize)), env),
     }
 }
 
@@ -69,349 +301,1899 @@ impl Eval<Ty> for Expr
 {
     fn eval(&self, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
     {
-        match self
+        // errors point at the innermost offending expression, the types of
+        // integer literals are given as far as inferred
+        let (t, r) = expr_type(self, env).map_err(|err| err.or_span(self.span))?;
+        Ok((resolve_ty(t, env), r))
+    }
+}
+
+fn expr_type(expr: &Expr, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
+{
+    match &expr.kind
+    {
+        ExprKind::BinOp(op, l, r) => 
         {
-            Expr::BinOp(op, l, r) => 
+            let l_type = l.eval(env)?;
+            // `s + t` moves `s`, comparisons borrow their operands
+            if matches!(op, Op::Add)
             {
-                let l_type = l.eval(env)?;
-                let r_type = r.eval(env)?;
-                let optype = op.unify(l_type.0, r_type.0)?;
-                Ok(optype)
-            },
-            Expr::Block(b) => 
+                consume(l, &l_type.0, env)?;
+            }
+            let r_type = r.eval(env)?;
+            let optype = op.unify(l_type.0, r_type.0, env)?;
+            Ok(optype)
+        },
+        ExprKind::Block(b) => 
+        {
+            b.eval(env)
+        },
+        // a variable shadows the functions of the same name
+        ExprKind::Call(id, _, args) if env.v.get(id).is_some() => 
+        {
+            let (params, ret) = match env.v.get(id).map(strip_mut)
             {
-                b.eval(env)
-            },
-            Expr::Call(id, args) => 
+                Some(Ty::Lit(Type::Fn(params, ret))) | Some(Ty::Lit(Type::ImplFn(params, ret))) => (params, ret),
+                t => return Err(Error::type_err(MISMATCHED_TYPES, format!("expected function, found `{}`", t.unwrap()))),
+            };
+            if params.len() != args.0.len()
             {
-                let f = env.f.0.get(id).unwrap().clone();
-                let mut i = 0;
-                for param in f.0.parameters.0.clone() 
+                return Err(Error::type_err(WRONG_ARG_COUNT, arity_message(id, params.len(), args.0.len()).replacen("function", "closure", 1)));
+            }
+            check_args(params, args, env)?;
+            Ok((Ty::Lit(*ret), None))
+        },
+        ExprKind::Call(id, targs, args) => 
+        {
+            let f = match env.f.0.get(id)
+            {
+                Some(f) => f.clone(),
+                None => return Err(Error::type_err(UNRESOLVED_NAME, format!("cannot find function `{}` in this scope", id))),
+            };
+            if f.1.is_some()
+            {
+                // intrinsics (println!) take a format string followed by any arguments
+                match args.0.first()
                 {
-                    let a = args.0.get(i).clone();
-                    let arg;
-                    if a != None
+                    Some(fmt) => 
                     {
-                        arg = a.unwrap();
-                    }
-                    else
+                        let fmt_t = fmt.eval(env)?.0;
+                        unify(fmt_t, Ty::Lit(Type::str_ref()), Ty::Lit(Type::str_ref()), env).map_err(|err| err.or_span(fmt.span))?;
+                    },
+                    None => return Err(Error::type_err(WRONG_ARG_COUNT, format!("`{}` requires a format string", id))),
+                }
+                for arg in &args.0[1..]
+                {
+                    if let Ty::Lit(t @ Type::Named(_)) | Ty::Lit(t @ Type::Tuple(_)) | Ty::Lit(t @ Type::Array(..)) | Ty::Lit(t @ Type::Vec(_)) | Ty::Lit(t @ Type::Fn(..)) | Ty::Lit(t @ Type::ImplFn(..)) = arg.eval(env)?.0
                     {
-                        break;
-                    }
-                    let arg = arg.eval(env)?.0;
-                    if Ty::Lit(param.ty.clone()) != arg.clone() {
-                        //Throw error since arg and param types don't match
-                        return unify(Ty::Lit(param.ty), arg.clone(), arg)
+                        return Err(Error::type_err(MISMATCHED_TYPES, format!("`{}` doesn't implement `std::fmt::Display`", t))
+                            .with_span(arg.span));
                     }
-                    i = i+1;
                 }
+                return Ok((Ty::Lit(f.0.ty.unwrap_or(Type::Unit)), None));
+            }
+            if !f.0.generics.is_empty() || !targs.is_empty()
+            {
+                return generic_call(&f.0, targs, args, expr.span, env);
+            }
 
-                if f.0.ty.is_some()
-                {
-                    Ok((Ty::Lit(f.0.ty.unwrap()), None))
-                }
-                else
+            let params = &f.0.parameters.0;
+            if params.len() != args.0.len()
+            {
+                return Err(Error::type_err(WRONG_ARG_COUNT, arity_message(id, params.len(), args.0.len()))
+                    .with_label(f.0.span, "function defined here"));
+            }
+            for (param, arg) in params.iter().zip(&args.0)
+            {
+                let arg_t = value(arg, env)?;
+                let param_t = Ty::Lit(param.ty.clone());
+                if !fits_ty(&arg_t, &param_t, env)
                 {
-                    Ok((Ty::Lit(Type::Unit), None))
+                    return Err(Error::type_err(
+                            MISMATCHED_TYPES, 
+                            format!("expected type `{}` for parameter `{}` of `{}`, got type `{}`", param_t, param.pat, id, arg_t))
+                        .with_span(arg.span)
+                        .with_label(param.span, "parameter defined here"));
                 }
-            },
-            Expr::Ident(id) => match env.v.get(&id) 
+            }
+
+            Ok((Ty::Lit(f.0.ty.unwrap_or(Type::Unit)), None))
+        },
+        ExprKind::Ident(id) => 
+        {
+            check_moved(id, &[], env)?;
+            match env.v.get(id) 
             {
+                // `mut` only matters when assigning, reading gives the plain type
+                Some(Ty::Mut(t)) => Ok((*t, None)),
                 Some(t) => Ok((t, None)),
-                None => Err("variable not found".to_string()),
-            },
-            Expr::IfThenElse(cond, t, _else) => 
+                // a function item used as a value is a function pointer
+                None => match env.f.0.get(id)
+                {
+                    Some((f, None)) if f.generics.is_empty() => Ok((Ty::Lit(f.fn_type()), None)),
+                    Some((_, None)) => Err(Error::type_err(TYPE_ANNOTATIONS_NEEDED, format!("type annotations needed, cannot use the generic function `{}` as a value", id))),
+                    _ => Err(Error::type_err(UNRESOLVED_NAME, format!("variable `{}` not found", id))),
+                },
+            }
+        },
+        ExprKind::IfThenElse(cond, t, _else) => 
+        {
+            let cond_t = cond.eval(env)?;
+            // each branch starts from the moves of the condition
+            let start = env.moves.moved.clone();
+            let do_t = t.eval(env)?;
+            let then_moved = std::mem::replace(&mut env.moves.moved, start.clone());
+            let then_diverges = do_t.0 == Ty::Lit(Type::Never);
+            unify(cond_t.0, Ty::Lit(Type::Bool), Ty::Lit(Type::Bool), env)?;
+            
+            if _else.is_none()
             {
-                let cond_t = cond.eval(env)?;
-                let do_t = t.eval(env)?;
-                unify(cond_t.0, Ty::Lit(Type::Bool), Ty::Lit(Type::Bool))?;
-                
-                if _else.is_none()
+                env.moves.moved = join_branches(vec![(start, false), (then_moved, then_diverges)]);
+                // without an else branch the then branch must be ()
+                unify(do_t.0, Ty::Lit(Type::Unit), Ty::Lit(Type::Unit), env)
+                    .map_err(|err| err.or_span(t.span))
+            }
+            else 
+            {
+                let e_type = _else.as_ref().unwrap().eval(env)?; //This, this is the way, this is the way I wanna live
+                let else_moved = std::mem::take(&mut env.moves.moved);
+                let else_diverges = e_type.0 == Ty::Lit(Type::Never);
+                env.moves.moved = join_branches(vec![(then_moved, then_diverges), (else_moved, else_diverges)]);
+                match do_t.0
                 {
-                    Ok((Ty::Lit(Type::Unit), None))
+                    // a diverging then branch takes the type of the else branch
+                    Ty::Lit(Type::Never) => Ok((e_type.0, None)),
+                    do_t => unify(e_type.0, do_t.clone(), do_t, env)
+                        .map_err(|err| err.or_span(_else.as_ref().unwrap().span)),
                 }
-                else 
+            }
+        },
+        ExprKind::Loop(label, body) => 
+        {
+            // the type is decided by the first break, `!` if there is none
+            env.loops.push((label.clone(), "loop", Some(Ty::Lit(Type::Never))));
+            env.moves.loops.push((vec![], vec![]));
+            let before = env.moves.moved.clone();
+            let body_t = body.eval(env);
+            let (_, _, break_t) = env.loops.pop().unwrap();
+            let (breaks, continues) = env.moves.loops.pop().unwrap();
+            let body_t = body_t?.0;
+            check_iteration(&before, continues, body_t == Ty::Lit(Type::Never), env)?;
+            // after the loop, as left by its breaks
+            env.moves.moved = breaks;
+            unify(body_t, Ty::Lit(Type::Unit), Ty::Lit(Type::Unit), env).map_err(|err| err.or_span(body.span))?;
+            Ok((break_t.unwrap(), None))
+        },
+        ExprKind::Break(label, e) => 
+        {
+            let i = find_loop(env, label, "break")?;
+            let t = match e
+            {
+                Some(e) => value(e, env)?,
+                None => Ty::Lit(Type::Unit),
+            };
+            join_moves(&mut env.moves.loops[i].0, env.moves.moved.clone());
+            let keyword = env.loops[i].1;
+            match env.loops[i].2.clone()
+            {
+                None if e.is_some() => return Err(Error::type_err(BREAK_WITH_VALUE, format!("`break` with value from a `{}` loop", keyword))
+                    .with_help(format!("use `break` on its own without a value inside this `{}` loop", keyword))),
+                None => (),
+                Some(Ty::Lit(Type::Never)) => env.loops[i].2 = Some(t),
+                Some(expected) => 
                 {
-                    let e_type = _else.as_ref().unwrap().eval(env)?; //This, this is the way, this is the way I wanna live
-                    unify(do_t.0.clone(), e_type.0.clone(), do_t.0.clone())?;
-                    Ok((Ty::Lit(Type::Unit), None))
-                }
-            },
-            Expr::Lit(Literal::Bool(_)) => 
+                    unify(t, expected.clone(), expected, env).map_err(|err| err.or_span(e.as_ref().map_or(expr.span, |e| e.span)))?;
+                },
+            }
+            Ok((Ty::Lit(Type::Never), None))
+        },
+        ExprKind::Continue(label) => 
+        {
+            let i = find_loop(env, label, "continue")?;
+            join_moves(&mut env.moves.loops[i].1, env.moves.moved.clone());
+            Ok((Ty::Lit(Type::Never), None))
+        },
+        ExprKind::Struct(id, fields) => 
+        {
+            let decl = match env.s.0.get(id)
             {
-                Ok((Ty::Lit(Type::Bool), None))
-            },
-            Expr::Lit(Literal::Int(_)) => 
+                Some(decl) => decl.clone(),
+                None => return Err(Error::type_err(UNRESOLVED_NAME, format!("cannot find struct `{}` in this scope", id))),
+            };
+            field_inits(&format!("struct `{}`", id), id, &decl.fields, decl.span, fields, env)?;
+            Ok((Ty::Lit(Type::Named(id.clone())), None))
+        },
+        ExprKind::Variant(e, v, kind, fields) => 
+        {
+            variant_type(e, v, *kind, fields, env)
+        },
+        ExprKind::Match(e, arms) => 
+        {
+            match_type(e, arms, env)
+        },
+        ExprKind::Tuple(es) => 
+        {
+            let mut ts = vec![];
+            for e in es
             {
-                Ok((Ty::Lit(Type::I32), None))
-            },
-            Expr::Lit(Literal::String(_)) => 
+                ts.push(element_type(e, "a tuple", env)?);
+            }
+            Ok((Ty::Lit(Type::Tuple(ts)), None))
+        },
+        ExprKind::Array(es) => 
+        {
+            let t = elements_type(es, "an array", env)?;
+            Ok((Ty::Lit(Type::Array(Box::new(t), es.len())), None))
+        },
+        ExprKind::Repeat(e, n) => 
+        {
+            let t = element_type(e, "an array", env)?;
+            if !is_copy(&Ty::Lit(t.clone()), env)
             {
-                Ok((Ty::Lit(Type::String), None))
-            },
-            Expr::Lit(Literal::Unit) => 
+                return Err(Error::type_err(MISMATCHED_TYPES, format!("the trait bound `{}: Copy` is not satisfied", t))
+                    .with_span(e.span)
+                    .with_note("the element of `[e; n]` is copied, use `vec![e; n]` to clone it"));
+            }
+            Ok((Ty::Lit(Type::Array(Box::new(t), *n)), None))
+        },
+        ExprKind::Vec(es) => 
+        {
+            let t = elements_type(es, "a vector", env)?;
+            Ok((Ty::Lit(Type::Vec(Box::new(t))), None))
+        },
+        ExprKind::VecRepeat(e, n) => 
+        {
+            let t = element_type(e, "a vector", env)?;
+            let n_t = n.eval(env)?.0;
+            index_type(n_t, env).map_err(|err| err.or_span(n.span))?;
+            Ok((Ty::Lit(Type::Vec(Box::new(t))), None))
+        },
+        ExprKind::Assoc(t, f, args) => 
+        {
+            let (params, ret) = match (t.as_str(), f.as_str())
             {
-                Ok((Ty::Lit(Type::Unit), None))
-            },
-            /*Expr::Not(e) => 
+                ("Vec", "new") => (vec![], Type::Vec(Box::new(Type::Never))),
+                ("String", "new") => (vec![], Type::String),
+                ("String", "from") => (vec![Type::str_ref()], Type::String),
+                _ => return Err(Error::type_err(UNRESOLVED_NAME, format!("no function or associated item named `{}` found for `{}`", f, t))),
+            };
+            if params.len() != args.0.len()
             {
-                todo!()
-            },*/
-            Expr::Par(e) =>
+                return Err(Error::type_err(WRONG_ARG_COUNT, arity_message(&format!("{}::{}", t, f), params.len(), args.0.len())));
+            }
+            check_args(params, args, env)?;
+            Ok((Ty::Lit(ret), None))
+        },
+        ExprKind::Index(e, i) => 
+        {
+            let i_t = i.eval(env)?.0;
+            index_type(i_t, env).map_err(|err| err.or_span(i.span))?;
+            match auto_deref(e.eval(env)?.0)
             {
-                e.eval(env)
-            },
-            Expr::UnOp(u, e) => 
+                Ty::Lit(Type::Array(t, _)) | Ty::Lit(Type::Vec(t)) => Ok((Ty::Lit(*t), None)),
+                t => Err(Error::type_err(MISMATCHED_TYPES, format!("cannot index into a value of type `{}`", t)).with_span(e.span)),
+            }
+        },
+        ExprKind::Method(e, m, args) => 
+        {
+            method_type(e, m, args, env)
+        },
+        ExprKind::Closure(c) => 
+        {
+            closure_type(c, expr.span, env)
+        },
+        // between numbers, from `bool` and `char` to integers, and from `u8` to `char`
+        ExprKind::Cast(e, t) => 
+        {
+            let e_t = match e.eval(env)?.0
+            {
+                Ty::Mut(t) => *t,
+                t => t,
+            };
+            let ok = match (&e_t, t)
+            {
+                (Ty::Lit(Type::Bool), _) => t.int_ty().is_some(),
+                (Ty::Lit(Type::Char), _) => t.int_ty().is_some() || *t == Type::Char,
+                (Ty::Lit(Type::Int(IntTy::U8)), Type::Char) => true,
+                // `97 as char`, the literal is a `u8`
+                (Ty::Lit(Type::Integer(_)), Type::Char) => fits_ty(&e_t, &Ty::Lit(Type::Int(IntTy::U8)), env),
+                (e_t, _) => is_number(e_t) && (t.int_ty().is_some() || *t == Type::F64),
+            };
+            match ok
+            {
+                true => Ok((Ty::Lit(t.clone()), None)),
+                false => Err(Error::type_err(MISMATCHED_TYPES, format!("cannot cast `{}` as `{}`", e_t, t))),
+            }
+        },
+        ExprKind::Field(e, f) => 
+        {
+            // a field of a variable is checked on its own, `s.a` may be used
+            // after `s.b` is moved out of
+            let t = match place(expr)
             {
-                let expr = (*e.clone()).eval(env)?;
-                match u 
+                Some((id, fields)) => 
                 {
-                    UnOp::Bang => 
-                    {
-                        unify(expr.0.clone(), Ty::Lit(Type::Bool), Ty::Lit(Type::Bool))
-                    },
-                    UnOp::DeRef => 
-                    {
-                        match expr.0 
-                        {
-                            Ty::Lit(Type::Ref(e)) => Ok((Ty::Lit(*e), None)),
-                            _ => Err(format!("Expected Ty::Lit; got {:?}", expr.0))
-                        }
-                    },
-                    UnOp::Mut => 
+                    check_moved(id, &fields, env)?;
+                    unchecked(e, env)?.0
+                },
+                None => e.eval(env)?.0,
+            };
+            // fields are accessed through references, `r.a` for `(*r).a`
+            let t = match t
+            {
+                Ty::Mut(t) => *t,
+                t => t,
+            };
+            let t = match t
+            {
+                Ty::Lit(Type::Ref(t)) => *t,
+                Ty::Lit(t) => t,
+                t => return Err(Error::type_err(UNKNOWN_FIELD, format!("no field `{}` on type `{}`", f, t))),
+            };
+            match &t
+            {
+                Type::Named(s) => match env.s.field(s, f)
+                {
+                    Some((_, field)) => Ok((Ty::Lit(field.ty.clone()), None)),
+                    None => Err(Error::type_err(UNKNOWN_FIELD, format!("no field `{}` on type `{}`", f, t))),
+                },
+                // `t.0`, the index is the name of the field
+                Type::Tuple(ts) => match f.parse::<usize>().ok().and_then(|i| ts.get(i))
+                {
+                    Some(t) => Ok((Ty::Lit(t.clone()), None)),
+                    None => Err(Error::type_err(UNKNOWN_FIELD, format!("no field `{}` on type `{}`", f, t))),
+                },
+                _ => Err(Error::type_err(UNKNOWN_FIELD, format!("`{}` is a primitive type and therefore doesn't have fields", t))),
+            }
+        },
+        ExprKind::Return(e) => 
+        {
+            let (id, expected) = env.ret.clone()
+                .ok_or_else(|| Error::type_err(RETURN_OUTSIDE_FN, "`return` outside of a function"))?;
+            let t = match e
+            {
+                Some(e) => value(e, env)?,
+                None => Ty::Lit(Type::Unit),
+            };
+            if id == CLOSURE && expected == Ty::Lit(Type::Never)
+            {
+                // the first `return` decides the return type of a closure without one
+                env.ret = Some((id, strip_mut(t)));
+                return Ok((Ty::Lit(Type::Never), None));
+            }
+            unify(t, expected.clone(), expected.clone(), env).map_err(|err| 
+            {
+                err.or_span(e.as_ref().map_or(expr.span, |e| e.span))
+                    .with_note(format!("the return type of `{}` is `{}`", id, expected))
+            })?;
+            Ok((Ty::Lit(Type::Never), None))
+        },
+        ExprKind::Lit(Literal::Bool(_)) => 
+        {
+            Ok((Ty::Lit(Type::Bool), None))
+        },
+        ExprKind::Lit(Literal::Int(_)) => 
+        {
+            Ok((Ty::Lit(Type::I32), None))
+        },
+        ExprKind::Lit(Literal::Sized(_, t)) => 
+        {
+            Ok((Ty::Lit(Type::Int(*t)), None))
+        },
+        ExprKind::Lit(Literal::Unsuffixed(i)) => 
+        {
+            Ok((Ty::Lit(integer(expr.span, *i, env)), None))
+        },
+        ExprKind::Lit(Literal::Float(_)) => 
+        {
+            Ok((Ty::Lit(Type::F64), None))
+        },
+        ExprKind::Lit(Literal::Char(_)) => 
+        {
+            Ok((Ty::Lit(Type::Char), None))
+        },
+        ExprKind::Lit(Literal::String(_)) => 
+        {
+            Ok((Ty::Lit(Type::str_ref()), None))
+        },
+        ExprKind::Lit(Literal::Unit) => 
+        {
+            Ok((Ty::Lit(Type::Unit), None))
+        },
+        /*Expr::Not(e) => 
+        {
+            todo!()
+        },*/
+        ExprKind::Par(e) =>
+        {
+            e.eval(env)
+        },
+        ExprKind::UnOp(u, e) => 
+        {
+            let expr = (*e.clone()).eval(env)?;
+            match u 
+            {
+                UnOp::Bang => 
+                {
+                    unify(expr.0.clone(), Ty::Lit(Type::Bool), Ty::Lit(Type::Bool), env)
+                },
+                UnOp::Neg => match int_ty(&expr.0)
+                {
+                    Some(t) if t.signed() => Ok((expr.0, None)),
+                    None if matches!(expr.0, Ty::Lit(Type::F64) | Ty::Lit(Type::Integer(_))) => Ok((expr.0, None)),
+                    None if !is_param(&expr.0) => unify(expr.0.clone(), Ty::Lit(Type::I32), Ty::Lit(Type::I32), env),
+                    _ => Err(Error::type_err(MISMATCHED_TYPES, format!("cannot apply unary operator `-` to type `{}`", expr.0))),
+                },
+                UnOp::DeRef => 
+                {
+                    match expr.0 
                     {
-                        Ok((Ty::Mut(Box::new(expr.0)), None))
-                    },
-                    UnOp::Ref =>
+                        Ty::Lit(Type::Ref(e)) => Ok((Ty::Lit(*e), None)),
+                        _ => Err(Error::type_err(INVALID_DEREF, format!("type `{}` cannot be dereferenced", expr.0)))
+                    }
+                },
+                UnOp::Mut => 
+                {
+                    Ok((Ty::Mut(Box::new(expr.0)), None))
+                },
+                UnOp::Ref =>
+                {
+                     match expr.0 
                     {
-                         match expr.0 
+                        Ty::Lit(type_) => Ok((Ty::Lit(Type::Ref(Box::new(type_))), None)),
+                        // `&mut e`, the type does not track the mutability of references
+                        Ty::Mut(t) => match *t
                         {
                             Ty::Lit(type_) => Ok((Ty::Lit(Type::Ref(Box::new(type_))), None)),
-                            _ => Err(format!("Expected Ty::Lit; got {:?}", expr.0))
-                        }
-                    },
-                }
-            },
-        }
+                            t => Err(Error::type_err(INVALID_REF, format!("cannot take a reference to `{}`", t)))
+                        },
+                        _ => Err(Error::type_err(INVALID_REF, format!("cannot take a reference to `{}`", expr.0)))
+                    }
+                },
+            }
+        },
     }
 }
 
-impl Eval<Ty> for Block 
+// The type of an element of a tuple or array (described by `what`)
+fn element_type(e: &Expr, what: &str, env: &mut Env<Ty>) -> Result<Type, Error>
 {
-    fn eval(&self, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
+    match value(e, env)?
     {
-        env.v.push_scope();
-
-        let mut return_ty = (Ty::Lit(Type::Unit), None);
-        for stmt in &self.statements 
-        {
-            // update the return type for each iteration
-            return_ty = stmt.eval(env)?;
-        }
-        env.v.pop_scope();
-        if self.semi
-        {
-            Ok((Ty::Lit(Type::Unit), None))
-        }
-        else
+        Ty::Lit(t) => Ok(t),
+        Ty::Mut(t) => match *t
         {
-            Ok(return_ty)
-        }
+            Ty::Lit(t) => Ok(t),
+            t => Err(Error::type_err(MISMATCHED_TYPES, format!("cannot store `{}` in {}", t, what)).with_span(e.span)),
+        },
+        t => Err(Error::type_err(MISMATCHED_TYPES, format!("cannot store `{}` in {}", t, what)).with_span(e.span)),
     }
 }
 
-impl Eval<Ty> for FnDeclaration 
+// The elements of an array or vector (described by `what`) have the type of
+// the first one that does not diverge
+fn elements_type(es: &[Expr], what: &str, env: &mut Env<Ty>) -> Result<Type, Error>
 {
-    fn eval(&self, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
+    let mut elem_t = Type::Never;
+    for e in es
     {
-        if self.ty.is_none()
-        {
-            Ok((Ty::Lit(Type::Unit), None))
-        } 
-        else 
+        let t = element_type(e, what, env)?;
+        match elem_t
         {
-            Ok((Ty::Lit(self.ty.clone().unwrap()), None))
+            Type::Never => elem_t = t,
+            _ => 
+            {
+                let expected = Ty::Lit(elem_t.clone());
+                unify(Ty::Lit(t), expected.clone(), expected, env).map_err(|err| err.or_span(e.span))?;
+            },
         }
     }
+    Ok(elem_t)
 }
 
-impl Eval<Ty> for Prog 
+// The plain type of a variable, `mut` left out
+fn strip_mut(t: Ty) -> Ty
 {
-    fn eval(&self, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
+    match t
     {
-        #[allow(unused_must_use)] 
-        {
-            env.f.add_functions_unique(self.0.clone());
-        }
-        for _f in self.0.clone()
-        {
-            _f.eval(env)?;
-        }
-        match env.f.0.get("main")
-        {
-            Some(_f) => Err("Ok")?,
-            None => Err("Main not found")?,
-        }
+        Ty::Mut(t) => *t,
+        t => t,
+    }
+}
+
+// The name of closures, as the enclosing function of their body
+const CLOSURE: &str = "closure";
+
+// A closure is `fn(T1, T2) -> R` if it captures nothing, and otherwise
+// `impl Fn(T1, T2) -> R`. The captured variables (the free variables of the
+// body bound outside of it) are read only, as closures are `Fn`, and like a
+// function body, the body is checked where only the captured variables and
+// the parameters are bound
+fn closure_type(c: &Closure, span: Span, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error>
+{
+    let mut captures = vec![];
+    for (id, mutated) in c.free_vars()
+    {
+        if let Some(t) = env.v.get(&id)
+        {
+            check_moved(&id, &[], env).map_err(|err| err.with_span(span))?;
+            if let Some(at) = mutated
+            {
+                return Err(Error::type_err(IMMUTABLE_ASSIGN, format!("cannot assign to `{}`, as it is a captured variable in a `Fn` closure", id))
+                    .with_span(at)
+                    .with_label(span, "in this closure"));
+            }
+            captures.push((id, strip_mut(t)));
+        }
+    }
+    for p in &c.parameters.0
+    {
+        check_type(&p.ty, env).map_err(|err| err.or_span(p.span))?;
+    }
+    if let Some(ty) = &c.ty
+    {
+        check_type(ty, env)?;
+    }
+    let mut fn_env: Env<Ty> = Env::new();
+    fn_env.f = env.f.clone();
+    fn_env.s = env.s.clone();
+    fn_env.e = env.e.clone();
+    fn_env.ret = Some((CLOSURE.to_string(), Ty::Lit(c.ty.clone().unwrap_or(Type::Never))));
+    fn_env.v.push_scope();
+    for (id, t) in &captures
+    {
+        let r = fn_env.v.alloc(id, t.clone());
+        fn_env.moves.captured.push(r);
+    }
+    let mut bindings = vec![];
+    for p in &c.parameters.0
+    {
+        pattern_type(&p.pat, &p.ty, env, &mut bindings)?;
+        irrefutable(&p.pat, &p.ty, "closure argument", env)?;
+    }
+    for (id, ty) in bindings
+    {
+        fn_env.v.alloc(&id, ty);
+    }
+    fn_env.inst = std::mem::take(&mut env.inst);
+    fn_env.lits = std::mem::take(&mut env.lits);
+    fn_env.moves.spans = std::mem::take(&mut env.moves.spans);
+    let body_t = c.body.eval(&mut fn_env);
+    env.inst = std::mem::take(&mut fn_env.inst);
+    env.lits = std::mem::take(&mut fn_env.lits);
+    let body_t = body_t?.0;
+    consume(&c.body, &body_t, &mut fn_env)?;
+    env.moves.spans = std::mem::take(&mut fn_env.moves.spans);
+    let body_t = strip_mut(body_t);
+    // a `move` closure takes the captured values
+    if c.moves
+    {
+        for (id, t) in &captures
+        {
+            if let (Ty::Lit(ty), false) = (t, is_copy(t, env))
+            {
+                env.moves.moved.push(Move { var: env.v.get_ref(id).unwrap(), fields: vec![], ty: ty.clone(), span });
+            }
+        }
+    }
+    let ret = match fn_env.ret.unwrap().1
+    {
+        Ty::Lit(Type::Never) => body_t,
+        ret => unify(body_t, ret.clone(), ret, env).map_err(|err| err.or_span(c.body.span))?.0,
+    };
+    let ret = match ret
+    {
+        Ty::Lit(t) => t,
+        t => return Err(Error::type_err(MISMATCHED_TYPES, format!("cannot return `{}` from a closure", t)).with_span(c.body.span)),
+    };
+    let params = c.parameters.0.iter().map(|p| p.ty.clone()).collect();
+    match captures.is_empty()
+    {
+        true => Ok((Ty::Lit(Type::Fn(params, Box::new(ret))), None)),
+        false => Ok((Ty::Lit(Type::ImplFn(params, Box::new(ret))), None)),
+    }
+}
+
+// Indexing and methods go through references, `r[i]` for `(*r)[i]`
+fn auto_deref(t: Ty) -> Ty
+{
+    match t
+    {
+        Ty::Mut(t) => auto_deref(*t),
+        Ty::Lit(Type::Ref(t)) => Ty::Lit(*t),
+        t => t,
+    }
+}
+
+// The type of the loop variable of a `for` loop over `iter`
+fn item_type(iter: &Iter, env: &mut Env<Ty>) -> Result<Ty, Error>
+{
+    match iter
+    {
+        // the bounds are integers of the same type
+        Iter::Range(range) => 
+        {
+            let start_t = range.start.eval(env)?.0;
+            let t = match int_ty(&start_t)
+            {
+                Some(_) => start_t,
+                // `0..n`, the literal takes the type of the end
+                None if matches!(start_t, Ty::Lit(Type::Integer(_))) => start_t,
+                None => unify(start_t, Ty::Lit(Type::I32), Ty::Lit(Type::I32), env).map_err(|err| err.or_span(range.start.span))?.0,
+            };
+            let end_t = range.end.eval(env)?.0;
+            unify(end_t, t.clone(), t.clone(), env).map_err(|err| err.or_span(range.end.span))?;
+            Ok(t)
+        },
+        Iter::Chars(s) => match auto_deref(s.eval(env)?.0)
+        {
+            Ty::Lit(Type::String) | Ty::Lit(Type::Str) => Ok(Ty::Lit(Type::Char)),
+            t => Err(Error::type_err(UNRESOLVED_NAME, format!("no method named `chars` found for type `{}`", t)).with_span(s.span)),
+        },
+    }
+}
+
+// The built in methods, by the type of the receiver
+fn method_type(e: &Expr, m: &str, args: &Arguments, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error>
+{
+    let t = auto_deref(e.eval(env)?.0);
+    let (params, ret): (Vec<Type>, Type) = match (&t, m)
+    {
+        (Ty::Lit(Type::Array(..)), "len") | (Ty::Lit(Type::Vec(_)), "len") => (vec![], Type::Int(IntTy::Usize)),
+        // the first push decides the element type of `Vec::new()` and `vec![]`
+        (Ty::Lit(Type::Vec(t)), "push") if **t == Type::Never && args.0.len() == 1 => 
+        {
+            let elem_t = element_type(&args.0[0], "a vector", env)?;
+            if let ExprKind::Ident(id) = &e.kind
+            {
+                let r = env.v.get_ref(id).unwrap();
+                let ty = Ty::Lit(Type::Vec(Box::new(elem_t)));
+                let ty = match env.v.de_ref(r.clone())
+                {
+                    Ty::Mut(_) => Ty::Mut(Box::new(ty)),
+                    _ => ty,
+                };
+                env.v.set_ref(r, ty);
+            }
+            return Ok((Ty::Lit(Type::Unit), None));
+        },
+        (Ty::Lit(Type::Vec(t)), "push") => (vec![(**t).clone()], Type::Unit),
+        (Ty::Lit(Type::Vec(t)), "pop") => (vec![], (**t).clone()),
+        (Ty::Lit(Type::String), "len") | (Ty::Lit(Type::Str), "len") => (vec![], Type::Int(IntTy::Usize)),
+        (Ty::Lit(Type::String), "push_str") => (vec![Type::str_ref()], Type::Unit),
+        (Ty::Lit(t), "to_string") if t.int_ty().is_some() || matches!(t, Type::Integer(_) | Type::F64 | Type::Char | Type::Bool | Type::String | Type::Str) => (vec![], Type::String),
+        // there is no iterator type
+        (Ty::Lit(Type::String), "chars") | (Ty::Lit(Type::Str), "chars") => 
+        {
+            return Err(Error::type_err(MISMATCHED_TYPES, "`chars()` is only supported as the iterator of a `for` loop"))
+        },
+        _ => return Err(Error::type_err(UNRESOLVED_NAME, format!("no method named `{}` found for type `{}`", m, t))),
+    };
+    if params.len() != args.0.len()
+    {
+        return Err(Error::type_err(WRONG_ARG_COUNT, arity_message(m, params.len(), args.0.len()).replacen("function", "method", 1)));
+    }
+    check_args(params, args, env)?;
+    Ok((Ty::Lit(ret), None))
+}
+
+// The limits on the instances of generic functions checked one inside the
+// other, and on the length of their names (as rustc's `recursion_limit` and
+// `type_length_limit`, the types of the arguments may double with each)
+const RECURSION_LIMIT_DEPTH: usize = 64;
+const TYPE_LENGTH_LIMIT: usize = 1 << 12;
+
+// A call of the generic function `f`, checked against its signature with the
+// type arguments given, or inferred from the types of the arguments. Its
+// instance is checked later (see `instances`) and recorded with the calls of
+// it (see `monomorphize`).
+fn generic_call(f: &FnDeclaration, targs: &[Type], args: &Arguments, span: Span, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error>
+{
+    let params = &f.parameters.0;
+    if params.len() != args.0.len()
+    {
+        return Err(Error::type_err(WRONG_ARG_COUNT, arity_message(&f.id, params.len(), args.0.len()))
+            .with_label(f.span, "function defined here"));
+    }
+    if !targs.is_empty() && targs.len() != f.generics.len()
+    {
+        return Err(Error::type_err(WRONG_ARG_COUNT, arity_message(&f.id, f.generics.len(), targs.len()).replace("argument", "generic argument"))
+            .with_label(f.span, "function defined here"));
+    }
+    for t in targs
+    {
+        check_type(t, env)?;
+    }
+    let mut arg_ts = vec![];
+    for arg in &args.0
+    {
+        arg_ts.push(strip_mut(value(arg, env)?));
+    }
+    let mut subst: Vec<(String, Type)> = f.generics.iter().cloned().zip(targs.iter().cloned()).collect();
+    for (param, arg_t) in params.iter().zip(&arg_ts)
+    {
+        if let Ty::Lit(t) = arg_t
+        {
+            infer(&param.ty, t, &f.generics, &mut subst);
+        }
+    }
+    let mut targs = vec![];
+    for g in &f.generics
+    {
+        match subst.iter().find(|(h, _)| h == g)
+        {
+            Some((_, t)) => targs.push(t.clone()),
+            None => return Err(Error::type_err(TYPE_ANNOTATIONS_NEEDED, format!("type annotations needed, cannot infer the type parameter `{}` of `{}`", g, f.id))
+                .with_label(f.span, "function defined here")),
+        }
+    }
+    let subst: Vec<(String, Type)> = f.generics.iter().cloned().zip(targs.iter().cloned()).collect();
+    for ((param, arg), arg_t) in params.iter().zip(&args.0).zip(&arg_ts)
+    {
+        let param_t = Ty::Lit(param.ty.subst(&subst));
+        if !fits_ty(arg_t, &param_t, env)
+        {
+            return Err(Error::type_err(
+                    MISMATCHED_TYPES, 
+                    format!("expected type `{}` for parameter `{}` of `{}`, got type `{}`", resolve_ty(param_t, env), param.pat, f.id, resolve_ty(arg_t.clone(), env)))
+                .with_span(arg.span)
+                .with_label(param.span, "parameter defined here"));
+        }
+    }
+    // the instance is checked at the end of the function, once the integer
+    // literals among the type arguments are inferred from the uses of the
+    // result, `let b: u8 = id(5)` is `id::<u8>`
+    let subst: Vec<(String, Type)> = f.generics.iter().cloned().zip(targs.iter().cloned()).collect();
+    let ret = f.ty.clone().unwrap_or(Type::Unit).subst(&subst);
+    env.inst.pending.push(Pending { span, f: f.clone(), targs, fns: env.f.clone(), structs: env.s.clone(), enums: env.e.clone() });
+    Ok((Ty::Lit(ret), None))
+}
+
+// Checks the instances of the generic functions called, each once, as a
+// function of its own with the functions and types in scope at the call.
+// Integer literals not inferred otherwise are `i32`, `max(1, 2)` is
+// `max::<i32>` and `max(1, 2u8)` is `max::<u8>`.
+fn instances(pending: Vec<Pending>, env: &mut Env<Ty>) -> Result<(), Error>
+{
+    for Pending { span, f, targs, fns, structs, enums } in pending
+    {
+        let targs: Vec<Type> = targs.iter().map(|t| default_ints(t, &mut env.lits)).collect();
+        let subst: Vec<(String, Type)> = f.generics.iter().cloned().zip(targs.iter().cloned()).collect();
+        let id = turbofish(&f.id, &targs);
+        env.inst.calls.push((span, id.clone()));
+        if fns.0.contains_key(&id) || env.inst.fns.iter().any(|g| g.id == id)
+        {
+            continue;
+        }
+        // a recursive call with ever larger type arguments, as in
+        // `fn f<T>(a: T) { f((a, a)) }`, makes instances without end
+        if env.inst.depth >= RECURSION_LIMIT_DEPTH || id.len() > TYPE_LENGTH_LIMIT
+        {
+            let limit = if env.inst.depth >= RECURSION_LIMIT_DEPTH { "recursion" } else { "type-length" };
+            return Err(Error::type_err(RECURSION_LIMIT, format!("reached the {} limit while instantiating `{}`", limit, f.id))
+                .with_span(span)
+                .with_label(f.span, "function defined here"));
+        }
+        // in scope while checked, for recursive calls
+        let instance = f.instantiate(&id, &subst);
+        let scope = (std::mem::replace(&mut env.f, fns), std::mem::replace(&mut env.s, structs), std::mem::replace(&mut env.e, enums));
+        env.f.0.insert(id.clone(), (instance.clone(), None));
+        let calls = std::mem::take(&mut env.inst.calls);
+        env.inst.depth += 1;
+        let checked = instance.eval(env);
+        env.inst.depth -= 1;
+        let own = std::mem::replace(&mut env.inst.calls, calls);
+        env.f = scope.0;
+        env.s = scope.1;
+        env.e = scope.2;
+        // the instances reaching a limit are not all listed
+        checked.map_err(|err| match err.code
+        {
+            RECURSION_LIMIT => err,
+            _ => err.with_label(span, format!("in `{}`, instantiated here", id)),
+        })?;
+        env.inst.fns.push(instance.rename_calls(&own));
+    }
+    Ok(())
+}
+
+// Binds the type parameters in the type of a parameter to the matching parts
+// of the type of the argument. The first binding is kept, a conflicting one
+// fails the check of the arguments, and `!` binds nothing.
+fn infer(param: &Type, arg: &Type, generics: &[String], subst: &mut Vec<(String, Type)>)
+{
+    match (param, arg)
+    {
+        (Type::Named(g), _) if generics.contains(g) && *arg != Type::Never && !subst.iter().any(|(h, _)| h == g) => 
+        {
+            subst.push((g.clone(), arg.clone()));
+        },
+        (Type::Ref(p), Type::Ref(a)) | (Type::Vec(p), Type::Vec(a)) | (Type::Array(p, _), Type::Array(a, _)) => infer(p, a, generics, subst),
+        (Type::Tuple(ps), Type::Tuple(args)) => 
+        {
+            for (p, a) in ps.iter().zip(args)
+            {
+                infer(p, a, generics, subst);
+            }
+        },
+        (Type::Fn(ps, p), Type::Fn(args, a)) | (Type::ImplFn(ps, p), Type::Fn(args, a)) | (Type::ImplFn(ps, p), Type::ImplFn(args, a)) => 
+        {
+            for (p, a) in ps.iter().zip(args)
+            {
+                infer(p, a, generics, subst);
+            }
+            infer(p, a, generics, subst);
+        },
+        _ => (),
+    }
+}
+
+// The spans of the moves out of variables and fields in `e` (a program or a
+// block), as far as it type checks, for the borrow checker
+pub fn move_spans<E: Eval<Ty>>(e: &E) -> Vec<Span>
+{
+    let mut env: Env<Ty> = Env::new();
+    let _ = e.eval(&mut env);
+    env.moves.spans
+}
+
+// The program with the generic functions replaced by their instances, the
+// calls of generic functions by calls of the instances, and the integer
+// literals without a suffix given their inferred types, for the backends
+// (which do not know the types of the arguments). A program without generic
+// functions that fails the type check is returned as is, its integer
+// literals are `i32`.
+pub fn monomorphize(prog: &Prog) -> Result<Prog, Error>
+{
+    let mut env: Env<Ty> = Env::new();
+    let lits = match prog.eval(&mut env).and_then(|_| literal_types(&mut env))
+    {
+        Ok(lits) => lits,
+        Err(_) if prog.0.iter().all(|f| f.generics.is_empty()) => return Ok(prog.clone()),
+        Err(err) => return Err(err),
+    };
+    let mut fns: Vec<FnDeclaration> = prog.0.iter()
+        .filter(|f| f.generics.is_empty())
+        .map(|f| f.rename_calls(&env.inst.calls))
+        .collect();
+    fns.append(&mut env.inst.fns);
+    let fns = fns.iter().map(|f| f.type_literals(&lits)).collect();
+    Ok(Prog(fns, prog.1.clone(), prog.2.clone()))
+}
+
+// The arguments of a built in method or associated function
+fn check_args(params: Vec<Type>, args: &Arguments, env: &mut Env<Ty>) -> Result<(), Error>
+{
+    for (param, arg) in params.into_iter().zip(&args.0)
+    {
+        let expected = Ty::Lit(param);
+        unify(value(arg, env)?, expected.clone(), expected, env).map_err(|err| err.or_span(arg.span))?;
+    }
+    Ok(())
+}
+
+// The fields initialized by a struct literal or enum variant `id` (described by `what`)
+fn field_inits(what: &str, id: &str, decl_fields: &[Field], decl_span: Span, fields: &[(String, Expr)], env: &mut Env<Ty>) -> Result<(), Error>
+{
+    let mut given: Vec<&String> = vec![];
+    for (f, e) in fields
+    {
+        if given.contains(&f)
+        {
+            return Err(Error::type_err(DUPLICATE_FIELD, format!("field `{}` specified more than once", f)).with_span(e.span));
+        }
+        given.push(f);
+        let field = match decl_fields.iter().find(|field| &field.id == f)
+        {
+            Some(field) => field,
+            None => return Err(Error::type_err(UNKNOWN_FIELD, format!("{} has no field named `{}`", what, f))
+                .with_span(e.span)
+                .with_label(decl_span, format!("{} defined here", what))),
+        };
+        let t = value(e, env)?;
+        let expected = Ty::Lit(field.ty.clone());
+        unify(t, expected.clone(), expected, env).map_err(|err| err.or_span(e.span))?;
+    }
+    let missing: Vec<String> = decl_fields.iter()
+        .filter(|field| !given.contains(&&field.id))
+        .map(|field| format!("`{}`", field.id))
+        .collect();
+    match missing.len()
+    {
+        0 => Ok(()),
+        1 => Err(Error::type_err(MISSING_FIELD, format!("missing field {} in initializer of `{}`", missing[0], id))),
+        n => Err(Error::type_err(MISSING_FIELD, format!("missing fields {} and {} in initializer of `{}`", missing[..n - 1].join(", "), missing[n - 1], id))),
+    }
+}
+
+fn kind_name(kind: VariantKind) -> &'static str
+{
+    match kind
+    {
+        VariantKind::Unit => "unit",
+        VariantKind::Tuple => "tuple",
+        VariantKind::Struct => "struct",
+    }
+}
+
+// The declaration of variant `v` of enum `e`, used as `kind`
+fn find_variant(e: &str, v: &str, kind: VariantKind, env: &Env<Ty>) -> Result<Variant, Error>
+{
+    let decl = match env.e.0.get(e)
+    {
+        Some(decl) => decl,
+        None => return Err(Error::type_err(UNRESOLVED_NAME, format!("cannot find enum `{}` in this scope", e))),
+    };
+    let variant = match env.e.variant(e, v)
+    {
+        Some((_, variant)) => variant.clone(),
+        None => return Err(Error::type_err(UNKNOWN_VARIANT, format!("no variant named `{}` found for enum `{}`", v, e))
+            .with_label(decl.span, format!("enum `{}` defined here", e))),
+    };
+    if variant.kind != kind
+    {
+        return Err(Error::type_err(MISMATCHED_TYPES, format!("expected {} variant, found {} variant `{}::{}`", kind_name(kind), kind_name(variant.kind), e, v))
+            .with_label(variant.span, format!("`{}::{}` defined here", e, v)));
+    }
+    Ok(variant)
+}
+
+fn variant_type(e: &str, v: &str, kind: VariantKind, fields: &[(String, Expr)], env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error>
+{
+    let variant = find_variant(e, v, kind, env)?;
+    let id = format!("{}::{}", e, v);
+    if kind == VariantKind::Tuple && fields.len() != variant.fields.len()
+    {
+        return Err(Error::type_err(WRONG_ARG_COUNT, arity_message(&id, variant.fields.len(), fields.len()).replacen("function", "variant", 1))
+            .with_label(variant.span, "variant defined here"));
+    }
+    field_inits(&format!("variant `{}`", id), &id, &variant.fields, variant.span, fields, env)?;
+    Ok((Ty::Lit(Type::Named(e.to_string())), None))
+}
+
+// Checks pattern `p` against a value of type `t`, collecting its bindings
+fn pattern_type(p: &Pattern, t: &Type, env: &mut Env<Ty>, bindings: &mut Vec<(String, Ty)>) -> Result<(), Error>
+{
+    let mismatch = |found: &dyn fmt::Display| Error::type_err(MISMATCHED_TYPES, format!("mismatched types: expected `{}`, found `{}`", t, found))
+        .with_span(p.span)
+        .with_note("the pattern must have the type of the matched value");
+    match &p.kind
+    {
+        PatternKind::Wild => Ok(()),
+        PatternKind::Lit(Literal::Unsuffixed(i)) => 
+        {
+            let lt = integer(p.span, *i, env);
+            match fits(&lt, t, &mut env.lits)
+            {
+                true => Ok(()),
+                false => Err(mismatch(&lt)),
+            }
+        },
+        PatternKind::Lit(l) => match Ty::from(l)
+        {
+            Ty::Lit(lt) if fits(&lt, t, &mut env.lits) => Ok(()),
+            lt => Err(mismatch(&lt)),
+        },
+        PatternKind::Ident(m, id) => 
+        {
+            if bindings.iter().any(|(b, _)| b == id)
+            {
+                return Err(Error::type_err(DUPLICATE_BINDING, format!("identifier `{}` is bound more than once in the same pattern", id))
+                    .with_span(p.span));
+            }
+            let ty = Ty::Lit(t.clone());
+            bindings.push((id.clone(), if m.0 { Ty::Mut(Box::new(ty)) } else { ty }));
+            Ok(())
+        },
+        PatternKind::Variant(e, v, kind, fields, rest) => 
+        {
+            let variant = find_variant(e, v, *kind, env).map_err(|err| err.or_span(p.span))?;
+            if t != &Type::Named(e.clone())
+            {
+                return Err(mismatch(e));
+            }
+            let id = format!("{}::{}", e, v);
+            let mut given: Vec<&String> = vec![];
+            for (f, fp) in fields
+            {
+                if given.contains(&f)
+                {
+                    return Err(Error::type_err(DUPLICATE_FIELD, format!("field `{}` bound more than once in the pattern", f)).with_span(fp.span));
+                }
+                given.push(f);
+                match variant.fields.iter().find(|field| &field.id == f)
+                {
+                    Some(field) => pattern_type(fp, &field.ty, env, bindings)?,
+                    None if *kind == VariantKind::Tuple => return Err(Error::type_err(WRONG_ARG_COUNT, 
+                            format!("this pattern has {} fields, but the corresponding tuple variant `{}` has {}", fields.len(), id, variant.fields.len()))
+                        .with_span(p.span)
+                        .with_label(variant.span, "variant defined here")),
+                    None => return Err(Error::type_err(UNKNOWN_FIELD, format!("variant `{}` does not have a field named `{}`", id, f))
+                        .with_span(fp.span)
+                        .with_label(variant.span, "variant defined here")),
+                }
+            }
+            let missing: Vec<String> = variant.fields.iter()
+                .filter(|field| !given.contains(&&field.id))
+                .map(|field| format!("`{}`", field.id))
+                .collect();
+            match (*kind, missing.is_empty() || *rest)
+            {
+                (_, true) => Ok(()),
+                (VariantKind::Tuple, false) => Err(Error::type_err(WRONG_ARG_COUNT, 
+                        format!("this pattern has {} fields, but the corresponding tuple variant `{}` has {}", fields.len(), id, variant.fields.len()))
+                    .with_span(p.span)
+                    .with_help("use `..` to ignore the rest of the fields")),
+                (_, false) => Err(Error::type_err(MISSING_FIELD, format!("pattern does not mention field{} {}", if missing.len() == 1 { "" } else { "s" }, missing.join(", ")))
+                    .with_span(p.span)
+                    .with_help("use `..` to ignore the rest of the fields")),
+            }
+        },
+        PatternKind::Tuple(pats) => match t
+        {
+            Type::Tuple(ts) if ts.len() == pats.len() => 
+            {
+                for (p, t) in pats.iter().zip(ts)
+                {
+                    pattern_type(p, t, env, bindings)?;
+                }
+                Ok(())
+            },
+            Type::Tuple(ts) => Err(Error::type_err(MISMATCHED_TYPES, 
+                    format!("mismatched types: expected a tuple with {} elements, found one with {} elements", ts.len(), pats.len()))
+                .with_span(p.span)),
+            _ => Err(Error::type_err(MISMATCHED_TYPES, format!("mismatched types: expected `{}`, found tuple", t))
+                .with_span(p.span)
+                .with_note("the pattern must have the type of the matched value")),
+        },
+    }
+}
+
+// `let` and parameter patterns must match every value of their type
+fn irrefutable(p: &Pattern, t: &Type, what: &str, env: &Env<Ty>) -> Result<(), Error>
+{
+    let missing = exhaustive::missing_patterns(&[p], t, &env.e);
+    match missing.is_empty()
+    {
+        true => Ok(()),
+        false => Err(Error::type_err(NON_EXHAUSTIVE, format!("refutable pattern in {}: {} not covered", what, listing(&missing)))
+            .with_span(p.span)
+            .with_help("use a `match` to handle the values not covered")),
+    }
+}
+
+// `let p = e;` for patterns other than a single identifier, the bindings
+// get the types of the parts of the value
+fn let_pattern_type(p: &Pattern, t: &Option<Type>, e: &Option<Expr>, env: &mut Env<Ty>) -> Result<(), Error>
+{
+    let t = match (e, t)
+    {
+        (Some(e), t) => 
+        {
+            let e_t = match value(e, env)?
+            {
+                Ty::Mut(t) => *t,
+                t => t,
+            };
+            match (t, e_t)
+            {
+                (Some(t), e_t) => 
+                {
+                    unify(e_t, Ty::Lit(t.clone()), Ty::Lit(t.clone()), env).map_err(|err| err.or_span(e.span))?;
+                    t.clone()
+                },
+                (None, Ty::Lit(t)) => t,
+                (None, e_t) => return Err(Error::type_err(MISMATCHED_TYPES, format!("cannot destructure a value of type `{}`", e_t)).with_span(e.span)),
+            }
+        },
+        (None, Some(t)) => t.clone(),
+        (None, None) => return Err(Error::type_err(MISMATCHED_TYPES, "type annotations needed for the pattern")
+            .with_span(p.span)
+            .with_help("give the pattern a type, or initialize it")),
+    };
+    check_type(&t, env)?;
+    let mut bindings = vec![];
+    pattern_type(p, &t, env, &mut bindings)?;
+    irrefutable(p, &t, "local binding", env)?;
+    for (id, ty) in bindings
+    {
+        env.v.alloc(&id, ty);
+        reinit(&id, &[], env);
+    }
+    Ok(())
+}
+
+// e.g. "`E::A`, `E::B(_)` and `E::C { x: _ }`", at most three patterns are listed
+fn listing(pats: &[Pattern]) -> String
+{
+    let pats: Vec<String> = pats.iter().map(|p| format!("`{}`", p)).collect();
+    match pats.len()
+    {
+        1 => pats[0].clone(),
+        2 | 3 => format!("{} and {}", pats[..pats.len() - 1].join(", "), pats[pats.len() - 1]),
+        n => format!("{} and {} more", pats[..3].join(", "), n - 3),
+    }
+}
+
+fn match_type(e: &Expr, arms: &[Arm], env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error>
+{
+    let t = match e.eval(env)?.0
+    {
+        Ty::Mut(t) => *t,
+        t => t,
+    };
+    let t = match t
+    {
+        Ty::Lit(t) => t,
+        t => return Err(Error::type_err(MISMATCHED_TYPES, format!("cannot match on a value of type `{}`", t)).with_span(e.span)),
+    };
+    // the type of the arms, `!` until an arm that does not diverge
+    let mut match_t = Ty::Lit(Type::Never);
+    // each arm starts from the moves of the matched value
+    let start = env.moves.moved.clone();
+    let mut branches = vec![];
+    for arm in arms
+    {
+        env.moves.moved = start.clone();
+        let mut bindings = vec![];
+        pattern_type(&arm.pat, &t, env, &mut bindings)?;
+        // binding a part of the value that is not `Copy` moves it
+        if bindings.iter().any(|(_, ty)| !is_copy(ty, env))
+        {
+            consume(e, &Ty::Lit(t.clone()), env)?;
+        }
+        // the bindings are scoped to the arm
+        env.v.push_scope();
+        for (id, ty) in bindings
+        {
+            env.v.alloc(&id, ty);
+        }
+        let arm_t = value(&arm.body, env);
+        env.v.pop_scope();
+        let arm_t = match arm_t?
+        {
+            Ty::Mut(t) => *t,
+            t => t,
+        };
+        branches.push((std::mem::take(&mut env.moves.moved), arm_t == Ty::Lit(Type::Never)));
+        match match_t
+        {
+            Ty::Lit(Type::Never) => match_t = arm_t,
+            _ => { unify(arm_t, match_t.clone(), match_t.clone(), env).map_err(|err| err.or_span(arm.body.span))?; },
+        }
+    }
+    env.moves.moved = join_branches(branches);
+
+    let pats: Vec<&Pattern> = arms.iter().map(|arm| &arm.pat).collect();
+    if let Some(i) = exhaustive::unreachable(&pats, &t, &env.e)
+    {
+        return Err(Error::type_err(UNREACHABLE_PATTERN, "unreachable pattern")
+            .with_span(pats[i].span)
+            .with_note("the values of this pattern are matched by the arms above it")
+            .with_help("remove this arm"));
+    }
+    let missing = exhaustive::missing_patterns(&pats, &t, &env.e);
+    if !missing.is_empty()
+    {
+        let mut err = Error::type_err(NON_EXHAUSTIVE, format!("non-exhaustive patterns: {} not covered", listing(&missing)))
+            .with_span(e.span)
+            .with_help("ensure that all possible cases are being handled by adding match arms, or a wildcard arm `_ => ..`");
+        if let Type::Named(id) = &t
+        {
+            if let Some(decl) = env.e.0.get(id)
+            {
+                err = err.with_label(decl.span, format!("`{}` defined here", id));
+            }
+        }
+        return Err(err);
+    }
+    Ok((match_t, None))
+}
+
+// The enclosing loop targeted by a `break` or `continue`
+fn find_loop(env: &Env<Ty>, label: &Option<String>, keyword: &str) -> Result<usize, Error>
+{
+    match label
+    {
+        Some(l) => env.loops.iter().rposition(|(label, _, _)| label.as_ref() == Some(l))
+            .ok_or_else(|| Error::type_err(UNDECLARED_LABEL, format!("use of undeclared label `{}`", l))),
+        None => env.loops.len().checked_sub(1)
+            .ok_or_else(|| Error::type_err(OUTSIDE_OF_LOOP, format!("`{}` outside of a loop", keyword))),
+    }
+}
+
+// `String` and `Vec` own a block of the heap and are moved, not copied, as
+// are the tuples, arrays, structs and enums holding them
+fn is_copy(t: &Ty, env: &Env<Ty>) -> bool
+{
+    match t
+    {
+        Ty::Lit(t) => copy_type(t, env, &mut vec![]),
+        Ty::Mut(t) => is_copy(t, env),
+        Ty::Ref(_) => true,
+    }
+}
+
+fn copy_type(t: &Type, env: &Env<Ty>, seen: &mut Vec<String>) -> bool
+{
+    match t
+    {
+        Type::String | Type::Vec(_) => false,
+        Type::Tuple(ts) => ts.iter().all(|t| copy_type(t, env, seen)),
+        Type::Array(t, _) => copy_type(t, env, seen),
+        // a type met again is decided by its other parts
+        Type::Named(id) if seen.contains(id) => true,
+        Type::Named(id) =>
+        {
+            seen.push(id.clone());
+            let fields: Vec<Field> = match (env.s.0.get(id), env.e.0.get(id))
+            {
+                (Some(s), _) => s.fields.clone(),
+                (_, Some(e)) => e.variants.iter().flat_map(|v| v.fields.clone()).collect(),
+                _ => vec![],
+            };
+            fields.iter().all(|field| copy_type(&field.ty, env, seen))
+        },
+        _ => true,
+    }
+}
+
+// The type of `e` used as a value (see `consume`)
+fn value(e: &Expr, env: &mut Env<Ty>) -> Result<Ty, Error>
+{
+    let t = e.eval(env)?.0;
+    consume(e, &t, env)?;
+    Ok(t)
+}
+
+// A variable or a field of it, e.g. `s.a.0` is `s` with the path `["a", "0"]`
+fn place(e: &Expr) -> Option<(&String, Vec<String>)>
+{
+    match &e.kind
+    {
+        ExprKind::Ident(id) => Some((id, vec![])),
+        ExprKind::Par(e) => place(e),
+        ExprKind::Field(e, f) => place(e).map(|(id, mut fields)| 
+        {
+            fields.push(f.clone());
+            (id, fields)
+        }),
+        _ => None,
+    }
+}
+
+fn place_name(id: &str, fields: &[String]) -> String
+{
+    std::iter::once(id.to_string()).chain(fields.iter().cloned()).collect::<Vec<_>>().join(".")
+}
+
+// The value of `e` of type `t` is used, rather than read or borrowed in place,
+// so unless it is `Copy`, the variable or field it is taken from is moved out
+// of. Nothing is moved out of a reference or an index.
+fn consume(e: &Expr, t: &Ty, env: &mut Env<Ty>) -> Result<(), Error>
+{
+    let ty = match strip_mut(t.clone())
+    {
+        Ty::Lit(ty) if !is_copy(t, env) => ty,
+        _ => return Ok(()),
+    };
+    let mut root = e;
+    while let ExprKind::Field(inner, _) | ExprKind::Par(inner) = &root.kind
+    {
+        root = inner;
+    }
+    let behind_ref = || Error::type_err(MOVE_OUT_OF_BORROW, format!("cannot move out of `{}` which is behind a reference", e)).with_span(e.span);
+    match (&root.kind, place(e))
+    {
+        (ExprKind::Ident(id), Some((_, fields))) => match env.v.get_ref(id)
+        {
+            Some(r) if env.moves.captured.contains(&r) =>
+                Err(Error::type_err(MOVE_OUT_OF_BORROW, format!("cannot move out of `{}`, a captured variable in an `Fn` closure", id)).with_span(e.span)),
+            Some(r) => match strip_mut(env.v.de_ref(r.clone()))
+            {
+                Ty::Lit(Type::Ref(_)) => Err(behind_ref()),
+                _ =>
+                {
+                    env.moves.moved.push(Move { var: r, fields, ty, span: e.span });
+                    env.moves.spans.push(e.span);
+                    Ok(())
+                },
+            },
+            None => Ok(()),
+        },
+        (ExprKind::Index(v, _), _) => Err(Error::type_err(MOVE_OUT_OF_BORROW, format!("cannot move out of index of `{}`", v)).with_span(e.span)),
+        (ExprKind::UnOp(UnOp::DeRef, _), _) => Err(behind_ref()),
+        // a temporary
+        _ => Ok(()),
+    }
+}
+
+// A variable, or a field of it, is used, it must not be moved out of, nor
+// may a part of it
+fn check_moved(id: &str, fields: &[String], env: &Env<Ty>) -> Result<(), Error>
+{
+    let r = match env.v.get_ref(id)
+    {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+    let overlapping = env.moves.moved.iter()
+        .find(|m| m.var == r && (fields.starts_with(&m.fields) || m.fields.starts_with(fields)));
+    match overlapping
+    {
+        Some(m) => Err(use_of_moved(id, fields, m, "moved here")),
+        None => Ok(()),
+    }
+}
+
+// As rustc words it, the place `id.fields` is used after the move `m` (of
+// it, or a part of it), labeled "value ..."
+fn use_of_moved(id: &str, fields: &[String], m: &Move, label: &str) -> Error
+{
+    let moved = place_name(id, &m.fields);
+    let (message, label, note) = match m.fields.len() > fields.len()
+    {
+        true => (format!("use of partially moved value: `{}`", place_name(id, fields)), format!("value partially {}", label), "partial move"),
+        false => (format!("use of moved value: `{}`", moved), format!("value {}", label), "move"),
+    };
+    Error::type_err(USE_OF_MOVED_VALUE, message)
+        .with_label(m.span, label)
+        .with_note(format!("{} occurs because `{}` has type `{}`, which does not implement the `Copy` trait", note, moved, m.ty))
+}
+
+// The type of the variable, or a field of it, `e` where the moves are not
+// checked, as it is assigned or its fields are checked on their own
+fn unchecked(e: &Expr, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error>
+{
+    let moved = std::mem::take(&mut env.moves.moved);
+    let t = e.eval(env);
+    env.moves.moved = moved;
+    t
+}
+
+// A variable, or a field of it, is (re)initialized, by `let` or an
+// assignment
+fn reinit(id: &str, fields: &[String], env: &mut Env<Ty>)
+{
+    if let Some(r) = env.v.get_ref(id)
+    {
+        env.moves.moved.retain(|m| m.var != r || !m.fields.starts_with(fields));
+    }
+}
+
+// Adds the moves of `other`, a place may be moved out of by either
+fn join_moves(moved: &mut Vec<Move>, other: Vec<Move>)
+{
+    for m in other
+    {
+        if !moved.iter().any(|n| n.var == m.var && n.fields == m.fields)
+        {
+            moved.push(m);
+        }
+    }
+}
+
+// The moves after the branches of an `if` or `match`, each given with whether
+// it diverges, those that do are left out (e.g. after `if c { return s; }`)
+fn join_branches(branches: Vec<(Vec<Move>, bool)>) -> Vec<Move>
+{
+    let mut moved = vec![];
+    for (other, diverges) in branches
+    {
+        if !diverges
+        {
+            join_moves(&mut moved, other);
+        }
+    }
+    moved
+}
+
+// A loop body is checked once, so a variable declared outside of the loop
+// and moved out of by the body must not reach the next iteration, from the
+// end of the body (unless it diverges) or a `continue`, unless it was
+// already moved before the loop
+fn check_iteration(before: &[Move], continues: Vec<Move>, diverges: bool, env: &Env<Ty>) -> Result<(), Error>
+{
+    let mut next = continues;
+    if !diverges
+    {
+        join_moves(&mut next, env.moves.moved.clone());
+    }
+    for m in next
+    {
+        if m.var.scope_index() >= env.v.depth() || before.iter().any(|n| n.var == m.var && n.fields == m.fields)
+        {
+            continue;
+        }
+        if let Some((id, _)) = env.v.bindings().into_iter().find(|(_, r)| *r == m.var)
+        {
+            return Err(use_of_moved(&id, &m.fields, &m, "moved here, in previous iteration of loop").with_span(m.span));
+        }
+    }
+    Ok(())
+}
+
+impl Eval<Ty> for Block 
+{
+    fn eval(&self, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
+    {
+        let outermost = env.v.depth() == 0;
+        env.v.push_scope();
+        // the functions declared in the block are in scope for the whole block,
+        // shadowing outer functions with the same name
+        let fns = env.f.clone();
+        let structs = env.s.clone();
+        let enums = env.e.clone();
+        for stmt in &self.statements 
+        {
+            match &stmt.kind
+            {
+                StatementKind::Fn(decl) => { env.f.0.insert(decl.id.clone(), (decl.clone(), None)); },
+                StatementKind::Struct(decl) => { env.s.0.insert(decl.id.clone(), decl.clone()); },
+                StatementKind::Enum(decl) => { env.e.0.insert(decl.id.clone(), decl.clone()); },
+                _ => (),
+            }
+        }
+
+        let mut return_ty = Ok((Ty::Lit(Type::Unit), None));
+        let mut diverges = false;
+        for stmt in &self.statements 
+        {
+            // update the return type for each iteration
+            return_ty = stmt.eval(env);
+            match &return_ty
+            {
+                Err(_) => break,
+                Ok((Ty::Lit(Type::Never), _)) => diverges = true,
+                Ok(_) => (),
+            }
+        }
+        env.f = fns;
+        env.s = structs;
+        env.e = enums;
+        env.v.pop_scope();
+        let mut return_ty = return_ty?;
+        if outermost
+        {
+            // a block checked on its own, outside of any function, ends the
+            // inference of its integer literals
+            literal_types(env)?;
+            return_ty.0 = resolve_ty(return_ty.0, env);
+        }
+        if self.semi && diverges
+        {
+            // e.g. `{ break; }`, the end of the block is never reached
+            Ok((Ty::Lit(Type::Never), None))
+        }
+        else if self.semi
+        {
+            Ok((Ty::Lit(Type::Unit), None))
+        }
+        else
+        {
+            Ok(return_ty)
+        }
+    }
+}
+
+impl Eval<Ty> for FnDeclaration 
+{
+    fn eval(&self, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
+    {
+        // the body of a generic function is checked once where it is declared,
+        // with its type parameters opaque, and again for each of its instances
+        if !self.generics.is_empty()
+        {
+            return opaque_check(self, env);
+        }
+        // functions do not capture, so the body is checked in a fresh environment
+        // where only the parameters are bound
+        for p in &self.parameters.0
+        {
+            check_param_type(&p.ty, env).map_err(|err| err.or_span(p.span))?;
+        }
+        if let Some(ty) = &self.ty
+        {
+            check_type(ty, env).map_err(|err| err.or_span(self.span))?;
+        }
+        let ty = Ty::Lit(self.ty.clone().unwrap_or(Type::Unit));
+        let mut fn_env: Env<Ty> = Env::new();
+        fn_env.f = env.f.clone();
+        fn_env.s = env.s.clone();
+        fn_env.e = env.e.clone();
+        fn_env.ret = Some((self.id.clone(), ty.clone()));
+        fn_env.v.push_scope();
+        // the bindings of all parameters, the names must be distinct
+        let mut bindings = vec![];
+        for p in &self.parameters.0
+        {
+            pattern_type(&p.pat, &p.ty, env, &mut bindings)?;
+            irrefutable(&p.pat, &p.ty, "function argument", env)?;
+        }
+        for (id, ty) in bindings
+        {
+            fn_env.v.alloc(&id, ty);
+        }
+        // the instances of generic functions and the types of integer
+        // literals are shared by all functions
+        fn_env.inst = std::mem::take(&mut env.inst);
+        fn_env.lits = std::mem::take(&mut env.lits);
+        fn_env.moves.spans = std::mem::take(&mut env.moves.spans);
+        let outer = std::mem::take(&mut fn_env.inst.pending);
+        let body_ty = self.body.eval(&mut fn_env);
+        let pending = std::mem::replace(&mut fn_env.inst.pending, outer);
+        env.inst = fn_env.inst;
+        env.lits = fn_env.lits;
+        env.moves.spans = fn_env.moves.spans;
+        let body_ty = body_ty?.0;
+
+        let ret = unify(body_ty, ty.clone(), ty.clone(), env).map_err(|err| 
+        {
+            // point at the value returned, if any
+            let span = match self.body.statements.last()
+            {
+                Some(stmt) if !self.body.semi => stmt.span,
+                _ => self.body.span,
+            };
+            err.with_span(span)
+                .with_note(format!("the return type of `{}` is `{}`", self.id, ty))
+        })?;
+        // the integer literals of the body are inferred
+        instances(pending, env)?;
+        Ok(ret)
+    }
+}
+
+// The generic function `f` checked as a function of its own, where each type
+// parameter `T` is a `Type::Param` that fits only itself and supports nothing
+// but comparisons (there are no bounds, the instances check them). The
+// instances and integer literals of this check are not kept.
+fn opaque_check(f: &FnDeclaration, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error>
+{
+    let params: Vec<(String, Type)> = f.generics.iter().map(|g| (g.clone(), Type::Param(g.clone()))).collect();
+    let inst = std::mem::take(&mut env.inst);
+    let lits = std::mem::take(&mut env.lits);
+    let checked = f.instantiate(&f.id, &params).eval(env).and_then(|_| literal_types(env));
+    env.inst = inst;
+    env.lits = lits;
+    checked?;
+    Ok((Ty::Lit(Type::Unit), None))
+}
+
+// Named types must be declared, `str` is only used behind a reference, and
+// `impl Fn` only for parameters
+fn check_type(t: &Type, env: &Env<Ty>) -> Result<(), Error>
+{
+    match t
+    {
+        Type::Named(s) if !env.s.0.contains_key(s) && !env.e.0.contains_key(s) => 
+            Err(Error::type_err(UNRESOLVED_NAME, format!("cannot find type `{}` in this scope", s))),
+        Type::Ref(t) if **t == Type::Str => Ok(()),
+        Type::Str => Err(Error::type_err(MISMATCHED_TYPES, "the size for values of type `str` cannot be known at compilation time")),
+        Type::Ref(t) => check_type(t, env),
+        Type::Tuple(ts) => ts.iter().try_for_each(|t| check_type(t, env)),
+        Type::Array(t, _) | Type::Vec(t) => check_type(t, env),
+        Type::Fn(ts, t) => ts.iter().chain(Some(&**t)).try_for_each(|t| check_type(t, env)),
+        Type::ImplFn(..) => Err(Error::type_err(MISMATCHED_TYPES, "`impl Fn` is only supported as the type of a function parameter")),
+        _ => Ok(()),
+    }
+}
+
+// A parameter of a function may also be `impl Fn`
+fn check_param_type(t: &Type, env: &Env<Ty>) -> Result<(), Error>
+{
+    match t
+    {
+        Type::ImplFn(ts, t) => ts.iter().chain(Some(&**t)).try_for_each(|t| check_type(t, env)),
+        t => check_type(t, env),
+    }
+}
+
+// Field names must be unique and their types declared
+fn check_fields(fields: &[Field], env: &Env<Ty>) -> Result<(), Error>
+{
+    for (i, field) in fields.iter().enumerate()
+    {
+        if let Some(first) = fields[..i].iter().find(|f| f.id == field.id)
+        {
+            return Err(Error::type_err(DUPLICATE_FIELD, format!("field `{}` is already declared", field.id))
+                .with_span(field.span)
+                .with_label(first.span, "first declared here"));
+        }
+        check_type(&field.ty, env).map_err(|err| err.or_span(field.span))?;
+    }
+    Ok(())
+}
+
+impl Eval<Ty> for StructDeclaration 
+{
+    fn eval(&self, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
+    {
+        check_fields(&self.fields, env)?;
+        Ok((Ty::Lit(Type::Unit), None))
+    }
+}
+
+impl Eval<Ty> for EnumDeclaration 
+{
+    fn eval(&self, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
+    {
+        for (i, variant) in self.variants.iter().enumerate()
+        {
+            if let Some(first) = self.variants[..i].iter().find(|v| v.id == variant.id)
+            {
+                return Err(Error::type_err(DUPLICATE_FIELD, format!("variant `{}` is already declared", variant.id))
+                    .with_span(variant.span)
+                    .with_label(first.span, "first declared here"));
+            }
+            check_fields(&variant.fields, env)?;
+        }
+        Ok((Ty::Lit(Type::Unit), None))
+    }
+}
+
+impl Eval<Ty> for Prog 
+{
+    fn eval(&self, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
+    {
+        env.add_types_unique(self.1.clone(), self.2.clone())?;
+        for s in &self.1
+        {
+            s.eval(env)?;
+        }
+        for e in &self.2
+        {
+            e.eval(env)?;
+        }
+        env.f.add_functions_unique(self.0.clone())?;
+        let (print, intrinsic) = vm_println();
+        env.f.0.insert(print.id.clone(), (print, Some(intrinsic)));
+        for _f in self.0.clone()
+        {
+            _f.eval(env)?;
+        }
+        // the integer literals must be in range of the types inferred
+        literal_types(env)?;
+        match env.f.0.get("main")
+        {
+            Some((f, _)) if !f.generics.is_empty() => Err(Error::type_err(MISMATCHED_TYPES, "`main` function is not allowed to have generic parameters").with_span(f.span)),
+            Some(_f) => Ok((Ty::Lit(Type::Unit), None)),
+            None => Err(Error::type_err(MAIN_NOT_FOUND, "Main not found")),
+        }
     }
 }
 
 impl Eval<Ty> for Statement
 {
-    #[allow(unused_variables)]
-    #[allow(unreachable_code)]
     fn eval(&self, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
     {
-        Ok
-        (
-            match self
+        statement_type(self, env).map_err(|err| err.or_span(self.span))
+    }
+}
+
+#[allow(unused_variables)]
+#[allow(unreachable_code)]
+fn statement_type(stmt: &Statement, env: &mut Env<Ty>) -> Result<(Ty, Option<Ref>), Error> 
+{
+    Ok
+    (
+        match &stmt.kind
+        {
+            StatementKind::Assign(id, e) =>
             {
-                Statement::Assign(id, e) =>
+                // a variable or field moved out of may be assigned again, but
+                // not a field of a moved value
+                let id_type = match place(id)
+                {
+                    Some((var, fields)) => 
+                    {
+                        let r = env.v.get_ref(var);
+                        if let Some(m) = env.moves.moved.iter().find(|m| Some(&m.var) == r.as_ref() && m.fields.len() < fields.len() && fields.starts_with(&m.fields))
+                        {
+                            return Err(Error::type_err(USE_OF_MOVED_VALUE, format!("assign to part of moved value: `{}`", place_name(var, &m.fields)))
+                                .with_span(id.span)
+                                .with_label(m.span, "value moved here"));
+                        }
+                        unchecked(id, env)?.0
+                    },
+                    None => id.eval(env)?.0,
+                };
+                let e_type = value(e, env)?;
+                match (&id.kind, id_type)
+                {
+                    // declared without type and value (`let a;`), the first assignment decides
+                    (ExprKind::Ident(key), Ty::Lit(Type::Unit)) => 
+                    {
+                        let r = env.v.get_ref(key).unwrap();
+                        let ty = match env.v.de_ref(r.clone())
+                        {
+                            Ty::Mut(_) => Ty::Mut(Box::new(e_type)),
+                            _ => e_type,
+                        };
+                        env.v.set_ref(r, ty);
+                    },
+                    (_, id_type) => 
+                    {
+                        unify(e_type, id_type.clone(), id_type, env).map_err(|err| err.or_span(e.span))?;
+                    },
+                }
+                if let Some((var, fields)) = place(id)
+                {
+                    reinit(var, &fields, env);
+                }
+                (Ty::Lit(Type::Unit), None)
+            },
+            StatementKind::Expr(e) =>
+            {
+                let mut _type = e.eval(env)?;
+                consume(e, &_type.0, env)?;
+                _type.0 = match _type.0
+                {
+                    Ty::Mut(b) => *b,
+                    _=> _type.0,
+                };
+                _type
+            },
+            StatementKind::Fn(decl) =>
+            {
+                // the declaration itself is an item of type ()
+                decl.eval(env)?;
+                (Ty::Lit(Type::Unit), None)
+            },
+            StatementKind::Struct(decl) =>
+            {
+                decl.eval(env)?;
+                (Ty::Lit(Type::Unit), None)
+            },
+            StatementKind::Enum(decl) =>
+            {
+                decl.eval(env)?;
+                (Ty::Lit(Type::Unit), None)
+            },
+            StatementKind::Let(p, t, e) =>
+            {
+                let (m, id) = match &p.kind
+                {
+                    PatternKind::Ident(m, id) => (m, id),
+                    _ => 
+                    {
+                        let_pattern_type(p, t, e, env)?;
+                        return Ok((Ty::Lit(Type::Unit), None));
+                    },
+                };
+                if let Some(t) = t
+                {
+                    check_type(t, env)?;
+                }
+                let e_val : Ty;
+                if e.is_some()
                 {
-                    let id_type = id.eval(env)?.0;
-                    let m = id.eval(env)?.1;
-                    let ty: Option<Ty> = env.v.get(&id.to_string());
-                    if !m.is_none()
+                    e_val = value(e.as_ref().unwrap(), env)?;
+                }
+                else
+                {
+                    e_val = Ty::Lit(Type::Unit);
+                }
+
+                match (e, t)
+                {
+                    (Some(e), Some(t)) =>
                     {
-                        match env.v.de_ref(m.unwrap()) 
+                        if unify(e_val.clone(),Ty::Lit((*t).clone()), Ty::Lit((*t).clone()), env).is_err() 
                         {
-                            Ty::Mut(_) => {},
-                            Ty::Lit(Type::Ref(_)) => return Err("Can't assign to Reference".to_string()),
-                            _ => return Err("Can't assign to none mutable".to_string())
+                            return Err(Error::type_err(MISMATCHED_TYPES, "Missmatching types in let-statement"))
                         }
-                    }
-                    let e_type = e.eval(env)?; //Angels crying
-                    if !ty.is_none()
-                    {
-                        match ty.unwrap() 
+
+                        // the declared type, `let a: [i32; 0] = [];` is no `[!; 0]`
+                        if m.0 
                         {
-                            Ty::Lit(Type::Unit) => 
-                            { 
-                                match id 
-                                {
-                                    Expr::Ident(key) => {env.v.alloc(&key, e_type.0);},
-                                    _ => unreachable!()
-                                }
-                            },
-                            _ => 
-                            {
-                                let res1 = id.eval(env);
-                                let res2 = e.eval(env);
-                                if res1.is_err() || res2.is_err() || unify(res1.clone()?.0, res2?.0, res1.clone()?.0).is_err() 
-                                {
-                                    return Err("Error in assignment".to_string())
-                                }
-                            },
+                            env.v.alloc(id, Ty::Mut(Box::new(Ty::Lit(t.clone()))));
+                        }
+                        else
+                        {
+                            env.v.alloc(id, Ty::Lit(t.clone()));
                         }
                     }
-                    (Ty::Lit(Type::Unit), None)
-                },
-                Statement::Expr(e) =>
-                {
-                    let mut _type = e.eval(env)?;
-                    _type.0 = match _type.0
-                    {
-                        Ty::Mut(b) => *b,
-                        _=> _type.0,
-                    };
-                    _type
-                },
-                Statement::Fn(decl) =>
-                {
-                    decl.eval(env)?
-                },
-                Statement::Let(m, id, t, e) =>
-                {
-                    let e_val : Ty;
-                    if e.is_some()
-                    {
-                        e_val = e.as_ref().unwrap().eval(env)?.0;
-                    }
-                    else
+                    (Some(e), None) =>
                     {
-                        e_val = Ty::Lit(Type::Unit);
-                    }
+                        if m.0 
+                        {
+                            env.v.alloc(id, Ty::Mut(Box::new(e_val.clone())));
+                        }
+                        else
+                        {
+                            env.v.alloc(id, e_val.clone());
+                        }
 
-                    match (e, t)
+                    }
+                    (None, Some(t)) =>
                     {
-                        (Some(e), Some(t)) =>
+                        if m.0 
                         {
-                            if unify(e_val.clone(),Ty::Lit((*t).clone()), Ty::Lit((*t).clone())).is_err() 
-                            {
-                                return Err("Missmatching types in let-statement".to_string())
-                            }
-
-                            if m.0 
-                            {
-                                env.v.alloc(&id, Ty::Mut(Box::new(e_val.clone())));
-                            }
-                            else
-                            {
-                                env.v.alloc(&id, e_val);
-                            }
+                            env.v.alloc(id, Ty::Mut(Box::new(Ty::Lit((*t).clone()))));
                         }
-                        (Some(e), None) =>
+                        else
                         {
-                            if m.0 
-                            {
-                                env.v.alloc(&id, Ty::Mut(Box::new(e_val.clone())));
-                            }
-                            else
-                            {
-                                env.v.alloc(&id, e_val.clone());
-                            }
-    
+                            env.v.alloc(id, Ty::Lit((*t).clone()));
                         }
-                        (None, Some(t)) =>
+
+                    }
+                    (None, None) =>
+                    {
+                        if m.0 
                         {
-                            if m.0 
-                            {
-                                env.v.alloc(&id, Ty::Mut(Box::new(Ty::Lit((*t).clone()))));
-                            }
-                            else
-                            {
-                                env.v.alloc(&id, Ty::Lit((*t).clone()));
-                            }
-    
+                            env.v.alloc(id, Ty::Mut(Box::new(Ty::Lit(Type::Unit))));
                         }
-                        (None, None) =>
+                        else
                         {
-                            if m.0 
-                            {
-                                env.v.alloc(&id, Ty::Mut(Box::new(Ty::Lit(Type::Unit))));
-                            }
-                            else
-                            {
-                                env.v.alloc(&id, Ty::Lit(Type::Unit));
-                            }
+                            env.v.alloc(id, Ty::Lit(Type::Unit));
                         }
                     }
+                }
+                reinit(id, &[], env);
+                (Ty::Lit(Type::Unit), None)
+            },
+            StatementKind::While(label, e, b) =>
+            {
+                let cond_t = e.eval(env)?;
+                // breaks from a while loop carry no value
+                env.loops.push((label.clone(), "while", None));
+                env.moves.loops.push((vec![], vec![]));
+                let before = env.moves.moved.clone();
+                let do_t = b.eval(env);
+                env.loops.pop();
+                let (breaks, continues) = env.moves.loops.pop().unwrap();
+                let do_t = do_t?;
+                check_iteration(&before, continues, do_t.0 == Ty::Lit(Type::Never), env)?;
+                // the body may run any number of times, or none
+                let mut moved = before;
+                join_moves(&mut moved, std::mem::take(&mut env.moves.moved));
+                join_moves(&mut moved, breaks);
+                env.moves.moved = moved;
+
+                if unify(cond_t.0, Ty::Lit(Type::Bool), Ty::Lit(Type::Bool), env).is_ok()
+                {
                     (Ty::Lit(Type::Unit), None)
-                },
-                Statement::While(e, b) =>
+                }
+                else
                 {
-                    let cond_t = e.eval(env)?;
-                    let do_t = b.eval(env)?;
-
-                    if unify(cond_t.0, Ty::Lit(Type::Bool), Ty::Lit(Type::Bool)).is_ok()
-                    {
-                        (Ty::Lit(Type::Unit), None)
-                    }
-                    else
-                    {
-                        return Err("Error message".to_string())
-                    }
-                },
-            }
-        )
-    }
+                    return Err(Error::type_err(MISMATCHED_TYPES, "Expected bool condition in while-statement"))
+                }
+            },
+            StatementKind::For(label, id, iter, b) =>
+            {
+                let t = item_type(iter, env)?;
+                // the loop variable is scoped to the body
+                env.v.push_scope();
+                env.v.alloc(id, t);
+                env.loops.push((label.clone(), "for", None));
+                env.moves.loops.push((vec![], vec![]));
+                let before = env.moves.moved.clone();
+                let do_t = b.eval(env);
+                env.loops.pop();
+                env.v.pop_scope();
+                let (breaks, continues) = env.moves.loops.pop().unwrap();
+                let do_t = do_t?;
+                check_iteration(&before, continues, do_t.0 == Ty::Lit(Type::Never), env)?;
+                // as for `while`
+                let mut moved = before;
+                join_moves(&mut moved, std::mem::take(&mut env.moves.moved));
+                join_moves(&mut moved, breaks);
+                env.moves.moved = moved;
+                unify(do_t.0, Ty::Lit(Type::Unit), Ty::Lit(Type::Unit), env).map_err(|err| err.or_span(b.span))?;
+                (Ty::Lit(Type::Unit), None)
+            },
+        }
+    )
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::Ty;
-    use crate::ast::{Block, Prog, Type};
+    use super::{monomorphize, Ty};
+    use crate::ast::{Block, IntTy, Prog, Type};
     use crate::common::parse_test;
+    use crate::error::*;
 
     #[test]
     fn test_block_let() {
@@ -428,70 +2210,289 @@ mod tests {
     }
 
     #[test]
-    fn test_block_let_shadow() {
+    fn test_block_let_shadow() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let a: i32 = 1;
+            let b: i32 = 2;
+            let a: i32 = 3;
+            let b: i32 = 4;
+
+            a + b
+        }",
+        );
+        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    }
+
+    #[test]
+    fn test_block_assign() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let mut a: i32 = 1;
+            a = 1 + 2;
+            a
+        }",
+        );
+        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    }
+
+    #[test]
+    fn test_expr_if_then_else() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let mut a: i32 = 1;
+            a = if a > 0 { a + 1 } else { a - 2 };
+            a
+        }",
+        );
+
+        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    }
+
+    #[test]
+    fn test_expr_if_then_else_bool() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let mut a: bool = false;
+            a = if a || false { a || false } else { a && true };
+            a
+        }",
+        );
+
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Bool));
+    }
+
+    #[test]
+    fn test_ref_deref() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let a: i32 = 1;
+            let b: &i32 = &a;
+            *b
+        }
+        ",
+        );
+
+        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    }
+
+    #[test]
+    fn test_ref_deref_err() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let a: i32 = 1;
+            let b: &bool = &a;
+            *b
+        }
+        ",
+        );
+
+        assert_eq!(v.is_err(), true);
+    }
+
+    #[test]
+    fn test_ref_deref_indirect() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let a = 1;
+            let b = &a;
+            let c = b;
+            *c
+        }
+        ",
+        );
+
+        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    }
+
+    #[test]
+    fn test_ref_deref_indirect2() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let a = 1;
+            let b = &a;
+            let c = &b;
+            **c
+        }
+        ",
+        );
+
+        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    }
+
+    #[test]
+    fn test_deref_assign_err() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let a = 1;
+            let b = &a;
+            *b = false;
+            a
+        }
+        ",
+        );
+
+        assert_eq!(v.is_err(), true);
+    }
+
+    #[test]
+    fn test_deref_assign() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let a = 1;
+            let b = &a;
+            *b = 7;
+            a
+        }
+        ",
+        );
+
+        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    }
+
+    #[test]
+    fn test_while_err() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let a = 2;
+            let b = false;
+            while a > 0 {
+                a = a - 1;
+                b = b + 1;
+            }
+            b
+        }
+        ",
+        );
+
+        assert_eq!(v.is_err(), true);
+    }
+
+    #[test]
+    fn test_while() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let a = 2;
+            let b = 1;
+            while a > 0 {
+                a = a - 1;
+                b = b + 1;
+            }
+            b
+        }
+        ",
+        );
+
+        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    }
+    #[test]
+    fn test_while_ref() {
+        let v = parse_test::<Block, Ty>(
+            "
+        {
+            let a = 2;
+            let b = 0;
+            let c = &b;
+            while a > 0 {
+                a = a - 1;
+                *c = (*c) + 1;
+            }
+            *c
+        }
+        ",
+        );
+
+        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    }
+
+    #[test]
+    fn test_while_ref2() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let a: i32 = 1;
-            let b: i32 = 2;
-            let a: i32 = 3;
-            let b: i32 = 4;
+            let a = 2;
+            let b = 0;
+            let c = &b;
+            let d = &a;
 
-            a + b
-        }",
+            while (*d) > 0 {
+                *d = (*d) - 1;
+                // not sure if this is even allowed in Rust
+                *&*c = (*c) + 1;
+            }
+            *c
+        }
+        ",
         );
+
         assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
     }
 
     #[test]
-    fn test_block_assign() {
+    fn test_bool() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let mut a: i32 = 1;
-            a = 1 + 2;
+            let a = true && false;
             a
-        }",
+        }
+        ",
         );
-        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Bool));
     }
 
     #[test]
-    fn test_expr_if_then_else() {
+    fn test_bool_bang() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let mut a: i32 = 1;
-            a = if a > 0 { a + 1 } else { a - 2 };
+            let a = true && !false;
             a
-        }",
+        }
+        ",
         );
 
-        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Bool));
     }
 
     #[test]
-    fn test_expr_if_then_else_bool() {
+    fn test_bool_bang2() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let mut a: bool = false;
-            a = if a || false { a || false } else { a && true };
+            let a = (!true) && false;
             a
-        }",
+        }
+        ",
         );
 
         assert_eq!(v.unwrap(), Ty::Lit(Type::Bool));
     }
 
     #[test]
-    fn test_ref_deref() {
+    fn test_local_block() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let a: i32 = 1;
-            let b: &i32 = &a;
-            *b
+            let a = 1;
+            {
+                let b = &a;
+                *b = 2;
+            };
+            a
         }
         ",
         );
@@ -500,29 +2501,251 @@ mod tests {
     }
 
     #[test]
-    fn test_ref_deref_err() {
+    fn test_local_block_assign() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let a: i32 = 1;
-            let b: &bool = &a;
-            *b
+            let a = 6;
+            let b = {
+                let b : &i32 = &a;
+                *b = (*b) + 1;
+                *b
+            };
+            b
+        }
+        ",
+        );
+
+        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    }
+
+    #[test]
+    fn test_prog_fn_sig() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn a(i: i32, bo: bool) -> i32 {
+            let q = 0;
+            fn b(j: i32) -> i32 {
+                a(j, c())
+            }
+
+            fn c() -> bool {
+                false
+            }
+
+            b(1 + i);
+            a(i, bo)
         }
         ",
         );
+        println!("v {:?}", v);
+        assert_eq!(v.is_err(), true);
+    }
+
+    #[test]
+    fn test_prog_fn_defined_twice() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn a() {
+        }
+
+        fn b() {
+            fn b() {
 
+            }
+
+        }
+        ",
+        );
+        println!("v {:?}", v);
         assert_eq!(v.is_err(), true);
     }
 
     #[test]
-    fn test_ref_deref_indirect() {
+    fn test_prog() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn main() {
+            let a = 1;
+            a;
+        }
+        ",
+        );
+        println!("v {:?}", v);
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_local_fn() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn main() {
+            fn f(i: i32, j: i32) -> i32 {
+                i + j
+            }
+            let a = f(1, 2);
+            // println!(\"a = {} and another a = {}\", a, a);
+        }
+        ",
+        );
+        println!("v {:?}", v);
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_prog_fn_body() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn add(a: i32, mut b: i32) -> i32 {
+            b = b + 1;
+            a + b
+        }
+
+        fn main() {
+            let a = add(1, 2);
+            println!(\"a = {}\", a);
+        }
+        ",
+        );
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Unit));
+    }
+
+    #[test]
+    fn test_prog_fn_return_err() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn f(b: bool) -> i32 {
+            let a = 1;
+            b
+        }
+
+        fn main() {
+        }
+        ",
+        );
+        let err = v.unwrap_err();
+        assert_eq!(err.message, "expected type `i32`, got type `bool`");
+        assert_eq!(err.span.unwrap().lo.line, 4);
+    }
+
+    #[test]
+    fn test_prog_fn_body_err() {
+        // the body is checked even if the function is never called
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn f() {
+            let a = 1 + true;
+        }
+
+        fn main() {
+        }
+        ",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_prog_fn_no_capture() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn main() {
+            let a = 1;
+            fn f() -> i32 {
+                a
+            }
+        }
+        ",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_call_unknown_fn() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn main() {
+            let a = f(1);
+        }
+        ",
+        );
+        let err = v.unwrap_err();
+        assert_eq!(err.code, UNRESOLVED_NAME);
+        assert_eq!(err.message, "cannot find function `f` in this scope");
+    }
+
+    #[test]
+    fn test_call_arity_err() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn f(a: i32) -> i32 {
+            a
+        }
+
+        fn main() {
+            f(1, 2);
+        }
+        ",
+        );
+        let err = v.unwrap_err();
+        assert_eq!(err.code, WRONG_ARG_COUNT);
+        assert_eq!(err.message, "function `f` takes 1 argument but 2 arguments were supplied");
+        assert_eq!(err.labels[0].0.lo.line, 2);
+    }
+
+    #[test]
+    fn test_call_arg_type_err() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn f(a: i32, b: bool) {
+        }
+
+        fn main() {
+            f(1, 2);
+        }
+        ",
+        );
+        let err = v.unwrap_err();
+        assert_eq!(err.code, MISMATCHED_TYPES);
+        assert_eq!(err.message, "expected type `bool` for parameter `b` of `f`, got type `{integer}`");
+        let span = err.span.unwrap();
+        assert_eq!((span.lo.line, span.lo.column), (6, 17));
+    }
+
+    #[test]
+    fn test_println_args() {
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn main() {
+            println!(\"{} {}\", 1, true);
+        }
+        ",
+        );
+        assert!(v.is_ok());
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn main() {
+            println!(1);
+        }
+        ",
+        );
+        assert!(v.is_err());
+    }
+
+    #[test]
+    fn test_check_if_then_else_shadowing() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let a = 1;
-            let b = &a;
-            let c = b;
-            *c
+            let a: i32 = 1 + 2; // a == 3
+            let a: i32 = 2 + a; // a == 5
+            if true {
+                a = a - 1;      // outer a == 4
+                let a: i32 = 0; // inner a == 0
+                a = a + 1       // inner a == 1
+            } else {
+                a = a - 1
+            };
+            a   // a == 4
         }
         ",
         );
@@ -531,317 +2754,799 @@ mod tests {
     }
 
     #[test]
-    fn test_ref_deref_indirect2() {
+    fn test_error_span() {
+        let v = parse_test::<Block, Ty>("{\n    let a = 1;\n    a + b\n}");
+        let span = v.unwrap_err().span.unwrap();
+        assert_eq!((span.lo.line, span.lo.column), (3, 8));
+        assert_eq!((span.hi.line, span.hi.column), (3, 9));
+    }
+
+    #[test]
+    fn test_ref() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let a = 1;
-            let b = &a;
-            let c = &b;
-            **c
+            let a = &1;
+            *a
         }
         ",
         );
-
         assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
     }
 
     #[test]
-    fn test_deref_assign_err() {
+    fn test_operators() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let a = 1;
-            let b = &a;
-            *b = false;
-            a
+            let a = -(7 % 3);
+            (a <= 1) || (a != 2)
         }
         ",
         );
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Bool));
+    }
 
-        assert_eq!(v.is_err(), true);
+    #[test]
+    fn test_operators_err() {
+        assert!(parse_test::<Block, Ty>("{ -true }").is_err());
+        assert!(parse_test::<Block, Ty>("{ true % 2 }").is_err());
+        assert!(parse_test::<Block, Ty>("{ 1 && true }").is_err());
+        assert!(parse_test::<Block, Ty>("{ 1 != true }").is_err());
     }
 
     #[test]
-    fn test_deref_assign() {
+    fn test_loop_break_type() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let a = 1;
-            let b = &a;
-            *b = 7;
-            a
-        }
-        ",
+            let a: i32 = 'a: loop {
+                loop {
+                    break 'a 1;
+                }
+            };
+            let b: bool = loop {
+                if a > 0 { break true; } else { break false; }
+            };
+            let c: i32 = if b { 2 } else { loop {} };
+            'w: while b { continue 'w; }
+            loop { break; }
+        }",
         );
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Unit));
+    }
 
-        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    #[test]
+    fn test_loop_never() {
+        // a loop without break diverges, and fits any type
+        let v = parse_test::<Prog, Ty>("fn f() -> i32 { loop {} } fn main() {}");
+        assert!(v.is_ok());
+        let v = parse_test::<Prog, Ty>("fn f() -> i32 { loop { break; } } fn main() {}");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
     }
 
     #[test]
-    fn test_while_err() {
-        let v = parse_test::<Block, Ty>(
+    fn test_loop_err() {
+        let v = parse_test::<Block, Ty>("{ loop { break 1; break true; } }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ break; }");
+        assert_eq!(v.unwrap_err().code, OUTSIDE_OF_LOOP);
+        let v = parse_test::<Block, Ty>("{ loop { continue 'a; } }");
+        assert_eq!(v.unwrap_err().code, UNDECLARED_LABEL);
+        let v = parse_test::<Block, Ty>("{ while true { break 1; } }");
+        assert_eq!(v.unwrap_err().code, BREAK_WITH_VALUE);
+        // labels do not reach into nested functions
+        let v = parse_test::<Block, Ty>("{ 'a: loop { fn f() { break 'a; } } }");
+        assert_eq!(v.unwrap_err().code, UNDECLARED_LABEL);
+    }
+
+    #[test]
+    fn test_return() {
+        let v = parse_test::<Prog, Ty>(
             "
-        {
-            let a = 2;
-            let b = false;
+        fn f(a: i32) -> i32 {
             while a > 0 {
-                a = a - 1;
-                b = b + 1;
+                if a == 2 { return 2; }
             }
-            b
+            if a < 0 { return -1 } else { return 1; }
         }
+        fn g() { return; }
+        fn main() { let a: bool = return; }
         ",
         );
+        assert!(v.is_ok());
+    }
 
-        assert_eq!(v.is_err(), true);
+    #[test]
+    fn test_return_err() {
+        let v = parse_test::<Prog, Ty>("fn f() -> i32 { if true { return true; } 1 } fn main() {}");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, MISMATCHED_TYPES);
+        assert_eq!(err.notes, ["the return type of `f` is `i32`"]);
+        let v = parse_test::<Prog, Ty>("fn f() -> i32 { return; } fn main() {}");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ return 1; }");
+        assert_eq!(v.unwrap_err().code, RETURN_OUTSIDE_FN);
     }
 
     #[test]
-    fn test_while() {
+    fn test_for() {
         let v = parse_test::<Block, Ty>(
             "
         {
-            let a = 2;
-            let b = 1;
-            while a > 0 {
-                a = a - 1;
-                b = b + 1;
+            let mut s = 0;
+            let n = 3;
+            'a: for i in (0..=n * 2).rev() {
+                for j in 0..i {
+                    if j > 2 { continue 'a; }
+                    s = s + i * j;
+                }
             }
-            b
-        }
-        ",
+            s
+        }",
         );
-
         assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
     }
+
+    #[test]
+    fn test_for_err() {
+        let v = parse_test::<Block, Ty>("{ for i in 0..true {} }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        // the loop variable is scoped to the body
+        let v = parse_test::<Block, Ty>("{ for i in 0..1 {} i }");
+        assert_eq!(v.unwrap_err().code, UNRESOLVED_NAME);
+        let v = parse_test::<Block, Ty>("{ for i in 0..1 { i } }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ for i in 0..1 { break 1; } }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, BREAK_WITH_VALUE);
+        assert_eq!(err.message, "`break` with value from a `for` loop");
+    }
+
     #[test]
-    fn test_while_ref() {
-        let v = parse_test::<Block, Ty>(
+    fn test_struct() {
+        let v = parse_test::<Prog, Ty>(
             "
-        {
-            let a = 2;
-            let b = 0;
-            let c = &b;
-            while a > 0 {
-                a = a - 1;
-                *c = (*c) + 1;
-            }
-            *c
+        struct Point { x: i32, y: i32 }
+        struct Line { from: Point, to: Point }
+        fn mid(l: &Line) -> Point {
+            Point { x: (l.from.x + l.to.x) / 2, y: (l.from.y + l.to.y) / 2 }
+        }
+        fn main() -> bool {
+            struct Flag { on: bool }
+            let mut l = Line { to: Point { x: 2, y: 2 }, from: Point { y: 0, x: 0 } };
+            let x = &mut l.to.x;
+            *x = 4;
+            l.from.y = 1;
+            let f = Flag { on: mid(&l).x == 2 };
+            f.on
         }
         ",
         );
+        assert!(v.is_ok());
+    }
 
-        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    #[test]
+    fn test_struct_err() {
+        let v = parse_test::<Block, Ty>("{ struct P { a: i32, b: i32, c: bool } P { b: 1 } }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, MISSING_FIELD);
+        assert_eq!(err.message, "missing fields `a` and `c` in initializer of `P`");
+        let v = parse_test::<Block, Ty>("{ struct P { a: i32 } P { a: 1, b: 2 } }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, UNKNOWN_FIELD);
+        assert_eq!(err.message, "struct `P` has no field named `b`");
+        let v = parse_test::<Block, Ty>("{ struct P { a: i32 } let p = P { a: 1 }; p.b }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, UNKNOWN_FIELD);
+        assert_eq!(err.message, "no field `b` on type `P`");
+        let v = parse_test::<Block, Ty>("{ struct P { a: i32 } P { a: true } }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ struct P { a: i32 } P { a: 1, a: 2 } }");
+        assert_eq!(v.unwrap_err().code, DUPLICATE_FIELD);
+        let v = parse_test::<Block, Ty>("{ struct P { a: Q } }");
+        assert_eq!(v.unwrap_err().code, UNRESOLVED_NAME);
+        let v = parse_test::<Prog, Ty>("struct P { a: i32 } struct P { b: i32 } fn main() {}");
+        assert_eq!(v.unwrap_err().code, DUPLICATE_TYPE);
+        // struct declarations are scoped to their block
+        let v = parse_test::<Block, Ty>("{ { struct P { a: i32 } }; P { a: 1 } }");
+        assert_eq!(v.unwrap_err().code, UNRESOLVED_NAME);
     }
 
     #[test]
-    fn test_while_ref2() {
-        let v = parse_test::<Block, Ty>(
+    fn test_match() {
+        let v = parse_test::<Prog, Ty>(
             "
-        {
-            let a = 2;
-            let b = 0;
-            let c = &b;
-            let d = &a;
-
-            while (*d) > 0 {
-                *d = (*d) - 1;
-                // not sure if this is even allowed in Rust
-                *&*c = (*c) + 1;
+        enum Shape { Dot, Circle(i32), Rect { w: i32, h: i32 } }
+        fn area(s: &Shape) -> i32 {
+            match *s {
+                Shape::Dot => 0,
+                Shape::Circle(0) => return 0,
+                Shape::Circle(r) => 3 * r * r,
+                Shape::Rect { w: 1, h } => h,
+                Shape::Rect { w, h: mut h } => { h = h * w; h }
             }
-            *c
+        }
+        fn main() -> bool {
+            let b = match area(&Shape::Rect { w: 2, h: 3 }) {
+                6 => true,
+                _ => false,
+            };
+            match b { true => b, false => loop {} }
         }
         ",
         );
-
-        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+        assert!(v.is_ok());
     }
 
     #[test]
-    fn test_bool() {
-        let v = parse_test::<Block, Ty>(
+    fn test_tuple() {
+        let v = parse_test::<Prog, Ty>(
             "
-        {
-            let a = true && false;
-            a
+        fn swap((a, b): (i32, bool), mut n: i32) -> (bool, i32) {
+            n = n + 1;
+            (b, a + n)
+        }
+        fn main() {
+            let t = (1, (true, 3));
+            let (a, (mut b, c)) = t;
+            b = !b;
+            let (x, _): (bool, i32) = swap((a, b), c);
+            let y: i32 = t.1.1;
+            let m = match t { (1, (true, z)) => z, (_, _) => 0 };
         }
         ",
         );
+        assert!(v.is_ok());
+    }
 
-        assert_eq!(v.unwrap(), Ty::Lit(Type::Bool));
+    #[test]
+    fn test_tuple_err() {
+        let v = parse_test::<Block, Ty>("{ let (a, b) = (1, 2, 3); }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, MISMATCHED_TYPES);
+        assert_eq!(err.message, "mismatched types: expected a tuple with 3 elements, found one with 2 elements");
+        let v = parse_test::<Block, Ty>("{ let x = 1; let (a, b) = x; }");
+        assert_eq!(v.unwrap_err().message, "mismatched types: expected `{integer}`, found tuple");
+        let v = parse_test::<Block, Ty>("{ let (1, b) = (1, 2); }");
+        assert_eq!(v.unwrap_err().code, NON_EXHAUSTIVE);
+        let v = parse_test::<Block, Ty>("{ let (a, a) = (1, 2); }");
+        assert_eq!(v.unwrap_err().code, DUPLICATE_BINDING);
+        let v = parse_test::<Block, Ty>("{ let (a, b); }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let t = (1, true); t.2 }");
+        assert_eq!(v.unwrap_err().code, UNKNOWN_FIELD);
+        let v = parse_test::<Block, Ty>("{ let t: (i32, bool) = (true, 1); }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Prog, Ty>("fn f((a, b): (i32, i32), c: i32) -> i32 { a + b + c } fn main() { f((1, 2), (3,)); }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
     }
 
     #[test]
-    fn test_bool_bang() {
-        let v = parse_test::<Block, Ty>(
+    fn test_array() {
+        let v = parse_test::<Prog, Ty>(
             "
-        {
-            let a = true && !false;
-            a
+        fn sum(a: &[i32; 3]) -> i32 { a[0] + (*a)[1] + a[2] }
+        fn main() {
+            let mut a = [1, 2, 3];
+            a[0] = sum(&a);
+            let e: [bool; 0] = [];
+            let m = [[true; 2], [false, true]];
+            let b: bool = m[1][0];
+            let n: i32 = (a.len() + m[0].len() + e.len()) as i32;
+            let x = [return, 1];
         }
         ",
         );
+        assert!(v.is_ok());
+    }
 
-        assert_eq!(v.unwrap(), Ty::Lit(Type::Bool));
+    #[test]
+    fn test_array_err() {
+        let v = parse_test::<Block, Ty>("{ [1, true] }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let a: [i32; 3] = [1, 2]; }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let a = [1, 2]; a[true] }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let a = (1, 2); a[0] }");
+        assert_eq!(v.unwrap_err().message, "cannot index into a value of type `({integer}, {integer})`");
+        let v = parse_test::<Block, Ty>("{ let mut a = [1, 2]; a[0] = false; }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let a = [1, 2]; a.size() }");
+        assert_eq!(v.unwrap_err().message, "no method named `size` found for type `[{integer}; 2]`");
+        let v = parse_test::<Block, Ty>("{ let a = [1, 2]; a.len(1) }");
+        assert_eq!(v.unwrap_err().code, WRONG_ARG_COUNT);
     }
 
     #[test]
-    fn test_bool_bang2() {
-        let v = parse_test::<Block, Ty>(
+    fn test_vec() {
+        let v = parse_test::<Prog, Ty>(
             "
-        {
-            let a = (!true) && false;
-            a
+        fn sum(v: &Vec<i32>) -> i32 { v[0] + v.len() as i32 }
+        fn main() {
+            let mut v = Vec::new();
+            v.push(1);
+            let mut w: Vec<bool> = vec![];
+            w.push(true);
+            let n: i32 = sum(&v) + v.pop();
+            let m = vec![[1, 2]; n];
+            let b: bool = w[0];
+            let x: i32 = m[0][1];
         }
         ",
         );
+        assert!(v.is_ok());
+    }
 
-        assert_eq!(v.unwrap(), Ty::Lit(Type::Bool));
+    #[test]
+    fn test_vec_err() {
+        let v = parse_test::<Block, Ty>("{ let mut v = vec![1]; v.push(true); }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let mut v = Vec::new(); v.push(1); v.push(false); }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let v: Vec<i32> = vec![true]; }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let v = vec![0; true]; }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let v = vec![1]; v == v }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let v = Vec::with_capacity(1); }");
+        assert_eq!(v.unwrap_err().message, "no function or associated item named `with_capacity` found for `Vec`");
+        let v = parse_test::<Block, Ty>("{ let v = vec![1]; v.pop(1) }");
+        assert_eq!(v.unwrap_err().code, WRONG_ARG_COUNT);
     }
 
     #[test]
-    fn test_local_block() {
-        let v = parse_test::<Block, Ty>(
+    fn test_int_types() {
+        let v = parse_test::<Prog, Ty>(
             "
-        {
-            let a = 1;
-            {
-                let b = &a;
-                *b = 2;
-            };
-            a
+        fn f(x: u8) -> u64 { x as u64 * 2u64 }
+        fn main() {
+            let a: i64 = -5i64 + 3i64;
+            let b: u64 = f(200u8) + a as u64;
+            let v = vec![1, 2];
+            let i: usize = 1usize;
+            let e: i32 = v[i] + v[0];
+            for j in 0u16..10u16 {
+                let k: u16 = j;
+            }
+            let t: i8 = true as i8;
+            let c: bool = 1u32 < 2u32;
         }
         ",
         );
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_int_types_err() {
+        // no implicit widening
+        let v = parse_test::<Block, Ty>("{ 1u8 + 1i32 }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let a: u64 = 1u32; }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let a: i64 = 1; a < 1i32 }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let a: u8 = 1; let b: i32 = a; }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ -1u8 }");
+        assert_eq!(v.unwrap_err().message, "cannot apply unary operator `-` to type `u8`");
+        let v = parse_test::<Block, Ty>("{ (1, 2) as u8 }");
+        assert_eq!(v.unwrap_err().message, "cannot cast `({integer}, {integer})` as `u8`");
+        let v = parse_test::<Block, Ty>("{ 1 as bool }");
+        assert_eq!(v.unwrap_err().message, "cannot cast `{integer}` as `bool`");
+        let v = parse_test::<Block, Ty>("{ for i in 0u8..10i32 {} }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let v = vec![1]; let n: i32 = v.len(); }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+    }
 
+    #[test]
+    fn test_int_literals() {
+        // an integer literal without a suffix takes the type it is used as
+        let v = parse_test::<Block, Ty>("{ let x: u8 = 5; x }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Int(IntTy::U8)));
+        let v = parse_test::<Block, Ty>("{ 1u8 + 1 }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Int(IntTy::U8)));
+        let v = parse_test::<Block, Ty>("{ let x: i64 = 3; x * 2 }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Int(IntTy::I64)));
+        let v = parse_test::<Block, Ty>("{ let x: u64 = 18446744073709551615; x }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Int(IntTy::U64)));
+        let v = parse_test::<Block, Ty>("{ let x = 1; let y: u16 = x; x }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Int(IntTy::U16)));
+        let v = parse_test::<Block, Ty>("{ let v = vec![1, 2]; for i in 0..v.len() { let j: usize = i; } v.len() }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Int(IntTy::Usize)));
+        let v = parse_test::<Block, Ty>("{ let c = 1i64 < 1; let n = -128; let m: i8 = n; c }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Bool));
+        let v = parse_test::<Block, Ty>("{ 97 as char }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Char));
+        // otherwise it is an `i32`
+        let v = parse_test::<Block, Ty>("{ let x = 1; x + 2 }");
         assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+        let v = parse_test::<Prog, Ty>("fn f(a: u8) -> u8 { a } fn main() { let x: u8 = f(250) + 5; }");
+        assert!(v.is_ok());
     }
 
     #[test]
-    fn test_local_block_assign() {
-        let v = parse_test::<Block, Ty>(
+    fn test_int_literals_err() {
+        let err = parse_test::<Block, Ty>("{ let x: u8 = 300; }").unwrap_err();
+        assert_eq!(err.code, LITERAL_OUT_OF_RANGE);
+        assert_eq!(err.message, "literal out of range for `u8`");
+        let v = parse_test::<Block, Ty>("{ let x = 2147483648; }");
+        assert_eq!(v.unwrap_err().code, LITERAL_OUT_OF_RANGE);
+        let v = parse_test::<Block, Ty>("{ let x = -2147483649; }");
+        assert_eq!(v.unwrap_err().code, LITERAL_OUT_OF_RANGE);
+        let v = parse_test::<Block, Ty>("{ let x: i8 = -129; }");
+        assert_eq!(v.unwrap_err().code, LITERAL_OUT_OF_RANGE);
+        let v = parse_test::<Block, Ty>("{ let x: u32 = -1; }");
+        assert_eq!(v.unwrap_err().code, LITERAL_OUT_OF_RANGE);
+        let v = parse_test::<Block, Ty>("{ 300 as char }");
+        assert_eq!(v.unwrap_err().code, LITERAL_OUT_OF_RANGE);
+    }
+
+    #[test]
+    fn test_float_char() {
+        let v = parse_test::<Prog, Ty>(
             "
-        {
-            let a = 6;
-            let b = {
-                let b : &i32 = &a;
-                *b = (*b) + 1;
-                *b
-            };
-            b
+        fn area(r: f64) -> f64 { 3.14 * r * r }
+        fn main() {
+            let a: f64 = area(2.0) / 2.0 - -1.5 % 1.0;
+            let b: bool = a < 1e3 && 'a' <= 'z' && 'x' != '\\n';
+            let c: char = 97u8 as char;
+            let i: i64 = a as i64 + c as i64;
+            let x: f64 = i as f64;
+            let d = match c { 'a' => 1, _ => 2 };
         }
         ",
         );
+        assert!(v.is_ok());
+    }
 
-        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    #[test]
+    fn test_float_char_err() {
+        let v = parse_test::<Block, Ty>("{ 1 + 1.5 }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, MISMATCHED_TYPES);
+        assert_eq!(err.message, "cannot add `f64` to `{integer}`");
+        let v = parse_test::<Block, Ty>("{ 1.5 * 2u8 }");
+        assert_eq!(v.unwrap_err().message, "cannot multiply `f64` by `u8`");
+        let v = parse_test::<Block, Ty>("{ 1.5 < 2 }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ 'a' + 'b' }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ 'a' < 1 }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ 1i32 as char }");
+        assert_eq!(v.unwrap_err().message, "cannot cast `i32` as `char`");
+        let v = parse_test::<Block, Ty>("{ 'a' as f64 }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ for x in 0.0..1.0 {} }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
     }
 
     #[test]
-    fn test_prog_fn_sig() {
+    fn test_string() {
         let v = parse_test::<Prog, Ty>(
             "
-        fn a(i: i32, bo: bool) -> i32 {
-            let q = 0;
-            fn b(j: i32) -> i32 {
-                a(j, c())
-            }
-
-            fn c() -> bool {
-                false
+        fn count(s: &str, x: char) -> i32 {
+            let mut n = 0;
+            for c in s.chars() {
+                if c == x { n = n + 1; }
             }
-
-            b(1 + i);
-            a(i, bo)
+            n
+        }
+        fn main() {
+            let mut s = String::new();
+            s.push_str(\"ab\");
+            let t: String = s + \"c\";
+            let r: &str = &t;
+            let n: i32 = count(&t, 'a') + count(\"abc\", 'b') + (t.len() + r.len()) as i32;
+            let b: bool = t == \"abc\" && \"abc\" == t && r < \"b\" && t.to_string() != String::from(r);
+            let u: String = 1.5.to_string() + &n.to_string();
+            let m = match r { \"abc\" => 1, _ => 2 };
         }
         ",
         );
-        println!("v {:?}", v);
-        assert_eq!(v.is_err(), true);
+        assert!(v.is_ok());
     }
 
     #[test]
-    fn test_prog_fn_defined_twice() {
+    fn test_string_err() {
+        let v = parse_test::<Block, Ty>("{ \"a\" + \"b\" }");
+        assert_eq!(v.unwrap_err().message, "cannot add `&str` to `&str`");
+        let v = parse_test::<Block, Ty>("{ let s = String::new(); s + String::new() }");
+        assert_eq!(v.unwrap_err().message, "expected type `&str`, got type `String`");
+        let v = parse_test::<Block, Ty>("{ let s: String = \"a\"; }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let s = \"a\"; s.push_str(\"b\"); }");
+        assert_eq!(v.unwrap_err().code, UNRESOLVED_NAME);
+        let v = parse_test::<Block, Ty>("{ let s = \"a\"; s < String::new() }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let c = \"a\".chars(); }");
+        assert_eq!(v.unwrap_err().message, "`chars()` is only supported as the iterator of a `for` loop");
+        let v = parse_test::<Block, Ty>("{ for c in [1].chars() {} }");
+        assert_eq!(v.unwrap_err().message, "no method named `chars` found for type `[{integer}; 1]`");
+        let v = parse_test::<Prog, Ty>("fn f(s: str) {} fn main() {}");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ String::from(1) }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+    }
+
+    #[test]
+    fn test_closure() {
         let v = parse_test::<Prog, Ty>(
             "
-        fn a() {
-        }
-
-        fn b() {
-            fn b() {
-
-            }
-
+        fn apply(f: impl Fn(i32) -> i32, x: i32) -> i32 { f(x) }
+        fn twice(f: fn(i32) -> i32, x: i32) -> i32 { f(f(x)) }
+        fn main() {
+            let y = 10;
+            let add_y = |x: i32| x + y;
+            let double = |x: i32| x * 2;
+            let s = String::from(\"ab\");
+            let len = move || s.len() as i32;
+            let first = |(a, b): (i32, bool)| -> i32 { if b { return a; } 0 };
+            let sign = |x: i32| { if x < 0 { return false; } true };
+            let n: i32 = apply(add_y, 1) + apply(double, 2) + twice(double, 3) + len() + first((1, true));
+            let b: bool = sign(n);
         }
         ",
         );
-        println!("v {:?}", v);
-        assert_eq!(v.is_err(), true);
+        assert!(v.is_ok());
+        // a closure capturing nothing is a `fn`, otherwise an `impl Fn`
+        let v = parse_test::<Block, Ty>("{ |x: i32| x == 1 }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Fn(vec![Type::I32], Box::new(Type::Bool))));
+        let v = parse_test::<Block, Ty>("{ let y = 1; |x: i32| x + y }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::ImplFn(vec![Type::I32], Box::new(Type::I32))));
     }
 
     #[test]
-    fn test_prog() {
+    fn test_closure_err() {
+        let v = parse_test::<Block, Ty>("{ let mut y = 1; let f = |x: i32| { y = x; }; }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, IMMUTABLE_ASSIGN);
+        assert_eq!(err.message, "cannot assign to `y`, as it is a captured variable in a `Fn` closure");
+        let v = parse_test::<Block, Ty>("{ let mut v = vec![1]; let f = || v.push(2); }");
+        assert_eq!(v.unwrap_err().code, IMMUTABLE_ASSIGN);
+        let v = parse_test::<Prog, Ty>("fn f(g: fn(i32) -> i32) -> i32 { g(1) } fn main() { let y = 1; f(|x: i32| x + y); }");
+        assert_eq!(v.unwrap_err().message, "expected type `fn(i32) -> i32` for parameter `g` of `f`, got type `impl Fn(i32) -> i32`");
+        let v = parse_test::<Block, Ty>("{ let f = |x: i32| x; f(true) }");
+        assert_eq!(v.unwrap_err().message, "expected type `i32`, got type `bool`");
+        let v = parse_test::<Block, Ty>("{ let f = |x: i32| x; f(1, 2) }");
+        assert_eq!(v.unwrap_err().message, "closure `f` takes 1 argument but 2 arguments were supplied");
+        let v = parse_test::<Block, Ty>("{ let f = 1; f(1) }");
+        assert_eq!(v.unwrap_err().message, "expected function, found `{integer}`");
+        let v = parse_test::<Block, Ty>("{ let f = |x: i32| -> bool { x }; }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ let f: impl Fn() = || 1; }");
+        assert_eq!(v.unwrap_err().message, "`impl Fn` is only supported as the type of a function parameter");
+        let v = parse_test::<Block, Ty>("{ loop { let f = || { break; }; } }");
+        assert_eq!(v.unwrap_err().code, OUTSIDE_OF_LOOP);
+    }
+
+    #[test]
+    fn test_fn_pointer() {
         let v = parse_test::<Prog, Ty>(
             "
+        fn add(a: i32, b: i32) -> i32 { a + b }
+        fn apply(f: fn(i32, i32) -> i32, a: i32) -> i32 { f(a, a) }
+        fn apply_impl(f: impl Fn(i32, i32) -> i32) -> i32 { f(1, 2) }
         fn main() {
-            let a = 1;
-            a;
+            fn neg(x: i32) -> i32 { -x }
+            let f = add;
+            let mut g: fn(i32) -> i32 = neg;
+            g = |x: i32| x + 1;
+            let n: i32 = f(1, 2) + apply(add, 3) + apply_impl(f) + g(4);
         }
         ",
         );
-        println!("v {:?}", v);
-        assert_eq!(v.unwrap_err(), "Ok");
+        assert!(v.is_ok());
+        let v = parse_test::<Block, Ty>("{ fn f(x: i32) {} f }");
+        assert_eq!(v.unwrap(), Ty::Lit(Type::Fn(vec![Type::I32], Box::new(Type::Unit))));
     }
 
     #[test]
-    fn test_local_fn() {
+    fn test_fn_pointer_err() {
+        let v = parse_test::<Prog, Ty>("fn add(a: i32, b: i32) -> i32 { a + b } fn main() { let f: fn(i32) -> i32 = add; }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Prog, Ty>("fn add(a: i32, b: i32) -> i32 { a + b } fn main() { let f = add; f(1); }");
+        assert_eq!(v.unwrap_err().code, WRONG_ARG_COUNT);
+        let v = parse_test::<Prog, Ty>("fn add(a: i32, b: i32) -> i32 { a + b } fn main() { let b: bool = add == add; }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Prog, Ty>("fn main() { let f = sub; }");
+        assert_eq!(v.unwrap_err().message, "variable `sub` not found");
+    }
+
+    #[test]
+    fn test_generic() {
         let v = parse_test::<Prog, Ty>(
             "
+        struct P { x: i32 }
+        fn max<T>(a: T, b: T) -> T { if a > b { a } else { b } }
+        fn identity<T>(x: T) -> T { x }
+        fn swap<A, B>(p: (A, B)) -> (B, A) { (p.1, p.0) }
+        fn first<T>(v: &Vec<T>) -> T { v[0] }
+        fn count<T>(n: i32, x: T) -> i32 { if n == 0 { 0 } else { 1 + count(n - 1, x) } }
+        fn apply<T>(f: impl Fn(T) -> T, x: T) -> T { identity(f(x)) }
         fn main() {
-            fn f(i: i32, j: i32) -> i32 {
-                i + j
-            }
-            let a = f(1, 2);
-            // println!(\"a = {} and another a = {}\", a, a);
+            let v = vec![1, 2];
+            let a: i32 = max(1, 2) + first(&v) + count(3, P { x: 1 }) + apply(|x: i32| x + 1, 1);
+            let b: f64 = max::<f64>(1.0, 2.0);
+            let p: P = identity(P { x: 1 });
+            let s: (bool, i32) = swap((1, true));
+            let c: char = apply(|c: char| c, 'a');
         }
         ",
         );
-        println!("v {:?}", v);
-        assert_eq!(v.unwrap_err(), "Ok");
+        assert!(v.is_ok());
+        // the type arguments of integer literals are inferred from the result
+        let v = parse_test::<Prog, Ty>(
+            "
+        fn id<T>(a: T) -> T { a }
+        fn main() {
+            let b: u8 = id(5);
+            let c: u64 = id(1) + 2u64;
+            let d: i64 = { id(3000000000) };
+        }
+        ",
+        );
+        assert!(v.is_ok());
+        let v = parse_test::<Prog, Ty>("fn id<T>(a: T) -> T { a } fn main() { let b: u8 = id(256); }");
+        assert_eq!(v.unwrap_err().code, LITERAL_OUT_OF_RANGE);
     }
 
     #[test]
-    fn test_check_if_then_else_shadowing() {
-        let v = parse_test::<Block, Ty>(
+    fn test_generic_err() {
+        let max = "fn max<T>(a: T, b: T) -> T { if a > b { a } else { b } }";
+        let err = parse_test::<Prog, Ty>(&format!("struct P {{ x: i32 }} {} fn main() {{ max(P {{ x: 1 }}, P {{ x: 2 }}); }}", max)).unwrap_err();
+        assert_eq!(err.message, "binary operation `>` cannot be applied to type `P`");
+        assert_eq!(err.labels[0].1, "in `max::<P>`, instantiated here");
+        let v = parse_test::<Prog, Ty>(&format!("{} fn main() {{ max(1, true); }}", max));
+        assert_eq!(v.unwrap_err().message, "expected type `{integer}` for parameter `b` of `max`, got type `bool`");
+        let v = parse_test::<Prog, Ty>(&format!("{} fn main() {{ let b: bool = max(1, 2); }}", max));
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Prog, Ty>(&format!("{} fn main() {{ max::<i32, bool>(1, 2); }}", max));
+        assert_eq!(v.unwrap_err().message, "function `max` takes 1 generic argument but 2 generic arguments were supplied");
+        let v = parse_test::<Prog, Ty>(&format!("{} fn main() {{ let f = max; }}", max));
+        assert_eq!(v.unwrap_err().code, TYPE_ANNOTATIONS_NEEDED);
+        let v = parse_test::<Prog, Ty>("fn zero<T>() -> i32 { 0 } fn main() { zero(); }");
+        assert_eq!(v.unwrap_err().message, "type annotations needed, cannot infer the type parameter `T` of `zero`");
+        let v = parse_test::<Prog, Ty>("fn zero<T>(a: T) -> bool { a == 0 } fn main() { zero(1); }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Prog, Ty>("fn id(x: i32) -> i32 { x } fn main() { id::<i32>(1); }");
+        assert_eq!(v.unwrap_err().code, WRONG_ARG_COUNT);
+        let v = parse_test::<Prog, Ty>("fn main<T>() {}");
+        assert!(v.is_err());
+        // the body is checked where it is declared, for any `T`
+        let err = parse_test::<Prog, Ty>("fn bad<T>(a: T) -> i32 { a } fn main() {}").unwrap_err();
+        assert_eq!(err.code, MISMATCHED_TYPES);
+        assert_eq!(err.message, "expected type `i32`, got type `T`");
+        let v = parse_test::<Prog, Ty>("fn neg<T>(a: T) -> T { -a } fn main() {}");
+        assert_eq!(v.unwrap_err().message, "cannot apply unary operator `-` to type `T`");
+        let v = parse_test::<Prog, Ty>("fn add<T>(a: T, b: T) -> T { a + b } fn main() { add(1, 2); }");
+        assert_eq!(v.unwrap_err().message, "cannot add `T` to `T`");
+        let v = parse_test::<Prog, Ty>("fn pick<A, B>(a: A, b: B) -> A { b } fn main() {}");
+        assert_eq!(v.unwrap_err().message, "expected type `A`, got type `B`");
+        let v = parse_test::<Prog, Ty>("fn f<T>(a: T) -> i32 { a.len() } fn main() {}");
+        assert!(v.is_err());
+        let v = parse_test::<Prog, Ty>("fn f<T>(a: T) -> u8 { 300 } fn main() {}");
+        assert_eq!(v.unwrap_err().code, LITERAL_OUT_OF_RANGE);
+        // comparisons are checked by the instances, there are no bounds
+        let v = parse_test::<Prog, Ty>("fn gt<T>(a: T, b: T) -> bool { a > b } fn main() {}");
+        assert!(v.is_ok());
+        // instances without end
+        let v = parse_test::<Prog, Ty>("fn rec<T>(a: T, n: i32) -> i32 { if n == 0 { 0 } else { rec(&a, n - 1) } } fn main() { rec(1, 3); }");
+        assert_eq!(v.unwrap_err().message, "reached the recursion limit while instantiating `rec`");
+        let v = parse_test::<Prog, Ty>("fn rec<T>(a: T, n: i32) -> i32 { if n == 0 { 0 } else { rec((a, a), n - 1) } } fn main() { rec(1, 3); }");
+        assert_eq!(v.unwrap_err().message, "reached the type-length limit while instantiating `rec`");
+    }
+
+    #[test]
+    fn test_monomorphize() {
+        let prog: Prog = syn::parse_str(
             "
-        {
-            let a: i32 = 1 + 2; // a == 3
-            let a: i32 = 2 + a; // a == 5
-            if true {
-                a = a - 1;      // outer a == 4
-                let a: i32 = 0; // inner a == 0
-                a = a + 1       // inner a == 1
-            } else {
-                a = a - 1
-            };
-            a   // a == 4
-        }
+        fn max<T>(a: T, b: T) -> T { if a > b { a } else { b } }
+        fn twice<T>(a: T) -> T { max(a, a) }
+        fn main() { max(1, 2); twice('a'); max(3, 4); }
         ",
-        );
+        )
+        .unwrap();
+        let prog = monomorphize(&prog).unwrap();
+        let ids: Vec<&str> = prog.0.iter().map(|f| f.id.as_str()).collect();
+        assert_eq!(ids, vec!["main", "max::<i32>", "max::<char>", "twice::<char>"]);
+        assert!(prog.0[0].body.to_string().contains("max::<i32>(1, 2, )"));
+        assert!(prog.0[3].body.to_string().contains("max::<char>(a, a, )"));
+        assert_eq!(prog.0[3].parameters.0[0].ty, Type::Char);
+    }
 
-        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+    #[test]
+    fn test_match_err() {
+        let v = parse_test::<Block, Ty>("{ enum E { A, B(bool), C { x: i32 } } match E::A { E::B(true) => 1, E::C { x: 1 } => 2 } }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, NON_EXHAUSTIVE);
+        assert_eq!(err.message, "non-exhaustive patterns: `E::A`, `E::B(false)` and `E::C { x: _ }` not covered");
+        let v = parse_test::<Block, Ty>("{ match 1 { 1 => 1, 2 => 2 } }");
+        assert_eq!(v.unwrap_err().message, "non-exhaustive patterns: `_` not covered");
+        let v = parse_test::<Block, Ty>("{ enum E { A, B } match E::A { E::A => 1, x => 2, E::B => 3 } }");
+        assert_eq!(v.unwrap_err().code, UNREACHABLE_PATTERN);
+        let v = parse_test::<Block, Ty>("{ enum E { A, B } match E::A { E::A => 1, E::B => true } }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        let v = parse_test::<Block, Ty>("{ enum E { A, B } match E::A { E::A => 1, E::C => 2 } }");
+        assert_eq!(v.unwrap_err().code, UNKNOWN_VARIANT);
+        let v = parse_test::<Block, Ty>("{ enum E { A(i32, i32) } match E::A(1, 2) { E::A(x, x) => x } }");
+        assert_eq!(v.unwrap_err().code, DUPLICATE_BINDING);
+        let v = parse_test::<Block, Ty>("{ enum E { A(i32, i32) } match E::A(1, 2) { E::A(x) => x } }");
+        assert_eq!(v.unwrap_err().code, WRONG_ARG_COUNT);
+        let v = parse_test::<Block, Ty>("{ enum E { A(i32, i32) } E::A(1) }");
+        assert_eq!(v.unwrap_err().message, "variant `E::A` takes 2 arguments but 1 argument was supplied");
+        let v = parse_test::<Block, Ty>("{ enum E { A { x: i32 } } E::A(1) }");
+        assert_eq!(v.unwrap_err().message, "expected tuple variant, found struct variant `E::A`");
+        // the bindings of an arm are scoped to the arm
+        let v = parse_test::<Block, Ty>("{ match 1 { x => x }; x }");
+        assert_eq!(v.unwrap_err().code, UNRESOLVED_NAME);
+        let v = parse_test::<Prog, Ty>("struct S { a: i32 } enum S { A } fn main() {}");
+        assert_eq!(v.unwrap_err().code, DUPLICATE_TYPE);
     }
 
     #[test]
-    fn test_ref() {
-        let v = parse_test::<Block, Ty>(
+    fn test_moves() {
+        let v = parse_test::<Prog, Ty>(
             "
-        {
-            let a = &1;
-            *a
+        struct P { name: String, age: i32 }
+        fn take(s: String) -> usize { s.len() }
+        fn main() {
+            let mut s = String::from(\"ab\");
+            let n = take(s);
+            s = String::from(\"x\");
+            let t = s + \"y\";
+            let mut u = String::new();
+            let mut k = 0;
+            while k < 3 {
+                let w = u;
+                u = w + \"z\";
+                k = k + 1;
+            }
+            let p = P { name: String::from(\"a\"), age: 3 };
+            let name = p.name;
+            let age = p.age;
+            let v = vec![1];
+            let c = if n > 1 { v } else { vec![2] };
+            println!(\"{} {} {}\", t, u, c.len());
         }
         ",
         );
-        assert_eq!(v.unwrap(), Ty::Lit(Type::I32));
+        assert!(v.is_ok());
+    }
+
+    #[test]
+    fn test_moves_err() {
+        let v = parse_test::<Block, Ty>("{ let s = String::from(\"ab\"); let mut t = s; t.push_str(\"cd\"); s.len() }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, USE_OF_MOVED_VALUE);
+        assert_eq!(err.message, "use of moved value: `s`");
+        let v = parse_test::<Block, Ty>("{ let mut v = vec![1]; let mut w = v; w.push(2); v.len() }");
+        assert_eq!(v.unwrap_err().message, "use of moved value: `v`");
+        let v = parse_test::<Block, Ty>("{ let a = [vec![1], vec![2]]; let b = a; a[0].len() }");
+        assert_eq!(v.unwrap_err().message, "use of moved value: `a`");
+        let v = parse_test::<Block, Ty>("{ let t = (String::new(), 1); let u = t.0; let w = t; }");
+        assert_eq!(v.unwrap_err().message, "use of partially moved value: `t`");
+        let v = parse_test::<Block, Ty>("{ let s = String::new(); if true { let t = s; } s }");
+        assert_eq!(v.unwrap_err().code, USE_OF_MOVED_VALUE);
+        let v = parse_test::<Block, Ty>("{ let s = String::new(); loop { let t = s; } }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, USE_OF_MOVED_VALUE);
+        assert_eq!(err.labels[0].1, "value moved here, in previous iteration of loop");
+        let v = parse_test::<Block, Ty>("{ let s = String::new(); loop { let t = s; break; } }");
+        assert!(v.is_ok());
+        let v = parse_test::<Block, Ty>("{ let s = String::new(); let f = move || s.len(); s }");
+        assert_eq!(v.unwrap_err().code, USE_OF_MOVED_VALUE);
+        let v = parse_test::<Block, Ty>("{ let s = String::new(); let f = || s; }");
+        assert_eq!(v.unwrap_err().code, MOVE_OUT_OF_BORROW);
+        let v = parse_test::<Block, Ty>("{ let v = vec![String::new()]; let s = v[0]; }");
+        assert_eq!(v.unwrap_err().message, "cannot move out of index of `v`");
+        let v = parse_test::<Block, Ty>("{ let s = String::new(); let r = &s; let t = *r; }");
+        assert_eq!(v.unwrap_err().code, MOVE_OUT_OF_BORROW);
+        let v = parse_test::<Block, Ty>("{ let s = String::new(); let a = [s; 2]; }");
+        assert_eq!(v.unwrap_err().message, "the trait bound `String: Copy` is not satisfied");
     }
 }
@@ -1,16 +1,79 @@
 use crate::ast::*;
 use crate::common::*;
-use crate::env::{Env, Ref};
+use crate::env::{arity_message, EnumEnv, Env, Fields, Flow, Ref, StructEnv, VarEnv};
 use crate::intrinsics::vm_println;
-use crate::error::Error;
+use crate::type_check::monomorphize;
+use crate::error::*;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+// Bound on nested calls, to report runaway recursion as an error before
+// the native stack (see `STACK_SIZE` in main.rs) overflows, also in an
+// unoptimized build, the same for the stack machine
+pub const MAX_CALL_DEPTH: usize = 1_000;
+
+// `break` and `continue` are pending in `env.flow` while the evaluation
+// returns to the targeted loop. Evaluates `e`, and leaves the enclosing
+// function early (with a dummy value) if control flow is pending.
+macro_rules! eval_or_leave {
+    ($e:expr, $env:expr) => {{
+        let v = $e.eval($env)?;
+        if $env.flow.is_some() {
+            return Ok((Val::UnInit, None));
+        }
+        v
+    }};
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Val 
 {
     Lit(Literal),
     Ref(Ref),
+    // a struct value, with the fields in declaration order
+    Struct(String, Vec<(String, Val)>),
+    // an enum value, by enum and variant, with the fields in declaration order
+    Enum(String, String, Vec<(String, Val)>),
+    Tuple(Vec<Val>),
+    Array(Vec<Val>),
+    // a vector, by the address of its heap block
+    Vec(usize),
+    // a `String`, by the address of a heap block of chars (a `&str` is a
+    // string literal, or a reference to a `String`)
+    String(usize),
+    // a function item used as a value, by name
+    Fn(String),
+    // a closure, with its captured variables by name, holding references to
+    // them (or for `move` closures, their values)
+    Closure(Rc<Closure>, Vec<(String, Val)>),
+    // a compiled closure (bytecode.rs), by function index, with the values
+    // of its captured variables
+    Code(usize, Vec<Val>),
     UnInit,
-    Mut(Box<Val>),
+}
+
+impl Fields for Val 
+{
+    fn field(&self, i: usize) -> Option<&Val> 
+    {
+        match self 
+        {
+            Val::Struct(_, fields) | Val::Enum(_, _, fields) => fields.get(i).map(|(_, v)| v),
+            Val::Tuple(vs) | Val::Array(vs) => vs.get(i),
+            _ => None,
+        }
+    }
+
+    fn field_mut(&mut self, i: usize) -> Option<&mut Val> 
+    {
+        match self 
+        {
+            Val::Struct(_, fields) | Val::Enum(_, _, fields) => fields.get_mut(i).map(|(_, v)| v),
+            Val::Tuple(vs) | Val::Array(vs) => vs.get_mut(i),
+            _ => None,
+        }
+    }
 }
 
 // Helpers for Val
@@ -22,7 +85,7 @@ impl Val
         match self 
         {
             Val::Lit(Literal::Bool(b)) => Ok(*b),
-            _ => Err(format!("cannot get Bool from {:?}", self)),
+            _ => Err(Error::runtime(VALUE_MISMATCH, format!("cannot get Bool from {:?}", self))),
         }
     }
 
@@ -31,8 +94,19 @@ impl Val
         match self 
         {
             Val::Lit(Literal::Int(i)) => Ok(*i),
-            _ => Err(format!("cannot get integer from {:?}", self)),
+            _ => Err(Error::runtime(VALUE_MISMATCH, format!("cannot get integer from {:?}", self))),
+        }
+    }
+
+    // an integer of any type, with its type
+    pub fn get_integer(&self) -> Result<(i128, IntTy), Error> 
+    {
+        match self 
+        {
+            Val::Lit(l) => l.int_value(),
+            _ => None,
         }
+        .ok_or_else(|| Error::runtime(VALUE_MISMATCH, format!("cannot get integer from {:?}", self)))
     }
 
     pub fn get_string(&self) -> Result<Literal, Error> 
@@ -40,180 +114,1102 @@ impl Val
         match self 
         {
             Val::Lit(s) => Ok(s.clone()),
-            _ => Err(format!("cannot get string from {:?}", self)),
+            _ => Err(Error::runtime(VALUE_MISMATCH, format!("cannot get string from {:?}", self))),
         }
     }
 
 }
 
+// the value fits a parameter of type `ty`, references are followed, and
+// `fits_fn` decides for the function values of each backend
+pub fn has_type(v: &Val, ty: &Type, vars: &VarEnv<Val>, fits_fn: &dyn Fn(&Val, &Type) -> bool) -> bool
+{
+    match (v, ty)
+    {
+        (Val::Lit(Literal::Bool(_)), Type::Bool) => true,
+        (Val::Lit(Literal::Int(_)), Type::I32) => true,
+        (Val::Lit(Literal::Sized(_, t)), Type::Int(u)) => t == u,
+        (Val::Lit(Literal::Float(_)), Type::F64) => true,
+        (Val::Lit(Literal::Char(_)), Type::Char) => true,
+        (Val::Lit(Literal::String(_)), Type::Ref(t)) => **t == Type::Str,
+        (Val::String(_), Type::String) | (Val::String(_), Type::Str) => true,
+        (Val::Lit(Literal::Unit), Type::Unit) => true,
+        (Val::Struct(s, _), Type::Named(t)) => s == t,
+        (Val::Enum(e, _, _), Type::Named(t)) => e == t,
+        (Val::Tuple(vs), Type::Tuple(ts)) => vs.len() == ts.len() && vs.iter().zip(ts).all(|(v, t)| has_type(v, t, vars, fits_fn)),
+        (Val::Array(vs), Type::Array(t, n)) => vs.len() == *n && vs.iter().all(|v| has_type(v, t, vars, fits_fn)),
+        (Val::Vec(addr), Type::Vec(t)) => vars.heap_elems(*addr).iter().all(|v| has_type(v, t, vars, fits_fn)),
+        (Val::Ref(r), Type::Ref(t)) => vars.try_de_ref(r.clone()).is_ok_and(|v| has_type(&v, t, vars, fits_fn)),
+        (Val::Fn(_), _) | (Val::Closure(..), _) | (Val::Code(..), _) => fits_fn(v, ty),
+        _ => false,
+    }
+}
+
+// a function item or closure fits a `fn` or `impl Fn` type
+fn fits_fn(v: &Val, ty: &Type, env: &Env<Val>) -> bool
+{
+    match (v, ty)
+    {
+        (Val::Fn(id), Type::Fn(params, ret)) | (Val::Fn(id), Type::ImplFn(params, ret)) => match env.f.0.get(id)
+        {
+            Some((f, _)) => f.fn_type() == Type::Fn(params.clone(), ret.clone()),
+            None => false,
+        },
+        // only a closure capturing nothing is a `fn`
+        (Val::Closure(c, captures), Type::Fn(params, _)) => captures.is_empty() && c.parameters.0.iter().map(|p| &p.ty).eq(params.iter()),
+        (Val::Closure(c, _), Type::ImplFn(params, _)) => c.parameters.0.iter().map(|p| &p.ty).eq(params.iter()),
+        _ => false,
+    }
+}
+
+// a value for diagnostics, references are followed
+pub fn show(v: &Val, vars: &VarEnv<Val>) -> String
+{
+    match v
+    {
+        Val::Lit(l) => l.to_string(),
+        Val::Ref(r) => match vars.try_de_ref(r.clone())
+        {
+            Ok(v) => format!("&{}", show(&v, vars)),
+            Err(_) => "&<dangling>".to_string(),
+        },
+        Val::Struct(s, fields) => 
+        {
+            let fields: Vec<String> = fields.iter().map(|(id, v)| format!("{}: {}", id, show(v, vars))).collect();
+            format!("{} {{ {} }}", s, fields.join(", "))
+        },
+        // the fields of tuple variants are named by position
+        Val::Enum(e, v, fields) => match fields.first()
+        {
+            None => format!("{}::{}", e, v),
+            Some((id, _)) if id == "0" => 
+            {
+                let fields: Vec<String> = fields.iter().map(|(_, v)| show(v, vars)).collect();
+                format!("{}::{}({})", e, v, fields.join(", "))
+            },
+            Some(_) => 
+            {
+                let fields: Vec<String> = fields.iter().map(|(id, v)| format!("{}: {}", id, show(v, vars))).collect();
+                format!("{}::{} {{ {} }}", e, v, fields.join(", "))
+            },
+        },
+        Val::Tuple(vs) => match vs.as_slice()
+        {
+            [v] => format!("({},)", show(v, vars)),
+            _ => 
+            {
+                let vs: Vec<String> = vs.iter().map(|v| show(v, vars)).collect();
+                format!("({})", vs.join(", "))
+            },
+        },
+        Val::Array(vs) => 
+        {
+            let vs: Vec<String> = vs.iter().map(|v| show(v, vars)).collect();
+            format!("[{}]", vs.join(", "))
+        },
+        Val::Vec(addr) => 
+        {
+            let vs: Vec<String> = vars.heap_elems(*addr).iter().map(|v| show(v, vars)).collect();
+            format!("[{}]", vs.join(", "))
+        },
+        Val::String(_) => format!("{:?}", text(v, vars).unwrap_or_default()),
+        Val::Fn(id) => format!("fn {}", id),
+        Val::Closure(..) | Val::Code(..) => "<closure>".to_string(),
+        Val::UnInit => "<uninitialized>".to_string(),
+    }
+}
+
+// The text of a `String` or `&str`, references are followed
+pub fn text(v: &Val, vars: &VarEnv<Val>) -> Result<String, Error>
+{
+    match v
+    {
+        Val::Lit(Literal::String(s)) => Ok(s.clone()),
+        Val::String(addr) => Ok(vars.heap_elems(*addr).iter()
+            .filter_map(|c| match c
+            {
+                Val::Lit(Literal::Char(c)) => Some(*c),
+                _ => None,
+            })
+            .collect()),
+        Val::Ref(r) => text(&vars.try_de_ref(r.clone())?, vars),
+        _ => Err(Error::runtime(VALUE_MISMATCH, format!("cannot get string from {:?}", v))),
+    }
+}
+
+// A new `String` holding `s`
+fn new_string(s: &str, vars: &mut VarEnv<Val>) -> Val
+{
+    Val::String(vars.heap_alloc(s.chars().map(|c| Val::Lit(Literal::Char(c))).collect()))
+}
+
+// `s + t` and `s.push_str(t)`, the text of `t` is pushed onto the heap block
+// of the `String` at `addr`
+fn append(addr: usize, t: &Val, vars: &mut VarEnv<Val>) -> Result<(), Error>
+{
+    for c in text(t, vars)?.chars()
+    {
+        vars.heap_push(addr, Val::Lit(Literal::Char(c)));
+    }
+    Ok(())
+}
+
+// An argument of `println!`, a `String` or reference is shown by its value
+pub fn printable(v: Val, vars: &VarEnv<Val>) -> Result<Literal, Error>
+{
+    match v
+    {
+        Val::String(_) => Ok(Literal::String(text(&v, vars)?)),
+        Val::Ref(r) => printable(vars.try_de_ref(r)?, vars),
+        v => v.get_string(),
+    }
+}
+
 // Helper for Op
 impl Op 
 {
-    pub fn eval(&self, left: Val, right: Val) -> Result<Val, Error> 
+    pub fn eval(&self, left: Val, right: Val, vars: &mut VarEnv<Val>) -> Result<Val, Error> 
     {
-        use Literal::{Bool, Int};
+        use Literal::Bool;
         match self 
         {
-            Op::Add => Ok(Val::Lit(Int(left.get_int()? + right.get_int()?))),
-            Op::Sub => Ok(Val::Lit(Int(left.get_int()? - right.get_int()?))),
-            Op::Mul => Ok(Val::Lit(Int(left.get_int()? * right.get_int()?))),
-            Op::Div => Ok(Val::Lit(Int(left.get_int()? / right.get_int()?))),
+            Op::Add => match left
+            {
+                Val::String(addr) => 
+                {
+                    append(addr, &right, vars)?;
+                    Ok(left)
+                },
+                _ => arith(*self, &left, &right),
+            },
+            Op::Sub | Op::Mul | Op::Div | Op::Rem => arith(*self, &left, &right),
             Op::And => Ok(Val::Lit(Bool(left.get_bool()? && right.get_bool()?))),
             Op::Or => Ok(Val::Lit(Bool(left.get_bool()? || right.get_bool()?))),
-            Op::Eq => Ok(Val::Lit(Bool(left == right))), // overloading
-            Op::Lt => Ok(Val::Lit(Bool(left.get_int()? < right.get_int()?))),
-            Op::Gt => Ok(Val::Lit(Bool(left.get_int()? > right.get_int()?))),
+            Op::Eq => Ok(Val::Lit(Bool(equal(&left, &right, vars)))), // overloading
+            Op::Ne => Ok(Val::Lit(Bool(!equal(&left, &right, vars)))),
+            Op::Lt => Ok(Val::Lit(Bool(ordering(&left, &right, vars)? == Some(Ordering::Less)))),
+            Op::Le => Ok(Val::Lit(Bool(matches!(ordering(&left, &right, vars)?, Some(Ordering::Less | Ordering::Equal))))),
+            Op::Gt => Ok(Val::Lit(Bool(ordering(&left, &right, vars)? == Some(Ordering::Greater)))),
+            Op::Ge => Ok(Val::Lit(Bool(matches!(ordering(&left, &right, vars)?, Some(Ordering::Greater | Ordering::Equal))))),
         }
     }
 }
 
+// Strings are equal by their text, wherever it is stored
+fn equal(left: &Val, right: &Val, vars: &VarEnv<Val>) -> bool
+{
+    match (text(left, vars), text(right, vars))
+    {
+        (Ok(l), Ok(r)) => l == r,
+        _ => left == right,
+    }
+}
+
+// The order of two numbers, chars, bools or strings, `None` if a float is `NaN`
+fn ordering(left: &Val, right: &Val, vars: &VarEnv<Val>) -> Result<Option<Ordering>, Error>
+{
+    if let (Ok(l), Ok(r)) = (text(left, vars), text(right, vars))
+    {
+        return Ok(Some(l.cmp(&r)));
+    }
+    match (left, right)
+    {
+        (Val::Lit(Literal::Float(l)), Val::Lit(Literal::Float(r))) => Ok(l.partial_cmp(r)),
+        (Val::Lit(Literal::Char(l)), Val::Lit(Literal::Char(r))) => Ok(Some(l.cmp(r))),
+        (Val::Lit(Literal::Bool(l)), Val::Lit(Literal::Bool(r))) => Ok(Some(l.cmp(r))),
+        _ => Ok(Some(left.get_integer()?.0.cmp(&right.get_integer()?.0))),
+    }
+}
+
+// Arithmetic in the type of the operands, a result outside of the type is
+// an error (as in debug builds of Rust)
+fn arith(op: Op, left: &Val, right: &Val) -> Result<Val, Error>
+{
+    if let (Val::Lit(Literal::Float(l)), Val::Lit(Literal::Float(r))) = (left, right)
+    {
+        // IEEE 754, dividing by zero gives an infinity (or `NaN`)
+        return Ok(Val::Lit(Literal::Float(match op
+        {
+            Op::Add => l + r,
+            Op::Sub => l - r,
+            Op::Mul => l * r,
+            Op::Div => l / r,
+            _ => l % r,
+        })));
+    }
+    let ((l, t), (r, r_t)) = (left.get_integer()?, right.get_integer()?);
+    if t != r_t
+    {
+        return Err(Error::runtime(VALUE_MISMATCH, format!("cannot apply `{}` to `{}` and `{}`", op, t, r_t)));
+    }
+    let (i, what) = match op
+    {
+        Op::Add => (Some(l + r), "add"),
+        Op::Sub => (Some(l - r), "subtract"),
+        // u64 products may not fit in an i128
+        Op::Mul => (l.checked_mul(r), "multiply"),
+        Op::Div | Op::Rem if r == 0 => return Err(Error::runtime(DIVIDE_BY_ZERO, match op
+        {
+            Op::Div => "attempt to divide by zero",
+            _ => "attempt to calculate the remainder with a divisor of zero",
+        })),
+        Op::Div => (Some(l / r), "divide"),
+        // `MIN % -1` overflows, as `MIN / -1` does
+        Op::Rem if l == t.min() && r == -1 => (None, "calculate the remainder"),
+        Op::Rem => (Some(l % r), "calculate the remainder"),
+        _ => unreachable!("ICE - not an arithmetic operator"),
+    };
+    match i.filter(|i| t.fits(*i))
+    {
+        Some(i) => Ok(Val::Lit(Literal::int(i, t))),
+        None => Err(Error::runtime(ARITHMETIC_OVERFLOW, format!("attempt to {} with overflow", what))),
+    }
+}
+
+// `-v`, in the type of `v`
+pub fn neg(v: &Val) -> Result<Val, Error>
+{
+    if let Val::Lit(Literal::Float(x)) = v
+    {
+        return Ok(Val::Lit(Literal::Float(-x)));
+    }
+    let (i, t) = v.get_integer()?;
+    match t.fits(-i)
+    {
+        true => Ok(Val::Lit(Literal::int(-i, t))),
+        false => Err(Error::runtime(ARITHMETIC_OVERFLOW, "attempt to negate with overflow")),
+    }
+}
+
+// `v as t`, an integer is truncated (or extended) to the width of `t`, a
+// float is rounded towards zero and saturated to the range of `t`
+pub fn cast(v: &Val, t: &Type) -> Result<Val, Error>
+{
+    let mismatch = || Error::runtime(VALUE_MISMATCH, format!("cannot cast `{:?}` as `{}`", v, t));
+    let l = match (v, t)
+    {
+        (Val::Lit(Literal::Float(x)), Type::F64) => Literal::Float(*x),
+        (Val::Lit(Literal::Float(x)), t) => 
+        {
+            let t = t.int_ty().ok_or_else(mismatch)?;
+            // `NaN` is 0
+            Literal::int((*x as i128).clamp(t.min(), t.max()), t)
+        },
+        (Val::Lit(Literal::Char(c)), Type::Char) => Literal::Char(*c),
+        (v, Type::F64) => Literal::Float(v.get_integer()?.0 as f64),
+        (v, Type::Char) => Literal::Char(char::from(u8::try_from(v.get_integer()?.0).map_err(|_| mismatch())?)),
+        (v, t) => 
+        {
+            let i = match v
+            {
+                Val::Lit(Literal::Bool(b)) => *b as i128,
+                Val::Lit(Literal::Char(c)) => *c as i128,
+                v => v.get_integer()?.0,
+            };
+            let t = t.int_ty().ok_or_else(mismatch)?;
+            Literal::int(t.wrap(i), t)
+        },
+    };
+    Ok(Val::Lit(l))
+}
+
 impl Eval<Val> for Expr 
 {
     fn eval(&self, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error> 
     {
-        match self 
+        // errors point at the innermost offending expression
+        eval_expr(self, env).map_err(|err| err.or_span(self.span))
+    }
+}
+
+fn eval_expr(expr: &Expr, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error> 
+{
+    match &expr.kind 
+    {
+        ExprKind::BinOp(op, left, right) => 
         {
-            Expr::BinOp(op, left, right) => 
-            {
-                Ok((op.eval(left.eval(env)?.0, right.eval(env)?.0)?, None))
-            },
-            Expr::Block(bl) => 
+            let l = eval_or_leave!(left, env).0;
+            let r = eval_or_leave!(right, env).0;
+            Ok((op.eval(l, r, &mut env.v)?, None))
+        },
+        ExprKind::Block(bl) => 
+        {
+            bl.eval(env)
+        },
+        // a variable shadows the functions of the same name
+        ExprKind::Call(id, _, args) if env.v.get(id).is_some() => match env.v.get(id).unwrap()
+        {
+            Val::Fn(f) => call_fn(&f, args, env),
+            Val::Closure(c, captures) => call_closure(id, &c, captures, args, env),
+            v => Err(Error::runtime(VALUE_MISMATCH, format!("expected function, found `{}`", show(&v, &env.v)))),
+        },
+        ExprKind::Call(id, _, params) => call_fn(id, params, env),
+        ExprKind::Ident(id) => match env.v.get(&id)
+        {
+            Some(t) => Ok((t, env.v.get_ref(id))),
+            None if env.f.0.contains_key(id) => Ok((Val::Fn(id.clone()), None)),
+            None => Err(Error::runtime(UNBOUND_VARIABLE, format!("Variable `{}` not found", id))),
+        },
+        ExprKind::IfThenElse(c, t, e) => 
+        match eval_or_leave!(c, env).0.get_bool()? 
+        {
+            true => (*t).eval(env),
+            false => match e 
             {
-                bl.eval(env)
+                Some(e) => e.eval(env),
+                None => Ok((Val::Lit(Literal::Unit), None)),
             },
-            Expr::Call(id, params) => 
+        },
+        ExprKind::Lit(literal) => 
+        {
+            Ok((Val::Lit(literal.or_i32()?), None))
+        },
+        ExprKind::Par(e) => 
+        {
+            e.eval(env)
+        }
+        ExprKind::UnOp(u, e) => 
+        {
+            u.eval(*e.clone(), env)
+        },
+        ExprKind::Loop(label, body) => loop
+        {
+            body.eval(env)?;
+            match env.flow.take()
             {
-                //Check if the function exists.
-                if !env.f.0.contains_key(id)
+                Some(Flow::Break(l, v)) if targets(label, &l) => return Ok((v, None)),
+                Some(Flow::Continue(l)) if targets(label, &l) => (),
+                None => (),
+                // an outer loop is targeted
+                flow => 
                 {
-                    return Err("Missing function".to_string())
-                }
-
-                let env_temp = env.clone();
-                let _fn = env_temp.f.0.get(id).unwrap();
-                if _fn.0.id == "println!"
-                {
-                    let mut args : Vec<Literal> = Vec::new();
-                    for arg in params.0.iter()
-                    {
-                        args.push(arg.eval(env)?.0.get_string()?);
-                    }
-                Ok((Val::Lit(_fn.1.unwrap()(args)), None))
-                }
-                else 
+                    env.flow = flow;
+                    return Ok((Val::UnInit, None))
+                },
+            }
+        },
+        ExprKind::Break(label, e) => 
+        {
+            let v = match e
+            {
+                Some(e) => eval_or_leave!(e, env).0,
+                None => Val::Lit(Literal::Unit),
+            };
+            env.flow = Some(Flow::Break(label.clone(), v));
+            Ok((Val::UnInit, None))
+        },
+        ExprKind::Continue(label) => 
+        {
+            env.flow = Some(Flow::Continue(label.clone()));
+            Ok((Val::UnInit, None))
+        },
+        ExprKind::Return(e) => 
+        {
+            let v = match e
+            {
+                Some(e) => eval_or_leave!(e, env).0,
+                None => Val::Lit(Literal::Unit),
+            };
+            env.flow = Some(Flow::Return(v));
+            Ok((Val::UnInit, None))
+        },
+        ExprKind::Struct(id, fields) => struct_lit(id, fields, env),
+        ExprKind::Field(e, f) => field(e, f, env),
+        ExprKind::Variant(e, v, _, fields) => variant_lit(e, v, fields, env),
+        ExprKind::Match(e, arms) => match_arms(e, arms, env),
+        ExprKind::Tuple(es) => elements(es, Val::Tuple, env),
+        ExprKind::Array(es) => elements(es, Val::Array, env),
+        ExprKind::Repeat(e, n) => 
+        {
+            let v = eval_or_leave!(e, env).0;
+            Ok((Val::Array(vec![v; *n]), None))
+        },
+        ExprKind::Index(e, i) => index(e, i, env),
+        ExprKind::Method(e, m, args) => method(e, m, args, env),
+        // the captured variables still bound outside of the body, referenced
+        // (or for `move` closures, copied) when the closure is created
+        ExprKind::Closure(c) => 
+        {
+            let mut captures = vec![];
+            for (id, _) in c.free_vars()
+            {
+                if let Some(r) = env.v.get_ref(&id)
                 {
-                    env.v.push_scope();
-                    let mut i = 0;
-                    for arg in params.0.clone()
+                    let v = match c.moves
                     {
-                        let arg_id = _fn.0.parameters.0[i].id.clone();
-                        let arg_val = arg.eval(env)?.0;
-                        env.v.alloc(&arg_id, arg_val.clone());
-                        i = i+1;
-                    }
-                    let b : Block = _fn.0.body.clone();
-                    let retval = b.eval(env);
-                    env.v.pop_scope();
-                    retval
+                        true => env.v.de_ref(r),
+                        false => Val::Ref(r),
+                    };
+                    captures.push((id, v));
                 }
-            },
-            Expr::Ident(id) => match env.v.get(&id)
+            }
+            Ok((Val::Closure(Rc::new(c.clone()), captures), None))
+        },
+        ExprKind::Cast(e, t) => 
+        {
+            let v = eval_or_leave!(e, env).0;
+            Ok((cast(&v, t)?, None))
+        },
+        ExprKind::Assoc(t, f, args) => match (t.as_str(), f.as_str(), args.0.as_slice())
+        {
+            ("Vec", "new", []) => Ok((Val::Vec(env.v.heap_alloc(vec![])), None)),
+            ("String", "new", []) => Ok((new_string("", &mut env.v), None)),
+            ("String", "from", [s]) => 
             {
-                Some(t) => Ok((t, env.v.get_ref(id))),
-                None => Err("Variable not found".to_string()),
+                let v = eval_or_leave!(s, env).0;
+                Ok((call_method(v, "to_string", vec![], &mut env.v)?, None))
             },
-            Expr::IfThenElse(c, t, e) => 
-            match c.eval(env)?.0.get_bool()? 
+            _ => Err(Error::runtime(UNBOUND_FUNCTION, format!("cannot find function `{}::{}`", t, f))),
+        },
+        ExprKind::Vec(es) => 
+        {
+            let mut vs = vec![];
+            for e in es
             {
-                true => (*t).eval(env),
-                false => match e 
-                {
-                    Some(e) => e.eval(env),
-                    None => Ok((Val::Lit(Literal::Unit), None)),
-                },
-            },
-            Expr::Lit(literal) => 
+                vs.push(eval_or_leave!(e, env).0);
+            }
+            Ok((Val::Vec(env.v.heap_alloc(vs)), None))
+        },
+        ExprKind::VecRepeat(e, n) => 
+        {
+            let v = eval_or_leave!(e, env).0;
+            let (n, _) = eval_or_leave!(n, env).0.get_integer()?;
+            Ok((vec_repeat(v, n, &mut env.v)?, None))
+        },
+    }
+}
+
+// A call of the function `id` (or the intrinsic `println!`), the arguments
+// are evaluated in the caller's scope and bound in a scope of their own
+fn call_fn(id: &str, params: &Arguments, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error>
+{
+    //Check if the function exists.
+    if !env.f.0.contains_key(id)
+    {
+        return Err(Error::runtime(UNBOUND_FUNCTION, format!("cannot find function `{}` in this scope", id)))
+    }
+
+    let _fn = env.f.0[id].clone();
+    if _fn.0.id == "println!"
+    {
+        let mut args : Vec<Literal> = Vec::new();
+        for arg in params.0.iter()
+        {
+            let v = eval_or_leave!(arg, env).0;
+            args.push(printable(v, &env.v)?);
+        }
+        Ok((Val::Lit(_fn.1.unwrap()(args)), None))
+    }
+    else 
+    {
+        let parameters = &_fn.0.parameters.0;
+        if parameters.len() != params.0.len()
+        {
+            return Err(Error::runtime(ARITY_MISMATCH, arity_message(id, parameters.len(), params.0.len())))
+        }
+        // the arguments are evaluated in the caller's scope
+        let mut args = vec![];
+        for (param, arg) in parameters.iter().zip(params.0.iter())
+        {
+            let v = eval_or_leave!(arg, env).0;
+            if !has_type(&v, &param.ty, &env.v, &|v, t| fits_fn(v, t, env))
             {
-                Ok((Val::Lit(literal.clone()), None))
-            },
-            Expr::Par(e) => 
+                return Err(Error::runtime(
+                        VALUE_MISMATCH, 
+                        format!("expected type `{}` for parameter `{}` of `{}`, got `{}`", param.ty, param.pat, id, show(&v, &env.v)))
+                    .with_span(arg.span))
+            }
+            args.push(v);
+        }
+        if env.calls >= MAX_CALL_DEPTH
+        {
+            return Err(Error::runtime(STACK_OVERFLOW, format!("stack overflow in call to `{}`, recursion is too deep", id)))
+        }
+        env.v.push_frame();
+        for (param, arg_val) in _fn.0.parameters.0.iter().zip(args)
+        {
+            bind_pattern(&param.pat, &arg_val, env)?;
+        }
+        env.calls += 1;
+        let retval = _fn.0.body.eval(env);
+        env.calls -= 1;
+        env.v.pop_frame();
+        returned(env, retval)
+    }
+}
+
+// The body of a closure is evaluated in a scope of its own, binding the
+// captured variables (by the value referenced, as the body cannot assign
+// them) and the parameters
+fn call_closure(id: &str, c: &Closure, captures: Vec<(String, Val)>, args: &Arguments, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error>
+{
+    let parameters = &c.parameters.0;
+    if parameters.len() != args.0.len()
+    {
+        return Err(Error::runtime(ARITY_MISMATCH, arity_message(id, parameters.len(), args.0.len()).replacen("function", "closure", 1)))
+    }
+    let mut vals = vec![];
+    for (param, arg) in parameters.iter().zip(&args.0)
+    {
+        let v = eval_or_leave!(arg, env).0;
+        if !has_type(&v, &param.ty, &env.v, &|v, t| fits_fn(v, t, env))
+        {
+            return Err(Error::runtime(
+                    VALUE_MISMATCH, 
+                    format!("expected type `{}` for parameter `{}` of `{}`, got `{}`", param.ty, param.pat, id, show(&v, &env.v)))
+                .with_span(arg.span))
+        }
+        vals.push(v);
+    }
+    if env.calls >= MAX_CALL_DEPTH
+    {
+        return Err(Error::runtime(STACK_OVERFLOW, format!("stack overflow in call to `{}`, recursion is too deep", id)))
+    }
+    let mut bound = vec![];
+    for (capture, v) in captures
+    {
+        let v = match (c.moves, v)
+        {
+            (false, Val::Ref(r)) => env.v.try_de_ref(r)?,
+            (_, v) => v,
+        };
+        bound.push((capture, v));
+    }
+    env.v.push_frame();
+    for (capture, v) in bound
+    {
+        env.v.alloc(&capture, v);
+    }
+    for (param, v) in parameters.iter().zip(vals)
+    {
+        bind_pattern(&param.pat, &v, env)?;
+    }
+    env.calls += 1;
+    let retval = c.body.eval(env);
+    env.calls -= 1;
+    env.v.pop_frame();
+    returned(env, retval)
+}
+
+// the place of an element is the place of the array, extended by the index
+fn index(e: &Expr, i: &Expr, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error>
+{
+    let (v, place) = match eval_or_leave!(e, env)
+    {
+        // auto-deref
+        (Val::Ref(r), _) => (env.v.try_de_ref(r.clone())?, Some(r)),
+        v => v,
+    };
+    let (i, _) = eval_or_leave!(i, env).0.get_integer()?;
+    element(&v, place, i, &env.v)
+}
+
+// the element `i` of the array or vector `v` at `place`, and its place, an
+// element of a vector is in the heap wherever the vector is
+pub fn element(v: &Val, place: Option<Ref>, i: i128, vars: &VarEnv<Val>) -> Result<(Val, Option<Ref>), Error>
+{
+    match v
+    {
+        Val::Array(vs) => 
+        {
+            let i = bounds_check(vs.len(), i)?;
+            Ok((vs[i].clone(), place.map(|r| r.field(i))))
+        },
+        Val::Vec(addr) => 
+        {
+            let i = bounds_check(vars.heap_elems(*addr).len(), i)?;
+            Ok((vars.heap_elems(*addr)[i].clone(), Some(vars.heap_ref(*addr, i))))
+        },
+        _ => Err(Error::runtime(VALUE_MISMATCH, format!("cannot index into `{:?}`", v))),
+    }
+}
+
+// the index `i` into an array or vector of length `len`, an error if out of bounds
+pub fn bounds_check(len: usize, i: i128) -> Result<usize, Error>
+{
+    match usize::try_from(i).ok().filter(|i| *i < len)
+    {
+        Some(i) => Ok(i),
+        None => Err(Error::runtime(INDEX_OUT_OF_BOUNDS, format!("index out of bounds: len is {} but index is {}", len, i))),
+    }
+}
+
+// the built in methods
+fn method(e: &Expr, m: &str, args: &Arguments, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error>
+{
+    let v = eval_or_leave!(e, env).0;
+    let mut vals = vec![];
+    for arg in &args.0
+    {
+        vals.push(eval_or_leave!(arg, env).0);
+    }
+    Ok((call_method(v, m, vals, &mut env.v)?, None))
+}
+
+// the built in method `m` on the receiver `v`, a vector or `String` is
+// changed in the heap through its handle
+pub fn call_method(v: Val, m: &str, args: Vec<Val>, vars: &mut VarEnv<Val>) -> Result<Val, Error>
+{
+    let v = match v
+    {
+        // auto-deref
+        Val::Ref(r) => vars.try_de_ref(r)?,
+        v => v,
+    };
+    match (&v, m, args.as_slice())
+    {
+        (Val::Array(vs), "len", []) => Ok(Val::Lit(Literal::int(vs.len() as i128, IntTy::Usize))),
+        (Val::Vec(addr), "len", []) => Ok(Val::Lit(Literal::int(vars.heap_elems(*addr).len() as i128, IntTy::Usize))),
+        (Val::Vec(addr), "push", [x]) => 
+        {
+            vars.heap_push(*addr, x.clone());
+            Ok(Val::Lit(Literal::Unit))
+        },
+        (Val::Vec(addr), "pop", []) => match vars.heap_pop(*addr)
+        {
+            Some(x) => Ok(x),
+            None => Err(Error::runtime(EMPTY_POP, "called `pop` on an empty vector")),
+        },
+        // the length in bytes, as in Rust
+        (Val::String(_), "len", []) | (Val::Lit(Literal::String(_)), "len", []) => Ok(Val::Lit(Literal::int(text(&v, vars)?.len() as i128, IntTy::Usize))),
+        (Val::String(addr), "push_str", [t]) => 
+        {
+            append(*addr, t, vars)?;
+            Ok(Val::Lit(Literal::Unit))
+        },
+        (Val::String(_), "to_string", []) => Ok(new_string(&text(&v, vars)?, vars)),
+        (Val::Lit(l), "to_string", []) => Ok(new_string(&l.to_string(), vars)),
+        // the iterator of `for c in s.chars()`, a vector of the characters
+        // as of the start of the loop
+        (Val::String(_), "chars", []) | (Val::Lit(Literal::String(_)), "chars", []) => 
+        {
+            let cs = text(&v, vars)?.chars().map(|c| Val::Lit(Literal::Char(c))).collect();
+            Ok(Val::Vec(vars.heap_alloc(cs)))
+        },
+        _ => Err(Error::runtime(VALUE_MISMATCH, format!("no method `{}` on `{:?}`", m, v))),
+    }
+}
+
+// `vec![v; n]`, a new heap block with `n` clones of `v`
+pub fn vec_repeat(v: Val, n: i128, vars: &mut VarEnv<Val>) -> Result<Val, Error>
+{
+    match usize::try_from(n)
+    {
+        Ok(n) => 
+        {
+            // clones of `v`, then `v` itself
+            let mut vs: Vec<Val> = (1..n).map(|_| clone_value(&v, vars)).collect();
+            if n > 0
             {
-                e.eval(env)
+                vs.push(v);
             }
-            Expr::UnOp(u, e) => 
+            Ok(Val::Vec(vars.heap_alloc(vs)))
+        },
+        Err(_) => Err(Error::runtime(VALUE_MISMATCH, format!("negative length `{}` in `vec![_; n]`", n))),
+    }
+}
+
+// A copy of `v` where each `String` and `Vec` has a heap block of its own,
+// as by `Clone`
+fn clone_value(v: &Val, vars: &mut VarEnv<Val>) -> Val
+{
+    let all = |vs: &[Val], vars: &mut VarEnv<Val>| vs.iter().map(|v| clone_value(v, vars)).collect();
+    match v
+    {
+        Val::Vec(addr) => 
+        {
+            let elems = vars.heap_elems(*addr).to_vec();
+            let elems = all(&elems, vars);
+            Val::Vec(vars.heap_alloc(elems))
+        },
+        Val::String(addr) => 
+        {
+            let chars = vars.heap_elems(*addr).to_vec();
+            Val::String(vars.heap_alloc(chars))
+        },
+        Val::Tuple(vs) => Val::Tuple(all(vs, vars)),
+        Val::Array(vs) => Val::Array(all(vs, vars)),
+        Val::Struct(id, fields) => Val::Struct(id.clone(), fields.iter().map(|(f, v)| (f.clone(), clone_value(v, vars))).collect()),
+        Val::Enum(e, id, fields) => Val::Enum(e.clone(), id.clone(), fields.iter().map(|(f, v)| (f.clone(), clone_value(v, vars))).collect()),
+        v => v.clone(),
+    }
+}
+
+// a tuple or array, evaluated from left to right
+fn elements(es: &[Expr], value: fn(Vec<Val>) -> Val, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error>
+{
+    let mut vs = vec![];
+    for e in es
+    {
+        vs.push(eval_or_leave!(e, env).0);
+    }
+    Ok((value(vs), None))
+}
+
+// as for structs, the fields are stored in declaration order
+fn variant_lit(e: &str, v: &str, fields: &[(String, Expr)], env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error>
+{
+    let mut vals = vec![];
+    for (f, e) in fields
+    {
+        vals.push((f.clone(), eval_or_leave!(e, env).0));
+    }
+    let variant = match env.e.variant(e, v)
+    {
+        Some((_, variant)) => variant,
+        None => return Err(Error::runtime(VALUE_MISMATCH, format!("no variant named `{}` found for enum `{}`", v, e))),
+    };
+    let mut variant_fields = vec![];
+    for field in &variant.fields
+    {
+        match vals.iter().position(|(f, _)| *f == field.id)
+        {
+            Some(i) => variant_fields.push(vals.remove(i)),
+            None => return Err(Error::runtime(VALUE_MISMATCH, format!("missing field `{}` in initializer of `{}::{}`", field.id, e, v))),
+        }
+    }
+    if let Some((f, _)) = vals.first()
+    {
+        return Err(Error::runtime(VALUE_MISMATCH, format!("variant `{}::{}` has no field named `{}`", e, v, f)));
+    }
+    Ok((Val::Enum(e.to_string(), v.to_string(), variant_fields), None))
+}
+
+// the first arm with a matching pattern is evaluated, with the
+// pattern variables bound in a fresh scope
+fn match_arms(e: &Expr, arms: &[Arm], env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error>
+{
+    let v = eval_or_leave!(e, env).0;
+    for arm in arms
+    {
+        let mut bindings = vec![];
+        if matches(&arm.pat, &v, &env.v, &mut bindings)
+        {
+            env.v.push_scope();
+            for (id, v) in bindings
             {
-                u.eval(*e.clone(), env)
+                env.v.alloc(&id, v);
+            }
+            let r = arm.body.eval(env);
+            env.v.pop_scope();
+            return Ok((r?.0, None));
+        }
+    }
+    Err(Error::runtime(NO_MATCHING_ARM, "non-exhaustive `match`, no arm matches the value"))
+}
+
+// whether `v` matches `p`, collecting the values of the pattern variables
+fn matches(p: &Pattern, v: &Val, vars: &VarEnv<Val>, bindings: &mut Vec<(String, Val)>) -> bool
+{
+    match (&p.kind, v)
+    {
+        (PatternKind::Wild, _) => true,
+        // a `&str` may be a reference to a `String`
+        (PatternKind::Lit(Literal::String(s)), v) => matches!(text(v, vars), Ok(t) if t == *s),
+        // an `i32` never matches a literal out of its range
+        (PatternKind::Lit(l), Val::Lit(lv)) => matches!(l.or_i32(), Ok(l) if l == *lv),
+        (PatternKind::Ident(_, id), v) => 
+        {
+            bindings.push((id.clone(), v.clone()));
+            true
+        },
+        (PatternKind::Variant(e, v, _, pats, _), Val::Enum(ve, vv, fields)) => 
+        {
+            e == ve && v == vv && pats.iter().all(|(f, p)| 
+                match fields.iter().find(|(id, _)| id == f)
+                {
+                    Some((_, fv)) => matches(p, fv, vars, bindings),
+                    None => false,
+                })
+        },
+        (PatternKind::Tuple(pats), Val::Tuple(vs)) => 
+        {
+            pats.len() == vs.len() && pats.iter().zip(vs).all(|(p, v)| matches(p, v, vars, bindings))
+        },
+        _ => false,
+    }
+}
+
+// binds the variables of an irrefutable pattern (of a `let` or a parameter)
+fn bind_pattern(p: &Pattern, v: &Val, env: &mut Env<Val>) -> Result<(), Error>
+{
+    let mut bindings = vec![];
+    if !matches(p, v, &env.v, &mut bindings)
+    {
+        return Err(Error::runtime(NO_MATCHING_ARM, format!("refutable pattern `{}` does not match `{}`", p, show(v, &env.v))).with_span(p.span));
+    }
+    for (id, v) in bindings
+    {
+        env.v.alloc(&id, v);
+    }
+    Ok(())
+}
+
+// the fields are evaluated in source order, and stored in declaration order
+fn struct_lit(id: &str, fields: &[(String, Expr)], env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error>
+{
+    let mut vals = vec![];
+    for (f, e) in fields
+    {
+        vals.push((f.clone(), eval_or_leave!(e, env).0));
+    }
+    let decl = match env.s.0.get(id)
+    {
+        Some(decl) => decl,
+        None => return Err(Error::runtime(VALUE_MISMATCH, format!("cannot find struct `{}` in this scope", id))),
+    };
+    let mut struct_fields = vec![];
+    for field in &decl.fields
+    {
+        match vals.iter().position(|(f, _)| *f == field.id)
+        {
+            Some(i) => struct_fields.push(vals.remove(i)),
+            None => return Err(Error::runtime(VALUE_MISMATCH, format!("missing field `{}` in initializer of `{}`", field.id, id))),
+        }
+    }
+    if let Some((f, _)) = vals.first()
+    {
+        return Err(Error::runtime(VALUE_MISMATCH, format!("struct `{}` has no field named `{}`", id, f)));
+    }
+    Ok((Val::Struct(id.to_string(), struct_fields), None))
+}
+
+// the place of a field is the place of the struct, extended by the field index
+fn field(e: &Expr, f: &str, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error>
+{
+    let (v, place) = match eval_or_leave!(e, env)
+    {
+        // auto-deref
+        (Val::Ref(r), _) => (env.v.try_de_ref(r.clone())?, Some(r)),
+        v => v,
+    };
+    match &v
+    {
+        Val::Struct(_, fields) => match fields.iter().position(|(id, _)| id == f)
+        {
+            Some(i) => Ok((fields[i].1.clone(), place.map(|r| r.field(i)))),
+            None => Err(Error::runtime(VALUE_MISMATCH, format!("no field `{}` on `{}`", f, show(&v, &env.v)))),
+        },
+        Val::Tuple(vs) => match f.parse::<usize>().ok().filter(|i| *i < vs.len())
+        {
+            Some(i) => Ok((vs[i].clone(), place.map(|r| r.field(i)))),
+            None => Err(Error::runtime(VALUE_MISMATCH, format!("no field `{}` on `{}`", f, show(&v, &env.v)))),
+        },
+        _ => Err(Error::runtime(VALUE_MISMATCH, format!("no field `{}` on `{}`", f, show(&v, &env.v)))),
+    }
+}
+
+// after an iteration of a `while` or `for` loop with label `label`, whether
+// a pending break/continue ends the loop
+fn leaves_loop(label: &Option<String>, env: &mut Env<Val>) -> bool
+{
+    match env.flow.take()
+    {
+        Some(Flow::Break(l, _)) if targets(label, &l) => true,
+        Some(Flow::Continue(l)) if targets(label, &l) => false,
+        None => false,
+        // an outer loop (or the function) is targeted
+        flow => 
+        {
+            env.flow = flow;
+            true
+        },
+    }
+}
+
+// a `break`/`continue` with label `l` targets the loop with label `label`
+fn targets(label: &Option<String>, l: &Option<String>) -> bool
+{
+    l.is_none() || l == label
+}
+
+// the result of a function body, the scopes of the blocks left by a
+// `return` are already popped on the way out
+fn returned(env: &mut Env<Val>, retval: Result<(Val, Option<Ref>), Error>) -> Result<(Val, Option<Ref>), Error>
+{
+    if retval.is_err()
+    {
+        env.flow = None;
+        return retval;
+    }
+    match env.flow.take()
+    {
+        None => retval,
+        Some(Flow::Return(v)) => Ok((v, None)),
+        // a `break`/`continue` cannot leave a function (checked by the type checker)
+        Some(Flow::Break(Some(l), _)) | Some(Flow::Continue(Some(l))) => 
+            Err(Error::runtime(LOOP_NOT_FOUND, format!("use of undeclared label `{}`", l))),
+        Some(Flow::Break(None, _)) => Err(Error::runtime(LOOP_NOT_FOUND, "`break` outside of a loop")),
+        Some(Flow::Continue(None)) => Err(Error::runtime(LOOP_NOT_FOUND, "`continue` outside of a loop")),
+    }
+}
+
+// structs and enums declared in the block are in scope for the whole block,
+// returns the types in scope outside of the block (if any are declared)
+fn declare_types(b: &Block, env: &mut Env<Val>) -> Option<(StructEnv, EnumEnv)>
+{
+    let mut outer = None;
+    for stmt in &b.statements
+    {
+        match &stmt.kind
+        {
+            StatementKind::Struct(decl) => 
+            {
+                outer.get_or_insert_with(|| (env.s.clone(), env.e.clone()));
+                env.s.0.insert(decl.id.clone(), decl.clone());
             },
+            StatementKind::Enum(decl) => 
+            {
+                outer.get_or_insert_with(|| (env.s.clone(), env.e.clone()));
+                env.e.0.insert(decl.id.clone(), decl.clone());
+            },
+            _ => (),
         }
     }
+    outer
 }
 
 impl Eval<Val> for Block 
 {
     fn eval(&self, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error> 
     {
+        let outer_types = declare_types(self, env);
         env.v.push_scope();
         let mut return_val = Val::Lit(Literal::Unit);
         for be in &self.statements 
         {
-            println!("be {:?}", be);
-            match be 
+            return_val = be.eval(env)?.0;
+            if env.flow.is_some()
             {
-                Statement::Assign(id, e) => 
-                {
-                    // the right hand side, in the "old" env
-                    let id_val = id.eval(env)?;
-                    let ex = e.eval(env)?;
-                    let err = env.v.get(&id.to_string());
-                    if err.is_none()
-                    {
-                        return Err("That variable has not been declared".to_string());
-                    }
-                    if id_val.1.is_some()
-                    {
-                        env.v.set_ref(id_val.1.unwrap(), ex.0);
-                    }
-                    else
-                    {
-                        return Err("Expected ref in assignment".to_string());
-                    }
-                },
-                Statement::Expr(e) => 
-                {
-                    return_val = e.eval(env)?.0;
-                },
-                Statement::Fn(fndecl) => 
+                // leaving the block by break/continue
+                break;
+            }
+        }
+        env.v.pop_scope();
+        if let Some((s, e)) = outer_types
+        {
+            env.s = s;
+            env.e = e;
+        }
+        match self.semi 
+        {
+            true => Ok((Val::Lit(Literal::Unit), None)),
+            false => Ok((return_val, None)),
+        }
+    }
+}
+
+impl Eval<Val> for Statement 
+{
+    fn eval(&self, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error> 
+    {
+        exec(self, env).map_err(|err| err.or_span(self.span))
+    }
+}
+
+// Statements other than expressions evaluate to ()
+fn exec(stmt: &Statement, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error> 
+{
+    match &stmt.kind 
+    {
+        StatementKind::Assign(id, e) => 
+        {
+            // the place on the left hand side, then the right hand side
+            let place = eval_or_leave!(id, env).1;
+            let ex = eval_or_leave!(e, env);
+            match place
+            {
+                Some(r) => env.v.try_set_ref(r, ex.0)?,
+                None => return Err(Error::runtime(NOT_A_PLACE, "Expected ref in assignment")),
+            }
+        },
+        StatementKind::Expr(e) => 
+        {
+            return e.eval(env);
+        },
+        StatementKind::Fn(fndecl) => 
+        {
+            fndecl.eval(env)?;
+        },
+        StatementKind::Struct(_) | StatementKind::Enum(_) => 
+        {
+            // registered when entering the block
+        },
+        StatementKind::Let(p, _, e) => 
+        {   
+            // the right hand side, in the "old" env
+            let l: Val;
+            match e
+            {
+                Some(e) => l = eval_or_leave!(e, env).0,
+                None => l = Val::UnInit
+            }
+            // the left hand side, an ident or a pattern taking the value apart
+            match (&p.kind, l)
+            {
+                (PatternKind::Ident(_, id), l) => { env.v.alloc(id, l); },
+                (_, Val::UnInit) => 
                 {
-                    fndecl.eval(env)?;
-                },
-                Statement::Let(m, id, _, e) => 
-                {   
-                    // the right hand side, in the "old" env
-                    let l: Val;
-                    match e
+                    for id in p.bindings()
                     {
-                        Some(e) => l = e.eval(env)?.0,
-                        None => l = Val::UnInit
+                        env.v.alloc(id, Val::UnInit);
                     }
-                    // the left hand side, for now just accept an ident
-                    env.v.alloc(id, l);
                 },
-                Statement::While(c, block) => 
+                (_, l) => bind_pattern(p, &l, env)?,
+            }
+        },
+        StatementKind::While(label, c, block) => 
+        {
+            while eval_or_leave!(c, env).0.get_bool()? 
+            {
+                block.eval(env)?;
+                if leaves_loop(label, env)
                 {
-                    while c.eval(env)?.0.get_bool()? 
-                    {
-                        block.eval(env)?;
-                    }
-                },
+                    break;
+                }
             }
-        }
+        },
+        StatementKind::For(label, id, Iter::Range(range), block) => 
+        {
+            let (start, t) = eval_or_leave!(range.start, env).0.get_integer()?;
+            let (end, _) = eval_or_leave!(range.end, env).0.get_integer()?;
+            let values: Box<dyn Iterator<Item = i128>> = match (range.inclusive, range.rev)
+            {
+                (false, false) => Box::new(start..end),
+                (true, false) => Box::new(start..=end),
+                (false, true) => Box::new((start..end).rev()),
+                (true, true) => Box::new((start..=end).rev()),
+            };
+            for_each(label, id, values.map(|i| Val::Lit(Literal::int(i, t))), block, env)?;
+        },
+        StatementKind::For(label, id, Iter::Chars(s), block) => 
+        {
+            // the characters as of the start of the loop
+            let v = eval_or_leave!(s, env).0;
+            let cs: Vec<Val> = text(&v, &env.v)?.chars().map(|c| Val::Lit(Literal::Char(c))).collect();
+            for_each(label, id, cs.into_iter(), block, env)?;
+        },
+    }
+    Ok((Val::Lit(Literal::Unit), None))
+}
+
+// runs the body of a `for` loop once for each of `values`
+fn for_each(label: &Option<String>, id: &str, values: impl Iterator<Item = Val>, block: &Block, env: &mut Env<Val>) -> Result<(), Error>
+{
+    for v in values
+    {
+        // the loop variable is scoped to the body
+        env.v.push_scope();
+        env.v.alloc(id, v);
+        let r = block.eval(env);
         env.v.pop_scope();
-        match self.semi 
+        r?;
+        if leaves_loop(label, env)
         {
-            true => Ok((Val::Lit(Literal::Unit), None)),
-            false => Ok((return_val, None)),
+            break;
         }
     }
+    Ok(())
 }
 
 impl Eval<Val> for FnDeclaration 
@@ -230,11 +1226,14 @@ impl Eval<Val> for Prog
     fn eval(&self, env: &mut Env<Val>) -> Result<(Val, Option<Ref>), Error> 
     {
         //env.f.add_functions_unique(self.0.clone());
+        // generic functions are instantiated for the types of each call
+        let prog = monomorphize(self)?;
         let mut mainfn: Option<FnDeclaration> = None;
         let (print, intrinsic) = vm_println();
         env.f.0.insert(print.id.clone(), (print, Some(intrinsic)));
+        env.add_types_unique(prog.1.clone(), prog.2.clone())?;
 
-        for _fn in self.0.clone()
+        for _fn in prog.0
         {
             if _fn.id == "main"
             {
@@ -244,7 +1243,7 @@ impl Eval<Val> for Prog
                 }
                 else
                 {
-                    return Err("Main doesn't support arguments.".to_string())
+                    return Err(Error::runtime(NO_ENTRY_POINT, "Main doesn't support arguments."))
                 }
             }
             _fn.eval(env)?;
@@ -252,11 +1251,12 @@ impl Eval<Val> for Prog
 
         if mainfn.is_none()
         {
-            Err("Main not found".to_string())
+            Err(Error::runtime(NO_ENTRY_POINT, "Main not found"))
         }
         else
         {
-            mainfn.unwrap().body.eval(env)
+            let retval = mainfn.unwrap().body.eval(env);
+            returned(env, retval)
         }
     }
 }
@@ -270,39 +1270,33 @@ impl UnOp
         {
             UnOp::Bang => 
             {
-                Ok((Val::Lit(Bool(!expr.eval(env)?.0.get_bool()?)), None))
+                Ok((Val::Lit(Bool(!eval_or_leave!(expr, env).0.get_bool()?)), None))
+            },
+            UnOp::Neg => 
+            {
+                Ok((neg(&eval_or_leave!(expr, env).0)?, None))
             },
             UnOp::DeRef => 
             {
-                let v = expr.eval(env)?;
+                let v = eval_or_leave!(expr, env);
                 let v = v.0;
                 match v
                 {
-                    Val::Mut(m) =>
-                    {
-                        match *m
-                        {
-                            Val::Ref(r) =>
-                            {
-                                Ok((env.v.de_ref(r.clone()), Some(r)))
-                            }
-                            _=> Err("Var is not a reference".to_string())
-                        }
-                    }
                     Val::Ref(r) =>
                     {
-                        Ok((env.v.de_ref(r.clone()), Some(r)))
+                        Ok((env.v.try_de_ref(r.clone())?, Some(r)))
                     }
-                    _ => Err("Var is not a reference!".to_string())
+                    _ => Err(Error::runtime(NOT_A_REFERENCE, "Var is not a reference!"))
                 }
             },
             UnOp::Mut => 
             {
-                Ok((Val::Mut(Box::new(expr.eval(env)?.0)), None))
+                // mutability is checked by the type checker
+                expr.eval(env)
             },
             UnOp::Ref => 
             {
-                let v = expr.eval(env)?;
+                let v = eval_or_leave!(expr, env);
                 let r = if !v.1.is_none()
                 {
                     v.1.unwrap()
@@ -310,7 +1304,7 @@ impl UnOp
                 else
                 {
                     let new_ref = env.v.stack_val(v.0.clone());
-                    env.v.set_ref(new_ref, v.0.clone());
+                    env.v.set_ref(new_ref.clone(), v.0.clone());
                     new_ref
                 };
                 Ok( (Val::Ref(r.clone()), Some(r)))
@@ -324,8 +1318,10 @@ impl UnOp
 mod tests {
     use super::Val;
     use crate::ast::Literal;
-    use crate::ast::{Block, Prog};
-    use crate::common::parse_test;
+    use crate::ast::{Block, IntTy, Prog};
+    use crate::common::{parse_test, Eval};
+    use crate::env::Env;
+    use crate::error::{ARITHMETIC_OVERFLOW, ARITY_MISMATCH, DANGLING_REFERENCE, DIVIDE_BY_ZERO, EMPTY_POP, INDEX_OUT_OF_BOUNDS, LITERAL_OVERFLOW, MISMATCHED_TYPES, NO_MATCHING_ARM, VALUE_MISMATCH};
 
     #[test]
     fn test_block_let() {
@@ -649,4 +1645,565 @@ mod tests {
         );
         assert_eq!(v.unwrap().get_int().unwrap(), 1);
     }
+
+    #[test]
+    fn test_call_args_scope() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn main() {
+            fn f(a: i32, b: i32) -> i32 {
+                a * 10 + b
+            }
+            let mut a = 0;
+            f({ a = a + 1; a }, { a = a + 2; a })
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 13);
+    }
+
+    #[test]
+    fn test_call_arity_err() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn main() {
+            fn f(a: i32, b: i32) -> i32 {
+                a + b
+            }
+            f(1)
+        }
+        ",
+        );
+        let err = v.unwrap_err();
+        assert_eq!(err.code, ARITY_MISMATCH);
+        assert_eq!(err.message, "function `f` takes 2 arguments but 1 argument was supplied");
+    }
+
+    #[test]
+    fn test_call_arg_type_err() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn main() {
+            fn f(a: i32, b: &bool) {
+            }
+            f(1, &2)
+        }
+        ",
+        );
+        let err = v.unwrap_err();
+        assert_eq!(err.code, VALUE_MISMATCH);
+        assert_eq!(err.message, "expected type `&bool` for parameter `b` of `f`, got `&2`");
+    }
+
+    #[test]
+    fn test_operators() {
+        let v = parse_test::<Block, Val>(
+            "
+        {
+            let a = -7 % 3;
+            let b = (3 <= 3) && ((4 >= 5) != true);
+            if b { a } else { -a }
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), -1);
+    }
+
+    #[test]
+    fn test_div_by_zero() {
+        let v = parse_test::<Block, Val>(
+            "
+        {
+            let a = 0;
+            1 % a
+        }
+        ",
+        );
+        assert_eq!(v.unwrap_err().code, DIVIDE_BY_ZERO);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let v = parse_test::<Block, Val>(
+            "
+        {
+            let a = 10 - 2 - 3;
+            let b = 2 + 3 * 4 % 5;
+            a == 5 && b == 4 || false
+        }
+        ",
+        );
+        assert!(v.unwrap().get_bool().unwrap());
+    }
+
+    #[test]
+    fn test_loop() {
+        let v = parse_test::<Block, Val>(
+            "
+        {
+            let mut i = 0;
+            let mut sum = 0;
+            let x = 'outer: loop {
+                i = i + 1;
+                if i % 2 == 0 {
+                    continue;
+                }
+                let mut j = 0;
+                while j < 10 {
+                    j = j + 1;
+                    if j > i { continue 'outer; }
+                    if i * j > 30 { break 'outer sum + 1000; }
+                    sum = sum + j;
+                }
+            };
+            x
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 1032);
+    }
+
+    #[test]
+    fn test_break_in_operand() {
+        let v = parse_test::<Block, Val>(
+            "
+        {
+            let mut a = 0;
+            'w: while true {
+                a = 1 + loop { break 'w; };
+            }
+            a + loop { break 2 }
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_return() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn find(n: i32) -> i32 {
+            let mut i = 0;
+            while i < 100 {
+                let sq = i * i;
+                if sq == n {
+                    return i;
+                }
+                i = i + 1;
+            }
+            -1
+        }
+        fn main() -> i32 {
+            let r = find(49) + find(50);
+            return r;
+            0
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 6);
+    }
+
+    #[test]
+    fn test_return_pops_scopes() {
+        // the scopes of the blocks left by `return` must not shadow the caller's
+        let ts: proc_macro2::TokenStream = "
+        fn f(n: i32) -> i32 {
+            let x = 100;
+            while true {
+                let x = 200;
+                if n > 0 {
+                    let x = 300;
+                    loop { return x; }
+                }
+                return x;
+            }
+            x
+        }
+        fn main() -> i32 {
+            let x = 1;
+            let a = f(1);
+            let b = f(0);
+            x + a + b
+        }
+        "
+        .parse()
+        .unwrap();
+        let prog: Prog = syn::parse2(ts).unwrap();
+        let mut env: Env<Val> = Env::new();
+        let depth = env.v.depth();
+        let v = prog.eval(&mut env).unwrap().0;
+        assert_eq!(v.get_int().unwrap(), 501);
+        assert_eq!(env.v.depth(), depth);
+        assert!(env.flow.is_none());
+    }
+
+    #[test]
+    fn test_for() {
+        let v = parse_test::<Block, Val>(
+            "
+        {
+            let mut s = 0;
+            for i in 0..5 { s = s + i; }
+            for i in (1..=3).rev() { s = s * 10 + i; }
+            let i = 100;
+            for i in 5..2 { s = 0; }
+            'o: for i in 0..=6 {
+                for j in 0..10 {
+                    if j > i { continue 'o; }
+                    if i == 5 { break 'o; }
+                    s = s + j;
+                }
+            }
+            s + i
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 10321 + 20 + 100);
+    }
+
+    #[test]
+    fn test_struct() {
+        let v = parse_test::<Prog, Val>(
+            "
+        struct Point { x: i32, y: i32 }
+        struct Line { from: Point, to: Point }
+        fn shift(p: &Point, d: i32) {
+            p.x = p.x + d;
+            (*p).y = (*p).y + d;
+        }
+        fn main() -> i32 {
+            let mut l = Line { to: Point { y: 2, x: 1 }, from: Point { x: 0, y: 0 } };
+            let x = &mut l.to.x;
+            *x = 10;
+            shift(&mut l.from, 3);
+            let to = l.to;
+            l.to.y = 5;
+            l.from.x * 1000 + l.from.y * 100 + to.x + to.y + l.to.y
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 3317);
+    }
+
+    #[test]
+    fn test_match() {
+        let v = parse_test::<Prog, Val>(
+            "
+        enum Shape {
+            Dot,
+            Circle(i32),
+            Rect { w: i32, h: i32 },
+        }
+        fn area(s: &Shape) -> i32 {
+            match *s {
+                Shape::Dot => 0,
+                Shape::Circle(0) => return -1,
+                Shape::Circle(r) => 3 * r * r,
+                Shape::Rect { w: 1, h } => h,
+                Shape::Rect { w, h: mut h } => {
+                    h = h * w;
+                    h
+                }
+            }
+        }
+        fn main() -> i32 {
+            enum Op {
+                Add(i32),
+                Neg,
+            }
+            let mut total = 0;
+            for i in 0..4 {
+                let op = if i % 2 == 0 { Op::Add(i) } else { Op::Neg };
+                total = match op {
+                    Op::Add(n) => total + n,
+                    Op::Neg => -total,
+                };
+            }
+            let s = Shape::Rect { h: 3, w: 2 };
+            area(&s) * 1000 + area(&Shape::Circle(2)) * 10 + area(&Shape::Circle(0)) + total
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 6117);
+    }
+
+    #[test]
+    fn test_tuple() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn swap((a, b): (i32, bool), mut n: i32) -> (bool, i32) {
+            n = n + 1;
+            (b, a + n)
+        }
+        fn main() -> i32 {
+            let t = (1, (true, 3));
+            let (a, (mut b, c)) = t;
+            b = !b;
+            let s = swap((a, b), c);
+            let mut u = (7,);
+            u.0 = u.0 + t.1.1;
+            let r = &mut u;
+            r.0 = r.0 * 2;
+            match s { (false, n) => n * 100 + u.0, (true, _) => 0 }
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 520);
+    }
+
+    #[test]
+    fn test_array() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn fill(r: &[i32; 4], v: i32) {
+            let mut i = 0;
+            while i < (*r).len() {
+                (*r)[i] = v + i;
+                i = i + 1;
+            }
+        }
+        fn main() -> i32 {
+            let mut a = [0; 4];
+            fill(&a, 10);
+            let e = &a[2];
+            *e = 100;
+            let m = [[1, 2], [3, 4]];
+            a[0] + a[1] + a[2] + a[3] + m[1][0] * 1000
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 3134);
+    }
+
+    #[test]
+    fn test_array_out_of_bounds() {
+        let v = parse_test::<Block, Val>("{ let a = [1, 2, 3]; let i = 3; a[i] }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, INDEX_OUT_OF_BOUNDS);
+        assert_eq!(err.message, "index out of bounds: len is 3 but index is 3");
+        let v = parse_test::<Block, Val>("{ let mut a = [1, 2, 3]; a[-1] = 0; }");
+        assert_eq!(v.unwrap_err().message, "index out of bounds: len is 3 but index is -1");
+    }
+
+    #[test]
+    fn test_vec() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn range(n: i32) -> Vec<i32> {
+            let mut v = Vec::new();
+            for i in 0..n {
+                v.push(i);
+            }
+            v
+        }
+        fn main() -> i32 {
+            let mut v = range(5);
+            let last = v.pop();
+            v[0] = 10;
+            let e = &v[1];
+            *e = 20;
+            let m = vec![vec![1; 2]; 3];
+            v[0] + v[1] + last * 100 + v.len() as i32 * 1000 + m[2][1] * 10000
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 14430);
+    }
+
+    #[test]
+    fn test_scope_dangling() {
+        // a reference outliving the block of the variable (not borrow checked)
+        let v = parse_test::<Block, Val>("{ let r = { let y = 5; &y }; let z = 100; *r }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, DANGLING_REFERENCE);
+        assert_eq!(err.message, "use of a reference to a variable that is out of scope");
+        // also once another block took the place of the scope
+        let v = parse_test::<Block, Val>("{ let r = { let y = 5; &y }; { let z = 100; *r } }");
+        assert_eq!(v.unwrap_err().code, DANGLING_REFERENCE);
+    }
+
+    #[test]
+    fn test_vec_dangling() {
+        // a reallocating push invalidates references into the vector
+        let v = parse_test::<Block, Val>("{ let mut v = vec![1]; let r = &v[0]; v.push(2); *r }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, DANGLING_REFERENCE);
+        assert_eq!(err.message, "use of a reference into a vector reallocated by a `push`");
+        // unless there is room left
+        let v = parse_test::<Block, Val>("{ let mut v = Vec::new(); v.push(1); let r = &v[0]; v.push(2); *r }");
+        assert_eq!(v.unwrap().get_int().unwrap(), 1);
+        let v = parse_test::<Block, Val>("{ let mut v = vec![1]; let r = &v[0]; v.pop(); *r }");
+        assert_eq!(v.unwrap_err().message, "use of a reference to an element removed by a `pop`");
+        let v = parse_test::<Block, Val>("{ let mut v = vec![1]; v.pop(); v.pop() }");
+        assert_eq!(v.unwrap_err().code, EMPTY_POP);
+        let v = parse_test::<Block, Val>("{ let v = vec![1, 2]; v[2] }");
+        assert_eq!(v.unwrap_err().code, INDEX_OUT_OF_BOUNDS);
+    }
+
+    #[test]
+    fn test_int_types() {
+        let int = |s: &str| parse_test::<Block, Val>(s).unwrap().get_integer().unwrap();
+        assert_eq!(int("{ 300 as u8 }"), (44, IntTy::U8));
+        assert_eq!(int("{ -1 as u32 }"), (4294967295, IntTy::U32));
+        assert_eq!(int("{ 200u8 as i8 }"), (-56, IntTy::I8));
+        assert_eq!(int("{ true as i64 }"), (1, IntTy::I64));
+        assert_eq!(int("{ 4000000000u64 * 4u64 }"), (16000000000, IntTy::U64));
+        assert_eq!(int("{ let mut s = 0u8; for i in 250u8..=255u8 { s = i; } s }"), (255, IntTy::U8));
+        assert_eq!(int("{ let a = [1, 2, 3]; a[2usize] }"), (3, IntTy::I32));
+    }
+
+    #[test]
+    fn test_int_overflow() {
+        let v = parse_test::<Block, Val>("{ 255u8 + 1u8 }");
+        let err = v.unwrap_err();
+        assert_eq!(err.code, ARITHMETIC_OVERFLOW);
+        assert_eq!(err.message, "attempt to add with overflow");
+        let v = parse_test::<Block, Val>("{ 0usize - 1usize }");
+        assert_eq!(v.unwrap_err().message, "attempt to subtract with overflow");
+        let v = parse_test::<Block, Val>("{ 2147483647 + 1 }");
+        assert_eq!(v.unwrap_err().code, ARITHMETIC_OVERFLOW);
+        let v = parse_test::<Block, Val>("{ let a = -128 as i8; -a }");
+        assert_eq!(v.unwrap_err().message, "attempt to negate with overflow");
+        let v = parse_test::<Block, Val>("{ let a = -128 as i8; a / -1i8 }");
+        assert_eq!(v.unwrap_err().message, "attempt to divide with overflow");
+        let v = parse_test::<Block, Val>("{ 1u16 % 0u16 }");
+        assert_eq!(v.unwrap_err().code, DIVIDE_BY_ZERO);
+    }
+
+    #[test]
+    fn test_float_char() {
+        let lit = |s: &str| match parse_test::<Block, Val>(s).unwrap() {
+            Val::Lit(l) => l,
+            v => panic!("expected a literal, got {:?}", v),
+        };
+        assert_eq!(lit("{ 1.5 + 2.0 * 3.0 }"), Literal::Float(7.5));
+        assert_eq!(lit("{ -7.5 % 2.0 }"), Literal::Float(-1.5));
+        assert_eq!(lit("{ 1.0 / 0.0 }"), Literal::Float(f64::INFINITY));
+        assert_eq!(lit("{ let nan = 0.0 / 0.0; nan < 1.0 || nan >= 1.0 || nan == nan }"), Literal::Bool(false));
+        assert_eq!(lit("{ 'a' < 'b' && 'b' <= 'b' && '\\n' != 'n' }"), Literal::Bool(true));
+        assert_eq!(lit("{ -2.9 as i32 }"), Literal::Int(-2));
+        assert_eq!(lit("{ 300.5 as u8 }"), Literal::Sized(255, IntTy::U8));
+        assert_eq!(lit("{ -1.0 as u64 }"), Literal::Sized(0, IntTy::U64));
+        assert_eq!(lit("{ 'é' as u8 }"), Literal::Sized(233, IntTy::U8));
+        assert_eq!(lit("{ 'A' as i32 }"), Literal::Int(65));
+        assert_eq!(lit("{ 98u8 as char }"), Literal::Char('b'));
+        assert_eq!(lit("{ 3u8 as f64 / 2.0 }"), Literal::Float(1.5));
+    }
+
+    #[test]
+    fn test_string() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn shout(s: &str) -> String {
+            let mut t = String::new();
+            for c in s.chars() {
+                if c == ' ' { continue; }
+                t.push_str(&c.to_string());
+            }
+            t + \"!\"
+        }
+        fn main() -> bool {
+            let s = String::from(\"h\u{e9} y\");
+            let t = shout(&s);
+            let r: &str = &t;
+            t == \"h\u{e9}y!\" && t.len() == 5 && r == \"h\u{e9}y!\" && \"ab\" < \"b\" && match r { \"h\u{e9}y!\" => true, _ => false }
+        }
+        ",
+        );
+        assert!(v.unwrap().get_bool().unwrap());
+        // a `String` is a handle to its text in the heap
+        let v = parse_test::<Block, Val>("{ let mut s = 12.to_string(); s.push_str(\"3\"); s }").unwrap();
+        assert!(matches!(v, Val::String(_)));
+        let v = parse_test::<Block, Val>("{ \"a\".len() + \"\u{e9}\".len() }");
+        assert_eq!(v.unwrap().get_integer().unwrap(), (3, IntTy::Usize));
+    }
+
+    #[test]
+    fn test_closure() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn apply(f: impl Fn(i32) -> i32, x: i32) -> i32 {
+            let y = 100;
+            f(x)
+        }
+        fn main() -> i32 {
+            let mut y = 1;
+            let add = |x: i32| x + y;
+            y = 2;
+            let keep = move |x: i32| x + y;
+            y = 3;
+            let nested = |x: i32| { let inner = |z: i32| z * x + y; inner(2) };
+            apply(add, 10) * 100 + apply(keep, 10) + nested(5)
+        }
+        ",
+        );
+        // `add` reads `y` at the call, `keep` holds its value when created
+        assert_eq!(v.unwrap().get_int().unwrap(), 1300 + 12 + 13);
+        let v = parse_test::<Block, Val>("{ let f = |x: i32| x; f(1, 2) }");
+        assert_eq!(v.unwrap_err().code, ARITY_MISMATCH);
+    }
+
+    #[test]
+    fn test_fn_pointer() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn add(a: i32, b: i32) -> i32 { a + b }
+        fn fold(xs: [i32; 3], f: fn(i32, i32) -> i32, init: i32) -> i32 {
+            let mut acc = init;
+            for i in 0..3 { acc = f(acc, xs[i]); }
+            acc
+        }
+        fn main() -> i32 {
+            fn mul(a: i32, b: i32) -> i32 { a * b }
+            let f = add;
+            fold([1, 2, 3], f, 0) * 100 + fold([1, 2, 3], mul, 1)
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 606);
+        let v = parse_test::<Prog, Val>("fn add(a: i32, b: i32) -> i32 { a + b } fn main() { let f = add; f(1) }");
+        assert_eq!(v.unwrap_err().code, ARITY_MISMATCH);
+    }
+
+    #[test]
+    fn test_generic() {
+        let v = parse_test::<Prog, Val>(
+            "
+        fn max<T>(a: T, b: T) -> T { if a > b { a } else { b } }
+        fn swap<A, B>(p: (A, B)) -> (B, A) { (p.1, p.0) }
+        fn main() -> i32 {
+            let s = swap((max('a', 'c'), max(1, 3)));
+            let b = max::<bool>(false, true);
+            if b && s.1 == 'c' { s.0 } else { 0 }
+        }
+        ",
+        );
+        assert_eq!(v.unwrap().get_int().unwrap(), 3);
+        // instantiated by the type checker
+        let v = parse_test::<Prog, Val>("fn id<T>(x: T) -> T { x } fn main() { let a: bool = id(1); }");
+        assert_eq!(v.unwrap_err().code, MISMATCHED_TYPES);
+        // `id::<u8>` and `id::<u64>`, inferred from the uses of the results
+        let v = parse_test::<Prog, Val>("fn id<T>(x: T) -> T { x } fn main() -> u64 { let b: u8 = id(255); let c: u64 = id(1) + 2u64; c + b as u64 }");
+        assert_eq!(v.unwrap(), Val::Lit(Literal::Sized(258, IntTy::U64)));
+    }
+
+    #[test]
+    fn test_match_no_arm() {
+        // not type checked
+        let v = parse_test::<Block, Val>("{ match 3 { 1 => 1, 2 => 2 } }");
+        assert_eq!(v.unwrap_err().code, NO_MATCHING_ARM);
+    }
+
+    #[test]
+    fn test_literal_overflow() {
+        // not type checked, an integer literal without a suffix is an `i32`
+        let v = parse_test::<Block, Val>("{ let x: u64 = 18446744073709551615; x }");
+        assert_eq!(v.unwrap_err().code, LITERAL_OVERFLOW);
+        let v = parse_test::<Block, Val>("{ match 1 { 5000000000 => 1, _ => 2 } }");
+        assert_eq!(v.unwrap().get_int().unwrap(), 2);
+        // and in a program that fails the type check
+        let v = parse_test::<Prog, Val>("fn main() { let x: u64 = 18446744073709551615; let q: u8 = 256; }");
+        assert_eq!(v.unwrap_err().code, LITERAL_OVERFLOW);
+    }
 }